@@ -11,9 +11,9 @@
 //ZIP32 functions
 void ask_to_ak(const uint8_t *ask_ptr, uint8_t *ak_ptr);
 
-void get_pkd(const uint8_t *seed_ptr, const uint32_t pos, const uint8_t *diversifier_ptr, uint8_t *pkd);
+parser_error_t get_pkd(const uint8_t *seed_ptr, const uint32_t pos, const uint8_t *diversifier_ptr, uint8_t *pkd);
 
-void get_pkd_from_seed(const uint8_t *seed_ptr, const uint32_t pos, const uint8_t *start_index, uint8_t *diversifier_ptr, uint8_t *pkd);
+parser_error_t get_pkd_from_seed(const uint8_t *seed_ptr, const uint32_t pos, const uint8_t *start_index, uint8_t *diversifier_ptr, uint8_t *pkd);
 
 void get_diversifier_list(const uint8_t *sk_ptr, uint8_t *diversifier_list);
 
@@ -35,43 +35,82 @@ void zip32_ivk(const uint8_t *ak_ptr, uint8_t *ivk_ptr, const uint32_t pos);
 
 void zip32_ovk(const uint8_t *seed_ptr, uint8_t *ovk, const uint32_t pos);
 
+parser_error_t mark_change_outputs(const uint8_t *inittx_ptr, uint16_t inittx_len, const uint8_t *seed_ptr,
+                                   const uint8_t *key_hashes_ptr, uint8_t key_hashes_len,
+                                   uint8_t *t_change_ptr, uint8_t *s_change_ptr);
+
 void zip32_child_proof_key(const uint8_t *seed_ptr, uint8_t *ak_ptr, uint8_t *nsk_ptr, const uint32_t pos);
 
 //Rseed
-void rseed_get_esk_epk(const uint8_t *seed_ptr, uint8_t *d_ptr, uint8_t *output_esk_ptr, uint8_t *output_epk_ptr);
+parser_error_t rseed_get_esk_epk(const uint8_t *seed_ptr, uint8_t *d_ptr, uint8_t *output_esk_ptr, uint8_t *output_epk_ptr);
 
 void rseed_get_rcm(const uint8_t *input, uint8_t *output_ptr);
 
 //Commitments
-void compute_note_commitment(uint8_t *inputptr, const uint8_t *rcmptr,const uint64_t value,const uint8_t *diversifier_ptr, const uint8_t *pkd);
+parser_error_t compute_note_commitment(uint8_t *inputptr, const uint8_t *rcmptr,const uint64_t value,const uint8_t *diversifier_ptr, const uint8_t *pkd);
 
-void compute_note_commitment_fullpoint(uint8_t *inputptr, const uint8_t *rcmptr,const uint64_t value, const uint8_t *diversifier_ptr, const uint8_t *pkd);
+parser_error_t compute_note_commitment_fullpoint(uint8_t *inputptr, const uint8_t *rcmptr,const uint64_t value, const uint8_t *diversifier_ptr, const uint8_t *pkd);
 
 void compute_value_commitment(const uint64_t value, const uint8_t *rcmptr, uint8_t *output);
 
-void compute_nullifier(uint8_t *ncmptr, uint64_t pos, const uint8_t *nsk_ptr, uint8_t *outputptr);
+parser_error_t compute_nullifier(uint8_t *ncmptr, uint64_t pos, const uint8_t *nsk_ptr, uint8_t *outputptr);
 
 void compute_valueBalance_commitment(const uint64_t u64, uint8_t *output);
 
 //Note encryption
 void blake2b_prf(uint8_t *inputptr, uint8_t *outptr);
 
-void ka_to_key(uint8_t *esk_ptr, uint8_t *pkd_ptr, uint8_t *epk_ptr, uint8_t *output_ptr);
+parser_error_t ka_to_key(uint8_t *esk_ptr, uint8_t *pkd_ptr, uint8_t *epk_ptr, uint8_t *output_ptr);
+
+parser_error_t prepare_enccompact_input(uint8_t leadbyte, uint8_t *d, uint64_t value, uint8_t *rnd, uint8_t memotype, uint8_t *output);
+
+parser_error_t get_note_rcm(uint8_t leadbyte, const uint8_t *rnd, uint8_t *rcm);
+
+uint8_t zip212_output_lead_byte(bool testnet, uint32_t expiry_height);
+
+parser_error_t check_enc_ciphertext(uint8_t leadbyte, const uint8_t *d, uint64_t value, const uint8_t *rnd, uint8_t memotype, const uint8_t *key, const uint8_t *enc_ciphertext);
+
+parser_error_t get_memo_page(const uint8_t *d, const uint8_t *pkd, const uint8_t *rnd, const uint8_t *enc_ciphertext, uint8_t *out, uint16_t out_len, uint8_t page_idx, uint8_t *page_count);
+
+//Host session
+parser_error_t session_open(const uint8_t *sk_ptr, const uint8_t *host_public_ptr, uint8_t *out_ptr);
+
+bool session_is_active();
+
+parser_error_t session_seal(uint8_t *buffer, uint16_t buffer_len, uint16_t payload_len, uint16_t *frame_len);
 
-void prepare_enccompact_input(uint8_t *d, uint64_t value, uint8_t *rcm, uint8_t memotype, uint8_t *output);
+void session_close();
+
+//Review
+parser_error_t tx_display_num_items(const uint8_t *inittx, uint16_t inittx_len, uint8_t t_change, uint8_t s_change, uint8_t *num_items);
+
+parser_error_t tx_display_get_item(const uint8_t *inittx, uint16_t inittx_len, uint8_t t_change, uint8_t s_change, uint8_t display_idx, uint8_t *out_key, uint16_t out_key_len, uint8_t *out_value, uint16_t out_value_len, uint8_t page_idx, uint8_t *page_count);
+
+//Fees
+bool inittx_fee_is_supported(uint64_t fee, uint8_t t_in_len, uint8_t t_out_len, uint8_t spend_len, uint8_t output_len);
 
 //RedJubjub
 void random_fr(uint8_t *alpha_ptr);
 
-void randomized_secret_from_seed(uint8_t *seed_ptr, uint32_t pos, uint8_t *alpha_ptr, uint8_t *output_ptr);
+parser_error_t randomized_secret_from_seed(uint8_t *seed_ptr, uint32_t pos, uint8_t *alpha_ptr, uint8_t *output_ptr);
+
+parser_error_t get_rk(uint8_t *ask_ptr, uint8_t *alpha_ptr, uint8_t *output_ptr);
+
+parser_error_t randomize_pk(uint8_t *alpha_ptr, uint8_t *pk_ptr);
+
+parser_error_t sign_redjubjub(uint8_t *key_ptr, uint8_t *msg_ptr, uint8_t *out_ptr);
+
+parser_error_t anti_exfil_commit(const uint8_t *host_commitment_ptr, uint8_t count, uint8_t *signer_commitments_ptr);
+
+parser_error_t anti_exfil_reveal(const uint8_t *host_data_ptr);
 
-void get_rk(uint8_t *ask_ptr, uint8_t *alpha_ptr, uint8_t *output_ptr);
+bool anti_exfil_is_armed();
 
-void randomize_pk(uint8_t *alpha_ptr, uint8_t *pk_ptr);
+parser_error_t sign_redjubjub_anti_exfil(uint8_t index, const uint8_t *key_ptr, const uint8_t *msg_ptr, uint8_t *out_ptr);
 
-void sign_redjubjub(uint8_t *key_ptr, uint8_t *msg_ptr, uint8_t *out_ptr);
+void anti_exfil_reset();
 
 //Session key
-void sessionkey_agree(uint8_t *scalar_ptr, uint8_t *point_ptr, uint8_t *output_ptr);
+parser_error_t sessionkey_agree(uint8_t *scalar_ptr, uint8_t *point_ptr, uint8_t *output_ptr);
 
 void pubkey_gen(uint8_t *scalar_ptr, uint8_t *output_ptr);
@@ -0,0 +1,156 @@
+//! String encodings of transparent and Sapling addresses.
+
+use core::convert::TryInto;
+
+use crate::errors::ParserError;
+use crate::types::PaymentAddress;
+
+pub const VERSION_P2SH: [u8; 2] = [0x1c, 0xbd];
+pub const VERSION_P2PKH: [u8; 2] = [0x1c, 0xb8];
+pub const SAPLING_HRP: &[u8] = b"zs";
+
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32_GENERATOR: [u32; 5] = [
+    0x3b6a_57b2,
+    0x2650_8e6d,
+    0x1ea1_19fa,
+    0x3d42_33dd,
+    0x2a14_62b3,
+];
+
+/// Encodes `version || payload` with a Base58Check checksum
+fn base58check(
+    version: &[u8; 2],
+    payload: &[u8; 20],
+    out: &mut [u8],
+) -> Result<usize, ParserError> {
+    let mut data = [0u8; 22];
+    data[..2].copy_from_slice(version);
+    data[2..].copy_from_slice(payload);
+    bs58::encode(&data[..])
+        .with_check()
+        .into(out)
+        .map_err(|_| ParserError::parser_unexpected_buffer_end)
+}
+
+/// Renders the address paid by a P2PKH or P2SH `scriptPubKey`
+pub fn transparent_from_script(script: &[u8], out: &mut [u8]) -> Result<usize, ParserError> {
+    match script {
+        [0x76, 0xa9, 0x14, hash @ .., 0x88, 0xac] if hash.len() == 20 => {
+            base58check(&VERSION_P2PKH, hash.try_into().unwrap(), out)
+        }
+        [0xa9, 0x14, hash @ .., 0x87] if hash.len() == 20 => {
+            base58check(&VERSION_P2SH, hash.try_into().unwrap(), out)
+        }
+        _ => Err(ParserError::parser_invalid_output_script),
+    }
+}
+
+fn bech32_polymod_step(chk: u32, value: u8) -> u32 {
+    let top = chk >> 25;
+    let mut chk = ((chk & 0x01ff_ffff) << 5) ^ value as u32;
+    for (i, g) in BECH32_GENERATOR.iter().enumerate() {
+        if (top >> i) & 1 == 1 {
+            chk ^= g;
+        }
+    }
+    chk
+}
+
+/// Bech32 (BIP-173) encoding of 8-bit `data` under `hrp`
+pub fn bech32_encode(hrp: &[u8], data: &[u8], out: &mut [u8]) -> Result<usize, ParserError> {
+    let data_len = (data.len() * 8).div_ceil(5);
+    let total = hrp.len() + 1 + data_len + 6;
+    if out.len() < total {
+        return Err(ParserError::parser_unexpected_buffer_end);
+    }
+
+    let mut chk = 1u32;
+    for &c in hrp {
+        chk = bech32_polymod_step(chk, c >> 5);
+    }
+    chk = bech32_polymod_step(chk, 0);
+    for &c in hrp {
+        chk = bech32_polymod_step(chk, c & 0x1f);
+    }
+
+    out[..hrp.len()].copy_from_slice(hrp);
+    out[hrp.len()] = b'1';
+    let mut idx = hrp.len() + 1;
+
+    let mut acc = 0u32;
+    let mut bits = 0u32;
+    let mut push = |v: u8, idx: &mut usize, chk: &mut u32| {
+        *chk = bech32_polymod_step(*chk, v);
+        out[*idx] = BECH32_CHARSET[v as usize];
+        *idx += 1;
+    };
+    for &b in data {
+        acc = (acc << 8) | b as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            push(((acc >> bits) & 0x1f) as u8, &mut idx, &mut chk);
+        }
+    }
+    if bits > 0 {
+        push(((acc << (5 - bits)) & 0x1f) as u8, &mut idx, &mut chk);
+    }
+
+    for _ in 0..6 {
+        chk = bech32_polymod_step(chk, 0);
+    }
+    chk ^= 1;
+    for i in 0..6 {
+        out[idx] = BECH32_CHARSET[((chk >> (5 * (5 - i))) & 0x1f) as usize];
+        idx += 1;
+    }
+    Ok(idx)
+}
+
+/// Bech32 `zs1...` encoding of a Sapling payment address
+pub fn sapling_address(addr: &PaymentAddress, out: &mut [u8]) -> Result<usize, ParserError> {
+    bech32_encode(SAPLING_HRP, &addr.to_bytes(), out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transparent_p2pkh() {
+        let script = hex::decode("76a91461aac8b58ac880a45fb06eeedfcf3017679778a988ac").unwrap();
+        let mut out = [0u8; 40];
+        let len = transparent_from_script(&script, &mut out).unwrap();
+        assert_eq!(&out[..len], b"t1Sn29Dzjh6YtkJksq2oECUuJPbquRdPxhG");
+
+        let script = hex::decode("a914a38dd00686b90262062a724df004a8d30d2e4b8987").unwrap();
+        let len = transparent_from_script(&script, &mut out).unwrap();
+        assert_eq!(&out[..len], b"t3ZUQxTaSU7jSfpSuFykQGLfsA9oKrNJsf9");
+
+        let bad = [0x6a, 0x00];
+        assert_eq!(
+            transparent_from_script(&bad, &mut out),
+            Err(ParserError::parser_invalid_output_script)
+        );
+    }
+
+    #[test]
+    fn test_sapling_address() {
+        let mut bytes = [0u8; PaymentAddress::LEN];
+        bytes[..11].copy_from_slice(&[
+            0x3b, 0xf6, 0xfa, 0x1f, 0x83, 0xbf, 0x45, 0x63, 0xc8, 0xa7, 0x13,
+        ]);
+        bytes[11..].copy_from_slice(&[
+            0x04, 0x54, 0xc0, 0x14, 0x13, 0x5e, 0xc6, 0x95, 0xa1, 0x86, 0x0f, 0x8d, 0x65, 0xb3,
+            0x73, 0x54, 0x6b, 0x62, 0x3f, 0x38, 0x8a, 0xbb, 0xec, 0xd0, 0xc8, 0xb2, 0x11, 0x1a,
+            0xbd, 0xec, 0x30, 0x1d,
+        ]);
+        let mut out = [0u8; 100];
+        let len = sapling_address(&PaymentAddress::from_bytes(&bytes), &mut out).unwrap();
+        assert_eq!(
+            &out[..len],
+            &b"zs180m058urhazk8j98zvz9fsq5zd0vd9dpsc8c6ednwd2xkc3l8z9thmxsezepzx4aascp62t6vy2"[..]
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! Cryptographic primitives the Sapling code relies on.
+//!
+//! On the device they are provided by the Ledger SDK (`bolos::LedgerBackend`).
+//! Host tools, simulators and unit tests use the pure-Rust `HostBackend`,
+//! selected with the `host-crypto` feature.
+
+use crate::errors::ParserError;
+
+pub trait CryptoBackend {
+    fn blake2b32_with_personalization(person: &[u8; 16], data: &[u8]) -> [u8; 32];
+
+    fn blake2b64_with_personalization(person: &[u8; 16], data: &[u8]) -> [u8; 64];
+
+    fn blake2b_redjubjub(a: &[u8], b: &[u8]) -> [u8; 64];
+
+    fn blake2b_expand_seed(a: &[u8], b: &[u8]) -> [u8; 64];
+
+    fn blake2b_expand_vec_two(in_a: &[u8], in_b: &[u8], in_c: &[u8]) -> [u8; 64];
+
+    fn blake2b_expand_vec_four(
+        in_a: &[u8],
+        in_b: &[u8],
+        in_c: &[u8],
+        in_d: &[u8],
+        in_e: &[u8],
+    ) -> [u8; 64];
+
+    fn aes256_encryptblock(k: &[u8], a: &[u8]) -> [u8; 16];
+
+    /// Multiplies `point` in place, failing if it is not a valid encoding
+    fn jubjub_scalarmult(point: &mut [u8], scalar: &[u8]) -> Result<(), ParserError>;
+
+    fn jubjub_scalarmult_spending_base(point: &mut [u8], scalar: &[u8]);
+
+    fn fill_random(dest: &mut [u8]);
+}
+
+#[cfg(any(test, feature = "host-crypto"))]
+pub type Backend = host::HostBackend;
+
+#[cfg(not(any(test, feature = "host-crypto")))]
+pub type Backend = crate::bolos::LedgerBackend;
+
+#[cfg(any(test, feature = "host-crypto"))]
+pub mod host {
+    use aes::{
+        block_cipher_trait::{generic_array::GenericArray, BlockCipher},
+        Aes256,
+    };
+    use blake2b_simd::Params as Blake2bParams;
+    use jubjub::AffinePoint;
+
+    use super::CryptoBackend;
+    use crate::constants;
+    use crate::errors::ParserError;
+
+    const PRF_EXPAND_PERSONALIZATION: &[u8; 16] = b"Zcash_ExpandSeed";
+    const REDJUBJUB_PERSONALIZATION: &[u8; 16] = b"Zcash_RedJubjubH";
+
+    fn blake2b64_multi(person: &[u8; 16], inputs: &[&[u8]]) -> [u8; 64] {
+        let mut state = Blake2bParams::new()
+            .hash_length(64)
+            .personal(person)
+            .to_state();
+        for input in inputs {
+            state.update(input);
+        }
+        *state.finalize().as_array()
+    }
+
+    /// Pure-Rust implementation of the SDK primitives
+    pub struct HostBackend;
+
+    impl CryptoBackend for HostBackend {
+        fn blake2b32_with_personalization(person: &[u8; 16], data: &[u8]) -> [u8; 32] {
+            let h = Blake2bParams::new()
+                .hash_length(32)
+                .personal(person)
+                .hash(data);
+            let mut hash = [0u8; 32];
+            hash.copy_from_slice(h.as_bytes());
+            hash
+        }
+
+        fn blake2b64_with_personalization(person: &[u8; 16], data: &[u8]) -> [u8; 64] {
+            blake2b64_multi(person, &[data])
+        }
+
+        fn blake2b_redjubjub(a: &[u8], b: &[u8]) -> [u8; 64] {
+            blake2b64_multi(REDJUBJUB_PERSONALIZATION, &[a, b])
+        }
+
+        fn blake2b_expand_seed(a: &[u8], b: &[u8]) -> [u8; 64] {
+            blake2b64_multi(PRF_EXPAND_PERSONALIZATION, &[a, b])
+        }
+
+        fn blake2b_expand_vec_two(in_a: &[u8], in_b: &[u8], in_c: &[u8]) -> [u8; 64] {
+            blake2b64_multi(PRF_EXPAND_PERSONALIZATION, &[in_a, in_b, in_c])
+        }
+
+        fn blake2b_expand_vec_four(
+            in_a: &[u8],
+            in_b: &[u8],
+            in_c: &[u8],
+            in_d: &[u8],
+            in_e: &[u8],
+        ) -> [u8; 64] {
+            blake2b64_multi(
+                PRF_EXPAND_PERSONALIZATION,
+                &[in_a, in_b, in_c, in_d, in_e],
+            )
+        }
+
+        fn aes256_encryptblock(k: &[u8], a: &[u8]) -> [u8; 16] {
+            let cipher = Aes256::new(GenericArray::from_slice(k));
+            let mut b = GenericArray::clone_from_slice(a);
+            cipher.encrypt_block(&mut b);
+            let mut out = [0u8; 16];
+            out.copy_from_slice(b.as_slice());
+            out
+        }
+
+        fn jubjub_scalarmult(point: &mut [u8], scalar: &[u8]) -> Result<(), ParserError> {
+            let mut bytes = [0u8; 32];
+            bytes.copy_from_slice(point);
+            let mut scalarbytes = [0u8; 32];
+            scalarbytes.copy_from_slice(scalar);
+            let p = AffinePoint::from_bytes(bytes);
+            if p.is_none().unwrap_u8() == 1 {
+                return Err(ParserError::parser_invalid_point);
+            }
+            let result = p.unwrap().to_niels().multiply_bits(&scalarbytes);
+            point.copy_from_slice(&AffinePoint::from(result).to_bytes());
+            Ok(())
+        }
+
+        fn jubjub_scalarmult_spending_base(point: &mut [u8], scalar: &[u8]) {
+            let mut scalarbytes = [0u8; 32];
+            scalarbytes.copy_from_slice(scalar);
+            let result = constants::SPENDING_KEY_BASE.multiply_bits(&scalarbytes);
+            point.copy_from_slice(&AffinePoint::from(result).to_bytes());
+        }
+
+        fn fill_random(dest: &mut [u8]) {
+            getrandom::getrandom(dest).expect("host randomness unavailable")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_vec_matches_concatenation() {
+        let a = [1u8; 32];
+        let b = [2u8; 5];
+        let c = [3u8; 7];
+        let mut bc = [0u8; 12];
+        bc[..5].copy_from_slice(&b);
+        bc[5..].copy_from_slice(&c);
+        assert_eq!(
+            Backend::blake2b_expand_vec_two(&a, &b, &c)[..],
+            Backend::blake2b_expand_seed(&a, &bc)[..]
+        );
+    }
+
+    #[test]
+    fn test_scalarmult_rejects_invalid_point() {
+        let mut point = [0xffu8; 32];
+        assert_eq!(
+            Backend::jubjub_scalarmult(&mut point, &[1u8; 32]),
+            Err(ParserError::parser_invalid_point)
+        );
+
+        let mut point = [0u8; 32];
+        Backend::jubjub_scalarmult_spending_base(&mut point, &[1u8; 32]);
+        let expected = point;
+        let mut one = [0u8; 32];
+        one[0] = 1;
+        assert_eq!(Backend::jubjub_scalarmult(&mut point, &one), Ok(()));
+        assert_eq!(point, expected);
+    }
+}
@@ -1,23 +1,9 @@
 //! Rust interfaces to Ledger SDK APIs.
 
-use crate::constants;
-use aes::{
-    block_cipher_trait::{
-        generic_array::typenum::{U16, U32, U8},
-        generic_array::GenericArray,
-        BlockCipher,
-    },
-    Aes256,
-};
-#[cfg(test)]
-use blake2b_simd::{Hash as Blake2bHash, Params as Blake2bParams};
+use crate::backend::{Backend, CryptoBackend};
+use crate::errors::ParserError;
 use blake2s_simd::{blake2s, Hash as Blake2sHash, Params as Blake2sParams};
-use core::convert::TryInto;
 use cstr_core::CStr;
-#[cfg(test)]
-#[cfg(any(unix, windows))]
-use getrandom::getrandom;
-use jubjub::AffinePoint;
 use rand::{CryptoRng, RngCore};
 
 extern "C" {
@@ -67,175 +53,170 @@ extern "C" {
     fn c_jubjub_spending_base_scalarmult(point: *mut u8, scalar: *const u8);
 }
 
-#[cfg(not(test))]
-pub fn sdk_jubjub_scalarmult_spending_base(point: &mut [u8], scalar: &[u8]) {
-    c_zemu_log_stack(b"scalarmult spending base in sdk\x00".as_ref());
-    unsafe {
-        c_jubjub_spending_base_scalarmult(point.as_mut_ptr(), scalar.as_ptr());
-        c_zemu_log_stack(b"after scalarmult spending base\x00".as_ref());
-        check_app_canary();
-    }
-}
+/// Primitives provided by the Ledger SDK
+pub struct LedgerBackend;
 
-#[cfg(test)]
-pub fn sdk_jubjub_scalarmult_spending_base(point: &mut [u8], scalar: &[u8]) {
-    let mut scalarbytes = [0u8; 32];
-    scalarbytes.copy_from_slice(&scalar);
-    let result = constants::SPENDING_KEY_BASE.multiply_bits(&scalarbytes);
-    point.copy_from_slice(&AffinePoint::from(result).to_bytes());
-}
+impl CryptoBackend for LedgerBackend {
+    fn blake2b32_with_personalization(person: &[u8; 16], data: &[u8]) -> [u8; 32] {
+        let mut hash = [0; 32];
+        unsafe {
+            c_blake2b32_withpersonal(
+                person.as_ptr(),
+                data.as_ptr(),
+                data.len() as u32,
+                hash.as_mut_ptr(),
+            );
+        }
+        hash
+    }
 
-#[cfg(not(test))]
-pub fn sdk_jubjub_scalarmult(point: &mut [u8], scalar: &[u8]) {
-    c_zemu_log_stack(b"scalarmult in sdk\x00".as_ref());
-    unsafe {
-        c_jubjub_scalarmult(point.as_mut_ptr(), scalar.as_ptr());
+    fn blake2b64_with_personalization(person: &[u8; 16], data: &[u8]) -> [u8; 64] {
+        let mut hash = [0; 64];
+        unsafe {
+            c_blake2b64_withpersonal(
+                person.as_ptr(),
+                data.as_ptr(),
+                data.len() as u32,
+                hash.as_mut_ptr(),
+            );
+        }
+        hash
     }
-}
 
-#[cfg(test)]
-pub fn sdk_jubjub_scalarmult(point: &mut [u8], scalar: &[u8]) {
-    let mut bytes = [0u8; 32];
-    bytes.copy_from_slice(&point);
-    let mut scalarbytes = [0u8; 32];
-    scalarbytes.copy_from_slice(&scalar);
-    let result = jubjub::AffinePoint::from_bytes(bytes)
-        .unwrap()
-        .to_niels()
-        .multiply_bits(&scalarbytes);
-    point.copy_from_slice(&AffinePoint::from(result).to_bytes());
-}
+    fn blake2b_redjubjub(a: &[u8], b: &[u8]) -> [u8; 64] {
+        let mut hash = [0; 64];
+        unsafe {
+            c_zcash_blake2b_redjubjub(
+                a.as_ptr(),
+                a.len() as u32,
+                b.as_ptr(),
+                b.len() as u32,
+                hash.as_mut_ptr(),
+            );
+        }
+        hash
+    }
 
-#[cfg(test)]
-pub fn blake2b32_with_personalization(person: &[u8; 16], data: &[u8]) -> [u8; 32] {
-    let h = Blake2bParams::new()
-        .hash_length(32)
-        .personal(person)
-        .hash(data);
-    let mut hash = [0u8; 32];
-    hash.copy_from_slice(&h.as_bytes());
-    hash
-}
+    fn blake2b_expand_seed(a: &[u8], b: &[u8]) -> [u8; 64] {
+        let mut hash = [0; 64];
+        unsafe {
+            c_zcash_blake2b_expand_seed(
+                a.as_ptr(),
+                a.len() as u32,
+                b.as_ptr(),
+                b.len() as u32,
+                hash.as_mut_ptr(),
+            );
+        }
+        hash
+    }
 
-#[cfg(not(test))]
-pub fn blake2b32_with_personalization(person: &[u8; 16], data: &[u8]) -> [u8; 32] {
-    let mut hash = [0; 32];
-    unsafe {
-        c_blake2b32_withpersonal(
-            person.as_ptr(),
-            data.as_ptr(),
-            data.len() as u32,
-            hash.as_mut_ptr(),
-        );
+    fn blake2b_expand_vec_two(in_a: &[u8], in_b: &[u8], in_c: &[u8]) -> [u8; 64] {
+        let mut hash = [0; 64];
+        unsafe {
+            c_zcash_blake2b_expand_vec_two(
+                in_a.as_ptr(),
+                in_a.len() as u32,
+                in_b.as_ptr(),
+                in_b.len() as u32,
+                in_c.as_ptr(),
+                in_c.len() as u32,
+                hash.as_mut_ptr(),
+            );
+        }
+        hash
     }
-    hash
-}
 
-#[cfg(test)]
-pub fn blake2b64_with_personalization(person: &[u8; 16], data: &[u8]) -> [u8; 64] {
-    let h = Blake2bParams::new()
-        .hash_length(64)
-        .personal(person)
-        .hash(data);
-    let mut hash = [0u8; 64];
-    hash.copy_from_slice(&h.as_bytes());
-    hash
-}
+    fn blake2b_expand_vec_four(
+        in_a: &[u8],
+        in_b: &[u8],
+        in_c: &[u8],
+        in_d: &[u8],
+        in_e: &[u8],
+    ) -> [u8; 64] {
+        let mut hash = [0; 64];
+        unsafe {
+            c_zcash_blake2b_expand_vec_four(
+                in_a.as_ptr(),
+                in_a.len() as u32,
+                in_b.as_ptr(),
+                in_b.len() as u32,
+                in_c.as_ptr(),
+                in_c.len() as u32,
+                in_d.as_ptr(),
+                in_d.len() as u32,
+                in_e.as_ptr(),
+                in_e.len() as u32,
+                hash.as_mut_ptr(),
+            );
+        }
+        hash
+    }
 
-#[cfg(not(test))]
-pub fn blake2b64_with_personalization(person: &[u8; 16], data: &[u8]) -> [u8; 64] {
-    let mut hash = [0; 64];
-    unsafe {
-        c_blake2b64_withpersonal(
-            person.as_ptr(),
-            data.as_ptr(),
-            data.len() as u32,
-            hash.as_mut_ptr(),
-        );
+    fn aes256_encryptblock(k: &[u8], a: &[u8]) -> [u8; 16] {
+        let mut out = [0u8; 16];
+        unsafe {
+            c_aes256_encryptblock(k.as_ptr(), a.as_ptr(), out.as_mut_ptr());
+        }
+        out
     }
-    hash
-}
 
-#[cfg(test)]
-pub fn blake2b_redjubjub(a: &[u8], b: &[u8]) -> [u8; 64] {
-    pub const REDJUBJUB_PERSONALIZATION: &[u8; 16] = b"Zcash_RedJubjubH";
+    fn jubjub_scalarmult(point: &mut [u8], scalar: &[u8]) -> Result<(), ParserError> {
+        c_zemu_log_stack(b"scalarmult in sdk\x00".as_ref());
+        unsafe {
+            c_jubjub_scalarmult(point.as_mut_ptr(), scalar.as_ptr());
+        }
+        // the SDK zeroes points it cannot decode, which is not an encoding
+        if point.iter().all(|b| *b == 0) {
+            return Err(ParserError::parser_invalid_point);
+        }
+        Ok(())
+    }
 
-    let h = Blake2bParams::new()
-        .hash_length(64)
-        .personal(REDJUBJUB_PERSONALIZATION)
-        .to_state()
-        .update(a)
-        .update(b)
-        .finalize();
+    fn jubjub_scalarmult_spending_base(point: &mut [u8], scalar: &[u8]) {
+        c_zemu_log_stack(b"scalarmult spending base in sdk\x00".as_ref());
+        unsafe {
+            c_jubjub_spending_base_scalarmult(point.as_mut_ptr(), scalar.as_ptr());
+            c_zemu_log_stack(b"after scalarmult spending base\x00".as_ref());
+        }
+        c_check_app_canary();
+    }
 
-    let result: [u8; 64] = *h.as_array();
-    result
+    fn fill_random(dest: &mut [u8]) {
+        unsafe {
+            bolos_cx_rng(dest.as_mut_ptr(), dest.len() as u32);
+        }
+    }
 }
 
-#[cfg(not(test))]
-pub fn blake2b_redjubjub(a: &[u8], b: &[u8]) -> [u8; 64] {
-    let mut hash = [0; 64];
-    unsafe {
-        c_zcash_blake2b_redjubjub(
-            a.as_ptr(),
-            a.len() as u32,
-            b.as_ptr(),
-            b.len() as u32,
-            hash.as_mut_ptr(),
-        );
-    }
-    hash
+pub fn sdk_jubjub_scalarmult_spending_base(point: &mut [u8], scalar: &[u8]) {
+    Backend::jubjub_scalarmult_spending_base(point, scalar)
 }
 
-#[cfg(not(test))]
-pub fn c_zemu_log_stack(s: &[u8]) {
-    unsafe { zemu_log_stack(s.as_ptr()) }
+pub fn sdk_jubjub_scalarmult(point: &mut [u8], scalar: &[u8]) -> Result<(), ParserError> {
+    Backend::jubjub_scalarmult(point, scalar)
 }
 
-#[cfg(test)]
-pub fn c_zemu_log_stack(_s: &[u8]) {}
+pub fn blake2b32_with_personalization(person: &[u8; 16], data: &[u8]) -> [u8; 32] {
+    Backend::blake2b32_with_personalization(person, data)
+}
 
-#[cfg(not(test))]
-pub fn c_check_app_canary() {
-    unsafe { check_app_canary() }
+pub fn blake2b64_with_personalization(person: &[u8; 16], data: &[u8]) -> [u8; 64] {
+    Backend::blake2b64_with_personalization(person, data)
 }
 
-#[cfg(test)]
-pub fn c_check_app_canary() {}
+pub fn blake2b_redjubjub(a: &[u8], b: &[u8]) -> [u8; 64] {
+    Backend::blake2b_redjubjub(a, b)
+}
 
-#[cfg(not(test))]
 pub fn blake2b_expand_seed(a: &[u8], b: &[u8]) -> [u8; 64] {
-    let mut hash = [0; 64];
-    unsafe {
-        c_zcash_blake2b_expand_seed(
-            a.as_ptr(),
-            a.len() as u32,
-            b.as_ptr(),
-            b.len() as u32,
-            hash.as_mut_ptr(),
-        );
-    }
-    hash
+    Backend::blake2b_expand_seed(a, b)
 }
 
-#[cfg(not(test))]
 pub fn blake2b_expand_vec_two(in_a: &[u8], in_b: &[u8], in_c: &[u8]) -> [u8; 64] {
-    let mut hash = [0; 64];
-    unsafe {
-        c_zcash_blake2b_expand_vec_two(
-            in_a.as_ptr(),
-            in_a.len() as u32,
-            in_b.as_ptr(),
-            in_b.len() as u32,
-            in_c.as_ptr(),
-            in_c.len() as u32,
-            hash.as_mut_ptr(),
-        );
-    }
-    hash
+    Backend::blake2b_expand_vec_two(in_a, in_b, in_c)
 }
 
-#[cfg(not(test))]
 pub fn blake2b_expand_vec_four(
     in_a: &[u8],
     in_b: &[u8],
@@ -243,62 +224,29 @@ pub fn blake2b_expand_vec_four(
     in_d: &[u8],
     in_e: &[u8],
 ) -> [u8; 64] {
-    let mut hash = [0; 64];
-    unsafe {
-        c_zcash_blake2b_expand_vec_four(
-            in_a.as_ptr(),
-            in_a.len() as u32,
-            in_b.as_ptr(),
-            in_b.len() as u32,
-            in_c.as_ptr(),
-            in_c.len() as u32,
-            in_d.as_ptr(),
-            in_d.len() as u32,
-            in_e.as_ptr(),
-            in_e.len() as u32,
-            hash.as_mut_ptr(),
-        );
-    }
-    hash
+    Backend::blake2b_expand_vec_four(in_a, in_b, in_c, in_d, in_e)
 }
 
-#[cfg(not(test))]
 pub fn aes256_encryptblock(k: &[u8], a: &[u8]) -> [u8; 16] {
-    let mut out = [0u8; 16];
-    unsafe {
-        c_aes256_encryptblock(k.as_ptr(), a.as_ptr(), out.as_mut_ptr());
-    }
-    out
+    Backend::aes256_encryptblock(k, a)
 }
 
-#[cfg(test)]
-pub fn aes256_encryptblock(k: &[u8], a: &[u8]) -> [u8; 16] {
-    let cipher: Aes256 = Aes256::new(GenericArray::from_slice(k));
-    //cipher.encrypt_block(block);
-
-    let mut b = GenericArray::clone_from_slice(a);
-    cipher.encrypt_block(&mut b);
-
-    let out: [u8; 16] = b.as_slice().try_into().expect("err");
-    out
+#[cfg(not(any(test, feature = "std")))]
+pub fn c_zemu_log_stack(s: &[u8]) {
+    unsafe { zemu_log_stack(s.as_ptr()) }
 }
 
-#[cfg(test)]
-pub fn blake2b_expand_seed(a: &[u8], b: &[u8]) -> [u8; 64] {
-    pub const PRF_EXPAND_PERSONALIZATION: &[u8; 16] = b"Zcash_ExpandSeed";
-
-    let h = Blake2bParams::new()
-        .hash_length(64)
-        .personal(PRF_EXPAND_PERSONALIZATION)
-        .to_state()
-        .update(a)
-        .update(b)
-        .finalize();
+#[cfg(any(test, feature = "std"))]
+pub fn c_zemu_log_stack(_s: &[u8]) {}
 
-    let result: [u8; 64] = *h.as_array();
-    result
+#[cfg(not(any(test, feature = "std")))]
+pub fn c_check_app_canary() {
+    unsafe { check_app_canary() }
 }
 
+#[cfg(any(test, feature = "std"))]
+pub fn c_check_app_canary() {}
+
 #[inline(never)]
 pub fn blake2s_diversification(tag: &[u8]) -> [u8; 32] {
     pub const KEY_DIVERSIFICATION_PERSONALIZATION: &[u8; 8] = b"Zcash_gd";
@@ -317,44 +265,6 @@ pub fn blake2s_diversification(tag: &[u8]) -> [u8; 32] {
     result
 }
 
-#[cfg(test)]
-pub fn blake2b_expand_vec_two(sk: &[u8], a: &[u8], b: &[u8]) -> [u8; 64] {
-    pub const PRF_EXPAND_PERSONALIZATION: &[u8; 16] = b"Zcash_ExpandSeed";
-    let mut h = Blake2bParams::new()
-        .hash_length(64)
-        .personal(PRF_EXPAND_PERSONALIZATION)
-        .to_state();
-    h.update(sk);
-    h.update(a);
-    h.update(b);
-    let mut hash = [0u8; 64];
-    hash.copy_from_slice(&h.finalize().as_bytes());
-    hash
-}
-
-#[cfg(test)]
-pub fn blake2b_expand_vec_four(
-    in_a: &[u8],
-    in_b: &[u8],
-    in_c: &[u8],
-    in_d: &[u8],
-    in_e: &[u8],
-) -> [u8; 64] {
-    pub const PRF_EXPAND_PERSONALIZATION: &[u8; 16] = b"Zcash_ExpandSeed";
-    let mut blake2b_state = Blake2bParams::new()
-        .hash_length(64)
-        .personal(PRF_EXPAND_PERSONALIZATION)
-        .to_state();
-    blake2b_state.update(in_a);
-    blake2b_state.update(in_b);
-    blake2b_state.update(in_c);
-    blake2b_state.update(in_d);
-    blake2b_state.update(in_e);
-    let mut hash = [0u8; 64];
-    hash.copy_from_slice(&blake2b_state.finalize().as_bytes());
-    hash
-}
-
 pub struct Trng;
 
 impl RngCore for Trng {
@@ -370,23 +280,10 @@ impl RngCore for Trng {
         u64::from_le_bytes(out)
     }
 
-    #[cfg(not(any(unix, windows)))]
     fn fill_bytes(&mut self, dest: &mut [u8]) {
-        unsafe {
-            bolos_cx_rng(dest.as_mut_ptr(), dest.len() as u32);
-        }
+        Backend::fill_random(dest)
     }
 
-    #[cfg(test)]
-    #[cfg(any(unix, windows))]
-    fn fill_bytes(&mut self, dest: &mut [u8]) {
-        getrandom(dest).unwrap()
-    }
-
-    #[cfg(not(test))]
-    #[cfg(any(unix, windows))]
-    fn fill_bytes(&mut self, _dest: &mut [u8]) {}
-
     fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
         self.fill_bytes(dest);
         Ok(())
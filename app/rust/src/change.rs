@@ -0,0 +1,231 @@
+//! Recognises outputs that send funds back to the signing account.
+//!
+//! A Sapling output is change when its `pk_d` equals `[ivk] g_d` for the
+//! account's incoming viewing key, i.e. it is one of the account's
+//! diversified addresses. A transparent output is change when it is a P2PKH
+//! script paying to the key of one of the transparent inputs being signed.
+//! Those keys are the ones the device derives from the BIP44 paths of the
+//! inputs, never the scripts the host claims the inputs have.
+
+use crate::errors::ParserError;
+use crate::txdisplay::TxSummary;
+use crate::types::{Ivk, PaymentAddress, SpendingKey};
+use crate::zip32::{default_pkd, derive_ivk, diversifier_is_valid};
+
+pub const PKH_LEN: usize = 20;
+
+/// Whether `address` is a diversified address of the account behind `ivk`
+pub fn is_sapling_change(ivk: &Ivk, address: &PaymentAddress) -> bool {
+    if !diversifier_is_valid(&address.diversifier) {
+        return false;
+    }
+    match default_pkd(&ivk.0, &address.diversifier.0) {
+        Ok(pk_d) => pk_d == address.pk_d,
+        Err(_) => false,
+    }
+}
+
+/// Public key hash paid by a P2PKH script
+pub fn p2pkh_hash(script: &[u8]) -> Option<&[u8]> {
+    match script {
+        [0x76, 0xa9, 0x14, hash @ .., 0x88, 0xac] if hash.len() == PKH_LEN => Some(hash),
+        _ => None,
+    }
+}
+
+/// Whether `script` is a P2PKH script paying to one of `input_key_hashes`,
+/// the Hash160 of the keys derived from the transparent input paths
+pub fn is_transparent_change<'k, I>(script: &[u8], input_key_hashes: I) -> bool
+where
+    I: IntoIterator<Item = &'k [u8; PKH_LEN]>,
+{
+    match p2pkh_hash(script) {
+        Some(hash) => input_key_hashes.into_iter().any(|key| key[..] == *hash),
+        None => false,
+    }
+}
+
+/// Viewing key of the signing account used to classify outputs as change
+pub struct ChangeDetector {
+    ivk: Ivk,
+}
+
+impl ChangeDetector {
+    pub fn new(ivk: Ivk) -> Self {
+        ChangeDetector { ivk }
+    }
+
+    /// Derives the account ivk for ZIP32 account `pos`
+    pub fn from_seed(sk: &SpendingKey, pos: u32) -> Self {
+        Self::new(derive_ivk(sk, pos))
+    }
+
+    pub fn is_sapling_change(&self, address: &PaymentAddress) -> bool {
+        is_sapling_change(&self.ivk, address)
+    }
+}
+
+/// Change outputs of an INITTX summary, as the bit masks taken by
+/// `TxDisplay::with_change`. Shielded outputs are checked against the account
+/// of every spend.
+pub fn change_masks(
+    summary: &TxSummary,
+    sk: &SpendingKey,
+    input_key_hashes: &[[u8; PKH_LEN]],
+) -> (u8, u8) {
+    let mut t_change = 0u8;
+    for (i, output) in summary.t_outputs().enumerate() {
+        if is_transparent_change(output.script, input_key_hashes) {
+            t_change |= 1 << i;
+        }
+    }
+    let mut s_change = 0u8;
+    for spend in summary.spends() {
+        let detector = ChangeDetector::from_seed(sk, spend.path);
+        for (i, output) in summary.outputs().enumerate() {
+            if detector.is_sapling_change(&output.address) {
+                s_change |= 1 << i;
+            }
+        }
+    }
+    (t_change, s_change)
+}
+
+/// Marks the change outputs of the INITTX data, given the Hash160 of the keys
+/// the device derived for each transparent input path
+#[no_mangle]
+pub extern "C" fn mark_change_outputs(
+    inittx_ptr: *const u8,
+    inittx_len: u16,
+    seed_ptr: *const [u8; 32],
+    key_hashes_ptr: *const [u8; PKH_LEN],
+    key_hashes_len: u8,
+    t_change_ptr: *mut u8,
+    s_change_ptr: *mut u8,
+) -> ParserError {
+    let inittx = unsafe { core::slice::from_raw_parts(inittx_ptr, inittx_len as usize) };
+    let sk = unsafe { SpendingKey::from_ptr(seed_ptr) };
+    let key_hashes =
+        unsafe { core::slice::from_raw_parts(key_hashes_ptr, key_hashes_len as usize) };
+    let t_change = unsafe { &mut *t_change_ptr };
+    let s_change = unsafe { &mut *s_change_ptr };
+    match TxSummary::parse(inittx) {
+        Ok(summary) => {
+            let (t, s) = change_masks(&summary, sk, key_hashes);
+            *t_change = t;
+            *s_change = s;
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::Diversifier;
+    use crate::zip32::default_payment_address_from_startindex;
+
+    const SEED: SpendingKey = SpendingKey::from_bytes([
+        0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24,
+        25, 26, 27, 28, 29, 30, 31,
+    ]);
+
+    #[test]
+    fn test_sapling_change() {
+        let mut start = Diversifier([0u8; 11]);
+        let own = default_payment_address_from_startindex(&SEED, 0, &mut start).unwrap();
+        let second = default_payment_address_from_startindex(&SEED, 0, &mut start).unwrap();
+        let mut start = Diversifier([0u8; 11]);
+        let other = default_payment_address_from_startindex(&SEED, 1, &mut start).unwrap();
+
+        let detector = ChangeDetector::from_seed(&SEED, 0);
+        assert!(detector.is_sapling_change(&own));
+        assert!(detector.is_sapling_change(&second));
+        assert!(!detector.is_sapling_change(&other));
+
+        let mut tampered = own;
+        tampered.pk_d[0] ^= 1;
+        assert!(!detector.is_sapling_change(&tampered));
+    }
+
+    #[test]
+    fn test_transparent_change() {
+        let p2pkh = |pkh: [u8; 20]| {
+            let mut script = [0u8; 25];
+            script[..3].copy_from_slice(&[0x76, 0xa9, 0x14]);
+            script[3..23].copy_from_slice(&pkh);
+            script[23..].copy_from_slice(&[0x88, 0xac]);
+            script
+        };
+        let own_key = [0x61u8; 20];
+        let other_key = [0u8; 20];
+        let own = p2pkh(own_key);
+
+        assert!(is_transparent_change(&own, &[other_key, own_key]));
+        assert!(!is_transparent_change(&own, &[other_key]));
+        assert!(!is_transparent_change(&own, &[]));
+
+        let mut p2sh = [0u8; 23];
+        p2sh[..2].copy_from_slice(&[0xa9, 0x14]);
+        p2sh[2..22].copy_from_slice(&own_key);
+        p2sh[22] = 0x87;
+        assert!(!is_transparent_change(&p2sh, &[own_key]));
+    }
+
+    #[test]
+    fn test_change_masks() {
+        let mut start = Diversifier([0u8; 11]);
+        let own = default_payment_address_from_startindex(&SEED, 0x8000_0000, &mut start).unwrap();
+        let mut start = Diversifier([0u8; 11]);
+        let other =
+            default_payment_address_from_startindex(&SEED, 0x8000_0001, &mut start).unwrap();
+        let own_key = [0x61u8; 20];
+
+        // one transparent input, outputs to its key and elsewhere, one spend
+        // from account 0 and outputs to accounts 1 and 0 (hardened, as the
+        // device derives them)
+        let mut data = vec![1, 2, 1, 2];
+        data.extend_from_slice(&[0u8; 20]);
+        data.extend_from_slice(&[0x19, 0x76, 0xa9, 0x14]);
+        data.extend_from_slice(&[0xaa; 20]);
+        data.extend_from_slice(&[0x88, 0xac]);
+        data.extend_from_slice(&50_000u64.to_le_bytes());
+        for key in [[0xaa; 20], own_key].iter() {
+            data.extend_from_slice(&[0x19, 0x76, 0xa9, 0x14]);
+            data.extend_from_slice(key);
+            data.extend_from_slice(&[0x88, 0xac]);
+            data.extend_from_slice(&10_000u64.to_le_bytes());
+        }
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&own.to_bytes());
+        data.extend_from_slice(&100_000u64.to_le_bytes());
+        for address in [other, own].iter() {
+            data.extend_from_slice(&address.to_bytes());
+            data.extend_from_slice(&60_000u64.to_le_bytes());
+            data.push(0xf6);
+            data.extend_from_slice(&[0u8; 33]);
+        }
+
+        // the input script the host sent pays [0xaa; 20], but only the key
+        // derived from the input path counts
+        let summary = TxSummary::parse(&data).unwrap();
+        assert_eq!(change_masks(&summary, &SEED, &[own_key]), (0b10, 0b10));
+        assert_eq!(change_masks(&summary, &SEED, &[]), (0, 0b10));
+
+        let (mut t_change, mut s_change) = (0u8, 0u8);
+        assert_eq!(
+            mark_change_outputs(
+                data.as_ptr(),
+                data.len() as u16,
+                &SEED.0,
+                [own_key].as_ptr(),
+                1,
+                &mut t_change,
+                &mut s_change
+            ),
+            ParserError::parser_ok
+        );
+        assert_eq!((t_change, s_change), (0b10, 0b10));
+    }
+}
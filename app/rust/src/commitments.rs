@@ -3,10 +3,12 @@ use byteorder::{BigEndian, ByteOrder, LittleEndian};
 use jubjub::{AffineNielsPoint, AffinePoint, ExtendedPoint, Fq, Fr};
 
 use crate::bolos::c_zemu_log_stack;
+use crate::errors::ParserError;
 use crate::pedersen::*;
 use crate::redjubjub::*;
 use crate::zeccrypto::prf_ock;
-use crate::zip32::{group_hash_from_div, nsk_to_nk,zip32_nsk_from_seed};
+use crate::types::{Diversifier, NoteCommitment, Nsk, Nullifier, ValueCommitment};
+use crate::zip32::{group_hash_from_div, nsk_to_nk, zip32_nsk_from_seed};
 
 pub const PEDERSEN_RANDOMNESS_BASE: AffineNielsPoint = AffinePoint::from_raw_unchecked(
     Fq::from_raw([
@@ -77,7 +79,7 @@ pub fn revert(source: &[u8; 32], dest: &mut [u8]) {
     for i in 0..32 {
         let mut uv = source[i];
         for j in 0..8 {
-            dest[i] ^= (uv & 1) as u8;
+            dest[i] ^= uv & 1;
             uv >>= 1;
             if j < 7 {
                 dest[i] <<= 1;
@@ -177,32 +179,19 @@ fn u64_to_bytes(value: u64) -> [u8; 32] {
 }
 
 #[inline(never)]
-pub fn prepare_and_hash_input_commitment(
-    value: u64,
-    g_d_ptr: *const [u8; 32],
-    pkd_ptr: *const [u8; 32],
-    output_ptr: *mut [u8; 32],
-)  {
+pub fn prepare_and_hash_input_commitment(value: u64, g_d: &[u8; 32], pk_d: &[u8; 32]) -> [u8; 32] {
     c_zemu_log_stack(b"prepare_and_hash_intput_for_notecommit\x00".as_ref());
-    let gd = unsafe { &*g_d_ptr };
-    let pkd = unsafe { &*pkd_ptr };
-
-    let mut prepared_msg =  [0u8; 73];
     let mut input_hash = [0u8; 73];
-    let output_msg = unsafe { &mut *output_ptr };
-
 
     let vbytes = write_u64_tobytes(value);
     input_hash[0..8].copy_from_slice(&vbytes);
 
-    revert(gd, &mut input_hash[8..40]);
-    revert(pkd, &mut input_hash[40..72]);
+    revert(g_d, &mut input_hash[8..40]);
+    revert(pk_d, &mut input_hash[40..72]);
 
     shiftsixbits(&mut input_hash);
-    prepared_msg.copy_from_slice(&input_hash);
 
-    let h = pedersen_hash_pointbytes(&mut prepared_msg, 582);
-    output_msg.copy_from_slice(&h);
+    pedersen_hash_pointbytes(&input_hash, 582)
 }
 
 #[inline(never)]
@@ -219,12 +208,11 @@ pub fn value_commitment_step2(rcm: &[u8; 32]) -> ExtendedPoint {
 }
 
 #[inline(never)]
-pub fn value_commitment(value: u64, rcm: &[u8; 32]) -> [u8; 32] {
-    c_zemu_log_stack(b"insidevaluecommitment\x00".as_ref());
-    let scalar = u64_to_bytes(value);
-    let mut x = VALUE_COMMITMENT_VALUE_BASE.multiply_bits(&scalar);
-    x += VALUE_COMMITMENT_RANDOM_BASE.multiply_bits(rcm);
-    extended_to_bytes(&x)
+pub fn value_commitment(value: u64, rcv: &[u8; 32]) -> ValueCommitment {
+    let mut x = value_commitment_step1(value);
+    let s = value_commitment_step2(rcv);
+    add_to_point(&mut x, &s);
+    ValueCommitment(extended_to_bytes(&x))
 }
 
 #[inline(never)]
@@ -261,9 +249,65 @@ pub fn prf_nf(nk: &[u8; 32], rho: &[u8; 32]) -> [u8; 32] {
 }
 
 #[inline(never)]
-pub fn bytes_to_extended(m: [u8; 32]) -> ExtendedPoint {
+pub fn bytes_to_extended(m: [u8; 32]) -> Result<ExtendedPoint, ParserError> {
     c_zemu_log_stack(b"bytes_to_extended\x00".as_ref());
-    ExtendedPoint::from(AffinePoint::from_bytes(m).unwrap())
+    let p = AffinePoint::from_bytes(m);
+    if bool::from(p.is_none()) {
+        return Err(ParserError::parser_invalid_point);
+    }
+    Ok(ExtendedPoint::from(p.unwrap()))
+}
+
+#[inline(never)]
+fn note_commitment_point(
+    value: u64,
+    diversifier: &Diversifier,
+    pk_d: &[u8; 32],
+    rcm: &[u8; 32],
+) -> Result<ExtendedPoint, ParserError> {
+    let gd = group_hash_from_div(diversifier)?;
+    if !bool::from(AffinePoint::from_bytes(*pk_d).is_some()) {
+        return Err(ParserError::parser_invalid_point);
+    }
+    let h = prepare_and_hash_input_commitment(value, &gd, pk_d);
+    c_zemu_log_stack(b"inside_notecmt\x00".as_ref());
+    let mut e = bytes_to_extended(h)?;
+    let s = multiply_with_pedersenbase(rcm);
+    add_to_point(&mut e, &s);
+    Ok(e)
+}
+
+/// Note commitment as the full point encoding, as needed to derive nullifiers
+#[inline(never)]
+pub fn note_commitment_full(
+    value: u64,
+    diversifier: &Diversifier,
+    pk_d: &[u8; 32],
+    rcm: &[u8; 32],
+) -> Result<NoteCommitment, ParserError> {
+    let e = note_commitment_point(value, diversifier, pk_d, rcm)?;
+    Ok(NoteCommitment(extended_to_bytes(&e)))
+}
+
+/// Note commitment u-coordinate (`cmu`), as it appears in output descriptions
+#[inline(never)]
+pub fn note_commitment_cmu(
+    value: u64,
+    diversifier: &Diversifier,
+    pk_d: &[u8; 32],
+    rcm: &[u8; 32],
+) -> Result<[u8; 32], ParserError> {
+    let e = note_commitment_point(value, diversifier, pk_d, rcm)?;
+    Ok(extended_to_u_bytes(&e))
+}
+
+#[inline(never)]
+pub fn nullifier(ncm: &NoteCommitment, pos: u64, nsk: &Nsk) -> Result<Nullifier, ParserError> {
+    let e = bytes_to_extended(ncm.0)?;
+    let nk = nsk_to_nk(nsk);
+    let scalar = Fr::from(pos);
+    let rho = mixed_pedersen(&e, scalar);
+    Ok(Nullifier(prf_nf(&nk.0, &rho)))
 }
 
 #[no_mangle]
@@ -272,67 +316,62 @@ pub extern "C" fn compute_nullifier(
     pos: u64,
     nsk_ptr: *const [u8; 32],
     output_ptr: *mut [u8; 32],
-) {
+) -> ParserError {
     c_zemu_log_stack(b"compute_nullifier\x00".as_ref());
-    let ncm = unsafe { *ncm_ptr };
-    let nsk = unsafe { &*nsk_ptr };
-    let mut nk = [0u8; 32];
-    nsk_to_nk(nsk, &mut nk);
-    let scalar = Fr::from(pos);
-    let e = bytes_to_extended(ncm);
-    let rho = mixed_pedersen(&e, scalar);
+    let ncm = unsafe { NoteCommitment::from_ptr(ncm_ptr) };
+    let nsk = unsafe { Nsk::from_ptr(nsk_ptr) };
     let output = unsafe { &mut *output_ptr };
-    output.copy_from_slice(&prf_nf(&nk, &rho));
+    match nullifier(ncm, pos, nsk) {
+        Ok(nf) => {
+            *output = nf.0;
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn compute_note_commitment(input_ptr: *mut [u8; 32],
-                                          rcm_ptr: *const [u8; 32],
-                                          value: u64,
-                                          diversifier_ptr: *const [u8; 11],
-                                          pkd_ptr: *const [u8; 32]) {
+pub extern "C" fn compute_note_commitment(
+    input_ptr: *mut [u8; 32],
+    rcm_ptr: *const [u8; 32],
+    value: u64,
+    diversifier_ptr: *const [u8; 11],
+    pkd_ptr: *const [u8; 32],
+) -> ParserError {
     c_zemu_log_stack(b"entry_preparenotecommit\x00".as_ref());
-
-    let mut gd = [0u8; 32];
-    let diversifier = unsafe { &*diversifier_ptr };
-    group_hash_from_div(diversifier, &mut gd);
-
+    let diversifier = unsafe { Diversifier::from_ptr(diversifier_ptr) };
     let pkd = unsafe { &*pkd_ptr };
+    let rcm = unsafe { &*rcm_ptr };
     let out = unsafe { &mut *input_ptr };
-    prepare_and_hash_input_commitment(value, &gd, pkd, out);
-    c_zemu_log_stack(b"inside_notecmt\x00".as_ref());
-    let rc = unsafe { &*rcm_ptr };
-    let mut e = bytes_to_extended(*out);
-    let s = multiply_with_pedersenbase(rc);
-    add_to_point(&mut e, &s);
-
-    out.copy_from_slice(&extended_to_u_bytes(&e));
+    match note_commitment_cmu(value, diversifier, pkd, rcm) {
+        Ok(cmu) => {
+            *out = cmu;
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
 }
 
-
 #[no_mangle]
 pub extern "C" fn compute_note_commitment_fullpoint(
     input_ptr: *mut [u8; 32],
     rcm_ptr: *const [u8; 32],
     value: u64,
     diversifier_ptr: *const [u8; 11],
-    pkd_ptr: *const [u8; 32]) {
+    pkd_ptr: *const [u8; 32],
+) -> ParserError {
     c_zemu_log_stack(b"entry_preparenotecommit_full\x00".as_ref());
-    let mut gd = [0u8; 32];
-    let diversifier = unsafe { &*diversifier_ptr };
-
-    group_hash_from_div(diversifier, &mut gd);
-
+    let diversifier = unsafe { Diversifier::from_ptr(diversifier_ptr) };
     let pkd = unsafe { &*pkd_ptr };
+    let rcm = unsafe { &*rcm_ptr };
     let out = unsafe { &mut *input_ptr };
-    prepare_and_hash_input_commitment(value, &gd, pkd, out);
-    c_zemu_log_stack(b"inside_notecmt\x00".as_ref());
-    let rc = unsafe { &*rcm_ptr };
-    let mut e = bytes_to_extended(*out);
-    let s = multiply_with_pedersenbase(rc);
-    add_to_point(&mut e, &s);
-
-    out.copy_from_slice(&extended_to_bytes(&e));
+    match note_commitment_full(value, diversifier, pkd, rcm) {
+        Ok(cm) => {
+            *out = cm.0;
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
 }
 
 #[no_mangle]
@@ -342,22 +381,16 @@ pub extern "C" fn compute_value_commitment(
     output_ptr: *mut [u8; 32],
 ) {
     c_zemu_log_stack(b"start_valuecmt\x00".as_ref());
-
     let rc = unsafe { &*rcm_ptr };
     let output_msg = unsafe { &mut *output_ptr };
-
-    //let vcm = value_commitment(value, rc);
-    let mut x = value_commitment_step1(value);
-    let s = value_commitment_step2(rc);
-    add_to_point(&mut x, &s);
-    let vcm = extended_to_bytes(&x);
-    output_msg.copy_from_slice(&vcm);
+    *output_msg = value_commitment(value, rc).0;
 }
 
 pub fn verify_bindingsig_keys(rcmsum: &[u8; 32], valuecommitsum: &[u8; 32]) -> bool {
-    let v = bytes_to_extended(*valuecommitsum);
-    let r = VALUE_COMMITMENT_RANDOM_BASE.multiply_bits(rcmsum);
-    v == r
+    match bytes_to_extended(*valuecommitsum) {
+        Ok(v) => v == VALUE_COMMITMENT_RANDOM_BASE.multiply_bits(rcmsum),
+        Err(_) => false,
+    }
 }
 
 #[cfg(test)]
@@ -367,30 +400,17 @@ mod tests {
     #[test]
     fn test_ncm_c() {
         let v = 100000;
-        let mut gd = [0u8; 32];
-        let div_ptr = [0u8; 11];
+        let div = Diversifier([0u8; 11]);
         let pkd = [0u8; 32];
         let rcm = [0u8; 32];
-        let output = [0u8; 32];
 
-        let div = &div_ptr ;
+        let gd = group_hash_from_div(&div).unwrap();
+        let h = prepare_and_hash_input_commitment(v, &gd, &pkd);
+        assert_ne!(h, [0u8; 32]);
 
-        group_hash_from_div(div, &mut gd);
-
-        prepare_and_hash_input_commitment(
-            v,
-            gd.as_ptr() as *const [u8; 32],
-            pkd.as_ptr() as *const [u8; 32],
-            output.as_ptr() as *mut [u8; 32],
-        );
-
-        compute_note_commitment(
-            output.as_ptr() as *mut [u8; 32],
-            rcm.as_ptr() as *const [u8; 32],
-            v,
-            div.as_ptr() as *const [u8; 11],
-            pkd.as_ptr() as *const [u8; 32]
-        );
+        let mut output = [0u8; 32];
+        let err = compute_note_commitment(&mut output, &rcm, v, div.as_bytes(), &pkd);
+        assert_eq!(err, ParserError::parser_ok);
 
         assert_eq!(
             output,
@@ -399,6 +419,7 @@ mod tests {
                 76, 7, 90, 151, 132, 85, 143, 180, 30, 26, 35, 160, 160, 197, 140, 21, 95
             ]
         );
+        assert_eq!(output, note_commitment_cmu(v, &div, &pkd, &rcm).unwrap());
     }
 
     #[test]
@@ -469,7 +490,7 @@ mod tests {
         ];
 
         let cv = value_commitment(value, &rcm);
-        assert_eq!(cvtest, cv);
+        assert_eq!(cvtest, cv.0);
     }
 
     #[test]
@@ -532,7 +553,9 @@ mod tests {
         zip32_nsk_from_seed(&seed,&mut nsk);
 
         let mut nf = [0u8; 32];
-        compute_nullifier(&cm, pos, &nsk,&mut nf);
+        let err = compute_nullifier(&cm, pos, &nsk, &mut nf);
+        assert_eq!(err, ParserError::parser_ok);
+        assert_eq!(nf, nullifier(&NoteCommitment(cm), pos, &Nsk(nsk)).unwrap().0);
 
 
         let nftest: [u8; 32] = [
@@ -612,4 +635,16 @@ mod tests {
         let b = AffinePoint::from(&t).get_u().to_bytes();
         assert_eq!(b, cmnul);
     }
+
+    #[test]
+    fn test_invalid_point() {
+        assert_eq!(
+            bytes_to_extended([0xff; 32]).err(),
+            Some(ParserError::parser_invalid_point)
+        );
+
+        let mut nf = [0u8; 32];
+        let err = compute_nullifier(&[0xff; 32], 0, &[0u8; 32], &mut nf);
+        assert_eq!(err, ParserError::parser_invalid_point);
+    }
 }
@@ -1,7 +1,7 @@
 #![allow(non_camel_case_types, non_upper_case_globals, non_snake_case)]
 
 #[repr(u32)]
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 /// ParserError is the counterpart of
 /// the parse_error_t in c,
 /// we redeclare it here, just for interpolation
@@ -26,6 +26,8 @@ pub enum ParserError {
     parser_invalid_output_script,
     parser_unexpected_type,
     parser_unexpected_method,
+    parser_not_allowed,
+    parser_not_supported,
     parser_unexpected_buffer_end,
     parser_unexpected_value,
     parser_unexpected_number_items,
@@ -33,4 +35,29 @@ pub enum ParserError {
     parser_unexpected_field,
     parser_value_out_of_range,
     parser_invalid_address,
+    parser_value_too_many_bytes,
+    parser_unexpected_module,
+    parser_unexpected_callIndex,
+    parser_unexpected_unparsed_bytes,
+    parser_print_not_supported,
+    parser_tx_nesting_not_supported,
+    parser_tx_nesting_limit_reached,
+    parser_tx_call_vec_too_large,
+    // Crypto related errors
+    parser_invalid_scalar,
+    parser_invalid_point,
+    parser_invalid_diversifier,
+}
+
+impl<I> From<nom::Err<(I, nom::error::ErrorKind)>> for ParserError {
+    fn from(err: nom::Err<(I, nom::error::ErrorKind)>) -> Self {
+        match err {
+            nom::Err::Incomplete(_) => ParserError::parser_unexpected_buffer_end,
+            nom::Err::Error((_, nom::error::ErrorKind::Eof))
+            | nom::Err::Failure((_, nom::error::ErrorKind::Eof)) => {
+                ParserError::parser_unexpected_buffer_end
+            }
+            _ => ParserError::parser_unexpected_value,
+        }
+    }
 }
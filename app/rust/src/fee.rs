@@ -0,0 +1,159 @@
+//! ZIP-317 conventional fee and sanity checks on the fee a transaction pays.
+
+use crate::errors::ParserError;
+
+/// Fee charged per logical action, in zatoshis
+pub const MARGINAL_FEE: u64 = 5_000;
+/// Minimum number of logical actions a transaction is charged for
+pub const GRACE_ACTIONS: u64 = 2;
+/// Fees above this many times the conventional fee are flagged as excessive
+pub const EXCESSIVE_FEE_FACTOR: u64 = 10;
+/// Default absolute fee above which a transaction is flagged as absurd (0.01 ZEC)
+pub const DEFAULT_ABSURD_FEE: u64 = 1_000_000;
+/// Fixed fee the app required before ZIP-317
+pub const LEGACY_FEE: u64 = 1_000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FeeWarning {
+    None,
+    BelowConventional,
+    Excessive,
+    Absurd,
+}
+
+impl FeeWarning {
+    /// Text shown on the review screen, if any
+    pub fn message(self) -> Option<&'static [u8]> {
+        match self {
+            FeeWarning::None => None,
+            FeeWarning::BelowConventional => Some(b"Below ZIP-317 fee"),
+            FeeWarning::Excessive => Some(b"Excessive fee"),
+            FeeWarning::Absurd => Some(b"Absurd fee"),
+        }
+    }
+}
+
+/// ZIP-317 conventional fee, assuming standard P2PKH inputs and outputs
+pub fn conventional_fee(
+    t_inputs: usize,
+    t_outputs: usize,
+    sapling_spends: usize,
+    sapling_outputs: usize,
+) -> u64 {
+    let logical_actions =
+        t_inputs.max(t_outputs) as u64 + sapling_spends.max(sapling_outputs) as u64;
+    MARGINAL_FEE * logical_actions.max(GRACE_ACTIONS)
+}
+
+/// Whether INITTX accepts `fee`: the legacy fixed fee, or the ZIP-317
+/// conventional fee of a transaction with these inputs and outputs
+pub fn fee_is_supported(
+    fee: u64,
+    t_inputs: usize,
+    t_outputs: usize,
+    sapling_spends: usize,
+    sapling_outputs: usize,
+) -> bool {
+    fee == LEGACY_FEE
+        || fee == conventional_fee(t_inputs, t_outputs, sapling_spends, sapling_outputs)
+}
+
+#[no_mangle]
+pub extern "C" fn inittx_fee_is_supported(
+    fee: u64,
+    t_inputs: u8,
+    t_outputs: u8,
+    sapling_spends: u8,
+    sapling_outputs: u8,
+) -> bool {
+    fee_is_supported(
+        fee,
+        t_inputs as usize,
+        t_outputs as usize,
+        sapling_spends as usize,
+        sapling_outputs as usize,
+    )
+}
+
+/// Fee paid by a transaction: transparent inputs − transparent outputs + valueBalance
+pub fn actual_fee(
+    t_in_total: u64,
+    t_out_total: u64,
+    value_balance: i64,
+) -> Result<u64, ParserError> {
+    let fee = t_in_total as i128 - t_out_total as i128 + value_balance as i128;
+    if fee < 0 || fee > u64::MAX as i128 {
+        return Err(ParserError::parser_value_out_of_range);
+    }
+    Ok(fee as u64)
+}
+
+/// Compares the fee paid with the conventional one; `absurd_threshold` is
+/// checked first and takes precedence
+pub fn classify_fee(fee: u64, conventional: u64, absurd_threshold: u64) -> FeeWarning {
+    if fee > absurd_threshold {
+        FeeWarning::Absurd
+    } else if fee < conventional {
+        FeeWarning::BelowConventional
+    } else if fee > conventional.saturating_mul(EXCESSIVE_FEE_FACTOR) {
+        FeeWarning::Excessive
+    } else {
+        FeeWarning::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conventional_fee() {
+        // grace actions apply to small transactions
+        assert_eq!(conventional_fee(1, 1, 0, 0), 10_000);
+        assert_eq!(conventional_fee(0, 0, 1, 2), 10_000);
+        assert_eq!(conventional_fee(3, 1, 0, 0), 15_000);
+        assert_eq!(conventional_fee(2, 1, 4, 2), 30_000);
+    }
+
+    #[test]
+    fn test_fee_is_supported() {
+        assert!(fee_is_supported(LEGACY_FEE, 1, 1, 0, 0));
+        assert!(fee_is_supported(10_000, 1, 1, 0, 0));
+        assert!(fee_is_supported(30_000, 2, 1, 4, 2));
+        assert!(!fee_is_supported(15_000, 1, 1, 0, 0));
+        assert!(!fee_is_supported(0, 0, 0, 1, 2));
+        assert!(inittx_fee_is_supported(15_000, 3, 1, 0, 0));
+    }
+
+    #[test]
+    fn test_actual_fee() {
+        assert_eq!(actual_fee(100_000, 90_000, 0), Ok(10_000));
+        assert_eq!(actual_fee(0, 50_000, 60_000), Ok(10_000));
+        assert_eq!(actual_fee(10_000, 0, -5_000), Ok(5_000));
+        assert_eq!(
+            actual_fee(0, 1, 0),
+            Err(ParserError::parser_value_out_of_range)
+        );
+    }
+
+    #[test]
+    fn test_classify_fee() {
+        assert_eq!(
+            classify_fee(10_000, 10_000, DEFAULT_ABSURD_FEE),
+            FeeWarning::None
+        );
+        assert_eq!(
+            classify_fee(1_000, 10_000, DEFAULT_ABSURD_FEE),
+            FeeWarning::BelowConventional
+        );
+        assert_eq!(
+            classify_fee(100_001, 10_000, DEFAULT_ABSURD_FEE),
+            FeeWarning::Excessive
+        );
+        assert_eq!(
+            classify_fee(2_000_000, 10_000, DEFAULT_ABSURD_FEE),
+            FeeWarning::Absurd
+        );
+        assert_eq!(classify_fee(50_000, 10_000, 40_000), FeeWarning::Absurd);
+    }
+}
@@ -0,0 +1,524 @@
+//! FROST(Jubjub, BLAKE2b-512) threshold spend authorization.
+//!
+//! Follows the two-round FROST protocol with the published FROST(Jubjub,
+//! BLAKE2b-512) ciphersuite of the `reddsa` crate: `H1`, `H3`, `H4` and `H5`
+//! are unpersonalized BLAKE2b-512 over `contextString || tag || m` with the
+//! tags `rho`, `nonce`, `msg` and `com`, and the challenge `H2` is the
+//! Sapling `H*`. Signatures are re-randomized by the spend's `alpha`, so the
+//! aggregate verifies under `rk = ak + alpha * G` like a single-key spend
+//! authorization signature, and the binding factors are bound to `rk`.
+//! How the trusted dealer derives its polynomial is local to this module.
+//!
+//! Signers are identified by non-zero `u16` indices and every list of
+//! commitments or shares must be sorted by identifier.
+
+use blake2b_simd::Params as Blake2bParams;
+use jubjub::{ExtendedPoint, Fr};
+use rand::RngCore;
+
+use crate::bolos::{blake2b64_with_personalization, Trng};
+use crate::commitments::bytes_to_extended;
+use crate::constants::SPENDING_KEY_BASE;
+use crate::errors::ParserError;
+use crate::pedersen::extended_to_bytes;
+use crate::redjubjub::{bytes_to_scalar, h_star, jubjub_sk_to_pk, randomized_pk};
+use crate::secret::Secret;
+use crate::types::Signature;
+use crate::zip304::verify_spend_auth_sig;
+
+const CONTEXT_STRING: &[u8] = b"FROST-RedJubjub-BLAKE2b-512-v1";
+const H1_TAG: &[u8] = b"rho";
+const H3_TAG: &[u8] = b"nonce";
+const H4_TAG: &[u8] = b"msg";
+const H5_TAG: &[u8] = b"com";
+const DEALER_PERSONALIZATION: &[u8; 16] = b"FROST_RedJubjubD";
+
+/// BLAKE2b-512 state for the ciphersuite hash with domain `tag`
+fn hash_state(tag: &[u8]) -> blake2b_simd::State {
+    let mut state = Blake2bParams::new().hash_length(64).to_state();
+    state.update(CONTEXT_STRING);
+    state.update(tag);
+    state
+}
+
+fn hash_to_array(tag: &[u8], m: &[u8]) -> [u8; 64] {
+    *hash_state(tag).update(m).finalize().as_array()
+}
+
+fn hash_to_scalar(tag: &[u8], m: &[u8]) -> Fr {
+    Fr::from_bytes_wide(&hash_to_array(tag, m))
+}
+
+fn identifier_scalar(identifier: u16) -> Result<Fr, ParserError> {
+    if identifier == 0 {
+        return Err(ParserError::parser_value_out_of_range);
+    }
+    Ok(Fr::from(identifier as u64))
+}
+
+fn base_mul(scalar: &Fr) -> ExtendedPoint {
+    SPENDING_KEY_BASE.multiply_bits(&scalar.to_bytes())
+}
+
+fn nonce_generate(secret: &[u8; 32]) -> Secret<Fr> {
+    let mut input = Secret::new([0u8; 64]);
+    Trng.fill_bytes(&mut input[..32]);
+    input[32..].copy_from_slice(secret);
+    Secret::new(hash_to_scalar(H3_TAG, &input[..]))
+}
+
+/// Splits a spend authorizing key into shares of a `min_signers`-of-`max_signers`
+/// sharing. Coefficients are derived from a random seed, so shares can be
+/// handed out one at a time.
+pub struct TrustedDealer {
+    secret: Secret<[u8; 32]>,
+    seed: Secret<[u8; 32]>,
+    min_signers: u16,
+    max_signers: u16,
+}
+
+impl TrustedDealer {
+    pub fn new(ask: &[u8; 32], min_signers: u16, max_signers: u16) -> Result<Self, ParserError> {
+        bytes_to_scalar(ask)?;
+        if min_signers < 2 || min_signers > max_signers {
+            return Err(ParserError::parser_value_out_of_range);
+        }
+        let mut seed = Secret::new([0u8; 32]);
+        Trng.fill_bytes(&mut seed[..]);
+        Ok(TrustedDealer {
+            secret: Secret::new(*ask),
+            seed,
+            min_signers,
+            max_signers,
+        })
+    }
+
+    fn coefficient(&self, j: u16) -> Secret<Fr> {
+        let mut input = Secret::new([0u8; 34]);
+        input[..32].copy_from_slice(&self.seed[..]);
+        input[32..].copy_from_slice(&j.to_le_bytes());
+        Secret::new(Fr::from_bytes_wide(&blake2b64_with_personalization(
+            DEALER_PERSONALIZATION,
+            &input[..],
+        )))
+    }
+
+    /// Group verifying key, equal to `ak` of the shared `ask`
+    pub fn group_key(&self) -> [u8; 32] {
+        jubjub_sk_to_pk(&self.secret)
+    }
+
+    /// Share for signer `identifier`, evaluated with Horner's rule
+    pub fn key_package(&self, identifier: u16) -> Result<KeyPackage, ParserError> {
+        if identifier > self.max_signers {
+            return Err(ParserError::parser_value_out_of_range);
+        }
+        let x = identifier_scalar(identifier)?;
+        let mut acc = Secret::new(Fr::zero());
+        for j in (1..self.min_signers).rev() {
+            *acc = *acc * x + *self.coefficient(j);
+        }
+        *acc = *acc * x + bytes_to_scalar(&self.secret)?;
+
+        let signing_share = Secret::new(acc.to_bytes());
+        Ok(KeyPackage {
+            identifier,
+            verifying_share: jubjub_sk_to_pk(&signing_share),
+            signing_share,
+            group_key: self.group_key(),
+            min_signers: self.min_signers,
+        })
+    }
+}
+
+/// Long-lived key material of one signer
+pub struct KeyPackage {
+    pub identifier: u16,
+    signing_share: Secret<[u8; 32]>,
+    pub verifying_share: [u8; 32],
+    pub group_key: [u8; 32],
+    pub min_signers: u16,
+}
+
+impl KeyPackage {
+    pub fn new(
+        identifier: u16,
+        signing_share: &[u8; 32],
+        group_key: &[u8; 32],
+        min_signers: u16,
+    ) -> Result<Self, ParserError> {
+        identifier_scalar(identifier)?;
+        bytes_to_scalar(signing_share)?;
+        bytes_to_extended(*group_key)?;
+        Ok(KeyPackage {
+            identifier,
+            signing_share: Secret::new(*signing_share),
+            verifying_share: jubjub_sk_to_pk(signing_share),
+            group_key: *group_key,
+            min_signers,
+        })
+    }
+}
+
+/// Public nonce commitments `(D, E)` broadcast in round one
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SigningCommitments {
+    pub identifier: u16,
+    pub hiding: [u8; 32],
+    pub binding: [u8; 32],
+}
+
+/// Secret nonces `(d, e)`; consumed by `sign` so they cannot be reused
+pub struct SigningNonces {
+    hiding: Secret<[u8; 32]>,
+    binding: Secret<[u8; 32]>,
+    pub commitments: SigningCommitments,
+}
+
+/// Round one: draws fresh nonces bound to the signer's share
+pub fn commit(key: &KeyPackage) -> SigningNonces {
+    let hiding = Secret::new(nonce_generate(&key.signing_share).to_bytes());
+    let binding = Secret::new(nonce_generate(&key.signing_share).to_bytes());
+    let commitments = SigningCommitments {
+        identifier: key.identifier,
+        hiding: jubjub_sk_to_pk(&hiding),
+        binding: jubjub_sk_to_pk(&binding),
+    };
+    SigningNonces {
+        hiding,
+        binding,
+        commitments,
+    }
+}
+
+/// Everything signers and the coordinator agree on before round two
+pub struct SigningPackage<'a> {
+    commitments: &'a [SigningCommitments],
+    sighash: [u8; 32],
+    alpha: [u8; 32],
+    rk: [u8; 32],
+}
+
+impl<'a> SigningPackage<'a> {
+    pub fn new(
+        commitments: &'a [SigningCommitments],
+        group_key: &[u8; 32],
+        alpha: &[u8; 32],
+        sighash: &[u8; 32],
+    ) -> Result<Self, ParserError> {
+        if commitments.len() < 2 {
+            return Err(ParserError::parser_unexpected_number_items);
+        }
+        let mut previous = 0u16;
+        for c in commitments {
+            if c.identifier <= previous {
+                return Err(ParserError::parser_unexpected_value);
+            }
+            previous = c.identifier;
+        }
+        Ok(SigningPackage {
+            commitments,
+            sighash: *sighash,
+            alpha: *alpha,
+            rk: randomized_pk(group_key, alpha)?,
+        })
+    }
+
+    /// Randomized verifying key the aggregate signature is valid under
+    pub fn rk(&self) -> [u8; 32] {
+        self.rk
+    }
+
+    fn commitment_list_hash(&self) -> [u8; 64] {
+        // the list has variable length, so it is hashed incrementally
+        let mut state = hash_state(H5_TAG);
+        for c in self.commitments {
+            state.update(&Fr::from(c.identifier as u64).to_bytes());
+            state.update(&c.hiding);
+            state.update(&c.binding);
+        }
+        *state.finalize().as_array()
+    }
+
+    fn binding_factor(&self, prefix: &[u8; 160], identifier: u16) -> Result<Fr, ParserError> {
+        let mut input = [0u8; 192];
+        input[..160].copy_from_slice(prefix);
+        input[160..].copy_from_slice(&identifier_scalar(identifier)?.to_bytes());
+        Ok(hash_to_scalar(H1_TAG, &input))
+    }
+
+    fn binding_prefix(&self) -> [u8; 160] {
+        let mut prefix = [0u8; 160];
+        prefix[..32].copy_from_slice(&self.rk);
+        prefix[32..96].copy_from_slice(&hash_to_array(H4_TAG, &self.sighash));
+        prefix[96..].copy_from_slice(&self.commitment_list_hash());
+        prefix
+    }
+
+    /// Group commitment `R = sum(D_i + rho_i * E_i)` and the binding factor
+    /// of `identifier`
+    fn group_commitment(&self, identifier: u16) -> Result<([u8; 32], Fr), ParserError> {
+        let prefix = self.binding_prefix();
+        let mut r = ExtendedPoint::identity();
+        let mut rho = None;
+        for c in self.commitments {
+            let rho_i = self.binding_factor(&prefix, c.identifier)?;
+            r += bytes_to_extended(c.hiding)? + bytes_to_extended(c.binding)? * rho_i;
+            if c.identifier == identifier {
+                rho = Some(rho_i);
+            }
+        }
+        let rho = rho.ok_or(ParserError::parser_context_mismatch)?;
+        Ok((extended_to_bytes(&r), rho))
+    }
+
+    fn challenge(&self, rbar: &[u8; 32]) -> Fr {
+        let mut msg = [0u8; 64];
+        msg[..32].copy_from_slice(&self.rk);
+        msg[32..].copy_from_slice(&self.sighash);
+        h_star(rbar, &msg)
+    }
+
+    /// `lambda_i = prod_{j != i} x_j / (x_j - x_i)` over the participants
+    fn lagrange_coefficient(&self, identifier: u16) -> Result<Fr, ParserError> {
+        let x_i = identifier_scalar(identifier)?;
+        let mut num = Fr::one();
+        let mut den = Fr::one();
+        for c in self.commitments {
+            if c.identifier == identifier {
+                continue;
+            }
+            let x_j = identifier_scalar(c.identifier)?;
+            num *= x_j;
+            den *= x_j - x_i;
+        }
+        let inv = den.invert();
+        if bool::from(inv.is_none()) {
+            return Err(ParserError::parser_unexpected_value);
+        }
+        Ok(num * inv.unwrap())
+    }
+}
+
+/// Signature share `z_i` of one participant
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SignatureShare {
+    pub identifier: u16,
+    pub share: [u8; 32],
+}
+
+/// Round two: `z_i = d_i + e_i * rho_i + lambda_i * s_i * c`
+pub fn sign(
+    package: &SigningPackage,
+    nonces: SigningNonces,
+    key: &KeyPackage,
+) -> Result<SignatureShare, ParserError> {
+    if package.commitments.len() < key.min_signers as usize {
+        return Err(ParserError::parser_unexpected_number_items);
+    }
+    let own = package
+        .commitments
+        .iter()
+        .find(|c| c.identifier == key.identifier);
+    if own != Some(&nonces.commitments) {
+        return Err(ParserError::parser_context_mismatch);
+    }
+
+    let (rbar, rho) = package.group_commitment(key.identifier)?;
+    let c = package.challenge(&rbar);
+    let lambda = package.lagrange_coefficient(key.identifier)?;
+
+    let d = Secret::new(bytes_to_scalar(&nonces.hiding)?);
+    let e = Secret::new(bytes_to_scalar(&nonces.binding)?);
+    let s = Secret::new(bytes_to_scalar(&key.signing_share)?);
+    let z = *d + *e * rho + lambda * *s * c;
+    Ok(SignatureShare {
+        identifier: key.identifier,
+        share: z.to_bytes(),
+    })
+}
+
+/// Checks one share against the signer's verifying share, so a coordinator
+/// can tell which participant misbehaved
+pub fn verify_share(
+    package: &SigningPackage,
+    share: &SignatureShare,
+    verifying_share: &[u8; 32],
+) -> Result<(), ParserError> {
+    let commitment = package
+        .commitments
+        .iter()
+        .find(|c| c.identifier == share.identifier)
+        .ok_or(ParserError::parser_context_mismatch)?;
+    let (rbar, rho) = package.group_commitment(share.identifier)?;
+    let c = package.challenge(&rbar);
+    let lambda = package.lagrange_coefficient(share.identifier)?;
+
+    let z = bytes_to_scalar(&share.share)?;
+    let expected = bytes_to_extended(commitment.hiding)?
+        + bytes_to_extended(commitment.binding)? * rho
+        + bytes_to_extended(*verifying_share)? * (lambda * c);
+    if extended_to_bytes(&base_mul(&z)) == extended_to_bytes(&expected) {
+        Ok(())
+    } else {
+        Err(ParserError::parser_unexpected_value)
+    }
+}
+
+/// Combines the shares into a spend authorization signature under `rk`,
+/// adding the re-randomization term `c * alpha`
+pub fn aggregate(
+    package: &SigningPackage,
+    shares: &[SignatureShare],
+) -> Result<Signature, ParserError> {
+    if shares.len() != package.commitments.len() {
+        return Err(ParserError::parser_unexpected_number_items);
+    }
+    let (rbar, _) = package.group_commitment(package.commitments[0].identifier)?;
+    let c = package.challenge(&rbar);
+
+    let mut z = c * bytes_to_scalar(&package.alpha)?;
+    for (share, commitment) in shares.iter().zip(package.commitments) {
+        if share.identifier != commitment.identifier {
+            return Err(ParserError::parser_context_mismatch);
+        }
+        z += bytes_to_scalar(&share.share)?;
+    }
+
+    let mut sig = [0u8; 64];
+    sig[..32].copy_from_slice(&rbar);
+    sig[32..].copy_from_slice(&z.to_bytes());
+    let sig = Signature(sig);
+
+    let mut msg = [0u8; 64];
+    msg[..32].copy_from_slice(&package.rk);
+    msg[32..].copy_from_slice(&package.sighash);
+    verify_spend_auth_sig(&package.rk, &msg, &sig)?;
+    Ok(sig)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redjubjub::random_scalar;
+
+    fn run(
+        dealer: &TrustedDealer,
+        signers: &[u16; 3],
+        alpha: &[u8; 32],
+        sighash: &[u8; 32],
+    ) -> Signature {
+        let k0 = dealer.key_package(signers[0]).unwrap();
+        let k1 = dealer.key_package(signers[1]).unwrap();
+        let k2 = dealer.key_package(signers[2]).unwrap();
+        let (n0, n1, n2) = (commit(&k0), commit(&k1), commit(&k2));
+        let commitments = [n0.commitments, n1.commitments, n2.commitments];
+        let package =
+            SigningPackage::new(&commitments, &dealer.group_key(), alpha, sighash).unwrap();
+
+        let shares = [
+            sign(&package, n0, &k0).unwrap(),
+            sign(&package, n1, &k1).unwrap(),
+            sign(&package, n2, &k2).unwrap(),
+        ];
+        for (share, key) in shares.iter().zip([&k0, &k1, &k2].iter()) {
+            assert_eq!(verify_share(&package, share, &key.verifying_share), Ok(()));
+        }
+        aggregate(&package, &shares).unwrap()
+    }
+
+    #[test]
+    fn test_ciphersuite_hash_domains() {
+        // Hi(m) = BLAKE2b-512(contextString || tag || m), no personalization
+        let m = [0x11u8; 32];
+        let expected = Blake2bParams::new()
+            .hash_length(64)
+            .hash(&[&b"FROST-RedJubjub-BLAKE2b-512-v1rho"[..], &m[..]].concat());
+        assert_eq!(hash_to_array(H1_TAG, &m)[..], expected.as_bytes()[..]);
+        assert_eq!(
+            hash_to_scalar(H1_TAG, &m),
+            Fr::from_bytes_wide(expected.as_array())
+        );
+        assert_ne!(hash_to_array(H4_TAG, &m), hash_to_array(H5_TAG, &m));
+    }
+
+    #[test]
+    fn test_threshold_signature_verifies_under_rk() {
+        let ask = random_scalar().to_bytes();
+        let alpha = random_scalar().to_bytes();
+        let sighash = [0x5au8; 32];
+        let dealer = TrustedDealer::new(&ask, 3, 5).unwrap();
+        assert_eq!(dealer.group_key(), jubjub_sk_to_pk(&ask));
+
+        let rk = randomized_pk(&dealer.group_key(), &alpha).unwrap();
+        let mut msg = [0u8; 64];
+        msg[..32].copy_from_slice(&rk);
+        msg[32..].copy_from_slice(&sighash);
+
+        // any subset of size min_signers works
+        for signers in [[1u16, 2, 3], [2, 4, 5], [1, 3, 5]].iter() {
+            let sig = run(&dealer, signers, &alpha, &sighash);
+            assert_eq!(verify_spend_auth_sig(&rk, &msg, &sig), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_bad_share_is_detected() {
+        let ask = random_scalar().to_bytes();
+        let alpha = random_scalar().to_bytes();
+        let sighash = [1u8; 32];
+        let dealer = TrustedDealer::new(&ask, 2, 3).unwrap();
+        let k1 = dealer.key_package(1).unwrap();
+        let k3 = dealer.key_package(3).unwrap();
+        let n1 = commit(&k1);
+        let n3 = commit(&k3);
+        let commitments = [n1.commitments, n3.commitments];
+        let package =
+            SigningPackage::new(&commitments, &dealer.group_key(), &alpha, &sighash).unwrap();
+
+        let s1 = sign(&package, n1, &k1).unwrap();
+        let mut s3 = sign(&package, n3, &k3).unwrap();
+        s3.share = (bytes_to_scalar(&s3.share).unwrap() + Fr::one()).to_bytes();
+
+        assert_eq!(verify_share(&package, &s1, &k1.verifying_share), Ok(()));
+        assert_eq!(
+            verify_share(&package, &s3, &k3.verifying_share),
+            Err(ParserError::parser_unexpected_value)
+        );
+        assert_eq!(
+            aggregate(&package, &[s1, s3]).err(),
+            Some(ParserError::parser_unexpected_value)
+        );
+    }
+
+    #[test]
+    fn test_invalid_packages() {
+        let ask = random_scalar().to_bytes();
+        let alpha = [0u8; 32];
+        let sighash = [0u8; 32];
+        assert!(TrustedDealer::new(&ask, 4, 3).is_err());
+        assert!(TrustedDealer::new(&ask, 1, 3).is_err());
+
+        let dealer = TrustedDealer::new(&ask, 3, 3).unwrap();
+        assert!(dealer.key_package(0).is_err());
+        assert!(dealer.key_package(4).is_err());
+
+        let k1 = dealer.key_package(1).unwrap();
+        let k2 = dealer.key_package(2).unwrap();
+        let n1 = commit(&k1);
+        let n2 = commit(&k2);
+
+        // unsorted commitments
+        let unsorted = [n2.commitments, n1.commitments];
+        assert!(SigningPackage::new(&unsorted, &dealer.group_key(), &alpha, &sighash).is_err());
+
+        // fewer than min_signers participants
+        let commitments = [n1.commitments, n2.commitments];
+        let package =
+            SigningPackage::new(&commitments, &dealer.group_key(), &alpha, &sighash).unwrap();
+        assert_eq!(
+            sign(&package, n1, &k1).err(),
+            Some(ParserError::parser_unexpected_number_items)
+        );
+    }
+}
@@ -4,14 +4,15 @@
     dead_code,
     unused_imports,
     clippy::many_single_char_names,
-    clippy::needless_range_loop
+    clippy::needless_range_loop,
+    clippy::not_unsafe_ptr_arg_deref
 )]
 
 extern crate chacha20poly1305;
 extern crate core;
 #[cfg(test)]
 extern crate hex;
-#[cfg(test)]
+#[cfg(any(test, feature = "std"))]
 #[macro_use]
 extern crate std;
 
@@ -19,27 +20,41 @@ use blake2s_simd::{blake2s, Hash as Blake2sHash, Params as Blake2sParams};
 use byteorder::{BigEndian, ByteOrder, LittleEndian};
 use core::convert::TryInto;
 use core::mem;
-#[cfg(not(test))]
+#[cfg(not(any(test, feature = "host-crypto")))]
 use core::panic::PanicInfo;
 use jubjub::{AffineNielsPoint, AffinePoint, ExtendedNielsPoint, ExtendedPoint, Fq, Fr};
 pub use zxformat::{fpi64_to_str, fpu64_to_str};
 
 use crate::bolos::{c_check_app_canary, c_zemu_log_stack};
 
+pub mod address;
+pub mod backend;
+pub mod change;
 mod bolos;
-mod commitments;
-mod constants;
-mod errors;
-mod note_encryption;
-mod pedersen;
-mod redjubjub;
-mod zeccrypto;
-mod zip32;
-mod zxformat;
+pub mod commitments;
+pub mod constants;
+pub mod errors;
+pub mod fee;
+pub mod frost;
+pub mod memo;
+pub mod note_encryption;
+pub mod pedersen;
+pub mod redjubjub;
+pub mod secret;
+pub mod session;
+pub mod state;
+pub mod transaction;
+pub mod txdisplay;
+pub mod types;
+pub mod zeccrypto;
+pub mod zip212;
+pub mod zip304;
+pub mod zip32;
+pub mod zxformat;
 
 fn debug(_msg: &str) {}
 
-#[cfg(not(test))]
+#[cfg(not(any(test, feature = "host-crypto")))]
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
     loop {}
@@ -0,0 +1,130 @@
+//! ZIP-302 memo fields carried in Sapling note plaintexts.
+
+use crate::errors::ParserError;
+use crate::zxformat::{pageHex, pageString, pageUtf8};
+
+pub const MEMO_SIZE: usize = 512;
+/// Lead byte of a memo holding no data (followed by zeroes)
+pub const MEMO_EMPTY: u8 = 0xf6;
+/// Lead byte of a memo holding arbitrary binary data
+pub const MEMO_ARBITRARY: u8 = 0xff;
+const MEMO_TEXT_MAX_LEAD: u8 = 0xf4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Memo<'a> {
+    /// UTF-8 text with trailing zero padding removed. Interior NUL bytes are
+    /// refused, as the screen would cut the text short at the first one.
+    Text(&'a str),
+    Empty,
+    /// Data following the 0xFF lead byte
+    Arbitrary(&'a [u8]),
+    /// Lead byte reserved by ZIP-302 for future use
+    Reserved(u8),
+}
+
+impl<'a> Memo<'a> {
+    pub fn parse(memo: &'a [u8; MEMO_SIZE]) -> Result<Self, ParserError> {
+        match memo[0] {
+            lead if lead <= MEMO_TEXT_MAX_LEAD => {
+                let len = memo.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+                if memo[..len].contains(&0) {
+                    return Err(ParserError::parser_unexpected_characters);
+                }
+                core::str::from_utf8(&memo[..len])
+                    .map(Memo::Text)
+                    .map_err(|_| ParserError::parser_unexpected_characters)
+            }
+            MEMO_EMPTY if memo[1..].iter().all(|&b| b == 0) => Ok(Memo::Empty),
+            MEMO_ARBITRARY => Ok(Memo::Arbitrary(&memo[1..])),
+            lead => Ok(Memo::Reserved(lead)),
+        }
+    }
+
+    /// Writes page `page_idx` of the text shown for this memo, returning the
+    /// number of pages. Arbitrary data is shown as hex without its trailing
+    /// zero padding.
+    pub fn get_page(&self, out_value: &mut [u8], page_idx: u8) -> Result<u8, ParserError> {
+        match *self {
+            Memo::Text(text) if !text.is_empty() => pageUtf8(out_value, text, page_idx),
+            Memo::Text(_) | Memo::Empty => pageString(out_value, b"Empty", page_idx),
+            Memo::Arbitrary(data) => {
+                let len = data.iter().rposition(|&b| b != 0).map_or(1, |i| i + 1);
+                pageHex(out_value, &data[..len], page_idx)
+            }
+            Memo::Reserved(_) => pageString(out_value, b"Reserved", page_idx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memo_with(prefix: &[u8]) -> [u8; MEMO_SIZE] {
+        let mut memo = [0u8; MEMO_SIZE];
+        memo[..prefix.len()].copy_from_slice(prefix);
+        memo
+    }
+
+    fn c_str(buf: &[u8]) -> &str {
+        let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+        core::str::from_utf8(&buf[..end]).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = memo_with("thanks for the coffee ☕".as_bytes());
+        assert_eq!(
+            Memo::parse(&text),
+            Ok(Memo::Text("thanks for the coffee ☕"))
+        );
+        assert_eq!(Memo::parse(&memo_with(&[])), Ok(Memo::Text("")));
+        assert_eq!(Memo::parse(&memo_with(&[MEMO_EMPTY])), Ok(Memo::Empty));
+        assert_eq!(
+            Memo::parse(&memo_with(&[MEMO_EMPTY, 1])),
+            Ok(Memo::Reserved(MEMO_EMPTY))
+        );
+        assert_eq!(Memo::parse(&memo_with(&[0xf5])), Ok(Memo::Reserved(0xf5)));
+
+        let data = memo_with(&[MEMO_ARBITRARY, 0xde, 0xad]);
+        assert_eq!(Memo::parse(&data), Ok(Memo::Arbitrary(&data[1..])));
+
+        assert_eq!(
+            Memo::parse(&memo_with(&[0x61, 0xc3, 0x28])),
+            Err(ParserError::parser_unexpected_characters)
+        );
+        // text hidden after a NUL would never reach the screen
+        assert_eq!(
+            Memo::parse(&memo_with(b"pay alice\0and mallory")),
+            Err(ParserError::parser_unexpected_characters)
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let mut out = [0u8; 20];
+
+        let text = memo_with(b"pay rent for the month of october");
+        let memo = Memo::parse(&text).unwrap();
+        assert_eq!(memo.get_page(&mut out, 0), Ok(2));
+        assert_eq!(c_str(&out), "pay rent for the mo");
+        assert_eq!(memo.get_page(&mut out, 1), Ok(2));
+        assert_eq!(c_str(&out), "nth of october");
+
+        // the 3 byte "☕" would straddle the 19 byte page boundary
+        let text = memo_with("thanks for coffee ☕☕".as_bytes());
+        let memo = Memo::parse(&text).unwrap();
+        assert_eq!(memo.get_page(&mut out, 0), Ok(2));
+        assert_eq!(c_str(&out), "thanks for coffee ");
+        assert_eq!(memo.get_page(&mut out, 1), Ok(2));
+        assert_eq!(c_str(&out), "☕☕");
+
+        let empty = memo_with(&[MEMO_EMPTY]);
+        assert_eq!(Memo::parse(&empty).unwrap().get_page(&mut out, 0), Ok(1));
+        assert_eq!(c_str(&out), "Empty");
+
+        let data = memo_with(&[MEMO_ARBITRARY, 0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(Memo::parse(&data).unwrap().get_page(&mut out, 0), Ok(1));
+        assert_eq!(c_str(&out), "deadbeef");
+    }
+}
@@ -1,6 +1,9 @@
 use aes::block_cipher_trait::generic_array::{GenericArray, GenericArrayImplEven};
 use byteorder::{BigEndian, ByteOrder, LittleEndian};
 use chacha20poly1305::aead::heapless::{consts::U32, consts::*, Vec};
+use chacha20poly1305::aead::{AeadInPlace, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use core::convert::TryInto;
 
 use crate::bolos::{blake2b32_with_personalization, c_zemu_log_stack};
 use crate::commitments::{bytes_to_extended, bytes_to_u64, note_commitment, write_u64_tobytes};
@@ -8,48 +11,257 @@ use crate::constants::{
     COMPACT_NOTE_SIZE, ENC_CIPHERTEXT_SIZE, ENC_COMPACT_SIZE, NOTE_PLAINTEXT_SIZE,
     OUT_CIPHERTEXT_SIZE, OUT_PLAINTEXT_SIZE,
 };
+use crate::errors::ParserError;
+use crate::memo::{Memo, MEMO_EMPTY, MEMO_SIZE};
 use crate::pedersen::extended_to_u_bytes;
+use crate::types::Diversifier;
 use crate::zeccrypto::*;
+use crate::zip212::{NoteRandomness, Zip212Policy};
 use crate::zip32::{default_pkd, group_hash_from_div, multwithgd, pkd_group_hash};
 
+#[inline(never)]
+pub fn epk(esk: &[u8; 32], d: &Diversifier) -> Result<[u8; 32], ParserError> {
+    multwithgd(esk, &d.0)
+}
+
+/// Derives `(esk, epk)` for an output from its rseed, or its rcm before
+/// Canopy, see `NoteRandomness::output_esk`
+#[inline(never)]
+pub fn rseed_esk_epk(
+    rseed: &[u8; 32],
+    d: &Diversifier,
+) -> Result<([u8; 32], [u8; 32]), ParserError> {
+    let esk = rseed_generate_esk(rseed).to_bytes();
+    let epk = epk(&esk, d)?;
+    Ok((esk, epk))
+}
+
+/// Symmetric note encryption key `KDF^Sapling(KA^Sapling(esk, pk_d), epk)`
+#[inline(never)]
+pub fn note_encryption_key(
+    esk: &[u8; 32],
+    pkd: &[u8; 32],
+    epk: &[u8; 32],
+) -> Result<[u8; 32], ParserError> {
+    let shared_secret = sapling_ka_agree(esk, pkd)?;
+    Ok(kdf_sapling(&shared_secret, epk))
+}
+
+/// Compact note plaintext `leadbyte || d || v || rcm/rseed` followed by the
+/// memo type
+#[inline(never)]
+pub fn compact_note_plaintext(
+    d: &Diversifier,
+    value: u64,
+    rnd: &NoteRandomness,
+    memotype: u8,
+) -> [u8; COMPACT_NOTE_SIZE + 1] {
+    let mut input = [0; COMPACT_NOTE_SIZE + 1];
+    input[0] = rnd.lead_byte();
+    input[1..12].copy_from_slice(&d.0);
+
+    let mut vbytes = [0u8; 8];
+    LittleEndian::write_u64(&mut vbytes, value);
+
+    input[12..20].copy_from_slice(&vbytes);
+    input[20..COMPACT_NOTE_SIZE].copy_from_slice(rnd.as_bytes());
+    input[COMPACT_NOTE_SIZE] = memotype;
+    input
+}
+
+/// Full note plaintext `leadbyte || d || v || rcm/rseed || memo`
+#[inline(never)]
+pub fn note_plaintext(
+    d: &Diversifier,
+    value: u64,
+    rnd: &NoteRandomness,
+    memo: &[u8; MEMO_SIZE],
+) -> [u8; NOTE_PLAINTEXT_SIZE] {
+    let compact = compact_note_plaintext(d, value, rnd, memo[0]);
+    let mut plaintext = [0u8; NOTE_PLAINTEXT_SIZE];
+    plaintext[..COMPACT_NOTE_SIZE].copy_from_slice(&compact[..COMPACT_NOTE_SIZE]);
+    plaintext[COMPACT_NOTE_SIZE..].copy_from_slice(memo);
+    plaintext
+}
+
+fn seal<const N: usize, const M: usize>(key: &[u8; 32], plaintext: &[u8; N]) -> [u8; M] {
+    let mut buffer = *plaintext;
+    let tag = ChaCha20Poly1305::new(Key::from_slice(key))
+        .encrypt_in_place_detached(Nonce::from_slice(&[0u8; 12]), &[], &mut buffer)
+        .expect("plaintext fits in a ChaCha20 stream");
+    let mut ciphertext = [0u8; M];
+    ciphertext[..N].copy_from_slice(&buffer);
+    ciphertext[N..].copy_from_slice(&tag);
+    ciphertext
+}
+
+/// `enc_ciphertext` of `plaintext` under the note encryption `key`, as a host
+/// builds it
+pub fn encrypt_note(
+    key: &[u8; 32],
+    plaintext: &[u8; NOTE_PLAINTEXT_SIZE],
+) -> [u8; ENC_CIPHERTEXT_SIZE] {
+    seal(key, plaintext)
+}
+
+/// `out_ciphertext` of `pk_d || esk` under the outgoing cipher key `ock`
+pub fn encrypt_out_plaintext(
+    ock: &[u8; 32],
+    plaintext: &[u8; OUT_PLAINTEXT_SIZE],
+) -> [u8; OUT_CIPHERTEXT_SIZE] {
+    seal(ock, plaintext)
+}
+
+/// Checks `enc_ciphertext`, authentication tag included, is the encryption
+/// of `plaintext` under the note encryption `key`
+#[inline(never)]
+pub fn enc_ciphertext_matches(
+    key: &[u8; 32],
+    plaintext: &[u8; NOTE_PLAINTEXT_SIZE],
+    enc_ciphertext: &[u8; ENC_CIPHERTEXT_SIZE],
+) -> bool {
+    let mut buffer = *plaintext;
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = Nonce::from_slice(&[0u8; 12]);
+    match cipher.encrypt_in_place_detached(nonce, &[], &mut buffer) {
+        Ok(tag) => {
+            buffer[..] == enc_ciphertext[..NOTE_PLAINTEXT_SIZE]
+                && tag[..] == enc_ciphertext[NOTE_PLAINTEXT_SIZE..]
+        }
+        Err(_) => false,
+    }
+}
+
+/// Checks that `ciphertext` starts with the ChaCha20 encryption (block
+/// counter 1, zero nonce) of `plaintext`, as the device does for the compact
+/// note and `out_ciphertext` without their authentication tags
+#[inline(never)]
+pub fn ciphertext_prefix_matches(key: &[u8; 32], plaintext: &[u8], ciphertext: &[u8]) -> bool {
+    let mut buffer = [0u8; OUT_PLAINTEXT_SIZE];
+    if plaintext.len() > buffer.len() || ciphertext.len() < plaintext.len() {
+        return false;
+    }
+    let buffer = &mut buffer[..plaintext.len()];
+    buffer.copy_from_slice(plaintext);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = Nonce::from_slice(&[0u8; 12]);
+    match cipher.encrypt_in_place_detached(nonce, &[], buffer) {
+        Ok(_) => buffer[..] == ciphertext[..plaintext.len()],
+        Err(_) => false,
+    }
+}
+
+/// Decrypts `enc_ciphertext` under the note encryption `key`, checking its
+/// authentication tag
+#[inline(never)]
+fn open_enc_ciphertext(
+    key: &[u8; 32],
+    enc_ciphertext: &[u8; ENC_CIPHERTEXT_SIZE],
+) -> Result<[u8; NOTE_PLAINTEXT_SIZE], ParserError> {
+    let mut plaintext = [0u8; NOTE_PLAINTEXT_SIZE];
+    plaintext.copy_from_slice(&enc_ciphertext[..NOTE_PLAINTEXT_SIZE]);
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = Nonce::from_slice(&[0u8; 12]);
+    let tag = chacha20poly1305::Tag::from_slice(&enc_ciphertext[NOTE_PLAINTEXT_SIZE..]);
+    cipher
+        .decrypt_in_place_detached(nonce, &[], &mut plaintext, tag)
+        .map_err(|_| ParserError::parser_unexpected_value)?;
+    Ok(plaintext)
+}
+
+/// Decrypted and parsed note plaintext
+pub struct NotePlaintext {
+    pub diversifier: Diversifier,
+    pub value: u64,
+    pub rnd: NoteRandomness,
+    pub memo: [u8; MEMO_SIZE],
+}
+
+/// Decrypts `enc_ciphertext` and checks its lead byte against `policy`.
+/// For 0x02 notes `epk` must also match the esk derived from rseed.
+#[inline(never)]
+pub fn decrypt_note(
+    key: &[u8; 32],
+    epk: &[u8; 32],
+    enc_ciphertext: &[u8; ENC_CIPHERTEXT_SIZE],
+    policy: &Zip212Policy,
+) -> Result<NotePlaintext, ParserError> {
+    let plaintext = open_enc_ciphertext(key, enc_ciphertext)?;
+    if !policy.accepts(plaintext[0]) {
+        return Err(ParserError::parser_unexpected_value);
+    }
+    let rnd = NoteRandomness::from_lead_byte(
+        plaintext[0],
+        plaintext[20..COMPACT_NOTE_SIZE].try_into().unwrap(),
+    )?;
+    let diversifier = Diversifier(plaintext[1..12].try_into().unwrap());
+    if let Some(esk) = rnd.esk() {
+        if self::epk(&esk, &diversifier)? != *epk {
+            return Err(ParserError::parser_unexpected_value);
+        }
+    }
+
+    let mut memo = [0u8; MEMO_SIZE];
+    memo.copy_from_slice(&plaintext[COMPACT_NOTE_SIZE..]);
+    Ok(NotePlaintext {
+        diversifier,
+        value: LittleEndian::read_u64(&plaintext[12..20]),
+        rnd,
+        memo,
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn blake2b_prf(input_ptr: *const [u8; 128], out_ptr: *mut [u8; 32]) {
     c_zemu_log_stack(b"inside_blake2bprfock\x00".as_ref());
     let input = unsafe { &*input_ptr }; //ovk, cv, cmu, epk
-    pub const PRF_OCK_PERSONALIZATION: &[u8; 16] = b"Zcash_Derive_ock";
-    let hash = blake2b32_with_personalization(PRF_OCK_PERSONALIZATION, input);
-    let output = unsafe { &mut *out_ptr }; //ovk, cv, cmu, epk
-    output.copy_from_slice(&hash);
+    let output = unsafe { &mut *out_ptr };
+    *output = prf_ock(
+        input[0..32].try_into().unwrap(),
+        input[32..64].try_into().unwrap(),
+        input[64..96].try_into().unwrap(),
+        input[96..128].try_into().unwrap(),
+    );
 }
 
 #[no_mangle]
-pub fn get_epk(
+pub extern "C" fn get_epk(
     esk_ptr: *const [u8; 32],
     d_ptr: *const [u8; 11],
     output_ptr: *mut [u8; 32],
-) {
+) -> ParserError {
     c_zemu_log_stack(b"inside_getepk\x00".as_ref());
-    let esk = unsafe { &*esk_ptr }; //ovk, cv, cmu, epk
-    let d = unsafe { &*d_ptr };
+    let esk = unsafe { &*esk_ptr };
+    let d = unsafe { Diversifier::from_ptr(d_ptr) };
     let output = unsafe { &mut *output_ptr };
-    let epk = multwithgd(esk, d);
-    output.copy_from_slice(&epk);
+    match epk(esk, d) {
+        Ok(key) => {
+            *output = key;
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn rseed_get_esk_epk(rseed_ptr: *const [u8; 32],
-                                    d_ptr: *const [u8; 11],
-                                    output_esk_ptr: *mut [u8; 32],
-                                    output_epk_ptr: *mut [u8; 32]) {
+pub extern "C" fn rseed_get_esk_epk(
+    rseed_ptr: *const [u8; 32],
+    d_ptr: *const [u8; 11],
+    output_esk_ptr: *mut [u8; 32],
+    output_epk_ptr: *mut [u8; 32],
+) -> ParserError {
     let rseed = unsafe { &*rseed_ptr };
-//    let d = unsafe { &*d_ptr };
+    let d = unsafe { Diversifier::from_ptr(d_ptr) };
     let output_esk = unsafe { &mut *output_esk_ptr };
     let output_epk = unsafe { &mut *output_epk_ptr };
-    rseed_get_esk(rseed, output_esk);
-
-    //let epk = multwithgd(output_esk, d);
-    get_epk(output_esk,d_ptr,output_epk);
-    //output_epk.copy_from_slice(&epk);
+    match rseed_esk_epk(rseed, d) {
+        Ok((esk, epk)) => {
+            *output_esk = esk;
+            *output_epk = epk;
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
 }
 
 #[no_mangle]
@@ -58,43 +270,158 @@ pub extern "C" fn ka_to_key(
     pkd_ptr: *const [u8; 32],
     epk_ptr: *const [u8; 32],
     output_ptr: *mut [u8; 32],
-) {
+) -> ParserError {
     c_zemu_log_stack(b"inside_katokey\x00".as_ref());
-    let esk = unsafe { &*esk_ptr }; //ovk, cv, cmu, epk
+    let esk = unsafe { &*esk_ptr };
     let pkd = unsafe { &*pkd_ptr };
     let epk = unsafe { &*epk_ptr };
-    let shared_secret = sapling_ka_agree(esk, pkd);
-    let key = kdf_sapling(&shared_secret, epk);
-    let output = unsafe { &mut *output_ptr }; //ovk, cv, cmu, epk
-    output.copy_from_slice(&key);
+    let output = unsafe { &mut *output_ptr };
+    match note_encryption_key(esk, pkd, epk) {
+        Ok(key) => {
+            *output = key;
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
 }
 
 #[no_mangle]
 pub extern "C" fn prepare_enccompact_input(
+    leadbyte: u8,
     d_ptr: *const [u8; 11],
     value: u64,
-    rcm_ptr: *const [u8; 32],
+    rnd_ptr: *const [u8; 32],
     memotype: u8,
     output_ptr: *mut [u8; COMPACT_NOTE_SIZE + 1],
-) {
+) -> ParserError {
     c_zemu_log_stack(b"inside enccompactinput\x00".as_ref());
-    let d = unsafe { &*d_ptr };
-    let rcm = unsafe { &*rcm_ptr };
+    let d = unsafe { Diversifier::from_ptr(d_ptr) };
+    let rnd = unsafe { &*rnd_ptr };
+    let output = unsafe { &mut *output_ptr };
+    match NoteRandomness::from_lead_byte(leadbyte, *rnd) {
+        Ok(rnd) => {
+            *output = compact_note_plaintext(d, value, &rnd, memotype);
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
+}
 
+#[no_mangle]
+pub extern "C" fn get_note_rcm(
+    leadbyte: u8,
+    rnd_ptr: *const [u8; 32],
+    output_ptr: *mut [u8; 32],
+) -> ParserError {
+    let rnd = unsafe { &*rnd_ptr };
     let output = unsafe { &mut *output_ptr };
+    match NoteRandomness::from_lead_byte(leadbyte, *rnd) {
+        Ok(rnd) => {
+            *output = rnd.rcm();
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
+}
 
-    let mut input = [0; COMPACT_NOTE_SIZE + 1];
-    input[0] = 2;
-    input[1..12].copy_from_slice(d);
+/// Checks the full `enc_ciphertext` of an output against what the device
+/// shows for it. The device only holds the memo type, so the memo is
+/// recovered by decrypting, which also checks the authentication tag; it must
+/// start with `memotype`, an empty memo must really be empty, and any other
+/// memo must parse so that `get_memo_page` can show it.
+#[inline(never)]
+pub fn enc_ciphertext_matches_output(
+    key: &[u8; 32],
+    d: &Diversifier,
+    value: u64,
+    rnd: &NoteRandomness,
+    memotype: u8,
+    enc_ciphertext: &[u8; ENC_CIPHERTEXT_SIZE],
+) -> bool {
+    let plaintext = match open_enc_ciphertext(key, enc_ciphertext) {
+        Ok(plaintext) => plaintext,
+        Err(_) => return false,
+    };
+    let compact = compact_note_plaintext(d, value, rnd, memotype);
+    let memo: &[u8; MEMO_SIZE] = plaintext[COMPACT_NOTE_SIZE..].try_into().unwrap();
+    plaintext[..compact.len()] == compact[..]
+        && match Memo::parse(memo) {
+            Ok(Memo::Empty) => true,
+            Ok(_) => memotype != MEMO_EMPTY,
+            Err(_) => false,
+        }
+}
 
-    let mut vbytes = [0u8; 8];
-    LittleEndian::write_u64(&mut vbytes, value);
+/// Memo of an output the device built, decrypted from its checked
+/// `enc_ciphertext` with the key recomputed from the output's rseed or rcm
+#[inline(never)]
+pub fn output_memo(
+    d: &Diversifier,
+    pkd: &[u8; 32],
+    rnd: &[u8; 32],
+    enc_ciphertext: &[u8; ENC_CIPHERTEXT_SIZE],
+) -> Result<[u8; MEMO_SIZE], ParserError> {
+    let (esk, epk) = rseed_esk_epk(rnd, d)?;
+    let key = note_encryption_key(&esk, pkd, &epk)?;
+    let plaintext = open_enc_ciphertext(&key, enc_ciphertext)?;
+    let mut memo = [0u8; MEMO_SIZE];
+    memo.copy_from_slice(&plaintext[COMPACT_NOTE_SIZE..]);
+    Ok(memo)
+}
 
-    input[12..20].copy_from_slice(&vbytes);
-    input[20..COMPACT_NOTE_SIZE].copy_from_slice(rcm);
-    input[COMPACT_NOTE_SIZE] = memotype;
-    c_zemu_log_stack(b"before copy\x00".as_ref());
-    output.copy_from_slice(&input);
+#[no_mangle]
+pub extern "C" fn check_enc_ciphertext(
+    leadbyte: u8,
+    d_ptr: *const [u8; 11],
+    value: u64,
+    rnd_ptr: *const [u8; 32],
+    memotype: u8,
+    key_ptr: *const [u8; 32],
+    enc_ciphertext_ptr: *const [u8; ENC_CIPHERTEXT_SIZE],
+) -> ParserError {
+    let d = unsafe { Diversifier::from_ptr(d_ptr) };
+    let rnd = unsafe { &*rnd_ptr };
+    let key = unsafe { &*key_ptr };
+    let enc_ciphertext = unsafe { &*enc_ciphertext_ptr };
+    let rnd = match NoteRandomness::from_lead_byte(leadbyte, *rnd) {
+        Ok(rnd) => rnd,
+        Err(e) => return e,
+    };
+    if enc_ciphertext_matches_output(key, d, value, &rnd, memotype, enc_ciphertext) {
+        ParserError::parser_ok
+    } else {
+        ParserError::parser_unexpected_value
+    }
+}
+
+/// Writes page `page_idx` of the memo of an output that passed
+/// `check_enc_ciphertext` to the C string `out_ptr`
+#[no_mangle]
+pub extern "C" fn get_memo_page(
+    d_ptr: *const [u8; 11],
+    pkd_ptr: *const [u8; 32],
+    rnd_ptr: *const [u8; 32],
+    enc_ciphertext_ptr: *const [u8; ENC_CIPHERTEXT_SIZE],
+    out_ptr: *mut u8,
+    out_len: u16,
+    page_idx: u8,
+    page_count_ptr: *mut u8,
+) -> ParserError {
+    let d = unsafe { Diversifier::from_ptr(d_ptr) };
+    let pkd = unsafe { &*pkd_ptr };
+    let rnd = unsafe { &*rnd_ptr };
+    let enc_ciphertext = unsafe { &*enc_ciphertext_ptr };
+    let out = unsafe { core::slice::from_raw_parts_mut(out_ptr, out_len as usize) };
+    let page_count = unsafe { &mut *page_count_ptr };
+    let page = output_memo(d, pkd, rnd, enc_ciphertext)
+        .and_then(|memo| Memo::parse(&memo)?.get_page(out, page_idx));
+    match page {
+        Ok(count) => {
+            *page_count = count;
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
 }
 
 #[cfg(test)]
@@ -122,16 +449,241 @@ mod tests {
 
         let mut output = [0u8; 32];
 
-        ka_to_key(
+        let err = ka_to_key(
             esk.as_ptr() as *const [u8; 32],
             pk_d.as_ptr() as *const [u8; 32],
             epk.as_ptr() as *const [u8; 32],
             output.as_mut_ptr() as *mut [u8; 32],
         );
+        assert_eq!(err, ParserError::parser_ok);
 
-        let shared_secret = sapling_ka_agree(&esk, &pk_d);
+        let shared_secret = sapling_ka_agree(&esk, &pk_d).unwrap();
         let key = kdf_sapling(&shared_secret, &epk);
 
         assert_eq!(output, key);
+        assert_eq!(output, note_encryption_key(&esk, &pk_d, &epk).unwrap());
+    }
+
+    #[test]
+    fn test_enc_ciphertext_binds_memo() {
+        let key = [0x42u8; 32];
+        let d = Diversifier([7u8; 11]);
+        let rseed = NoteRandomness::Rseed([9u8; 32]);
+        let mut memo = [0u8; MEMO_SIZE];
+        memo[..5].copy_from_slice(b"hello");
+        let plaintext = note_plaintext(&d, 1000, &rseed, &memo);
+        assert_eq!(plaintext[0], 2);
+        assert_eq!(
+            &plaintext[COMPACT_NOTE_SIZE..COMPACT_NOTE_SIZE + 5],
+            b"hello"
+        );
+
+        let mut enc_ciphertext = encrypt_note(&key, &plaintext);
+        assert!(enc_ciphertext_matches(&key, &plaintext, &enc_ciphertext));
+
+        // the compact prefix alone no longer suffices: a different memo fails
+        memo[4] = b'!';
+        let other = note_plaintext(&d, 1000, &rseed, &memo);
+        assert!(!enc_ciphertext_matches(&key, &other, &enc_ciphertext));
+
+        enc_ciphertext[ENC_CIPHERTEXT_SIZE - 1] ^= 1;
+        assert!(!enc_ciphertext_matches(&key, &plaintext, &enc_ciphertext));
+        enc_ciphertext[ENC_CIPHERTEXT_SIZE - 1] ^= 1;
+
+        // the device only knows the memo type, the rest comes from decrypting
+        assert_eq!(
+            check_enc_ciphertext(2, &d.0, 1000, &[9u8; 32], b'h', &key, &enc_ciphertext),
+            ParserError::parser_ok
+        );
+        assert_eq!(
+            check_enc_ciphertext(2, &d.0, 1000, &[9u8; 32], MEMO_EMPTY, &key, &enc_ciphertext),
+            ParserError::parser_unexpected_value
+        );
+        assert_eq!(
+            check_enc_ciphertext(2, &d.0, 1001, &[9u8; 32], b'h', &key, &enc_ciphertext),
+            ParserError::parser_unexpected_value
+        );
+        assert_eq!(
+            check_enc_ciphertext(1, &d.0, 1000, &[9u8; 32], b'h', &key, &enc_ciphertext),
+            ParserError::parser_unexpected_value
+        );
+
+        enc_ciphertext[ENC_CIPHERTEXT_SIZE - 1] ^= 1;
+        assert_eq!(
+            check_enc_ciphertext(2, &d.0, 1000, &[9u8; 32], b'h', &key, &enc_ciphertext),
+            ParserError::parser_unexpected_value
+        );
+    }
+
+    #[test]
+    fn test_enc_ciphertext_default_memo_is_empty() {
+        let key = [0x42u8; 32];
+        let d = Diversifier([7u8; 11]);
+        let rseed = NoteRandomness::Rseed([9u8; 32]);
+        let mut memo = [0u8; MEMO_SIZE];
+        memo[0] = MEMO_EMPTY;
+        let enc_ciphertext = encrypt_note(&key, &note_plaintext(&d, 1000, &rseed, &memo));
+        assert!(enc_ciphertext_matches_output(
+            &key,
+            &d,
+            1000,
+            &rseed,
+            MEMO_EMPTY,
+            &enc_ciphertext
+        ));
+
+        // a "default" memo smuggling data behind its lead byte is refused
+        memo[100] = 1;
+        let enc_ciphertext = encrypt_note(&key, &note_plaintext(&d, 1000, &rseed, &memo));
+        assert!(!enc_ciphertext_matches_output(
+            &key,
+            &d,
+            1000,
+            &rseed,
+            MEMO_EMPTY,
+            &enc_ciphertext
+        ));
+    }
+
+    #[test]
+    fn test_memo_page() {
+        let seed = crate::types::SpendingKey::from_bytes([3u8; 32]);
+        let mut start = Diversifier([0u8; 11]);
+        let address =
+            crate::zip32::default_payment_address_from_startindex(&seed, 0, &mut start).unwrap();
+        let d = address.diversifier;
+        let rnd = [9u8; 32];
+        let (esk, epk) = rseed_esk_epk(&rnd, &d).unwrap();
+        let key = note_encryption_key(&esk, &address.pk_d, &epk).unwrap();
+        let rseed = NoteRandomness::Rseed(rnd);
+
+        let mut memo = [0u8; MEMO_SIZE];
+        memo[..25].copy_from_slice(b"rent for october, flat 3b");
+        let enc_ciphertext = encrypt_note(&key, &note_plaintext(&d, 1000, &rseed, &memo));
+        assert_eq!(
+            check_enc_ciphertext(2, &d.0, 1000, &rnd, b'r', &key, &enc_ciphertext),
+            ParserError::parser_ok
+        );
+
+        let mut out = [0u8; 20];
+        let mut page_count = 0u8;
+        assert_eq!(
+            get_memo_page(
+                &d.0,
+                &address.pk_d,
+                &rnd,
+                &enc_ciphertext,
+                out.as_mut_ptr(),
+                out.len() as u16,
+                1,
+                &mut page_count
+            ),
+            ParserError::parser_ok
+        );
+        assert_eq!(page_count, 2);
+        assert_eq!(&out[..7], b"lat 3b\0");
+
+        // a memo the screen could not show is not signed
+        memo[3] = 0;
+        let enc_ciphertext = encrypt_note(&key, &note_plaintext(&d, 1000, &rseed, &memo));
+        assert_eq!(
+            check_enc_ciphertext(2, &d.0, 1000, &rnd, b'r', &key, &enc_ciphertext),
+            ParserError::parser_unexpected_value
+        );
+    }
+
+    #[test]
+    fn test_enccompact_input_lead_byte() {
+        let d = Diversifier([7u8; 11]);
+        let mut output = [0u8; COMPACT_NOTE_SIZE + 1];
+        assert_eq!(
+            prepare_enccompact_input(1, &d.0, 1000, &[9u8; 32], 0xf6, &mut output),
+            ParserError::parser_ok
+        );
+        assert_eq!(output[0], 1);
+        assert_eq!(output[20..COMPACT_NOTE_SIZE], [9u8; 32]);
+        assert_eq!(output[COMPACT_NOTE_SIZE], 0xf6);
+
+        assert_eq!(
+            prepare_enccompact_input(2, &d.0, 1000, &[9u8; 32], 0xf6, &mut output),
+            ParserError::parser_ok
+        );
+        assert_eq!(output[0], 2);
+        assert_eq!(
+            prepare_enccompact_input(3, &d.0, 1000, &[9u8; 32], 0xf6, &mut output),
+            ParserError::parser_unexpected_value
+        );
+    }
+
+    #[test]
+    fn test_ciphertext_prefix_matches() {
+        let key = [0x42u8; 32];
+        let d = Diversifier([7u8; 11]);
+        let rseed = NoteRandomness::Rseed([9u8; 32]);
+        let mut memo = [0u8; MEMO_SIZE];
+        memo[0] = 0xf6;
+        let enc_ciphertext = encrypt_note(&key, &note_plaintext(&d, 1000, &rseed, &memo));
+
+        let compact = compact_note_plaintext(&d, 1000, &rseed, 0xf6);
+        assert!(ciphertext_prefix_matches(&key, &compact, &enc_ciphertext));
+        assert!(!ciphertext_prefix_matches(
+            &[0x43u8; 32],
+            &compact,
+            &enc_ciphertext
+        ));
+
+        let other = compact_note_plaintext(&d, 1001, &rseed, 0xf6);
+        assert!(!ciphertext_prefix_matches(&key, &other, &enc_ciphertext));
+        assert!(!ciphertext_prefix_matches(
+            &key,
+            &compact,
+            &enc_ciphertext[..10]
+        ));
+    }
+
+    #[test]
+    fn test_decrypt_both_lead_bytes() {
+        use crate::types::SpendingKey;
+        use crate::zip212::{Network, CANOPY_HEIGHT_MAINNET, ZIP212_GRACE_PERIOD};
+        use crate::zip32::default_payment_address_from_startindex;
+
+        let mut start = Diversifier([0u8; 11]);
+        let addr = default_payment_address_from_startindex(&SpendingKey([5u8; 32]), 0, &mut start)
+            .unwrap();
+        let d = addr.diversifier;
+        let key = [0x24u8; 32];
+        let mut memo = [0u8; MEMO_SIZE];
+        memo[0] = 0xf6;
+
+        let grace = Zip212Policy::new(Network::Mainnet, CANOPY_HEIGHT_MAINNET + 10);
+        let after = Zip212Policy::new(
+            Network::Mainnet,
+            CANOPY_HEIGHT_MAINNET + ZIP212_GRACE_PERIOD,
+        );
+        let before = Zip212Policy::new(Network::Mainnet, CANOPY_HEIGHT_MAINNET - 10);
+
+        // 0x01: explicit rcm, esk chosen independently
+        let v1 = NoteRandomness::Rcm([3u8; 32]);
+        let enc = encrypt_note(&key, &note_plaintext(&d, 42, &v1, &memo));
+        let note = decrypt_note(&key, &[0u8; 32], &enc, &grace).unwrap();
+        assert_eq!(note.rnd, v1);
+        assert_eq!(note.value, 42);
+        assert_eq!(note.diversifier, d);
+        assert!(decrypt_note(&key, &[0u8; 32], &enc, &before).is_ok());
+        assert!(decrypt_note(&key, &[0u8; 32], &enc, &after).is_err());
+
+        // 0x02: rseed, epk must be derived from it
+        let v2 = NoteRandomness::Rseed([4u8; 32]);
+        let (_, epk) = rseed_esk_epk(v2.as_bytes(), &d).unwrap();
+        let enc = encrypt_note(&key, &note_plaintext(&d, 42, &v2, &memo));
+        let note = decrypt_note(&key, &epk, &enc, &after).unwrap();
+        assert_eq!(note.rnd, v2);
+        assert_eq!(note.memo[0], 0xf6);
+        assert!(decrypt_note(&key, &[1u8; 32], &enc, &after).is_err());
+        assert!(decrypt_note(&key, &epk, &enc, &before).is_err());
+
+        let mut tampered = enc;
+        tampered[0] ^= 1;
+        assert!(decrypt_note(&key, &epk, &tampered, &after).is_err());
     }
 }
@@ -257,13 +257,13 @@ pub fn pedersen_hash_to_point(m: &[u8], bitsize: u32) -> ExtendedPoint {
 
 #[inline(never)]
 pub fn pedersen_hash(m: &[u8], bitsize: u32) -> [u8; 32] {
-    let result_point = pedersen_hash_to_point(&m, bitsize);
+    let result_point = pedersen_hash_to_point(m, bitsize);
     extended_to_u_bytes(&result_point)
 }
 
 #[inline(never)]
 pub fn pedersen_hash_pointbytes(m: &[u8], bitsize: u32) -> [u8; 32] {
-    let result_point = pedersen_hash_to_point(&m, bitsize);
+    let result_point = pedersen_hash_to_point(m, bitsize);
     extended_to_bytes(&result_point)
 }
 
@@ -284,9 +284,9 @@ mod tests {
             shift: 5,
             carry: 0,
         };
-        assert_eq!(b.next(), Some(7 as u8));
-        assert_eq!(b.next(), Some(7 as u8));
-        assert_eq!(b.next(), Some(4 as u8));
+        assert_eq!(b.next(), Some(7u8));
+        assert_eq!(b.next(), Some(7u8));
+        assert_eq!(b.next(), Some(4u8));
         assert_eq!(b.next(), None);
     }
 
@@ -3,13 +3,18 @@ use rand::RngCore;
 
 use crate::bolos::c_zemu_log_stack;
 use crate::bolos::{
-    blake2b_redjubjub, sdk_jubjub_scalarmult, sdk_jubjub_scalarmult_spending_base, Trng,
+    blake2b32_with_personalization, blake2b_redjubjub, sdk_jubjub_scalarmult,
+    sdk_jubjub_scalarmult_spending_base, Trng,
 };
 use crate::commitments::bytes_to_extended;
 use crate::constants;
 use crate::constants::*;
+use crate::errors::ParserError;
 use crate::pedersen::extended_to_bytes;
-use crate::zip32::zip32_child_ask_nsk;
+use crate::secret::Secret;
+use crate::state::Slot;
+use crate::types::{Ask, Signature, SpendingKey};
+use crate::zip32::derive_ask_nsk;
 
 #[inline(never)]
 pub fn h_star(a: &[u8], b: &[u8]) -> Fr {
@@ -29,16 +34,27 @@ pub fn jubjub_randomized_sk(sk: &Fr, alpha: &Fr) -> Fr {
 }
 
 #[inline(never)]
-pub fn jubjub_randomized_pk(pk: &mut ExtendedPoint, alpha: [u8; 32]) {
+pub fn jubjub_randomized_pk(pk: &mut ExtendedPoint, alpha: [u8; 32]) -> Result<(), ParserError> {
     let rndpk = jubjub_sk_to_pk(&alpha);
-    *pk += bytes_to_extended(rndpk);
+    *pk += bytes_to_extended(rndpk)?;
+    Ok(())
 }
 
+/// Parses a canonical scalar encoding
 #[inline(never)]
-pub fn sign_generate_r(msg: &[u8]) -> Fr {
-    let mut t = [0u8; 80];
-    Trng.fill_bytes(&mut t);
-    h_star(&t, msg)
+pub fn bytes_to_scalar(bytes: &[u8; 32]) -> Result<Fr, ParserError> {
+    let f = Fr::from_bytes(bytes);
+    if bool::from(f.is_none()) {
+        return Err(ParserError::parser_invalid_scalar);
+    }
+    Ok(f.unwrap())
+}
+
+#[inline(never)]
+pub fn sign_generate_r(msg: &[u8]) -> Secret<Fr> {
+    let mut t = Secret::new([0u8; 80]);
+    Trng.fill_bytes(&mut *t);
+    Secret::new(h_star(&*t, msg))
 }
 
 #[inline(never)]
@@ -51,15 +67,15 @@ pub fn sign_compute_rbar(r: &[u8; 32]) -> [u8; 32] {
 #[inline(never)]
 pub fn sign_compute_sbar(msg: &[u8], r: &Fr, rbar: &[u8], sfr: &Fr) -> [u8; 32] {
     c_zemu_log_stack(b"signcomputesbar\x00".as_ref());
-    let s = r + h_star(&rbar, msg) * sfr;
+    let s = r + h_star(rbar, msg) * sfr;
     s.to_bytes()
 }
 
 #[inline(never)]
 pub fn sign_complete(msg: &[u8], sk: &Fr) -> [u8; 64] {
     c_zemu_log_stack(b"signcomplete\x00".as_ref());
-    let r = sign_generate_r(&msg);
-    let rbar = sign_compute_rbar(&r.to_bytes());
+    let r = sign_generate_r(msg);
+    let rbar = sign_compute_rbar(&Secret::new(r.to_bytes()));
     let sbar = sign_compute_sbar(msg, &r, &rbar, sk);
     let mut sig = [0u8; 64];
     sig[..32].copy_from_slice(&rbar);
@@ -75,50 +91,276 @@ pub fn random_scalar() -> Fr {
 }
 
 #[inline(never)]
-pub fn sk_to_pk(sk_ptr: *const [u8; 32], pk_ptr: *mut [u8; 32]) {
-    c_zemu_log_stack(b"sk_to_pk\x00".as_ref());
-    let sk = unsafe { &*sk_ptr };
-    let pk = unsafe { &mut *pk_ptr };
-    let pubkey = jubjub_sk_to_pk(sk);
-    pk.copy_from_slice(&pubkey);
+pub fn randomized_secret(
+    sk: &[u8; 32],
+    alpha: &[u8; 32],
+) -> Result<Secret<[u8; 32]>, ParserError> {
+    c_zemu_log_stack(b"random_sk\x00".as_ref());
+    let mut skfr = Secret::new(bytes_to_scalar(sk)?);
+    let alphafr = Secret::new(bytes_to_scalar(alpha)?);
+    *skfr += *alphafr;
+    Ok(Secret::new(skfr.to_bytes()))
 }
 
+/// Randomized spend authorizing key `rsk = ask + alpha` for the given account
 #[inline(never)]
-pub fn randomized_secret(
-    sk_ptr: *const [u8; 32],
-    alpha_ptr: *const [u8; 32],
-    output_ptr: *mut [u8; 32],
-) {
-    c_zemu_log_stack(b"random_sk\x00".as_ref());
-    let alpha = unsafe { &*alpha_ptr };
-    let sk = unsafe { &*sk_ptr };
-    let output = unsafe { &mut *output_ptr };
-    let mut skfr = Fr::from_bytes(&sk).unwrap();
-    let alphafr = Fr::from_bytes(&alpha).unwrap();
-    skfr += alphafr;
-    output.copy_from_slice(&skfr.to_bytes());
+pub fn randomized_secret_from_sk(
+    sk: &SpendingKey,
+    pos: u32,
+    alpha: &[u8; 32],
+) -> Result<Secret<[u8; 32]>, ParserError> {
+    let (ask, _) = derive_ask_nsk(sk, pos);
+    randomized_secret(&ask.0, alpha)
+}
+
+/// Randomized spend validating key `rk = (ask + alpha) * G`
+#[inline(never)]
+pub fn rk(ask: &Ask, alpha: &[u8; 32]) -> Result<[u8; 32], ParserError> {
+    let rsk = randomized_secret(&ask.0, alpha)?;
+    Ok(jubjub_sk_to_pk(&rsk))
+}
+
+#[inline(never)]
+pub fn randomized_pk(pk: &[u8; 32], alpha: &[u8; 32]) -> Result<[u8; 32], ParserError> {
+    bytes_to_scalar(alpha)?;
+    let mut pubkey = bytes_to_extended(*pk)?;
+    jubjub_randomized_pk(&mut pubkey, *alpha)?;
+    Ok(extended_to_bytes(&pubkey))
+}
+
+#[inline(never)]
+pub fn sign(key: &[u8; 32], msg: &[u8; 32]) -> Result<Signature, ParserError> {
+    let sk = Secret::new(bytes_to_scalar(key)?);
+    Ok(Signature(sign_complete(msg, &sk)))
+}
+
+/// Message of a spend authorization signature: Sapling prefixes the sighash
+/// with the validating key `rk`
+pub fn spend_auth_message(rk: &[u8; 32], sighash: &[u8; 32]) -> [u8; 64] {
+    let mut msg = [0u8; 64];
+    msg[..32].copy_from_slice(rk);
+    msg[32..].copy_from_slice(sighash);
+    msg
+}
+
+/// Spend authorization signature of `sighash` under `rsk`
+#[inline(never)]
+pub fn sign_spend(rsk: &[u8; 32], sighash: &[u8; 32]) -> Result<Signature, ParserError> {
+    let sk = Secret::new(bytes_to_scalar(rsk)?);
+    let msg = spend_auth_message(&jubjub_sk_to_pk(rsk), sighash);
+    Ok(Signature(sign_complete(&msg, &sk)))
 }
 
+pub const ANTI_EXFIL_COMMIT_PERSONALIZATION: &[u8; 16] = b"Zcash_AntiExfilC";
+const ANTI_EXFIL_TWEAK_TAG: &[u8; 16] = b"Zcash_AntiExfilT";
+
+/// Commitment the host sends before the device reveals `R0`
+pub fn anti_exfil_host_commitment(host_data: &[u8; 32]) -> [u8; 32] {
+    blake2b32_with_personalization(ANTI_EXFIL_COMMIT_PERSONALIZATION, host_data)
+}
+
+#[inline(never)]
+fn anti_exfil_tweak(signer_commitment: &[u8; 32], host_data: &[u8; 32]) -> Fr {
+    let mut b = [0u8; 48];
+    b[..16].copy_from_slice(ANTI_EXFIL_TWEAK_TAG);
+    b[16..].copy_from_slice(host_data);
+    h_star(signer_commitment, &b)
+}
+
+/// Device nonce for one anti-exfiltration signature. The host commits to
+/// `host_data` before the device reveals `R0 = r0 * G`; the device then signs
+/// with `r = r0 + H*(R0 || tag || host_data)` so the host can check `rbar`
+/// without learning anything about `r0`.
+pub struct AntiExfilNonce {
+    r0: Secret<[u8; 32]>,
+    host_commitment: [u8; 32],
+    pub signer_commitment: [u8; 32],
+}
+
+impl AntiExfilNonce {
+    /// Draws `r0` after the host committed to its randomness
+    pub fn new(msg: &[u8], host_commitment: &[u8; 32]) -> Self {
+        let r0 = Secret::new(sign_generate_r(msg).to_bytes());
+        let signer_commitment = sign_compute_rbar(&r0);
+        AntiExfilNonce {
+            r0,
+            host_commitment: *host_commitment,
+            signer_commitment,
+        }
+    }
+
+    /// Signs `msg` once the host revealed `host_data`
+    pub fn sign(
+        self,
+        key: &[u8; 32],
+        msg: &[u8],
+        host_data: &[u8; 32],
+    ) -> Result<Signature, ParserError> {
+        if anti_exfil_host_commitment(host_data) != self.host_commitment {
+            return Err(ParserError::parser_context_mismatch);
+        }
+        let sk = Secret::new(bytes_to_scalar(key)?);
+        let mut r = Secret::new(bytes_to_scalar(&self.r0)?);
+        *r += anti_exfil_tweak(&self.signer_commitment, host_data);
+
+        let rbar = sign_compute_rbar(&Secret::new(r.to_bytes()));
+        let sbar = sign_compute_sbar(msg, &r, &rbar, &sk);
+        let mut sig = [0u8; 64];
+        sig[..32].copy_from_slice(&rbar);
+        sig[32..].copy_from_slice(&sbar);
+        Ok(Signature(sig))
+    }
+}
+
+/// Host-side check that the signature nonce includes `host_data`
+pub fn verify_anti_exfil_nonce(
+    signer_commitment: &[u8; 32],
+    host_data: &[u8; 32],
+    sig: &Signature,
+) -> Result<(), ParserError> {
+    let r0 = bytes_to_extended(*signer_commitment)?;
+    let t = anti_exfil_tweak(signer_commitment, host_data);
+    let expected = r0 + SPENDING_KEY_BASE.multiply_bits(&t.to_bytes());
+    if extended_to_bytes(&expected)[..] == sig.0[..32] {
+        Ok(())
+    } else {
+        Err(ParserError::parser_unexpected_value)
+    }
+}
+
+/// Spend authorization signature of the sighash `msg`, see `sign_spend`
 #[no_mangle]
 pub extern "C" fn sign_redjubjub(
     key_ptr: *const [u8; 32],
     msg_ptr: *const [u8; 32],
     out_ptr: *mut [u8; 64],
-) {
+) -> ParserError {
     c_zemu_log_stack(b"sign_redjubjub\x00".as_ref());
-    let key = unsafe { *key_ptr };
-    let msg = unsafe { *msg_ptr };
+    let key = unsafe { &*key_ptr };
+    let msg = unsafe { &*msg_ptr };
     let output = unsafe { &mut *out_ptr };
-    let sk = Fr::from_bytes(&key).unwrap();
-    output.copy_from_slice(&sign_complete(&msg, &sk));
+    match sign_spend(key, msg) {
+        Ok(sig) => {
+            *output = sig.0;
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
+}
+
+/// Most spend signatures in one transaction, as many as the device's spend list
+pub const ANTI_EXFIL_MAX_SIGNATURES: usize = 5;
+
+/// Anti-exfil nonces for the spend signatures of the transaction being
+/// signed. The `r0`s never leave rslib and each one is consumed by the first
+/// signature that uses it.
+struct AntiExfilTx {
+    host_data: Option<[u8; 32]>,
+    nonces: [Option<AntiExfilNonce>; ANTI_EXFIL_MAX_SIGNATURES],
+}
+
+static ANTI_EXFIL: Slot<AntiExfilTx> = Slot::new();
+
+/// First anti-exfil step: draws one nonce per spend signature against the
+/// host's commitment and writes the `R0`s to `signer_commitments_ptr`
+#[no_mangle]
+pub extern "C" fn anti_exfil_commit(
+    host_commitment_ptr: *const [u8; 32],
+    count: u8,
+    signer_commitments_ptr: *mut [u8; 32 * ANTI_EXFIL_MAX_SIGNATURES],
+) -> ParserError {
+    let host_commitment = unsafe { &*host_commitment_ptr };
+    let out = unsafe { &mut *signer_commitments_ptr };
+    ANTI_EXFIL.take();
+    if count as usize > ANTI_EXFIL_MAX_SIGNATURES {
+        return ParserError::parser_value_out_of_range;
+    }
+
+    let mut tx = AntiExfilTx {
+        host_data: None,
+        nonces: Default::default(),
+    };
+    for (i, slot) in tx.nonces.iter_mut().take(count as usize).enumerate() {
+        let nonce = AntiExfilNonce::new(&[i as u8], host_commitment);
+        out[i * 32..(i + 1) * 32].copy_from_slice(&nonce.signer_commitment);
+        *slot = Some(nonce);
+    }
+    ANTI_EXFIL.put(tx);
+    ParserError::parser_ok
+}
+
+/// Second step: the host opens its commitment before the device signs
+#[no_mangle]
+pub extern "C" fn anti_exfil_reveal(host_data_ptr: *const [u8; 32]) -> ParserError {
+    let host_data = unsafe { &*host_data_ptr };
+    ANTI_EXFIL.with(|tx| match tx {
+        Some(tx) if tx.host_data.is_none() => {
+            let commitment = anti_exfil_host_commitment(host_data);
+            let committed = tx
+                .nonces
+                .iter()
+                .flatten()
+                .all(|nonce| nonce.host_commitment == commitment);
+            if !committed {
+                return ParserError::parser_context_mismatch;
+            }
+            tx.host_data = Some(*host_data);
+            ParserError::parser_ok
+        }
+        _ => ParserError::parser_unexpected_method,
+    })
+}
+
+/// Whether spend signatures are to use the anti-exfil nonces
+#[no_mangle]
+pub extern "C" fn anti_exfil_is_armed() -> bool {
+    ANTI_EXFIL.with(|tx| tx.as_ref().is_some_and(|tx| tx.host_data.is_some()))
+}
+
+/// Spend authorization signature of the sighash `msg` with the nonce drawn for
+/// signature `index`, which cannot be used again
+#[no_mangle]
+pub extern "C" fn sign_redjubjub_anti_exfil(
+    index: u8,
+    key_ptr: *const [u8; 32],
+    msg_ptr: *const [u8; 32],
+    out_ptr: *mut [u8; 64],
+) -> ParserError {
+    c_zemu_log_stack(b"sign_redjubjub_antiexfil\x00".as_ref());
+    let key = unsafe { &*key_ptr };
+    let msg = unsafe { &*msg_ptr };
+    let output = unsafe { &mut *out_ptr };
+    let taken = ANTI_EXFIL.with(|tx| {
+        let tx = tx.as_mut()?;
+        let host_data = tx.host_data?;
+        let nonce = tx.nonces.get_mut(index as usize)?.take()?;
+        Some((nonce, host_data))
+    });
+    let (nonce, host_data) = match taken {
+        Some(taken) => taken,
+        None => return ParserError::parser_no_data,
+    };
+    let msg = spend_auth_message(&jubjub_sk_to_pk(key), msg);
+    match nonce.sign(key, &msg, &host_data) {
+        Ok(sig) => {
+            *output = sig.0;
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
+}
+
+/// Drops any pending nonces, e.g. when the transaction is reset
+#[no_mangle]
+pub extern "C" fn anti_exfil_reset() {
+    ANTI_EXFIL.take();
 }
 
 #[no_mangle]
 pub extern "C" fn random_fr(alpha_ptr: *mut [u8; 32]) {
     c_zemu_log_stack(b"random_fr\x00".as_ref());
     let alpha = unsafe { &mut *alpha_ptr };
-    let fr = random_scalar();
-    alpha.copy_from_slice(&fr.to_bytes());
+    *alpha = random_scalar().to_bytes();
 }
 
 #[no_mangle]
@@ -127,19 +369,17 @@ pub extern "C" fn randomized_secret_from_seed(
     pos: u32,
     alpha_ptr: *const [u8; 32],
     output_ptr: *mut [u8; 32],
-) {
-    c_zemu_log_stack(b"random_sk\x00".as_ref());
-    let mut ask = [0u8;32];
-    let mut nsk = [0u8;32];
+) -> ParserError {
+    let seed = unsafe { SpendingKey::from_ptr(seed_ptr) };
     let alpha = unsafe { &*alpha_ptr };
     let output = unsafe { &mut *output_ptr };
-
-    zip32_child_ask_nsk(seed_ptr,&mut ask, &mut nsk, pos);
-
-    let mut skfr = Fr::from_bytes(&ask).unwrap();
-    let alphafr = Fr::from_bytes(&alpha).unwrap();
-    skfr += alphafr;
-    output.copy_from_slice(&skfr.to_bytes());
+    match randomized_secret_from_sk(seed, pos, alpha) {
+        Ok(rsk) => {
+            *output = *rsk;
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
 }
 
 #[no_mangle]
@@ -147,29 +387,37 @@ pub extern "C" fn get_rk(
     ask_ptr: *const [u8; 32],
     alpha_ptr: *const [u8; 32],
     rk_ptr: *mut [u8; 32],
-) {
-    c_zemu_log_stack(b"random_sk\x00".as_ref());
+) -> ParserError {
+    let ask = unsafe { Ask::from_ptr(ask_ptr) };
     let alpha = unsafe { &*alpha_ptr };
-    let ask = unsafe { &*ask_ptr };
-    let rk = unsafe { &mut *rk_ptr };
-    let mut rsk = [0u8; 32];
-    randomized_secret(ask, alpha, &mut rsk);
-    sk_to_pk(&rsk, rk);
+    let output = unsafe { &mut *rk_ptr };
+    match rk(ask, alpha) {
+        Ok(key) => {
+            *output = key;
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn randomize_pk(alpha_ptr: *const [u8; 32], pk_ptr: *mut [u8; 32]) {
+pub extern "C" fn randomize_pk(alpha_ptr: *const [u8; 32], pk_ptr: *mut [u8; 32]) -> ParserError {
     c_zemu_log_stack(b"random_pk\x00".as_ref());
-    let alpha = unsafe { *alpha_ptr };
+    let alpha = unsafe { &*alpha_ptr };
     let pk = unsafe { &mut *pk_ptr };
-    let mut pubkey = bytes_to_extended(*pk);
-    jubjub_randomized_pk(&mut pubkey, alpha);
-    pk.copy_from_slice(&extended_to_bytes(&pubkey));
+    match randomized_pk(pk, alpha) {
+        Ok(rpk) => {
+            *pk = rpk;
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::secret::harness::wiped_during;
 
     #[test]
     pub fn test_jubjub_nonrandom() {
@@ -249,9 +497,9 @@ mod tests {
             ]
         );
 
-        let mut pk = bytes_to_extended(jubjub_sk_to_pk(&sk));
+        let mut pk = bytes_to_extended(jubjub_sk_to_pk(&sk)).unwrap();
 
-        jubjub_randomized_pk(&mut pk, alpha);
+        jubjub_randomized_pk(&mut pk, alpha).unwrap();
 
         let msg = [
             0x09, 0x09, 0x09, 0x09, 0x09, 0x09, 0x09, 0x09, 0x09, 0x09, 0x09, 0x09, 0x09, 0x09,
@@ -284,4 +532,206 @@ mod tests {
 
         assert_eq!(sbar, sbartest);
     }
+
+    #[test]
+    fn test_rk_matches_randomized_pk() {
+        let ask = Ask([
+            0x85, 0x83, 0x6f, 0x98, 0x32, 0xb2, 0x8d, 0xe7, 0xc6, 0x36, 0x13, 0xe2, 0xa6, 0xed,
+            0x36, 0xfb, 0x1a, 0xb4, 0x4f, 0xb0, 0xc1, 0x3f, 0xa8, 0x79, 0x8c, 0xd9, 0xcd, 0x30,
+            0x30, 0xd4, 0x55, 0x03,
+        ]);
+        let alpha = [
+            0xa2, 0xe8, 0xb9, 0xe1, 0x6d, 0x6f, 0xf3, 0xca, 0x6c, 0x53, 0xd4, 0xe8, 0x8a, 0xbb,
+            0xb9, 0x9b, 0xe7, 0xaf, 0x7e, 0x36, 0x59, 0x63, 0x1f, 0x1e, 0xae, 0x1e, 0xff, 0x23,
+            0x87, 0x4d, 0x8e, 0x0c,
+        ];
+
+        let ak = jubjub_sk_to_pk(&ask.0);
+        assert_eq!(rk(&ask, &alpha), randomized_pk(&ak, &alpha));
+
+        let mut rk_ffi = [0u8; 32];
+        assert_eq!(get_rk(&ask.0, &alpha, &mut rk_ffi), ParserError::parser_ok);
+        assert_eq!(rk_ffi, rk(&ask, &alpha).unwrap());
+    }
+
+    #[test]
+    fn test_invalid_inputs() {
+        let noncanonical = [0xff; 32];
+        let alpha = [0u8; 32];
+        let mut out = [0u8; 32];
+        let mut sig = [0u8; 64];
+
+        assert_eq!(
+            get_rk(&noncanonical, &alpha, &mut out),
+            ParserError::parser_invalid_scalar
+        );
+        assert_eq!(
+            sign_redjubjub(&noncanonical, &alpha, &mut sig),
+            ParserError::parser_invalid_scalar
+        );
+        assert_eq!(
+            randomized_pk(&noncanonical, &alpha),
+            Err(ParserError::parser_invalid_point)
+        );
+    }
+
+    #[test]
+    fn test_secrets_dropped_after_ffi() {
+        let seed = [0u8; 32];
+        let alpha = [1u8; 32];
+        let msg = [2u8; 32];
+        let mut rsk = [0u8; 32];
+        let mut sig = [0u8; 64];
+
+        let n = wiped_during(|| {
+            assert_eq!(
+                randomized_secret_from_seed(&seed, 1000, &alpha, &mut rsk),
+                ParserError::parser_ok
+            );
+        });
+        assert!(n > 0);
+
+        let n = wiped_during(|| {
+            assert_eq!(sign_redjubjub(&rsk, &msg, &mut sig), ParserError::parser_ok);
+        });
+        assert!(n > 0);
+    }
+
+    #[test]
+    fn test_spend_signature_is_key_prefixed() {
+        let ask = Ask([7u8; 32]);
+        let alpha = [3u8; 32];
+        let rsk = randomized_secret(&ask.0, &alpha).unwrap();
+        let rk = rk(&ask, &alpha).unwrap();
+        let sighash = [9u8; 32];
+
+        let mut sig = Signature([0u8; 64]);
+        assert_eq!(
+            sign_redjubjub(&*rsk, &sighash, &mut sig.0),
+            ParserError::parser_ok
+        );
+        assert_eq!(
+            crate::zip304::verify_spend_auth_sig(&rk, &spend_auth_message(&rk, &sighash), &sig),
+            Ok(())
+        );
+        assert_eq!(
+            crate::zip304::verify_spend_auth_sig(&rk, &sighash, &sig),
+            Err(ParserError::parser_unexpected_value)
+        );
+    }
+
+    #[test]
+    fn test_anti_exfil_roundtrip() {
+        let ask = Ask([7u8; 32]);
+        let alpha = [3u8; 32];
+        let rsk = randomized_secret(&ask.0, &alpha).unwrap();
+        let rk = rk(&ask, &alpha).unwrap();
+        let msg = [9u8; 32];
+        let host_data = [0x42u8; 32];
+
+        let commitment = anti_exfil_host_commitment(&host_data);
+        let nonce = AntiExfilNonce::new(&msg, &commitment);
+        let signer_commitment = nonce.signer_commitment;
+        let sig = nonce.sign(&rsk, &msg, &host_data).unwrap();
+
+        assert_eq!(
+            verify_anti_exfil_nonce(&signer_commitment, &host_data, &sig),
+            Ok(())
+        );
+        assert_eq!(
+            crate::zip304::verify_spend_auth_sig(&rk, &msg, &sig),
+            Ok(())
+        );
+
+        // a nonce that ignores the host randomness is detected
+        let plain = sign(&rsk, &msg).unwrap();
+        assert_eq!(
+            verify_anti_exfil_nonce(&signer_commitment, &host_data, &plain),
+            Err(ParserError::parser_unexpected_value)
+        );
+    }
+
+    #[test]
+    fn test_anti_exfil_rejects_changed_host_data() {
+        let key = [5u8; 32];
+        let msg = [1u8; 32];
+        let commitment = anti_exfil_host_commitment(&[0x42u8; 32]);
+        let nonce = AntiExfilNonce::new(&msg, &commitment);
+        assert_eq!(
+            nonce.sign(&key, &msg, &[0x43u8; 32]).err(),
+            Some(ParserError::parser_context_mismatch)
+        );
+    }
+
+    #[test]
+    fn test_anti_exfil_ffi() {
+        let key = [5u8; 32];
+        let msg = [1u8; 32];
+        let host_data = [0x42u8; 32];
+        let commitment = anti_exfil_host_commitment(&host_data);
+        let mut signer_commitments = [0u8; 32 * ANTI_EXFIL_MAX_SIGNATURES];
+        let mut sig = [0u8; 64];
+
+        assert_eq!(
+            anti_exfil_commit(&commitment, 6, &mut signer_commitments),
+            ParserError::parser_value_out_of_range
+        );
+        assert_eq!(
+            anti_exfil_commit(&commitment, 2, &mut signer_commitments),
+            ParserError::parser_ok
+        );
+        assert_ne!(signer_commitments[..32], signer_commitments[32..64]);
+        assert_eq!(signer_commitments[64..], [0u8; 96][..]);
+
+        // nothing is signed before the host opened its commitment
+        assert!(!anti_exfil_is_armed());
+        assert_eq!(
+            sign_redjubjub_anti_exfil(0, &key, &msg, &mut sig),
+            ParserError::parser_no_data
+        );
+        assert_eq!(
+            anti_exfil_reveal(&[0x43u8; 32]),
+            ParserError::parser_context_mismatch
+        );
+        assert_eq!(anti_exfil_reveal(&host_data), ParserError::parser_ok);
+        assert_eq!(
+            anti_exfil_reveal(&host_data),
+            ParserError::parser_unexpected_method
+        );
+        assert!(anti_exfil_is_armed());
+
+        for index in 0..2 {
+            assert_eq!(
+                sign_redjubjub_anti_exfil(index, &key, &msg, &mut sig),
+                ParserError::parser_ok
+            );
+            let mut signer_commitment = [0u8; 32];
+            signer_commitment.copy_from_slice(&signer_commitments[index as usize * 32..][..32]);
+            assert_eq!(
+                verify_anti_exfil_nonce(&signer_commitment, &host_data, &Signature(sig)),
+                Ok(())
+            );
+            let rk = jubjub_sk_to_pk(&key);
+            assert_eq!(
+                crate::zip304::verify_spend_auth_sig(
+                    &rk,
+                    &spend_auth_message(&rk, &msg),
+                    &Signature(sig)
+                ),
+                Ok(())
+            );
+            // each nonce signs once
+            assert_eq!(
+                sign_redjubjub_anti_exfil(index, &key, &msg, &mut sig),
+                ParserError::parser_no_data
+            );
+        }
+        assert_eq!(
+            sign_redjubjub_anti_exfil(2, &key, &msg, &mut sig),
+            ParserError::parser_no_data
+        );
+
+        anti_exfil_reset();
+        assert!(!anti_exfil_is_armed());
+    }
 }
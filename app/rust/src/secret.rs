@@ -0,0 +1,176 @@
+//! Containers for secret key material that is wiped when dropped.
+//!
+//! Intermediate spending keys, chain codes and scalars produced during
+//! derivation and signing are kept in a `Secret` so that they do not linger
+//! on the stack once the FFI call returns.
+
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+use jubjub::Fr;
+
+use crate::types::{Ask, Nsk, SpendingKey};
+
+/// Types whose memory can be overwritten with zeroes
+pub trait Zeroize {
+    fn zeroize(&mut self);
+}
+
+impl<const N: usize> Zeroize for [u8; N] {
+    #[inline(never)]
+    fn zeroize(&mut self) {
+        for b in self.iter_mut() {
+            unsafe { ptr::write_volatile(b, 0) };
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+impl Zeroize for Fr {
+    #[inline(never)]
+    fn zeroize(&mut self) {
+        unsafe { ptr::write_volatile(self, Fr::zero()) };
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+macro_rules! zeroize_newtype {
+    ($($name:ident),*) => {
+        $(
+            impl Zeroize for $name {
+                fn zeroize(&mut self) {
+                    self.0.zeroize();
+                }
+            }
+        )*
+    };
+}
+
+zeroize_newtype!(SpendingKey, Ask, Nsk);
+
+/// Owns a secret value and wipes it on drop
+pub struct Secret<T: Zeroize>(T);
+
+impl<T: Zeroize> Secret<T> {
+    pub fn new(value: T) -> Self {
+        Secret(value)
+    }
+}
+
+impl<T: Zeroize> Deref for Secret<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: Zeroize> DerefMut for Secret<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Zeroize> Drop for Secret<T> {
+    fn drop(&mut self) {
+        self.0.zeroize();
+        #[cfg(test)]
+        harness::record(&self.0);
+    }
+}
+
+impl<T: Zeroize> fmt::Debug for Secret<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+/// Test harness checking the `Secret`s dropped on the current thread.
+///
+/// Every drop reads the storage of the secret back through a pointer once it
+/// has been wiped, so a test can assert that an FFI call kept its
+/// intermediates in `Secret`s and that none of them was left in memory.
+#[cfg(test)]
+pub mod harness {
+    use core::cell::Cell;
+    use core::mem::size_of;
+    use core::ptr;
+
+    std::thread_local! {
+        static DROPPED: Cell<usize> = const { Cell::new(0) };
+        static NOT_WIPED: Cell<usize> = const { Cell::new(0) };
+    }
+
+    pub(super) fn record<T>(wiped: &T) {
+        let p = wiped as *const T as *const u8;
+        let clear = (0..size_of::<T>()).all(|i| unsafe { ptr::read_volatile(p.add(i)) } == 0);
+        DROPPED.with(|c| c.set(c.get() + 1));
+        if !clear {
+            NOT_WIPED.with(|c| c.set(c.get() + 1));
+        }
+    }
+
+    /// Runs `f` and returns how many secrets it dropped
+    pub fn dropped_during<F: FnOnce()>(f: F) -> usize {
+        let before = DROPPED.with(|c| c.get());
+        f();
+        DROPPED.with(|c| c.get()) - before
+    }
+
+    /// Runs `f` and returns how many secrets it dropped, panicking if any of
+    /// them still held a non-zero byte after its wipe
+    pub fn wiped_during<F: FnOnce()>(f: F) -> usize {
+        let before = NOT_WIPED.with(|c| c.get());
+        let n = dropped_during(f);
+        let not_wiped = NOT_WIPED.with(|c| c.get()) - before;
+        assert_eq!(not_wiped, 0, "{} of {} secrets not wiped", not_wiped, n);
+        n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::mem::MaybeUninit;
+
+    use super::*;
+
+    #[test]
+    fn test_secret_wiped_on_drop() {
+        let mut slot = MaybeUninit::new(Secret::new([0xaa_u8; 64]));
+        unsafe { ptr::drop_in_place(slot.as_mut_ptr()) };
+        let bytes = unsafe { &*(slot.as_ptr() as *const [u8; 64]) };
+        assert_eq!(bytes[..], [0u8; 64][..]);
+
+        let mut slot = MaybeUninit::new(Secret::new(Fr::one()));
+        unsafe { ptr::drop_in_place(slot.as_mut_ptr()) };
+        let fr = unsafe { &*(slot.as_ptr() as *const Fr) };
+        assert_eq!(*fr, Fr::zero());
+    }
+
+    #[test]
+    fn test_harness_counts_drops() {
+        let n = harness::wiped_during(|| {
+            let _a = Secret::new([1u8; 32]);
+            let _b = Secret::new(Ask([2u8; 32]));
+            let _c = Secret::new(Fr::one());
+        });
+        assert_eq!(n, 3);
+    }
+
+    struct Leaky([u8; 32]);
+
+    impl Zeroize for Leaky {
+        fn zeroize(&mut self) {}
+    }
+
+    #[test]
+    #[should_panic(expected = "1 of 2 secrets not wiped")]
+    fn test_harness_reads_back_the_wiped_memory() {
+        harness::wiped_during(|| {
+            let _a = Secret::new([1u8; 32]);
+            let _b = Secret::new(Leaky([2u8; 32]));
+        });
+    }
+}
@@ -0,0 +1,503 @@
+//! Authenticated encrypted channel between host and device.
+//!
+//! Both sides generate an ephemeral key on `SESSION_KEY_BASE` and run
+//! `session_key` on the peer's public key; small-order peer keys are refused
+//! since the agreement clears the cofactor. The device signs the transcript
+//! (both ephemeral keys and its identity key) with a long-term identity key
+//! derived from its seed, which the host pins on first use. The shared
+//! secret and the transcript are split into one ChaCha20-Poly1305 key per
+//! direction. Every frame carries an explicit 64-bit counter, which forms
+//! the nonce and must strictly increase, so replayed or reordered frames are
+//! rejected.
+//!
+//! Frame layout: `counter (8, LE) || ciphertext || tag (16)`.
+
+use chacha20poly1305::aead::{AeadInPlace, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, Tag};
+use jubjub::Fr;
+
+use crate::bolos::blake2b32_with_personalization;
+use crate::commitments::bytes_to_extended;
+use crate::errors::ParserError;
+use crate::redjubjub::{jubjub_sk_to_pk, random_scalar, sign_complete};
+use crate::secret::Secret;
+use crate::state::Slot;
+use crate::types::{Signature, SpendingKey};
+use crate::zeccrypto::{session_key, session_pubkey};
+use crate::zip304::verify_spend_auth_sig;
+use crate::zip32::prf_expand;
+
+pub const COUNTER_SIZE: usize = 8;
+pub const TAG_SIZE: usize = 16;
+pub const FRAME_OVERHEAD: usize = COUNTER_SIZE + TAG_SIZE;
+/// `identity || device ephemeral key || transcript signature`
+pub const SESSION_OPEN_REPLY_SIZE: usize = 32 + 32 + 64;
+
+const SESSION_DIR_PERSONALIZATION: &[u8; 16] = b"Zcash_SessionDir";
+const SESSION_TRANSCRIPT_PERSONALIZATION: &[u8; 16] = b"Zcash_SessionTrn";
+/// `PRF^expand` input of the identity key, apart from the one-byte Sapling
+/// and ZIP 32 domains
+const SESSION_IDENTITY_TAG: &[u8] = b"Zcash_SessionIdentity";
+const DIR_DEVICE_TO_HOST: u8 = 0x01;
+const DIR_HOST_TO_DEVICE: u8 = 0x02;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Role {
+    Device,
+    Host,
+}
+
+/// Ephemeral key pair for one session
+pub struct EphemeralKey {
+    scalar: Secret<[u8; 32]>,
+    pub public: [u8; 32],
+}
+
+impl EphemeralKey {
+    pub fn generate() -> Self {
+        let scalar = Secret::new(random_scalar().to_bytes());
+        let public = session_pubkey(&scalar);
+        EphemeralKey { scalar, public }
+    }
+}
+
+/// Long-term device key signing session transcripts
+pub struct Identity {
+    key: Secret<Fr>,
+    pub public: [u8; 32],
+}
+
+impl Identity {
+    pub fn from_seed(sk: &SpendingKey) -> Self {
+        let key = Secret::new(Fr::from_bytes_wide(&prf_expand(
+            sk.as_bytes(),
+            SESSION_IDENTITY_TAG,
+        )));
+        let public = jubjub_sk_to_pk(&Secret::new(key.to_bytes()));
+        Identity { key, public }
+    }
+}
+
+/// Rejects keys that do not decode and keys of small order, which would let
+/// the peer force a shared secret it can guess
+fn check_peer_public(public: &[u8; 32]) -> Result<(), ParserError> {
+    let point = bytes_to_extended(*public)?;
+    if bool::from(point.is_small_order()) {
+        return Err(ParserError::parser_invalid_point);
+    }
+    Ok(())
+}
+
+fn transcript(device_public: &[u8; 32], host_public: &[u8; 32], identity: &[u8; 32]) -> [u8; 32] {
+    let mut input = [0u8; 96];
+    input[..32].copy_from_slice(device_public);
+    input[32..64].copy_from_slice(host_public);
+    input[64..].copy_from_slice(identity);
+    blake2b32_with_personalization(SESSION_TRANSCRIPT_PERSONALIZATION, &input)
+}
+
+fn direction_key(shared: &[u8; 32], transcript: &[u8; 32], direction: u8) -> Secret<[u8; 32]> {
+    let mut input = Secret::new([0u8; 65]);
+    input[..32].copy_from_slice(shared);
+    input[32..64].copy_from_slice(transcript);
+    input[64] = direction;
+    Secret::new(blake2b32_with_personalization(
+        SESSION_DIR_PERSONALIZATION,
+        &input[..],
+    ))
+}
+
+fn nonce(direction: u8, counter: u64) -> [u8; 12] {
+    let mut nonce = [0u8; 12];
+    nonce[0] = direction;
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    nonce
+}
+
+pub struct Session {
+    role: Role,
+    send_key: Secret<[u8; 32]>,
+    recv_key: Secret<[u8; 32]>,
+    send_counter: u64,
+    /// Lowest counter still accepted from the peer
+    recv_counter: u64,
+}
+
+impl Session {
+    /// Device side: completes the key exchange with the host's ephemeral key
+    /// and signs the transcript with the device identity
+    pub fn accept(
+        identity: &Identity,
+        key: EphemeralKey,
+        host_public: &[u8; 32],
+    ) -> Result<(Self, Signature), ParserError> {
+        let transcript = transcript(&key.public, host_public, &identity.public);
+        let session = Self::establish(Role::Device, &key, host_public, &transcript)?;
+        let signature = Signature(sign_complete(&transcript, &identity.key));
+        Ok((session, signature))
+    }
+
+    /// Host side: completes the key exchange once the transcript signature
+    /// checks out against the device identity the host pinned
+    pub fn connect(
+        key: EphemeralKey,
+        device_public: &[u8; 32],
+        device_identity: &[u8; 32],
+        signature: &Signature,
+    ) -> Result<Self, ParserError> {
+        let transcript = transcript(device_public, &key.public, device_identity);
+        verify_spend_auth_sig(device_identity, &transcript, signature)?;
+        Self::establish(Role::Host, &key, device_public, &transcript)
+    }
+
+    fn establish(
+        role: Role,
+        key: &EphemeralKey,
+        peer_public: &[u8; 32],
+        transcript: &[u8; 32],
+    ) -> Result<Self, ParserError> {
+        check_peer_public(peer_public)?;
+        let shared = Secret::new(session_key(&key.scalar, peer_public)?);
+        let d2h = direction_key(&shared, transcript, DIR_DEVICE_TO_HOST);
+        let h2d = direction_key(&shared, transcript, DIR_HOST_TO_DEVICE);
+        let (send_key, recv_key) = match role {
+            Role::Device => (d2h, h2d),
+            Role::Host => (h2d, d2h),
+        };
+        Ok(Session {
+            role,
+            send_key,
+            recv_key,
+            send_counter: 0,
+            recv_counter: 0,
+        })
+    }
+
+    fn send_direction(&self) -> u8 {
+        match self.role {
+            Role::Device => DIR_DEVICE_TO_HOST,
+            Role::Host => DIR_HOST_TO_DEVICE,
+        }
+    }
+
+    fn recv_direction(&self) -> u8 {
+        match self.role {
+            Role::Device => DIR_HOST_TO_DEVICE,
+            Role::Host => DIR_DEVICE_TO_HOST,
+        }
+    }
+
+    /// Encrypts `payload` into `out`, returning the frame length
+    pub fn seal(&mut self, payload: &[u8], out: &mut [u8]) -> Result<usize, ParserError> {
+        if out.len() < payload.len() + FRAME_OVERHEAD {
+            return Err(ParserError::parser_unexpected_buffer_end);
+        }
+        out[COUNTER_SIZE..COUNTER_SIZE + payload.len()].copy_from_slice(payload);
+        self.seal_body(out, payload.len())
+    }
+
+    /// Encrypts the first `payload_len` bytes of `buffer` into a frame in the
+    /// same buffer, returning the frame length
+    pub fn seal_in_place(
+        &mut self,
+        buffer: &mut [u8],
+        payload_len: usize,
+    ) -> Result<usize, ParserError> {
+        if buffer.len() < payload_len + FRAME_OVERHEAD {
+            return Err(ParserError::parser_unexpected_buffer_end);
+        }
+        buffer.copy_within(..payload_len, COUNTER_SIZE);
+        self.seal_body(buffer, payload_len)
+    }
+
+    fn seal_body(&mut self, out: &mut [u8], payload_len: usize) -> Result<usize, ParserError> {
+        let len = payload_len + FRAME_OVERHEAD;
+        let counter = self.send_counter;
+        self.send_counter = counter
+            .checked_add(1)
+            .ok_or(ParserError::parser_value_out_of_range)?;
+
+        let counter_bytes = counter.to_le_bytes();
+        out[..COUNTER_SIZE].copy_from_slice(&counter_bytes);
+        let body = &mut out[COUNTER_SIZE..COUNTER_SIZE + payload_len];
+
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&self.send_key[..]));
+        let nonce = nonce(self.send_direction(), counter);
+        let tag = cipher
+            .encrypt_in_place_detached(Nonce::from_slice(&nonce), &counter_bytes, body)
+            .map_err(|_| ParserError::parser_unexpected_error)?;
+        out[COUNTER_SIZE + payload_len..len].copy_from_slice(&tag);
+        Ok(len)
+    }
+
+    /// Authenticates and decrypts `frame` in place, returning the payload.
+    /// Frames with a counter at or below the last accepted one are rejected.
+    pub fn open<'a>(&mut self, frame: &'a mut [u8]) -> Result<&'a [u8], ParserError> {
+        if frame.len() < FRAME_OVERHEAD {
+            return Err(ParserError::parser_unexpected_buffer_end);
+        }
+        let mut counter_bytes = [0u8; COUNTER_SIZE];
+        counter_bytes.copy_from_slice(&frame[..COUNTER_SIZE]);
+        let counter = u64::from_le_bytes(counter_bytes);
+        if counter < self.recv_counter {
+            return Err(ParserError::parser_context_mismatch);
+        }
+
+        let body_end = frame.len() - TAG_SIZE;
+        let (head, tag) = frame.split_at_mut(body_end);
+        let body = &mut head[COUNTER_SIZE..];
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(&self.recv_key[..]));
+        let nonce = nonce(self.recv_direction(), counter);
+        cipher
+            .decrypt_in_place_detached(
+                Nonce::from_slice(&nonce),
+                &counter_bytes,
+                body,
+                Tag::from_slice(tag),
+            )
+            .map_err(|_| ParserError::parser_unexpected_value)?;
+
+        self.recv_counter = counter
+            .checked_add(1)
+            .ok_or(ParserError::parser_value_out_of_range)?;
+        Ok(&frame[COUNTER_SIZE..body_end])
+    }
+}
+
+/// Session opened by the host with `INS_KEY_EXCHANGE`; its keys stay here
+static SESSION: Slot<Session> = Slot::new();
+
+/// Opens a new session with the host's ephemeral key, replacing any previous
+/// one. Writes `identity || device ephemeral key || signature` to `out_ptr`.
+#[no_mangle]
+pub extern "C" fn session_open(
+    sk_ptr: *const [u8; 32],
+    host_public_ptr: *const [u8; 32],
+    out_ptr: *mut [u8; SESSION_OPEN_REPLY_SIZE],
+) -> ParserError {
+    let sk = unsafe { SpendingKey::from_ptr(sk_ptr) };
+    let host_public = unsafe { &*host_public_ptr };
+    let out = unsafe { &mut *out_ptr };
+    SESSION.take();
+
+    let identity = Identity::from_seed(sk);
+    let key = EphemeralKey::generate();
+    let device_public = key.public;
+    match Session::accept(&identity, key, host_public) {
+        Ok((session, signature)) => {
+            SESSION.put(session);
+            out[..32].copy_from_slice(&identity.public);
+            out[32..64].copy_from_slice(&device_public);
+            out[64..].copy_from_slice(&signature.0);
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn session_is_active() -> bool {
+    SESSION.is_some()
+}
+
+/// Seals the first `payload_len` bytes of `buffer` in place for the host
+#[no_mangle]
+pub extern "C" fn session_seal(
+    buffer_ptr: *mut u8,
+    buffer_len: u16,
+    payload_len: u16,
+    frame_len_ptr: *mut u16,
+) -> ParserError {
+    let buffer = unsafe { core::slice::from_raw_parts_mut(buffer_ptr, buffer_len as usize) };
+    let frame_len = unsafe { &mut *frame_len_ptr };
+    SESSION.with(|session| match session {
+        Some(session) => session
+            .seal_in_place(buffer, payload_len as usize)
+            .map(|len| {
+                *frame_len = len as u16;
+                ParserError::parser_ok
+            })
+            .unwrap_or_else(|e| e),
+        None => ParserError::parser_no_data,
+    })
+}
+
+/// Drops the session keys, e.g. when the app goes back to idle
+#[no_mangle]
+pub extern "C" fn session_close() {
+    SESSION.take();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity() -> Identity {
+        Identity::from_seed(&SpendingKey([7u8; 32]))
+    }
+
+    fn pair() -> (Session, Session) {
+        let identity = identity();
+        let device_key = EphemeralKey::generate();
+        let host_key = EphemeralKey::generate();
+        let device_pub = device_key.public;
+        let host_pub = host_key.public;
+        let (device, signature) = Session::accept(&identity, device_key, &host_pub).unwrap();
+        let host = Session::connect(host_key, &device_pub, &identity.public, &signature).unwrap();
+        (device, host)
+    }
+
+    #[test]
+    fn test_roundtrip_both_directions() {
+        let (mut device, mut host) = pair();
+        let mut frame = [0u8; 64];
+
+        let len = device.seal(b"ivk bytes", &mut frame).unwrap();
+        assert_eq!(len, 9 + FRAME_OVERHEAD);
+        assert_ne!(&frame[COUNTER_SIZE..COUNTER_SIZE + 9], b"ivk bytes");
+        assert_eq!(host.open(&mut frame[..len]).unwrap(), b"ivk bytes");
+
+        let len = host.seal(b"ack", &mut frame).unwrap();
+        assert_eq!(device.open(&mut frame[..len]).unwrap(), b"ack");
+    }
+
+    #[test]
+    fn test_replay_and_tampering_rejected() {
+        let (mut device, mut host) = pair();
+        let mut first = [0u8; 40];
+        let mut second = [0u8; 40];
+        let len1 = device.seal(b"nullifier", &mut first).unwrap();
+        let len2 = device.seal(b"alpha", &mut second).unwrap();
+        let mut replay = first;
+        let mut again = second;
+
+        let mut tampered = second;
+        tampered[COUNTER_SIZE] ^= 1;
+        assert_eq!(
+            host.open(&mut tampered[..len2]).err(),
+            Some(ParserError::parser_unexpected_value)
+        );
+
+        assert_eq!(host.open(&mut second[..len2]).unwrap(), b"alpha");
+        // older frames are refused once a newer one was accepted
+        assert_eq!(
+            host.open(&mut replay[..len1]).err(),
+            Some(ParserError::parser_context_mismatch)
+        );
+        assert_eq!(
+            host.open(&mut again[..len2]).err(),
+            Some(ParserError::parser_context_mismatch)
+        );
+    }
+
+    #[test]
+    fn test_direction_keys_differ() {
+        let (mut device, _) = pair();
+        let mut frame = [0u8; 32];
+        let len = device.seal(b"loop", &mut frame).unwrap();
+        // a frame cannot be reflected back to its sender
+        assert!(device.open(&mut frame[..len]).is_err());
+    }
+
+    #[test]
+    fn test_small_order_peer_keys_rejected() {
+        // the identity (0, 1) and the order-2 point (0, -1)
+        let identity_point = {
+            let mut p = [0u8; 32];
+            p[0] = 1;
+            p
+        };
+        let order_two =
+            jubjub::AffinePoint::from_raw_unchecked(jubjub::Fq::zero(), -jubjub::Fq::one())
+                .to_bytes();
+
+        for bad in [identity_point, order_two].iter() {
+            assert_eq!(
+                Session::accept(&identity(), EphemeralKey::generate(), bad).err(),
+                Some(ParserError::parser_invalid_point)
+            );
+        }
+    }
+
+    #[test]
+    fn test_transcript_authenticated() {
+        let identity = identity();
+        let device_key = EphemeralKey::generate();
+        let device_pub = device_key.public;
+        let host_pub = EphemeralKey::generate().public;
+        let (_, signature) = Session::accept(&identity, device_key, &host_pub).unwrap();
+
+        // a man in the middle answering with its own key cannot reuse the
+        // device's signature, which covers the host's key as well
+        let attacker = EphemeralKey::generate();
+        assert!(Session::connect(
+            EphemeralKey::generate(),
+            &attacker.public,
+            &identity.public,
+            &signature
+        )
+        .is_err());
+        assert!(Session::connect(
+            EphemeralKey::generate(),
+            &device_pub,
+            &identity.public,
+            &signature
+        )
+        .is_err());
+
+        // nor sign for the pinned identity with another key
+        let other = Identity::from_seed(&SpendingKey([8u8; 32]));
+        let host_key = EphemeralKey::generate();
+        let host_pub = host_key.public;
+        let device_key = EphemeralKey::generate();
+        let device_pub = device_key.public;
+        let (_, signature) = Session::accept(&other, device_key, &host_pub).unwrap();
+        assert!(Session::connect(host_key, &device_pub, &identity.public, &signature).is_err());
+    }
+
+    #[test]
+    fn test_session_ffi() {
+        let sk = [7u8; 32];
+        let host_key = EphemeralKey::generate();
+        let mut reply = [0u8; SESSION_OPEN_REPLY_SIZE];
+        assert_eq!(
+            session_open(&sk, &host_key.public, &mut reply),
+            ParserError::parser_ok
+        );
+        assert!(session_is_active());
+
+        let mut device_identity = [0u8; 32];
+        let mut device_pub = [0u8; 32];
+        let mut signature = [0u8; 64];
+        device_identity.copy_from_slice(&reply[..32]);
+        device_pub.copy_from_slice(&reply[32..64]);
+        signature.copy_from_slice(&reply[64..]);
+        assert_eq!(device_identity, identity().public);
+        let mut host = Session::connect(
+            host_key,
+            &device_pub,
+            &device_identity,
+            &Signature(signature),
+        )
+        .unwrap();
+
+        let mut buffer = [0u8; 64];
+        buffer[..9].copy_from_slice(b"ivk bytes");
+        let mut frame_len = 0u16;
+        assert_eq!(
+            session_seal(buffer.as_mut_ptr(), 64, 9, &mut frame_len),
+            ParserError::parser_ok
+        );
+        assert_eq!(
+            host.open(&mut buffer[..frame_len as usize]).unwrap(),
+            b"ivk bytes"
+        );
+
+        session_close();
+        assert!(!session_is_active());
+        assert_eq!(
+            session_seal(buffer.as_mut_ptr(), 64, 9, &mut frame_len),
+            ParserError::parser_no_data
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! Values rslib keeps between two FFI calls.
+//!
+//! Secrets such as session keys and signing nonces never cross the FFI
+//! boundary; the C side only asks rslib to use them. The app handles one
+//! APDU at a time on a single thread, so on the device a slot is a plain
+//! cell. Host builds may call in from several threads and use a mutex.
+
+#[cfg(not(any(test, feature = "std")))]
+use core::cell::UnsafeCell;
+
+/// Holds at most one `T` across FFI calls
+#[cfg(not(any(test, feature = "std")))]
+pub struct Slot<T>(UnsafeCell<Option<T>>);
+
+// The device runs a single thread and `with` is never re-entered
+#[cfg(not(any(test, feature = "std")))]
+unsafe impl<T> Sync for Slot<T> {}
+
+#[cfg(not(any(test, feature = "std")))]
+impl<T> Slot<T> {
+    pub const fn new() -> Self {
+        Slot(UnsafeCell::new(None))
+    }
+
+    /// Runs `f` on the slot's content. `f` must not use the same slot.
+    pub fn with<R, F: FnOnce(&mut Option<T>) -> R>(&self, f: F) -> R {
+        f(unsafe { &mut *self.0.get() })
+    }
+}
+
+/// Holds at most one `T` across FFI calls
+#[cfg(any(test, feature = "std"))]
+pub struct Slot<T>(std::sync::Mutex<Option<T>>);
+
+#[cfg(any(test, feature = "std"))]
+impl<T> Slot<T> {
+    pub const fn new() -> Self {
+        Slot(std::sync::Mutex::new(None))
+    }
+
+    /// Runs `f` on the slot's content. `f` must not use the same slot.
+    pub fn with<R, F: FnOnce(&mut Option<T>) -> R>(&self, f: F) -> R {
+        let mut guard = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        f(&mut guard)
+    }
+}
+
+impl<T> Default for Slot<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Slot<T> {
+    /// Stores `value`, dropping what the slot held before
+    pub fn put(&self, value: T) {
+        self.with(|slot| *slot = Some(value));
+    }
+
+    /// Empties the slot, so its value can only be used once
+    pub fn take(&self) -> Option<T> {
+        self.with(Option::take)
+    }
+
+    pub fn is_some(&self) -> bool {
+        self.with(|slot| slot.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_slot_value_used_once() {
+        let slot = Slot::new();
+        assert!(!slot.is_some());
+        slot.put(1u32);
+        slot.put(2u32);
+        assert!(slot.is_some());
+        assert_eq!(slot.with(|v| v.map(|v| v + 1)), Some(3));
+        assert_eq!(slot.take(), Some(2));
+        assert_eq!(slot.take(), None);
+    }
+}
@@ -0,0 +1,573 @@
+//! Zero-allocation parser for raw Zcash transactions up to v4 (Sapling).
+//!
+//! Variable-length sections keep a reference to their raw bytes and are
+//! decoded on demand through iterators, so parsing never allocates.
+
+use core::convert::TryInto;
+use core::marker::PhantomData;
+
+use nom::bytes::complete::take;
+use nom::error::ErrorKind;
+use nom::number::complete::{le_i64, le_u16, le_u32, le_u64, le_u8};
+use nom::IResult;
+
+use crate::errors::ParserError;
+
+pub const OVERWINTER_VERSION_GROUP_ID: u32 = 0x03C4_8270;
+pub const SAPLING_VERSION_GROUP_ID: u32 = 0x892F_2085;
+
+const OVERWINTER_FLAG: u32 = 1 << 31;
+const TX_EXPIRY_HEIGHT_THRESHOLD: u32 = 500_000_000;
+const MAX_MONEY: i64 = 21_000_000 * 100_000_000;
+
+const BCTV14_PROOF_SIZE: usize = 296;
+const GROTH16_PROOF_SIZE: usize = 192;
+const JOINSPLIT_CIPHERTEXTS_SIZE: usize = 2 * 601;
+const ENC_CIPHERTEXT_SIZE: usize = 580;
+const OUT_CIPHERTEXT_SIZE: usize = 80;
+
+fn bytes<const N: usize>(input: &[u8]) -> IResult<&[u8], &[u8; N]> {
+    let (rem, raw) = take(N)(input)?;
+    Ok((rem, raw.try_into().unwrap()))
+}
+
+/// Bitcoin-style CompactSize, rejecting non-canonical encodings
+fn compact_size(input: &[u8]) -> IResult<&[u8], u64> {
+    let (rem, tag) = le_u8(input)?;
+    let (rem, value, min) = match tag {
+        0xfd => le_u16(rem).map(|(r, v)| (r, v as u64, 0xfd))?,
+        0xfe => le_u32(rem).map(|(r, v)| (r, v as u64, 0x1_0000))?,
+        0xff => le_u64(rem).map(|(r, v)| (r, v, 0x1_0000_0000))?,
+        _ => return Ok((rem, tag as u64)),
+    };
+    if value < min {
+        return Err(nom::Err::Error((input, ErrorKind::Verify)));
+    }
+    Ok((rem, value))
+}
+
+fn var_bytes(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    let (rem, len) = compact_size(input)?;
+    let len: usize = len
+        .try_into()
+        .map_err(|_| nom::Err::Error((input, ErrorKind::TooLarge)))?;
+    take(len)(rem)
+}
+
+/// Bounded output buffer used to serialize a parsed transaction
+pub struct ByteWriter<'b> {
+    buf: &'b mut [u8],
+    offset: usize,
+}
+
+impl<'b> ByteWriter<'b> {
+    pub fn new(buf: &'b mut [u8]) -> Self {
+        ByteWriter { buf, offset: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.offset
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offset == 0
+    }
+
+    pub fn put(&mut self, data: &[u8]) -> Result<(), ParserError> {
+        let end = self.offset + data.len();
+        if end > self.buf.len() {
+            return Err(ParserError::parser_unexpected_buffer_end);
+        }
+        self.buf[self.offset..end].copy_from_slice(data);
+        self.offset = end;
+        Ok(())
+    }
+
+    pub fn put_u32(&mut self, value: u32) -> Result<(), ParserError> {
+        self.put(&value.to_le_bytes())
+    }
+
+    pub fn put_u64(&mut self, value: u64) -> Result<(), ParserError> {
+        self.put(&value.to_le_bytes())
+    }
+
+    pub fn put_compact_size(&mut self, value: u64) -> Result<(), ParserError> {
+        match value {
+            0..=0xfc => self.put(&[value as u8]),
+            0xfd..=0xffff => {
+                self.put(&[0xfd])?;
+                self.put(&(value as u16).to_le_bytes())
+            }
+            0x1_0000..=0xffff_ffff => {
+                self.put(&[0xfe])?;
+                self.put_u32(value as u32)
+            }
+            _ => {
+                self.put(&[0xff])?;
+                self.put_u64(value)
+            }
+        }
+    }
+
+    pub fn put_var_bytes(&mut self, data: &[u8]) -> Result<(), ParserError> {
+        self.put_compact_size(data.len() as u64)?;
+        self.put(data)
+    }
+}
+
+/// Element of a length-prefixed list within a transaction
+pub trait Item<'a>: Sized {
+    fn parse(input: &'a [u8], version: u32) -> IResult<&'a [u8], Self>;
+
+    fn write(&self, w: &mut ByteWriter) -> Result<(), ParserError>;
+}
+
+/// Length-prefixed list that is validated once and decoded lazily
+pub struct Items<'a, T> {
+    count: usize,
+    version: u32,
+    raw: &'a [u8],
+    _item: PhantomData<T>,
+}
+
+impl<'a, T: Item<'a>> Items<'a, T> {
+    fn empty(version: u32) -> Self {
+        Items {
+            count: 0,
+            version,
+            raw: &[],
+            _item: PhantomData,
+        }
+    }
+
+    fn parse(input: &'a [u8], version: u32) -> IResult<&'a [u8], Self> {
+        let (mut rem, count) = compact_size(input)?;
+        let start = rem;
+        let mut n = 0usize;
+        while (n as u64) < count {
+            rem = T::parse(rem, version)?.0;
+            n += 1;
+        }
+        let raw = &start[..start.len() - rem.len()];
+        Ok((
+            rem,
+            Items {
+                count: n,
+                version,
+                raw,
+                _item: PhantomData,
+            },
+        ))
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    pub fn iter(&self) -> ItemsIter<'a, T> {
+        ItemsIter {
+            remaining: self.count,
+            version: self.version,
+            input: self.raw,
+            _item: PhantomData,
+        }
+    }
+
+    fn write(&self, w: &mut ByteWriter) -> Result<(), ParserError> {
+        w.put_compact_size(self.count as u64)?;
+        for item in self.iter() {
+            item.write(w)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct ItemsIter<'a, T> {
+    remaining: usize,
+    version: u32,
+    input: &'a [u8],
+    _item: PhantomData<T>,
+}
+
+impl<'a, T: Item<'a>> Iterator for ItemsIter<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.remaining == 0 {
+            return None;
+        }
+        let (rem, item) = T::parse(self.input, self.version).ok()?;
+        self.input = rem;
+        self.remaining -= 1;
+        Some(item)
+    }
+}
+
+pub struct TxIn<'a> {
+    pub prevout_hash: &'a [u8; 32],
+    pub prevout_index: u32,
+    pub script: &'a [u8],
+    pub sequence: u32,
+}
+
+impl<'a> Item<'a> for TxIn<'a> {
+    fn parse(input: &'a [u8], _version: u32) -> IResult<&'a [u8], Self> {
+        let (rem, prevout_hash) = bytes::<32>(input)?;
+        let (rem, prevout_index) = le_u32(rem)?;
+        let (rem, script) = var_bytes(rem)?;
+        let (rem, sequence) = le_u32(rem)?;
+        Ok((
+            rem,
+            TxIn {
+                prevout_hash,
+                prevout_index,
+                script,
+                sequence,
+            },
+        ))
+    }
+
+    fn write(&self, w: &mut ByteWriter) -> Result<(), ParserError> {
+        w.put(self.prevout_hash)?;
+        w.put_u32(self.prevout_index)?;
+        w.put_var_bytes(self.script)?;
+        w.put_u32(self.sequence)
+    }
+}
+
+pub struct TxOut<'a> {
+    pub value: u64,
+    pub script: &'a [u8],
+}
+
+impl<'a> Item<'a> for TxOut<'a> {
+    fn parse(input: &'a [u8], _version: u32) -> IResult<&'a [u8], Self> {
+        let (rem, value) = le_u64(input)?;
+        let (rem, script) = var_bytes(rem)?;
+        Ok((rem, TxOut { value, script }))
+    }
+
+    fn write(&self, w: &mut ByteWriter) -> Result<(), ParserError> {
+        w.put_u64(self.value)?;
+        w.put_var_bytes(self.script)
+    }
+}
+
+pub struct SpendDescription<'a> {
+    pub cv: &'a [u8; 32],
+    pub anchor: &'a [u8; 32],
+    pub nullifier: &'a [u8; 32],
+    pub rk: &'a [u8; 32],
+    pub zkproof: &'a [u8; GROTH16_PROOF_SIZE],
+    pub spend_auth_sig: &'a [u8; 64],
+}
+
+impl<'a> Item<'a> for SpendDescription<'a> {
+    fn parse(input: &'a [u8], _version: u32) -> IResult<&'a [u8], Self> {
+        let (rem, cv) = bytes(input)?;
+        let (rem, anchor) = bytes(rem)?;
+        let (rem, nullifier) = bytes(rem)?;
+        let (rem, rk) = bytes(rem)?;
+        let (rem, zkproof) = bytes(rem)?;
+        let (rem, spend_auth_sig) = bytes(rem)?;
+        Ok((
+            rem,
+            SpendDescription {
+                cv,
+                anchor,
+                nullifier,
+                rk,
+                zkproof,
+                spend_auth_sig,
+            },
+        ))
+    }
+
+    fn write(&self, w: &mut ByteWriter) -> Result<(), ParserError> {
+        w.put(self.cv)?;
+        w.put(self.anchor)?;
+        w.put(self.nullifier)?;
+        w.put(self.rk)?;
+        w.put(self.zkproof)?;
+        w.put(self.spend_auth_sig)
+    }
+}
+
+pub struct OutputDescription<'a> {
+    pub cv: &'a [u8; 32],
+    pub cmu: &'a [u8; 32],
+    pub ephemeral_key: &'a [u8; 32],
+    pub enc_ciphertext: &'a [u8; ENC_CIPHERTEXT_SIZE],
+    pub out_ciphertext: &'a [u8; OUT_CIPHERTEXT_SIZE],
+    pub zkproof: &'a [u8; GROTH16_PROOF_SIZE],
+}
+
+impl<'a> Item<'a> for OutputDescription<'a> {
+    fn parse(input: &'a [u8], _version: u32) -> IResult<&'a [u8], Self> {
+        let (rem, cv) = bytes(input)?;
+        let (rem, cmu) = bytes(rem)?;
+        let (rem, ephemeral_key) = bytes(rem)?;
+        let (rem, enc_ciphertext) = bytes(rem)?;
+        let (rem, out_ciphertext) = bytes(rem)?;
+        let (rem, zkproof) = bytes(rem)?;
+        Ok((
+            rem,
+            OutputDescription {
+                cv,
+                cmu,
+                ephemeral_key,
+                enc_ciphertext,
+                out_ciphertext,
+                zkproof,
+            },
+        ))
+    }
+
+    fn write(&self, w: &mut ByteWriter) -> Result<(), ParserError> {
+        w.put(self.cv)?;
+        w.put(self.cmu)?;
+        w.put(self.ephemeral_key)?;
+        w.put(self.enc_ciphertext)?;
+        w.put(self.out_ciphertext)?;
+        w.put(self.zkproof)
+    }
+}
+
+/// Sprout JoinSplit; the proof is BCTV14 before v4 and Groth16 from v4 on
+pub struct JoinSplitDescription<'a> {
+    pub vpub_old: u64,
+    pub vpub_new: u64,
+    pub anchor: &'a [u8; 32],
+    pub nullifiers: &'a [u8; 64],
+    pub commitments: &'a [u8; 64],
+    pub ephemeral_key: &'a [u8; 32],
+    pub random_seed: &'a [u8; 32],
+    pub macs: &'a [u8; 64],
+    pub proof: &'a [u8],
+    pub ciphertexts: &'a [u8; JOINSPLIT_CIPHERTEXTS_SIZE],
+}
+
+impl<'a> Item<'a> for JoinSplitDescription<'a> {
+    fn parse(input: &'a [u8], version: u32) -> IResult<&'a [u8], Self> {
+        let proof_size = if version >= 4 {
+            GROTH16_PROOF_SIZE
+        } else {
+            BCTV14_PROOF_SIZE
+        };
+        let (rem, vpub_old) = le_u64(input)?;
+        let (rem, vpub_new) = le_u64(rem)?;
+        let (rem, anchor) = bytes(rem)?;
+        let (rem, nullifiers) = bytes(rem)?;
+        let (rem, commitments) = bytes(rem)?;
+        let (rem, ephemeral_key) = bytes(rem)?;
+        let (rem, random_seed) = bytes(rem)?;
+        let (rem, macs) = bytes(rem)?;
+        let (rem, proof) = take(proof_size)(rem)?;
+        let (rem, ciphertexts) = bytes(rem)?;
+        Ok((
+            rem,
+            JoinSplitDescription {
+                vpub_old,
+                vpub_new,
+                anchor,
+                nullifiers,
+                commitments,
+                ephemeral_key,
+                random_seed,
+                macs,
+                proof,
+                ciphertexts,
+            },
+        ))
+    }
+
+    fn write(&self, w: &mut ByteWriter) -> Result<(), ParserError> {
+        w.put_u64(self.vpub_old)?;
+        w.put_u64(self.vpub_new)?;
+        w.put(self.anchor)?;
+        w.put(self.nullifiers)?;
+        w.put(self.commitments)?;
+        w.put(self.ephemeral_key)?;
+        w.put(self.random_seed)?;
+        w.put(self.macs)?;
+        w.put(self.proof)?;
+        w.put(self.ciphertexts)
+    }
+}
+
+pub struct Transaction<'a> {
+    pub overwintered: bool,
+    pub version: u32,
+    pub version_group_id: u32,
+    pub inputs: Items<'a, TxIn<'a>>,
+    pub outputs: Items<'a, TxOut<'a>>,
+    pub lock_time: u32,
+    pub expiry_height: u32,
+    pub value_balance: i64,
+    pub shielded_spends: Items<'a, SpendDescription<'a>>,
+    pub shielded_outputs: Items<'a, OutputDescription<'a>>,
+    pub joinsplits: Items<'a, JoinSplitDescription<'a>>,
+    pub joinsplit_pubkey: Option<&'a [u8; 32]>,
+    pub joinsplit_sig: Option<&'a [u8; 64]>,
+    pub binding_sig: Option<&'a [u8; 64]>,
+}
+
+impl<'a> Transaction<'a> {
+    pub fn from_bytes(data: &'a [u8]) -> Result<Self, ParserError> {
+        let (rem, header) = le_u32(data)?;
+        let overwintered = header & OVERWINTER_FLAG != 0;
+        let version = header & !OVERWINTER_FLAG;
+
+        let (rem, version_group_id) = if overwintered {
+            le_u32(rem)?
+        } else {
+            (rem, 0)
+        };
+        match (overwintered, version, version_group_id) {
+            (false, 1, _) | (false, 2, _) => {}
+            (true, 3, OVERWINTER_VERSION_GROUP_ID) | (true, 4, SAPLING_VERSION_GROUP_ID) => {}
+            _ => return Err(ParserError::parser_not_supported),
+        }
+        let is_sapling = version >= 4;
+
+        let (rem, inputs) = Items::parse(rem, version)?;
+        let (rem, outputs) = Items::parse(rem, version)?;
+        let (rem, lock_time) = le_u32(rem)?;
+
+        let (rem, expiry_height) = if overwintered {
+            le_u32(rem)?
+        } else {
+            (rem, 0)
+        };
+        if expiry_height >= TX_EXPIRY_HEIGHT_THRESHOLD {
+            return Err(ParserError::parser_value_out_of_range);
+        }
+
+        let (rem, value_balance, shielded_spends, shielded_outputs) = if is_sapling {
+            let (rem, value_balance) = le_i64(rem)?;
+            if !(-MAX_MONEY..=MAX_MONEY).contains(&value_balance) {
+                return Err(ParserError::parser_value_out_of_range);
+            }
+            let (rem, spends) = Items::parse(rem, version)?;
+            let (rem, outputs) = Items::parse(rem, version)?;
+            (rem, value_balance, spends, outputs)
+        } else {
+            (rem, 0, Items::empty(version), Items::empty(version))
+        };
+
+        let (rem, joinsplits) = if version >= 2 {
+            Items::parse(rem, version)?
+        } else {
+            (rem, Items::empty(version))
+        };
+        let (rem, joinsplit_pubkey, joinsplit_sig) = if joinsplits.is_empty() {
+            (rem, None, None)
+        } else {
+            let (rem, pubkey) = bytes(rem)?;
+            let (rem, sig) = bytes(rem)?;
+            (rem, Some(pubkey), Some(sig))
+        };
+
+        let (rem, binding_sig) =
+            if is_sapling && !(shielded_spends.is_empty() && shielded_outputs.is_empty()) {
+                let (rem, sig) = bytes(rem)?;
+                (rem, Some(sig))
+            } else {
+                (rem, None)
+            };
+
+        if !rem.is_empty() {
+            return Err(ParserError::parser_unexpected_unparsed_bytes);
+        }
+
+        Ok(Transaction {
+            overwintered,
+            version,
+            version_group_id,
+            inputs,
+            outputs,
+            lock_time,
+            expiry_height,
+            value_balance,
+            shielded_spends,
+            shielded_outputs,
+            joinsplits,
+            joinsplit_pubkey,
+            joinsplit_sig,
+            binding_sig,
+        })
+    }
+
+    /// Serializes the transaction into `out`, returning the number of bytes written
+    pub fn write(&self, out: &mut [u8]) -> Result<usize, ParserError> {
+        let mut w = ByteWriter::new(out);
+        let header = if self.overwintered {
+            self.version | OVERWINTER_FLAG
+        } else {
+            self.version
+        };
+        w.put_u32(header)?;
+        if self.overwintered {
+            w.put_u32(self.version_group_id)?;
+        }
+        self.inputs.write(&mut w)?;
+        self.outputs.write(&mut w)?;
+        w.put_u32(self.lock_time)?;
+        if self.overwintered {
+            w.put_u32(self.expiry_height)?;
+        }
+        if self.version >= 4 {
+            w.put_u64(self.value_balance as u64)?;
+            self.shielded_spends.write(&mut w)?;
+            self.shielded_outputs.write(&mut w)?;
+        }
+        if self.version >= 2 {
+            self.joinsplits.write(&mut w)?;
+        }
+        if let (Some(pubkey), Some(sig)) = (self.joinsplit_pubkey, self.joinsplit_sig) {
+            w.put(pubkey)?;
+            w.put(sig)?;
+        }
+        if let Some(sig) = self.binding_sig {
+            w.put(sig)?;
+        }
+        Ok(w.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_size_canonical() {
+        assert_eq!(compact_size(&[0xfc]).unwrap().1, 0xfc);
+        assert_eq!(compact_size(&[0xfd, 0xfd, 0x00]).unwrap().1, 0xfd);
+        assert!(compact_size(&[0xfd, 0x10, 0x00]).is_err());
+        assert!(compact_size(&[0xfe, 0xff, 0xff, 0x00, 0x00]).is_err());
+
+        let mut buf = [0u8; 9];
+        let mut w = ByteWriter::new(&mut buf);
+        w.put_compact_size(0x1_0000).unwrap();
+        assert_eq!(w.len(), 5);
+        assert_eq!(buf[..5], [0xfe, 0x00, 0x00, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn test_truncated_and_unknown_version() {
+        assert_eq!(
+            Transaction::from_bytes(&[0x01, 0x00]).err(),
+            Some(ParserError::parser_unexpected_buffer_end)
+        );
+        // overwintered v4 header with the Overwinter version group id
+        let data = [0x04, 0x00, 0x00, 0x80, 0x70, 0x82, 0xc4, 0x03];
+        assert_eq!(
+            Transaction::from_bytes(&data).err(),
+            Some(ParserError::parser_not_supported)
+        );
+    }
+}
@@ -0,0 +1,835 @@
+//! Review items shown on screen before signing a transaction.
+//!
+//! Items are generated from the transaction summary the host sends with
+//! INITTX, in the order of the review: transparent inputs, transparent
+//! outputs, shielded spends, shielded outputs, the change and finally the
+//! fee. `parser_getItem` in the C app pages them through `tx_display_get_item`.
+
+use byteorder::{ByteOrder, LittleEndian};
+use core::convert::TryInto;
+
+use crate::address::{sapling_address, transparent_from_script};
+use crate::errors::ParserError;
+use crate::fee::{actual_fee, classify_fee, conventional_fee, FeeWarning, DEFAULT_ABSURD_FEE};
+use crate::types::PaymentAddress;
+use crate::zxformat::{bytes_to_hex_str, fpu64_to_str, pageString};
+
+pub const NUM_ITEMS_TIN: usize = 2;
+pub const NUM_ITEMS_TOUT: usize = 2;
+pub const NUM_ITEMS_SSPEND: usize = 2;
+pub const NUM_ITEMS_SOUT: usize = 4;
+pub const NUM_ITEMS_CONST: usize = 1;
+
+pub const DEFAULT_MEMOTYPE: u8 = 0xf6;
+const ZEC_DECIMALS: u8 = 8;
+const TMP_BUF_LEN: usize = 100;
+
+/// Most entries of each kind the device keeps, see `T_IN_LIST_SIZE` and
+/// friends in constants.h
+pub const MAX_SUMMARY_ITEMS: usize = 5;
+
+const HEADER_LEN: usize = 4;
+const T_IN_INPUT_LEN: usize = 54;
+const T_OUT_INPUT_LEN: usize = 34;
+const SPEND_INPUT_LEN: usize = 55;
+const OUTPUT_INPUT_LEN: usize = 85;
+/// Scripts are sent as a length byte followed by up to 25 bytes
+const SCRIPT_SIZE: usize = 26;
+
+/// Previous output being spent by a transparent input
+pub struct TransparentInputInfo<'a> {
+    pub script: &'a [u8],
+    pub value: u64,
+}
+
+/// Transparent output created by the transaction
+pub struct TransparentOutputInfo<'a> {
+    pub script: &'a [u8],
+    pub value: u64,
+}
+
+/// Note being spent by a shielded spend
+pub struct SpendInfo {
+    /// Hardened ZIP32 account of the note
+    pub path: u32,
+    pub address: PaymentAddress,
+    pub value: u64,
+}
+
+/// Note being created by a shielded output
+pub struct OutputInfo {
+    pub address: PaymentAddress,
+    pub value: u64,
+    pub memotype: u8,
+    pub ovk: Option<[u8; 32]>,
+}
+
+/// Transaction summary sent with INITTX, read in place
+#[derive(Copy, Clone)]
+pub struct TxSummary<'a> {
+    data: &'a [u8],
+    t_in_len: usize,
+    t_out_len: usize,
+    spend_len: usize,
+    output_len: usize,
+}
+
+impl<'a> TxSummary<'a> {
+    /// Checks the layout of the INITTX data: four counts, then the
+    /// transparent inputs, transparent outputs, spends and outputs
+    pub fn parse(data: &'a [u8]) -> Result<Self, ParserError> {
+        if data.len() < HEADER_LEN {
+            return Err(ParserError::parser_unexpected_buffer_end);
+        }
+        let summary = TxSummary {
+            data,
+            t_in_len: data[0] as usize,
+            t_out_len: data[1] as usize,
+            spend_len: data[2] as usize,
+            output_len: data[3] as usize,
+        };
+        let counts = [
+            summary.t_in_len,
+            summary.t_out_len,
+            summary.spend_len,
+            summary.output_len,
+        ];
+        if counts.iter().any(|&n| n > MAX_SUMMARY_ITEMS) {
+            return Err(ParserError::parser_unexpected_number_items);
+        }
+        if data.len() != summary.outputs_offset() + summary.output_len * OUTPUT_INPUT_LEN {
+            return Err(ParserError::parser_unexpected_buffer_end);
+        }
+        let t_in_scripts = (0..summary.t_in_len).map(|i| summary.t_input_entry(i)[20..46].as_ref());
+        let t_out_scripts =
+            (0..summary.t_out_len).map(|i| summary.t_output_entry(i)[..26].as_ref());
+        if t_in_scripts
+            .chain(t_out_scripts)
+            .any(|script| script[0] as usize >= SCRIPT_SIZE)
+        {
+            return Err(ParserError::parser_invalid_output_script);
+        }
+        if (0..summary.output_len).any(|i| summary.output_entry(i)[52] > 1) {
+            return Err(ParserError::parser_unexpected_value);
+        }
+        Ok(summary)
+    }
+
+    fn t_outputs_offset(&self) -> usize {
+        HEADER_LEN + self.t_in_len * T_IN_INPUT_LEN
+    }
+
+    fn spends_offset(&self) -> usize {
+        self.t_outputs_offset() + self.t_out_len * T_OUT_INPUT_LEN
+    }
+
+    fn outputs_offset(&self) -> usize {
+        self.spends_offset() + self.spend_len * SPEND_INPUT_LEN
+    }
+
+    fn t_input_entry(&self, i: usize) -> &'a [u8] {
+        let start = HEADER_LEN + i * T_IN_INPUT_LEN;
+        &self.data[start..start + T_IN_INPUT_LEN]
+    }
+
+    fn t_output_entry(&self, i: usize) -> &'a [u8] {
+        let start = self.t_outputs_offset() + i * T_OUT_INPUT_LEN;
+        &self.data[start..start + T_OUT_INPUT_LEN]
+    }
+
+    fn spend_entry(&self, i: usize) -> &'a [u8] {
+        let start = self.spends_offset() + i * SPEND_INPUT_LEN;
+        &self.data[start..start + SPEND_INPUT_LEN]
+    }
+
+    fn output_entry(&self, i: usize) -> &'a [u8] {
+        let start = self.outputs_offset() + i * OUTPUT_INPUT_LEN;
+        &self.data[start..start + OUTPUT_INPUT_LEN]
+    }
+
+    pub fn t_inputs(&self) -> impl Iterator<Item = TransparentInputInfo<'a>> + '_ {
+        (0..self.t_in_len).map(move |i| {
+            let entry = self.t_input_entry(i);
+            TransparentInputInfo {
+                script: script_from_entry(&entry[20..46]),
+                value: LittleEndian::read_u64(&entry[46..54]),
+            }
+        })
+    }
+
+    pub fn t_outputs(&self) -> impl Iterator<Item = TransparentOutputInfo<'a>> + '_ {
+        (0..self.t_out_len).map(move |i| {
+            let entry = self.t_output_entry(i);
+            TransparentOutputInfo {
+                script: script_from_entry(&entry[..26]),
+                value: LittleEndian::read_u64(&entry[26..34]),
+            }
+        })
+    }
+
+    pub fn spends(&self) -> impl Iterator<Item = SpendInfo> + '_ {
+        (0..self.spend_len).map(move |i| {
+            let entry = self.spend_entry(i);
+            SpendInfo {
+                path: LittleEndian::read_u32(&entry[..4]) | 0x8000_0000,
+                address: PaymentAddress::from_bytes(entry[4..47].try_into().unwrap()),
+                value: LittleEndian::read_u64(&entry[47..55]),
+            }
+        })
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = OutputInfo> + '_ {
+        (0..self.output_len).map(move |i| {
+            let entry = self.output_entry(i);
+            OutputInfo {
+                address: PaymentAddress::from_bytes(entry[..43].try_into().unwrap()),
+                value: LittleEndian::read_u64(&entry[43..51]),
+                memotype: entry[51],
+                ovk: match entry[52] {
+                    1 => Some(entry[53..85].try_into().unwrap()),
+                    _ => None,
+                },
+            }
+        })
+    }
+}
+
+fn script_from_entry(entry: &[u8]) -> &[u8] {
+    &entry[1..1 + entry[0] as usize]
+}
+
+enum ItemKind {
+    TransparentInput(usize, usize),
+    TransparentOutput(usize, usize),
+    Spend(usize, usize),
+    Output(usize, usize),
+    Change,
+    Fee,
+    FeeWarning,
+}
+
+pub struct TxDisplay<'a> {
+    summary: TxSummary<'a>,
+    absurd_fee: u64,
+    t_change: u8,
+    s_change: u8,
+}
+
+impl<'a> TxDisplay<'a> {
+    /// Checks the summary values add up to a fee
+    pub fn new(summary: TxSummary<'a>) -> Result<Self, ParserError> {
+        let display = TxDisplay {
+            summary,
+            absurd_fee: DEFAULT_ABSURD_FEE,
+            t_change: 0,
+            s_change: 0,
+        };
+        display.fee()?;
+        Ok(display)
+    }
+
+    /// Fee above which the review shows an absurd fee warning
+    pub fn with_absurd_fee(mut self, absurd_fee: u64) -> Self {
+        self.absurd_fee = absurd_fee;
+        self
+    }
+
+    /// Collapses the outputs paying back to the signing account into a single
+    /// change item; bit `i` of `t_change` and `s_change` marks transparent
+    /// and shielded output `i`
+    pub fn with_change(mut self, t_change: u8, s_change: u8) -> Self {
+        self.t_change = t_change;
+        self.s_change = s_change;
+        self
+    }
+
+    fn is_t_out_change(&self, i: usize) -> bool {
+        self.t_change & (1 << i) != 0
+    }
+
+    fn is_s_out_change(&self, i: usize) -> bool {
+        self.s_change & (1 << i) != 0
+    }
+
+    fn visible_t_outputs(&self) -> usize {
+        (0..self.summary.t_out_len)
+            .filter(|&i| !self.is_t_out_change(i))
+            .count()
+    }
+
+    fn visible_s_outputs(&self) -> usize {
+        (0..self.summary.output_len)
+            .filter(|&i| !self.is_s_out_change(i))
+            .count()
+    }
+
+    /// Index of the `n`-th output not classified as change
+    fn nth_visible<I: Iterator<Item = bool>>(is_change: I, n: usize) -> Option<usize> {
+        is_change
+            .enumerate()
+            .filter(|(_, change)| !change)
+            .nth(n)
+            .map(|(i, _)| i)
+    }
+
+    /// Number of outputs collapsed as change
+    pub fn num_change_outputs(&self) -> usize {
+        self.summary.t_out_len - self.visible_t_outputs() + self.summary.output_len
+            - self.visible_s_outputs()
+    }
+
+    /// Total value sent back to the signing account
+    pub fn change_total(&self) -> Result<u64, ParserError> {
+        let t_change = self
+            .summary
+            .t_outputs()
+            .enumerate()
+            .filter(|(i, _)| self.is_t_out_change(*i))
+            .map(|(_, o)| o.value);
+        let s_change = self
+            .summary
+            .outputs()
+            .enumerate()
+            .filter(|(i, _)| self.is_s_out_change(*i))
+            .map(|(_, o)| o.value);
+        checked_sum(t_change.chain(s_change))
+    }
+
+    pub fn num_items(&self) -> usize {
+        let warning = match self.fee_warning() {
+            FeeWarning::None => 0,
+            _ => 1,
+        };
+        let change = if self.num_change_outputs() > 0 { 1 } else { 0 };
+        self.summary.t_in_len * NUM_ITEMS_TIN
+            + self.visible_t_outputs() * NUM_ITEMS_TOUT
+            + self.summary.spend_len * NUM_ITEMS_SSPEND
+            + self.visible_s_outputs() * NUM_ITEMS_SOUT
+            + change
+            + NUM_ITEMS_CONST
+            + warning
+    }
+
+    /// Fee paid by the summarised transaction: what the inputs and spends
+    /// bring in minus what the outputs take out
+    pub fn fee(&self) -> Result<u64, ParserError> {
+        let t_in = checked_sum(self.summary.t_inputs().map(|i| i.value))?;
+        let t_out = checked_sum(self.summary.t_outputs().map(|o| o.value))?;
+        let spent = checked_sum(self.summary.spends().map(|s| s.value))?;
+        let created = checked_sum(self.summary.outputs().map(|o| o.value))?;
+        let value_balance: i64 = (spent as i128 - created as i128)
+            .try_into()
+            .map_err(|_| ParserError::parser_value_out_of_range)?;
+        actual_fee(t_in, t_out, value_balance)
+    }
+
+    /// How the fee compares with the ZIP-317 conventional fee
+    pub fn fee_warning(&self) -> FeeWarning {
+        let conventional = conventional_fee(
+            self.summary.t_in_len,
+            self.summary.t_out_len,
+            self.summary.spend_len,
+            self.summary.output_len,
+        );
+        match self.fee() {
+            Ok(fee) => classify_fee(fee, conventional, self.absurd_fee),
+            Err(_) => FeeWarning::None,
+        }
+    }
+
+    fn kind(&self, idx: usize) -> Result<ItemKind, ParserError> {
+        let mut index = idx;
+        let sections = [
+            (self.summary.t_in_len, NUM_ITEMS_TIN),
+            (self.visible_t_outputs(), NUM_ITEMS_TOUT),
+            (self.summary.spend_len, NUM_ITEMS_SSPEND),
+            (self.visible_s_outputs(), NUM_ITEMS_SOUT),
+        ];
+        for (section, (count, per_item)) in sections.iter().enumerate() {
+            if index < count * per_item {
+                let (n, field) = (index / per_item, index % per_item);
+                return match section {
+                    0 => Ok(ItemKind::TransparentInput(n, field)),
+                    1 => Self::nth_visible(
+                        (0..self.summary.t_out_len).map(|i| self.is_t_out_change(i)),
+                        n,
+                    )
+                    .map(|n| ItemKind::TransparentOutput(n, field))
+                    .ok_or(ParserError::parser_unexpected_number_items),
+                    2 => Ok(ItemKind::Spend(n, field)),
+                    _ => Self::nth_visible(
+                        (0..self.summary.output_len).map(|i| self.is_s_out_change(i)),
+                        n,
+                    )
+                    .map(|n| ItemKind::Output(n, field))
+                    .ok_or(ParserError::parser_unexpected_number_items),
+                };
+            }
+            index -= count * per_item;
+        }
+        if self.num_change_outputs() > 0 {
+            if index == 0 {
+                return Ok(ItemKind::Change);
+            }
+            index -= 1;
+        }
+        if index < NUM_ITEMS_CONST {
+            return Ok(ItemKind::Fee);
+        }
+        if index == NUM_ITEMS_CONST && self.fee_warning() != FeeWarning::None {
+            return Ok(ItemKind::FeeWarning);
+        }
+        Err(ParserError::parser_display_idx_out_of_range)
+    }
+
+    /// Writes the title and the requested page of the value for item
+    /// `display_idx`, returning the number of pages
+    pub fn get_item(
+        &self,
+        display_idx: u8,
+        out_key: &mut [u8],
+        out_value: &mut [u8],
+        page_idx: u8,
+    ) -> Result<u8, ParserError> {
+        let kind = self.kind(display_idx as usize)?;
+        let missing = ParserError::parser_unexpected_number_items;
+
+        let mut tmp = [0u8; TMP_BUF_LEN];
+        let (key, len): (&[u8], usize) = match kind {
+            ItemKind::TransparentInput(n, field) => {
+                let input = self.summary.t_inputs().nth(n).ok_or(missing)?;
+                if field == 0 {
+                    (
+                        b"T-in addr",
+                        transparent_from_script(input.script, &mut tmp)?,
+                    )
+                } else {
+                    (
+                        b"T-in (ZEC)",
+                        fpu64_to_str(&mut tmp, input.value, ZEC_DECIMALS)?,
+                    )
+                }
+            }
+            ItemKind::TransparentOutput(n, field) => {
+                let out = self.summary.t_outputs().nth(n).ok_or(missing)?;
+                if field == 0 {
+                    (
+                        b"T-out addr",
+                        transparent_from_script(out.script, &mut tmp)?,
+                    )
+                } else {
+                    (
+                        b"T-out (ZEC)",
+                        fpu64_to_str(&mut tmp, out.value, ZEC_DECIMALS)?,
+                    )
+                }
+            }
+            ItemKind::Spend(n, field) => {
+                let spend = self.summary.spends().nth(n).ok_or(missing)?;
+                if field == 0 {
+                    (b"S-in addr", sapling_address(&spend.address, &mut tmp)?)
+                } else {
+                    (
+                        b"S-in (ZEC)",
+                        fpu64_to_str(&mut tmp, spend.value, ZEC_DECIMALS)?,
+                    )
+                }
+            }
+            ItemKind::Output(n, field) => {
+                let output = self.summary.outputs().nth(n).ok_or(missing)?;
+                match field {
+                    0 => (b"S-out addr", sapling_address(&output.address, &mut tmp)?),
+                    1 => (
+                        b"S-out (ZEC)",
+                        fpu64_to_str(&mut tmp, output.value, ZEC_DECIMALS)?,
+                    ),
+                    2 => {
+                        let memotype: &[u8] = if output.memotype == DEFAULT_MEMOTYPE {
+                            b"Default"
+                        } else {
+                            b"Custom"
+                        };
+                        tmp[..memotype.len()].copy_from_slice(memotype);
+                        (b"S-out Memotype", memotype.len())
+                    }
+                    _ => match output.ovk {
+                        Some(ovk) => (b"S-out OVK", bytes_to_hex_str(&mut tmp, &ovk)?),
+                        None => {
+                            tmp[..4].copy_from_slice(b"None");
+                            (b"S-out OVK", 4)
+                        }
+                    },
+                }
+            }
+            ItemKind::Change => (
+                b"Change (ZEC)",
+                fpu64_to_str(&mut tmp, self.change_total()?, ZEC_DECIMALS)?,
+            ),
+            ItemKind::Fee => (b"Fee", fpu64_to_str(&mut tmp, self.fee()?, ZEC_DECIMALS)?),
+            ItemKind::FeeWarning => {
+                let msg = self
+                    .fee_warning()
+                    .message()
+                    .ok_or(ParserError::parser_display_idx_out_of_range)?;
+                tmp[..msg.len()].copy_from_slice(msg);
+                (b"Warning", msg.len())
+            }
+        };
+
+        write_key(out_key, key)?;
+        pageString(out_value, &tmp[..len], page_idx)
+    }
+}
+
+fn checked_sum<I: Iterator<Item = u64>>(mut values: I) -> Result<u64, ParserError> {
+    values.try_fold(0u64, |total, v| {
+        total
+            .checked_add(v)
+            .ok_or(ParserError::parser_value_out_of_range)
+    })
+}
+
+fn write_key(out: &mut [u8], key: &[u8]) -> Result<(), ParserError> {
+    if out.len() <= key.len() {
+        return Err(ParserError::parser_unexpected_buffer_end);
+    }
+    for b in out.iter_mut() {
+        *b = 0;
+    }
+    out[..key.len()].copy_from_slice(key);
+    Ok(())
+}
+
+fn display_from_ptr<'a>(
+    inittx_ptr: *const u8,
+    inittx_len: u16,
+    t_change: u8,
+    s_change: u8,
+) -> Result<TxDisplay<'a>, ParserError> {
+    let inittx = unsafe { core::slice::from_raw_parts(inittx_ptr, inittx_len as usize) };
+    let display = TxDisplay::new(TxSummary::parse(inittx)?)?.with_change(t_change, s_change);
+    if display.num_items() > u8::MAX as usize {
+        return Err(ParserError::parser_unexpected_number_items);
+    }
+    Ok(display)
+}
+
+/// Number of review items for the INITTX data; `t_change` and `s_change`
+/// mark the change outputs as in `TxDisplay::with_change`
+#[no_mangle]
+pub extern "C" fn tx_display_num_items(
+    inittx_ptr: *const u8,
+    inittx_len: u16,
+    t_change: u8,
+    s_change: u8,
+    num_items_ptr: *mut u8,
+) -> ParserError {
+    let num_items = unsafe { &mut *num_items_ptr };
+    match display_from_ptr(inittx_ptr, inittx_len, t_change, s_change) {
+        Ok(display) => {
+            *num_items = display.num_items() as u8;
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
+}
+
+/// Writes the title and page `page_idx` of review item `display_idx` of the
+/// INITTX data to the C strings `out_key_ptr` and `out_value_ptr`
+#[no_mangle]
+pub extern "C" fn tx_display_get_item(
+    inittx_ptr: *const u8,
+    inittx_len: u16,
+    t_change: u8,
+    s_change: u8,
+    display_idx: u8,
+    out_key_ptr: *mut u8,
+    out_key_len: u16,
+    out_value_ptr: *mut u8,
+    out_value_len: u16,
+    page_idx: u8,
+    page_count_ptr: *mut u8,
+) -> ParserError {
+    let out_key = unsafe { core::slice::from_raw_parts_mut(out_key_ptr, out_key_len as usize) };
+    let out_value =
+        unsafe { core::slice::from_raw_parts_mut(out_value_ptr, out_value_len as usize) };
+    let page_count = unsafe { &mut *page_count_ptr };
+    let page = display_from_ptr(inittx_ptr, inittx_len, t_change, s_change)
+        .and_then(|display| display.get_item(display_idx, out_key, out_value, page_idx));
+    match page {
+        Ok(count) => {
+            *page_count = count;
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    fn c_str(buf: &[u8]) -> &str {
+        let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+        core::str::from_utf8(&buf[..end]).unwrap()
+    }
+
+    const T_OUT_SCRIPT: &str = "76a91461aac8b58ac880a45fb06eeedfcf3017679778a988ac";
+    const ADDRESS: [u8; PaymentAddress::LEN] = [7u8; PaymentAddress::LEN];
+
+    fn script_entry(script: &str) -> Vec<u8> {
+        let script = hex::decode(script).unwrap();
+        let mut entry = std::vec![script.len() as u8];
+        entry.extend_from_slice(&script);
+        entry.resize(SCRIPT_SIZE, 0);
+        entry
+    }
+
+    /// INITTX data with the given transparent input and output values, spends
+    /// and outputs, all to `ADDRESS` and the first output with an ovk
+    fn inittx(t_in: &[u64], t_out: &[u64], spends: &[u64], outputs: &[u64]) -> Vec<u8> {
+        let mut data = std::vec![
+            t_in.len() as u8,
+            t_out.len() as u8,
+            spends.len() as u8,
+            outputs.len() as u8,
+        ];
+        for value in t_in {
+            for i in 0..5u32 {
+                data.extend_from_slice(&i.to_le_bytes());
+            }
+            data.extend_from_slice(&script_entry(T_OUT_SCRIPT));
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in t_out {
+            data.extend_from_slice(&script_entry(T_OUT_SCRIPT));
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in spends {
+            data.extend_from_slice(&0u32.to_le_bytes());
+            data.extend_from_slice(&ADDRESS);
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        for (i, value) in outputs.iter().enumerate() {
+            data.extend_from_slice(&ADDRESS);
+            data.extend_from_slice(&value.to_le_bytes());
+            data.push(DEFAULT_MEMOTYPE);
+            if i == 0 {
+                data.push(1);
+                data.extend_from_slice(&[0xab; 32]);
+            } else {
+                data.push(0);
+                data.extend_from_slice(&[0; 32]);
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn test_items() {
+        let data = inittx(&[], &[55_000], &[100_000], &[20_000, 10_000]);
+        let display = TxDisplay::new(TxSummary::parse(&data).unwrap()).unwrap();
+        assert_eq!(display.num_items(), 2 + 2 + 4 + 4 + 1);
+        assert_eq!(display.fee(), Ok(15_000));
+        assert_eq!(display.fee_warning(), FeeWarning::None);
+
+        let mut key = [0u8; 40];
+        let mut value = [0u8; 40];
+        let expected = [
+            ("T-out addr", "t1Sn29Dzjh6YtkJksq2oECUuJPbquRdPxhG", 1),
+            ("T-out (ZEC)", "0.00055000", 1),
+            ("S-in addr", "zs1", 2),
+            ("S-in (ZEC)", "0.00100000", 1),
+            ("S-out addr", "zs1", 2),
+            ("S-out (ZEC)", "0.00020000", 1),
+            ("S-out Memotype", "Default", 1),
+            ("S-out OVK", "abababababababababababababababababababa", 2),
+            ("S-out addr", "zs1", 2),
+            ("S-out (ZEC)", "0.00010000", 1),
+            ("S-out Memotype", "Default", 1),
+            ("S-out OVK", "None", 1),
+            ("Fee", "0.00015000", 1),
+        ];
+        for (idx, (k, v, pages)) in expected.iter().enumerate() {
+            let count = display
+                .get_item(idx as u8, &mut key, &mut value, 0)
+                .unwrap();
+            assert_eq!(c_str(&key), *k);
+            assert_eq!(count, *pages);
+            assert!(c_str(&value).starts_with(v));
+        }
+        assert_eq!(
+            display.get_item(13, &mut key, &mut value, 0),
+            Err(ParserError::parser_display_idx_out_of_range)
+        );
+    }
+
+    #[test]
+    fn test_fee_warning() {
+        let data = inittx(&[], &[55_000], &[300_000], &[20_000, 10_000]);
+        let display = TxDisplay::new(TxSummary::parse(&data).unwrap()).unwrap();
+        assert_eq!(display.fee(), Ok(215_000));
+        assert_eq!(display.fee_warning(), FeeWarning::Excessive);
+        assert_eq!(display.num_items(), 14);
+
+        let mut key = [0u8; 40];
+        let mut value = [0u8; 40];
+        display.get_item(13, &mut key, &mut value, 0).unwrap();
+        assert_eq!(c_str(&key), "Warning");
+        assert_eq!(c_str(&value), "Excessive fee");
+
+        let display = display.with_absurd_fee(50_000);
+        assert_eq!(display.fee_warning(), FeeWarning::Absurd);
+    }
+
+    #[test]
+    fn test_values_must_add_up() {
+        // outputs worth more than what is spent
+        let data = inittx(&[], &[60_000], &[80_000], &[20_000, 10_000]);
+        assert_eq!(
+            TxDisplay::new(TxSummary::parse(&data).unwrap()).err(),
+            Some(ParserError::parser_value_out_of_range)
+        );
+
+        let data = inittx(&[u64::MAX, 1], &[], &[], &[]);
+        assert_eq!(
+            TxDisplay::new(TxSummary::parse(&data).unwrap()).err(),
+            Some(ParserError::parser_value_out_of_range)
+        );
+    }
+
+    #[test]
+    fn test_change_collapsed() {
+        let data = inittx(&[], &[55_000], &[100_000], &[20_000, 10_000]);
+        let display = TxDisplay::new(TxSummary::parse(&data).unwrap())
+            .unwrap()
+            .with_change(0, 0b10);
+        assert_eq!(display.num_change_outputs(), 1);
+        assert_eq!(display.num_items(), 2 + 2 + 4 + 1 + 1);
+        assert_eq!(display.change_total(), Ok(10_000));
+
+        let mut key = [0u8; 40];
+        let mut value = [0u8; 40];
+        display.get_item(5, &mut key, &mut value, 0).unwrap();
+        assert_eq!(c_str(&key), "S-out (ZEC)");
+        assert_eq!(c_str(&value), "0.00020000");
+        display.get_item(8, &mut key, &mut value, 0).unwrap();
+        assert_eq!(c_str(&key), "Change (ZEC)");
+        assert_eq!(c_str(&value), "0.00010000");
+        display.get_item(9, &mut key, &mut value, 0).unwrap();
+        assert_eq!(c_str(&key), "Fee");
+
+        // the transparent output and the first shielded output are change too
+        let display = display.with_change(0b1, 0b11);
+        assert_eq!(display.num_items(), 2 + 1 + 1);
+        assert_eq!(display.change_total(), Ok(85_000));
+        display.get_item(0, &mut key, &mut value, 0).unwrap();
+        assert_eq!(c_str(&key), "S-in addr");
+    }
+
+    #[test]
+    fn test_summary_layout() {
+        let data = inittx(&[70_000], &[60_000], &[], &[]);
+        let summary = TxSummary::parse(&data).unwrap();
+        let input = summary.t_inputs().next().unwrap();
+        assert_eq!(input.script, &hex::decode(T_OUT_SCRIPT).unwrap()[..]);
+        assert_eq!(input.value, 70_000);
+
+        assert_eq!(
+            TxSummary::parse(&data[..data.len() - 1]).err(),
+            Some(ParserError::parser_unexpected_buffer_end)
+        );
+        assert_eq!(
+            TxSummary::parse(&[]).err(),
+            Some(ParserError::parser_unexpected_buffer_end)
+        );
+
+        let mut bad = data.clone();
+        bad[HEADER_LEN + 20] = SCRIPT_SIZE as u8;
+        assert_eq!(
+            TxSummary::parse(&bad).err(),
+            Some(ParserError::parser_invalid_output_script)
+        );
+
+        let mut bad = inittx(&[], &[], &[100_000], &[20_000, 10_000]);
+        let ovk_flag = bad.len() - 33;
+        bad[ovk_flag] = 2;
+        assert_eq!(
+            TxSummary::parse(&bad).err(),
+            Some(ParserError::parser_unexpected_value)
+        );
+
+        let data = inittx(&[1; 6], &[], &[], &[]);
+        assert_eq!(
+            TxSummary::parse(&data).err(),
+            Some(ParserError::parser_unexpected_number_items)
+        );
+    }
+
+    #[test]
+    fn test_ffi() {
+        let data = inittx(&[], &[55_000], &[100_000], &[20_000, 10_000]);
+        let mut num_items = 0u8;
+        assert_eq!(
+            tx_display_num_items(data.as_ptr(), data.len() as u16, 0, 0b10, &mut num_items),
+            ParserError::parser_ok
+        );
+        assert_eq!(num_items, 10);
+
+        let mut key = [0u8; 20];
+        let mut value = [0u8; 20];
+        let mut page_count = 0u8;
+        assert_eq!(
+            tx_display_get_item(
+                data.as_ptr(),
+                data.len() as u16,
+                0,
+                0b10,
+                8,
+                key.as_mut_ptr(),
+                key.len() as u16,
+                value.as_mut_ptr(),
+                value.len() as u16,
+                0,
+                &mut page_count
+            ),
+            ParserError::parser_ok
+        );
+        assert_eq!(c_str(&key), "Change (ZEC)");
+        assert_eq!(c_str(&value), "0.00010000");
+        assert_eq!(page_count, 1);
+
+        assert_eq!(
+            tx_display_num_items(data.as_ptr(), 3, 0, 0, &mut num_items),
+            ParserError::parser_unexpected_buffer_end
+        );
+
+        // the legacy fee INITTX still accepts ends the review with a warning
+        let data = inittx(&[], &[69_000], &[100_000], &[20_000, 10_000]);
+        assert_eq!(
+            tx_display_num_items(data.as_ptr(), data.len() as u16, 0, 0, &mut num_items),
+            ParserError::parser_ok
+        );
+        assert_eq!(num_items, 14);
+        assert_eq!(
+            tx_display_get_item(
+                data.as_ptr(),
+                data.len() as u16,
+                0,
+                0,
+                num_items - 1,
+                key.as_mut_ptr(),
+                key.len() as u16,
+                value.as_mut_ptr(),
+                value.len() as u16,
+                0,
+                &mut page_count
+            ),
+            ParserError::parser_ok
+        );
+        assert_eq!(c_str(&key), "Warning");
+        assert_eq!(c_str(&value), "Below ZIP-317 fee");
+    }
+}
@@ -0,0 +1,177 @@
+//! Typed byte containers for Sapling keys, addresses and transaction fields.
+//!
+//! The `extern "C"` entry points receive raw pointers to fixed-size arrays.
+//! Internally everything is expressed through these newtypes so that Rust
+//! callers never need to go through `unsafe` pointer casts.
+
+use crate::constants::DIV_SIZE;
+
+macro_rules! bytes_newtype {
+    ($(#[$meta:meta])* $name:ident, $len:expr) => {
+        $(#[$meta])*
+        #[repr(transparent)]
+        #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+        pub struct $name(pub [u8; $len]);
+
+        bytes_newtype!(@impl $name, $len);
+    };
+    // Key material: neither implicitly copied nor printed
+    ($(#[$meta:meta])* secret $name:ident, $len:expr) => {
+        $(#[$meta])*
+        #[repr(transparent)]
+        #[derive(Clone, PartialEq, Eq)]
+        pub struct $name(pub [u8; $len]);
+
+        impl core::fmt::Debug for $name {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                f.write_str(concat!(stringify!($name), "(..)"))
+            }
+        }
+
+        bytes_newtype!(@impl $name, $len);
+    };
+    (@impl $name:ident, $len:expr) => {
+        impl $name {
+            pub const LEN: usize = $len;
+
+            pub const fn from_bytes(bytes: [u8; $len]) -> Self {
+                $name(bytes)
+            }
+
+            pub fn to_bytes(self) -> [u8; $len] {
+                self.0
+            }
+
+            pub fn as_bytes(&self) -> &[u8; $len] {
+                &self.0
+            }
+
+            /// Reinterprets a pointer received over FFI as a reference
+            ///
+            /// # Safety
+            /// `ptr` must be non-null and point to a readable array of the
+            /// right length that outlives the returned reference.
+            pub unsafe fn from_ptr<'a>(ptr: *const [u8; $len]) -> &'a Self {
+                &*(ptr as *const Self)
+            }
+        }
+
+        impl From<[u8; $len]> for $name {
+            fn from(bytes: [u8; $len]) -> Self {
+                $name(bytes)
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+    };
+}
+
+bytes_newtype!(
+    /// 32-byte seed the ZIP32 master spending key is derived from
+    secret SpendingKey,
+    32
+);
+bytes_newtype!(
+    /// Spend authorizing key (scalar)
+    secret Ask,
+    32
+);
+bytes_newtype!(
+    /// Proof authorizing key (scalar)
+    secret Nsk,
+    32
+);
+bytes_newtype!(
+    /// Spend validating key (point)
+    Ak,
+    32
+);
+bytes_newtype!(
+    /// Nullifier deriving key (point)
+    Nk,
+    32
+);
+bytes_newtype!(
+    /// Incoming viewing key (scalar)
+    Ivk,
+    32
+);
+bytes_newtype!(
+    /// Outgoing viewing key
+    Ovk,
+    32
+);
+bytes_newtype!(
+    /// ZIP32 diversifier
+    Diversifier,
+    DIV_SIZE
+);
+bytes_newtype!(
+    /// Full point encoding of a note commitment
+    NoteCommitment,
+    32
+);
+bytes_newtype!(
+    /// Value commitment `cv`
+    ValueCommitment,
+    32
+);
+bytes_newtype!(
+    /// Sapling nullifier
+    Nullifier,
+    32
+);
+bytes_newtype!(
+    /// RedJubjub signature `rbar || sbar`
+    Signature,
+    64
+);
+
+/// Sapling payment address `(d, pk_d)`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PaymentAddress {
+    pub diversifier: Diversifier,
+    pub pk_d: [u8; 32],
+}
+
+impl PaymentAddress {
+    pub const LEN: usize = DIV_SIZE + 32;
+
+    pub fn from_bytes(bytes: &[u8; Self::LEN]) -> Self {
+        let mut diversifier = [0u8; DIV_SIZE];
+        let mut pk_d = [0u8; 32];
+        diversifier.copy_from_slice(&bytes[..DIV_SIZE]);
+        pk_d.copy_from_slice(&bytes[DIV_SIZE..]);
+        PaymentAddress {
+            diversifier: Diversifier(diversifier),
+            pk_d,
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; Self::LEN] {
+        let mut bytes = [0u8; Self::LEN];
+        bytes[..DIV_SIZE].copy_from_slice(&self.diversifier.0);
+        bytes[DIV_SIZE..].copy_from_slice(&self.pk_d);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_payment_address_roundtrip() {
+        let mut bytes = [0u8; PaymentAddress::LEN];
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let addr = PaymentAddress::from_bytes(&bytes);
+        assert_eq!(addr.diversifier.0, [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+        assert_eq!(addr.to_bytes(), bytes);
+    }
+}
@@ -8,6 +8,8 @@ use rand::RngCore;
 use crate::bolos::{c_zemu_log_stack, Trng};
 use crate::commitments::bytes_to_extended;
 use crate::constants;
+use crate::errors::ParserError;
+use crate::secret::Secret;
 use crate::zip32::*;
 use crate::{bolos, pedersen::extended_to_bytes, zip32};
 
@@ -24,33 +26,32 @@ pub fn rseed_generate_esk(rseed: &[u8; 32]) -> Fr {
 }
 
 pub fn generate_esk() -> [u8; 32] {
-    let mut buffer = [0u8; 64];
-    Trng.fill_bytes(&mut buffer);
+    let mut buffer = Secret::new([0u8; 64]);
+    Trng.fill_bytes(&mut *buffer);
     //Trng.fill_bytes(&mut buffer); //fill with random bytes
     let esk = Fr::from_bytes_wide(&buffer);
     esk.to_bytes()
 }
 
 //epk
-pub fn derive_public(esk: &[u8; 32], g_d: &[u8; 32]) -> [u8; 32] {
-    let p = AffinePoint::from_bytes(*g_d).unwrap();
+pub fn derive_public(esk: &[u8; 32], g_d: &[u8; 32]) -> Result<[u8; 32], ParserError> {
+    let p = bytes_to_extended(*g_d)?;
     let q = p.to_niels().multiply_bits(esk);
-    let t = AffinePoint::from(q);
-    t.to_bytes()
+    Ok(extended_to_bytes(&q))
 }
 
 #[inline(never)]
-pub fn sapling_ka_agree(esk: &[u8; 32], pk_d: &[u8; 32]) -> [u8; 32] {
-    let mut y = bytes_to_extended(*pk_d);
+pub fn sapling_ka_agree(esk: &[u8; 32], pk_d: &[u8; 32]) -> Result<[u8; 32], ParserError> {
+    let mut y = bytes_to_extended(*pk_d)?;
     mul_by_cof(&mut y);
     niels_multbits(&mut y, esk);
-    extended_to_bytes(&y)
+    Ok(extended_to_bytes(&y))
 }
 
 pub fn kdf_sapling(dhsecret: &[u8; 32], epk: &[u8; 32]) -> [u8; 32] {
     let mut input = [0u8; 64];
-    (&mut input[..32]).copy_from_slice(dhsecret);
-    (&mut input[32..]).copy_from_slice(epk);
+    input[..32].copy_from_slice(dhsecret);
+    input[32..].copy_from_slice(epk);
     pub const KDF_SAPLING_PERSONALIZATION: &[u8; 16] = b"Zcash_SaplingKDF";
     bolos::blake2b32_with_personalization(KDF_SAPLING_PERSONALIZATION, &input)
 }
@@ -68,31 +69,40 @@ pub fn prf_ock(ovk: &[u8; 32], cv: &[u8; 32], cmu: &[u8; 32], epk: &[u8; 32]) ->
 #[inline(never)]
 pub fn prf_sessionkey(data: &[u8]) -> [u8; 32] {
     pub const PRF_SESSION_PERSONALIZATION: &[u8; 16] = b"Zcash_SessionKey";
-    bolos::blake2b32_with_personalization(PRF_SESSION_PERSONALIZATION, &data)
+    bolos::blake2b32_with_personalization(PRF_SESSION_PERSONALIZATION, data)
+}
+
+#[inline(never)]
+pub fn session_pubkey(scalar: &[u8; 32]) -> [u8; 32] {
+    let v = constants::SESSION_KEY_BASE.multiply_bits(scalar);
+    extended_to_bytes(&v)
+}
+
+#[inline(never)]
+pub fn session_key(scalar: &[u8; 32], point: &[u8; 32]) -> Result<[u8; 32], ParserError> {
+    let epk = sapling_ka_agree(scalar, point)?;
+    Ok(prf_sessionkey(&epk))
 }
 
 #[no_mangle]
 pub extern "C" fn pubkey_gen(scalar_ptr: *const [u8; 32], output_ptr: *mut [u8; 32]) {
     let scalar = unsafe { &*scalar_ptr };
     let output = unsafe { &mut *output_ptr };
-    let v = constants::SESSION_KEY_BASE.multiply_bits(scalar);
-    output.copy_from_slice(&extended_to_bytes(&v));
+    *output = session_pubkey(scalar);
 }
 
 #[no_mangle]
 pub extern "C" fn rseed_get_rcm(rseed_ptr: *const [u8; 32], output_ptr: *mut [u8; 32]) {
     let rseed = unsafe { &*rseed_ptr };
     let output = unsafe { &mut *output_ptr };
-    let p = rseed_generate_rcm(rseed);
-    output.copy_from_slice(&p.to_bytes());
+    *output = rseed_generate_rcm(rseed).to_bytes();
 }
 
 #[no_mangle]
-pub fn rseed_get_esk(rseed_ptr: *const [u8; 32], output_ptr: *mut [u8; 32]) {
+pub extern "C" fn rseed_get_esk(rseed_ptr: *const [u8; 32], output_ptr: *mut [u8; 32]) {
     let rseed = unsafe { &*rseed_ptr };
     let output = unsafe { &mut *output_ptr };
-    let p = rseed_generate_esk(rseed);
-    output.copy_from_slice(&p.to_bytes());
+    *output = rseed_generate_esk(rseed).to_bytes();
 }
 
 #[no_mangle]
@@ -100,14 +110,16 @@ pub extern "C" fn sessionkey_agree(
     scalar_ptr: *const [u8; 32],
     point_ptr: *const [u8; 32],
     output_ptr: *mut [u8; 32],
-) {
+) -> ParserError {
     c_zemu_log_stack(b"inside_katokey\x00".as_ref());
-    let scalar = unsafe { &*scalar_ptr }; //ovk, cv, cmu, epk
+    let scalar = unsafe { &*scalar_ptr };
     let point = unsafe { &*point_ptr };
-
-    let epk = sapling_ka_agree(scalar, point);
-    let sessionkey = prf_sessionkey(&epk);
-
-    let output = unsafe { &mut *output_ptr }; //ovk, cv, cmu, epk
-    output[0..32].copy_from_slice(&sessionkey);
+    let output = unsafe { &mut *output_ptr };
+    match session_key(scalar, point) {
+        Ok(key) => {
+            *output = key;
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
 }
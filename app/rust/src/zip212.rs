@@ -0,0 +1,234 @@
+//! ZIP-212 note plaintext formats.
+//!
+//! Before Canopy, note plaintexts start with lead byte 0x01 and carry `rcm`
+//! directly. From Canopy onwards they start with 0x02 and carry `rseed`,
+//! from which both `rcm` and `esk` are derived. During the grace period
+//! after activation receivers accept both formats.
+
+use crate::errors::ParserError;
+use crate::zeccrypto::{rseed_generate_esk, rseed_generate_rcm};
+
+pub const LEAD_BYTE_RCM: u8 = 0x01;
+pub const LEAD_BYTE_RSEED: u8 = 0x02;
+/// Blocks after Canopy activation during which 0x01 plaintexts are still accepted
+pub const ZIP212_GRACE_PERIOD: u32 = 32256;
+pub const CANOPY_HEIGHT_MAINNET: u32 = 1_046_400;
+pub const CANOPY_HEIGHT_TESTNET: u32 = 1_028_500;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+}
+
+impl Network {
+    pub fn canopy_height(self) -> u32 {
+        match self {
+            Network::Mainnet => CANOPY_HEIGHT_MAINNET,
+            Network::Testnet => CANOPY_HEIGHT_TESTNET,
+        }
+    }
+}
+
+/// Which lead bytes to produce and accept for a transaction mined at
+/// `target_height`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Zip212Policy {
+    canopy_height: u32,
+    target_height: u32,
+}
+
+impl Zip212Policy {
+    pub fn new(network: Network, target_height: u32) -> Self {
+        Self::with_canopy_height(network.canopy_height(), target_height)
+    }
+
+    /// Policy for networks with a custom activation height, e.g. regtest
+    pub fn with_canopy_height(canopy_height: u32, target_height: u32) -> Self {
+        Zip212Policy {
+            canopy_height,
+            target_height,
+        }
+    }
+
+    /// Policy for a transaction expiring at `expiry_height`, the only height
+    /// the device learns. An expiry height of 0 means the transaction never
+    /// expires.
+    ///
+    /// The transaction may be mined before its expiry height, so this is only
+    /// exact away from activation: a transaction expiring at or after Canopy
+    /// but mined before it gets 0x02 notes, which pre-Canopy wallets do not
+    /// accept. Hosts must give such transactions an expiry height below
+    /// activation.
+    pub fn for_expiry_height(network: Network, expiry_height: u32) -> Self {
+        let target_height = match expiry_height {
+            0 => u32::MAX,
+            h => h,
+        };
+        Self::new(network, target_height)
+    }
+
+    pub fn is_canopy_active(&self) -> bool {
+        self.target_height >= self.canopy_height
+    }
+
+    /// Lead byte for notes created by this transaction
+    pub fn output_lead_byte(&self) -> u8 {
+        if self.is_canopy_active() {
+            LEAD_BYTE_RSEED
+        } else {
+            LEAD_BYTE_RCM
+        }
+    }
+
+    /// Randomness carried by an output created under this policy
+    pub fn output_randomness(&self, bytes: [u8; 32]) -> NoteRandomness {
+        if self.is_canopy_active() {
+            NoteRandomness::Rseed(bytes)
+        } else {
+            NoteRandomness::Rcm(bytes)
+        }
+    }
+
+    /// Whether a decrypted note with lead byte `lead` is valid at the target height
+    pub fn accepts(&self, lead: u8) -> bool {
+        let grace_end = self.canopy_height.saturating_add(ZIP212_GRACE_PERIOD);
+        match lead {
+            LEAD_BYTE_RCM => self.target_height < grace_end,
+            LEAD_BYTE_RSEED => self.is_canopy_active(),
+            _ => false,
+        }
+    }
+}
+
+/// Commitment randomness as carried in a note plaintext
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NoteRandomness {
+    Rcm([u8; 32]),
+    Rseed([u8; 32]),
+}
+
+impl NoteRandomness {
+    pub fn from_lead_byte(lead: u8, bytes: [u8; 32]) -> Result<Self, ParserError> {
+        match lead {
+            LEAD_BYTE_RCM => Ok(NoteRandomness::Rcm(bytes)),
+            LEAD_BYTE_RSEED => Ok(NoteRandomness::Rseed(bytes)),
+            _ => Err(ParserError::parser_unexpected_value),
+        }
+    }
+
+    pub fn lead_byte(&self) -> u8 {
+        match self {
+            NoteRandomness::Rcm(_) => LEAD_BYTE_RCM,
+            NoteRandomness::Rseed(_) => LEAD_BYTE_RSEED,
+        }
+    }
+
+    /// The 32 bytes written to the plaintext after the value
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        match self {
+            NoteRandomness::Rcm(b) | NoteRandomness::Rseed(b) => b,
+        }
+    }
+
+    pub fn rcm(&self) -> [u8; 32] {
+        match self {
+            NoteRandomness::Rcm(rcm) => *rcm,
+            NoteRandomness::Rseed(rseed) => rseed_generate_rcm(rseed).to_bytes(),
+        }
+    }
+
+    /// `esk` a receiver can check `epk` against: 0x02 notes derive it from
+    /// rseed, 0x01 notes may use any esk
+    pub fn esk(&self) -> Option<[u8; 32]> {
+        match self {
+            NoteRandomness::Rcm(_) => None,
+            NoteRandomness::Rseed(rseed) => Some(rseed_generate_esk(rseed).to_bytes()),
+        }
+    }
+
+    /// `esk` of an output built for the device. 0x01 notes only need a
+    /// random esk, so it is derived from the random `rcm` the same way as
+    /// from rseed, and the device can recompute `epk` for either lead byte.
+    pub fn output_esk(&self) -> [u8; 32] {
+        rseed_generate_esk(self.as_bytes()).to_bytes()
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn zip212_output_lead_byte(testnet: bool, expiry_height: u32) -> u8 {
+    let network = if testnet {
+        Network::Testnet
+    } else {
+        Network::Mainnet
+    };
+    Zip212Policy::for_expiry_height(network, expiry_height).output_lead_byte()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_around_activation() {
+        let canopy = CANOPY_HEIGHT_MAINNET;
+
+        let before = Zip212Policy::new(Network::Mainnet, canopy - 1);
+        assert_eq!(before.output_lead_byte(), LEAD_BYTE_RCM);
+        assert!(before.accepts(LEAD_BYTE_RCM));
+        assert!(!before.accepts(LEAD_BYTE_RSEED));
+
+        let grace = Zip212Policy::new(Network::Mainnet, canopy + ZIP212_GRACE_PERIOD - 1);
+        assert_eq!(grace.output_lead_byte(), LEAD_BYTE_RSEED);
+        assert!(grace.accepts(LEAD_BYTE_RCM));
+        assert!(grace.accepts(LEAD_BYTE_RSEED));
+
+        let after = Zip212Policy::new(Network::Mainnet, canopy + ZIP212_GRACE_PERIOD);
+        assert!(!after.accepts(LEAD_BYTE_RCM));
+        assert!(after.accepts(LEAD_BYTE_RSEED));
+        assert!(!after.accepts(0x03));
+
+        let testnet = Zip212Policy::new(Network::Testnet, CANOPY_HEIGHT_TESTNET);
+        assert_eq!(testnet.output_lead_byte(), LEAD_BYTE_RSEED);
+        let regtest = Zip212Policy::with_canopy_height(100, 99);
+        assert_eq!(regtest.output_lead_byte(), LEAD_BYTE_RCM);
+    }
+
+    #[test]
+    fn test_policy_from_expiry_height() {
+        let canopy = CANOPY_HEIGHT_MAINNET;
+        assert_eq!(zip212_output_lead_byte(false, canopy - 1), LEAD_BYTE_RCM);
+        assert_eq!(zip212_output_lead_byte(false, canopy), LEAD_BYTE_RSEED);
+        assert_eq!(zip212_output_lead_byte(false, 0), LEAD_BYTE_RSEED);
+        assert_eq!(
+            zip212_output_lead_byte(true, CANOPY_HEIGHT_TESTNET - 1),
+            LEAD_BYTE_RCM
+        );
+
+        let bytes = [0x11u8; 32];
+        let before = Zip212Policy::for_expiry_height(Network::Mainnet, canopy - 1);
+        assert_eq!(before.output_randomness(bytes), NoteRandomness::Rcm(bytes));
+        let after = Zip212Policy::for_expiry_height(Network::Mainnet, canopy);
+        assert_eq!(after.output_randomness(bytes), NoteRandomness::Rseed(bytes));
+    }
+
+    #[test]
+    fn test_randomness() {
+        let bytes = [0x11u8; 32];
+        let v1 = NoteRandomness::from_lead_byte(LEAD_BYTE_RCM, bytes).unwrap();
+        assert_eq!(v1.rcm(), bytes);
+        assert_eq!(v1.esk(), None);
+        assert_eq!(v1.output_esk(), rseed_generate_esk(&bytes).to_bytes());
+
+        let v2 = NoteRandomness::from_lead_byte(LEAD_BYTE_RSEED, bytes).unwrap();
+        assert_eq!(v2.rcm(), rseed_generate_rcm(&bytes).to_bytes());
+        assert_eq!(v2.esk(), Some(rseed_generate_esk(&bytes).to_bytes()));
+        assert_eq!(v2.esk(), Some(v2.output_esk()));
+        assert_eq!(v2.as_bytes(), &bytes);
+
+        assert_eq!(
+            NoteRandomness::from_lead_byte(0, bytes),
+            Err(ParserError::parser_unexpected_value)
+        );
+    }
+}
@@ -0,0 +1,285 @@
+//! ZIP-304 message signatures proving control of a Sapling address.
+//!
+//! Signing happens in two steps. `prepare` creates a fake 1-zatoshi note to
+//! the address and returns the randomised key `rk`, its nullifier and the
+//! inputs the host needs to build the Spend proof. Once the host returns the
+//! proof, `sign` authorises `zkproof || message` with the randomised spend
+//! authorizing key. `verify` checks everything except the proof itself.
+//!
+//! This is a Rust API for hosts holding the seed; the device does not export
+//! it, as no APDU shows the message to the user before signing.
+
+use jubjub::{ExtendedPoint, Fr};
+
+use crate::bolos::blake2b32_with_personalization;
+use crate::commitments::{bytes_to_extended, note_commitment_full, nullifier};
+use crate::constants::SPENDING_KEY_BASE;
+use crate::errors::ParserError;
+use crate::redjubjub::{
+    bytes_to_scalar, h_star, random_scalar, randomized_pk, randomized_secret_from_sk, sign_complete,
+};
+use crate::secret::Secret;
+use crate::types::{Ak, Diversifier, Nsk, Nullifier, PaymentAddress, Signature, SpendingKey};
+use crate::zip32::{derive_proof_key, payment_address};
+
+pub const ZIP304_PERSONALIZATION_PREFIX: &[u8; 12] = b"ZIP304Signed";
+/// Value of the fake note spent by the signature
+pub const ZIP304_NOTE_VALUE: u64 = 1;
+pub const ZKPROOF_SIZE: usize = 192;
+/// Longest message the device will sign
+pub const ZIP304_MAX_MESSAGE_LEN: usize = 512;
+pub const ZIP304_SIGNATURE_SIZE: usize = 32 + 32 + ZKPROOF_SIZE + 64;
+
+/// Randomness fixed by `prepare` and reused by `sign`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Zip304Request {
+    pub address: PaymentAddress,
+    pub rcm: [u8; 32],
+    pub alpha: [u8; 32],
+    pub rk: [u8; 32],
+    pub nullifier: Nullifier,
+}
+
+/// Witness the host needs to create the Spend proof for the fake note
+pub struct ProofInputs {
+    pub ak: Ak,
+    pub nsk: Secret<Nsk>,
+    pub alpha: [u8; 32],
+    pub rcm: [u8; 32],
+    pub value: u64,
+    pub address: PaymentAddress,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Zip304Signature {
+    pub nullifier: Nullifier,
+    pub rk: [u8; 32],
+    pub zkproof: [u8; ZKPROOF_SIZE],
+    pub spend_auth_sig: Signature,
+}
+
+impl Zip304Signature {
+    /// `nullifier || rk || zkproof || spendAuthSig`
+    pub fn to_bytes(&self) -> [u8; ZIP304_SIGNATURE_SIZE] {
+        let mut out = [0u8; ZIP304_SIGNATURE_SIZE];
+        out[..32].copy_from_slice(&self.nullifier.0);
+        out[32..64].copy_from_slice(&self.rk);
+        out[64..64 + ZKPROOF_SIZE].copy_from_slice(&self.zkproof);
+        out[64 + ZKPROOF_SIZE..].copy_from_slice(&self.spend_auth_sig.0);
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8; ZIP304_SIGNATURE_SIZE]) -> Self {
+        let mut nullifier = [0u8; 32];
+        let mut rk = [0u8; 32];
+        let mut zkproof = [0u8; ZKPROOF_SIZE];
+        let mut sig = [0u8; 64];
+        nullifier.copy_from_slice(&bytes[..32]);
+        rk.copy_from_slice(&bytes[32..64]);
+        zkproof.copy_from_slice(&bytes[64..64 + ZKPROOF_SIZE]);
+        sig.copy_from_slice(&bytes[64 + ZKPROOF_SIZE..]);
+        Zip304Signature {
+            nullifier: Nullifier(nullifier),
+            rk,
+            zkproof,
+            spend_auth_sig: Signature(sig),
+        }
+    }
+}
+
+/// `rk || BLAKE2b-256("ZIP304Signed" || coin_type, zkproof || message)`,
+/// the message covered by the spend authorization signature
+fn signed_message(
+    rk: &[u8; 32],
+    zkproof: &[u8; ZKPROOF_SIZE],
+    message: &[u8],
+    coin_type: u32,
+) -> Result<[u8; 64], ParserError> {
+    if message.len() > ZIP304_MAX_MESSAGE_LEN {
+        return Err(ParserError::parser_value_too_many_bytes);
+    }
+    let mut personal = [0u8; 16];
+    personal[..12].copy_from_slice(ZIP304_PERSONALIZATION_PREFIX);
+    personal[12..].copy_from_slice(&coin_type.to_le_bytes());
+
+    let mut data = [0u8; ZKPROOF_SIZE + ZIP304_MAX_MESSAGE_LEN];
+    data[..ZKPROOF_SIZE].copy_from_slice(zkproof);
+    data[ZKPROOF_SIZE..ZKPROOF_SIZE + message.len()].copy_from_slice(message);
+    let digest = blake2b32_with_personalization(&personal, &data[..ZKPROOF_SIZE + message.len()]);
+
+    let mut out = [0u8; 64];
+    out[..32].copy_from_slice(rk);
+    out[32..].copy_from_slice(&digest);
+    Ok(out)
+}
+
+/// Picks the fake note and randomiser for signing with `address`, which
+/// must belong to ZIP32 account `pos`
+#[inline(never)]
+pub fn prepare(
+    sk: &SpendingKey,
+    pos: u32,
+    address: &PaymentAddress,
+) -> Result<(Zip304Request, ProofInputs), ParserError> {
+    if payment_address(sk, pos, &address.diversifier)? != *address {
+        return Err(ParserError::parser_invalid_address);
+    }
+    let rcm = random_scalar().to_bytes();
+    let alpha = random_scalar().to_bytes();
+
+    let (ak, nsk) = derive_proof_key(sk, pos);
+    let rk = randomized_pk(&ak.0, &alpha)?;
+    let cm = note_commitment_full(ZIP304_NOTE_VALUE, &address.diversifier, &address.pk_d, &rcm)?;
+    let nf = nullifier(&cm, 0, &nsk)?;
+
+    let request = Zip304Request {
+        address: *address,
+        rcm,
+        alpha,
+        rk,
+        nullifier: nf,
+    };
+    let inputs = ProofInputs {
+        ak,
+        nsk,
+        alpha,
+        rcm,
+        value: ZIP304_NOTE_VALUE,
+        address: *address,
+    };
+    Ok((request, inputs))
+}
+
+/// Signs `message` once the host has produced `zkproof` for `request`
+#[inline(never)]
+pub fn sign(
+    sk: &SpendingKey,
+    pos: u32,
+    request: &Zip304Request,
+    zkproof: &[u8; ZKPROOF_SIZE],
+    message: &[u8],
+    coin_type: u32,
+) -> Result<Zip304Signature, ParserError> {
+    Ok(Zip304Signature {
+        nullifier: request.nullifier,
+        rk: request.rk,
+        zkproof: *zkproof,
+        spend_auth_sig: spend_auth_sig(
+            sk,
+            pos,
+            &request.alpha,
+            &request.rk,
+            zkproof,
+            message,
+            coin_type,
+        )?,
+    })
+}
+
+fn spend_auth_sig(
+    sk: &SpendingKey,
+    pos: u32,
+    alpha: &[u8; 32],
+    rk: &[u8; 32],
+    zkproof: &[u8; ZKPROOF_SIZE],
+    message: &[u8],
+    coin_type: u32,
+) -> Result<Signature, ParserError> {
+    let msg = signed_message(rk, zkproof, message, coin_type)?;
+    let rsk = randomized_secret_from_sk(sk, pos, alpha)?;
+    let rsk = Secret::new(bytes_to_scalar(&rsk)?);
+    Ok(Signature(sign_complete(&msg, &rsk)))
+}
+
+/// RedJubjub verification of `sig` on `msg` under `vk`
+#[inline(never)]
+pub fn verify_spend_auth_sig(
+    vk: &[u8; 32],
+    msg: &[u8],
+    sig: &Signature,
+) -> Result<(), ParserError> {
+    let mut rbar = [0u8; 32];
+    let mut sbar = [0u8; 32];
+    rbar.copy_from_slice(&sig.0[..32]);
+    sbar.copy_from_slice(&sig.0[32..]);
+
+    let r = bytes_to_extended(rbar)?;
+    let s = bytes_to_scalar(&sbar)?;
+    let vk = bytes_to_extended(*vk)?;
+    let c: Fr = h_star(&rbar, msg);
+
+    let sg = SPENDING_KEY_BASE.multiply_bits(&s.to_bytes());
+    let check: ExtendedPoint = sg - r - vk * c;
+    if bool::from(check.mul_by_cofactor().is_identity()) {
+        Ok(())
+    } else {
+        Err(ParserError::parser_unexpected_value)
+    }
+}
+
+/// Checks the spend authorization signature of a ZIP-304 signature. The
+/// Spend proof, which binds `nullifier` and `rk` to the address, must be
+/// verified separately.
+pub fn verify(sig: &Zip304Signature, message: &[u8], coin_type: u32) -> Result<(), ParserError> {
+    let msg = signed_message(&sig.rk, &sig.zkproof, message, coin_type)?;
+    verify_spend_auth_sig(&sig.rk, &msg, &sig.spend_auth_sig)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::zip32::default_payment_address_from_startindex;
+
+    const COIN_TYPE: u32 = 133;
+
+    fn account() -> (SpendingKey, PaymentAddress) {
+        let sk = SpendingKey([0x2au8; 32]);
+        let mut start = Diversifier([0u8; 11]);
+        let address = default_payment_address_from_startindex(&sk, 0, &mut start).unwrap();
+        (sk, address)
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let (sk, address) = account();
+        let (request, inputs) = prepare(&sk, 0, &address).unwrap();
+        assert_eq!(inputs.value, ZIP304_NOTE_VALUE);
+        assert_eq!(randomized_pk(&inputs.ak.0, &inputs.alpha), Ok(request.rk));
+
+        let zkproof = [0x5au8; ZKPROOF_SIZE];
+        let message = b"I control this address (exchange attestation #42)";
+        let sig = sign(&sk, 0, &request, &zkproof, message, COIN_TYPE).unwrap();
+        assert_eq!(Zip304Signature::from_bytes(&sig.to_bytes()), sig);
+        assert_eq!(verify(&sig, message, COIN_TYPE), Ok(()));
+
+        assert!(verify(&sig, b"another message", COIN_TYPE).is_err());
+        assert!(verify(&sig, message, 1).is_err());
+        let mut other_proof = sig;
+        other_proof.zkproof[0] ^= 1;
+        assert!(verify(&other_proof, message, COIN_TYPE).is_err());
+    }
+
+    #[test]
+    fn test_nullifier_depends_on_note() {
+        let (sk, address) = account();
+        let (a, _) = prepare(&sk, 0, &address).unwrap();
+        let (b, _) = prepare(&sk, 0, &address).unwrap();
+        assert_ne!(a.nullifier, b.nullifier);
+        assert_ne!(a.rk, b.rk);
+    }
+
+    #[test]
+    fn test_rejects_foreign_address() {
+        let (sk, address) = account();
+        assert_eq!(
+            prepare(&sk, 1, &address).err(),
+            Some(ParserError::parser_invalid_address)
+        );
+        let (request, _) = prepare(&sk, 0, &address).unwrap();
+        let long = [0u8; ZIP304_MAX_MESSAGE_LEN + 1];
+        assert_eq!(
+            sign(&sk, 0, &request, &[0u8; ZKPROOF_SIZE], &long, COIN_TYPE).err(),
+            Some(ParserError::parser_value_too_many_bytes)
+        );
+    }
+}
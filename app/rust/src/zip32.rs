@@ -15,38 +15,29 @@ use itertools::zip;
 use jubjub::{AffineNielsPoint, AffinePoint, ExtendedPoint, Fq, Fr};
 
 use crate::commitments::bytes_to_extended;
+use crate::errors::ParserError;
 use crate::pedersen::extended_to_bytes;
-use crate::{bolos, c_check_app_canary, constants};
-
-
-extern "C" {
-    fn zemu_log_stack(buffer: *const u8);
-}
-
-#[cfg(not(test))]
-pub fn c_zemu_log_stack(s: &[u8]) {
-    unsafe { zemu_log_stack(s.as_ptr()) }
-}
-
-#[cfg(test)]
-pub fn c_zemu_log_stack(_s: &[u8]) {}
+use crate::secret::Secret;
+use crate::types::{Ak, Ask, Diversifier, Ivk, Nk, Nsk, Ovk, PaymentAddress, SpendingKey};
+use crate::{bolos, c_check_app_canary, c_zemu_log_stack, constants};
 
+/// `PRF^expand`; the output is key material and is wiped on drop
 #[inline(always)]
-pub fn prf_expand(sk: &[u8], t: &[u8]) -> [u8; 64] {
-    bolos::blake2b_expand_seed(sk, t)
+pub fn prf_expand(sk: &[u8], t: &[u8]) -> Secret<[u8; 64]> {
+    Secret::new(bolos::blake2b_expand_seed(sk, t))
 }
 
 #[inline(never)]
 pub fn sapling_derive_dummy_ask(sk_in: &[u8]) -> [u8; 32] {
-    let t = prf_expand(&sk_in, &[0x00]);
-    let ask = Fr::from_bytes_wide(&t);
+    let t = prf_expand(sk_in, &[0x00]);
+    let ask = Secret::new(Fr::from_bytes_wide(&t));
     ask.to_bytes()
 }
 
 #[inline(never)]
 pub fn sapling_derive_dummy_nsk(sk_in: &[u8]) -> [u8; 32] {
-    let t = prf_expand(&sk_in, &[0x01]);
-    let nsk = Fr::from_bytes_wide(&t);
+    let t = prf_expand(sk_in, &[0x01]);
+    let nsk = Secret::new(Fr::from_bytes_wide(&t));
     nsk.to_bytes()
 }
 
@@ -59,7 +50,7 @@ pub fn sapling_ask_to_ak(ask: &[u8; 32]) -> [u8; 32] {
 
 #[inline(never)]
 pub fn sapling_nsk_to_nk(nsk: &[u8; 32]) -> [u8; 32] {
-    let nk = constants::PROVING_KEY_BASE.multiply_bits(&nsk);
+    let nk = constants::PROVING_KEY_BASE.multiply_bits(nsk);
     AffinePoint::from(nk).to_bytes()
 }
 
@@ -236,26 +227,24 @@ pub fn ff1aes_list_with_startingindex(
     }
 }
 
+/// Hashes a diversifier to a point, failing if it does not yield a valid `g_d`
 #[inline(never)]
-pub fn pkd_group_hash(d: &[u8; 11]) -> [u8; 32] {
+fn diversifier_to_point(d: &[u8; 11]) -> Result<ExtendedPoint, ParserError> {
     let h = bolos::blake2s_diversification(d);
-
-    let v = AffinePoint::from_bytes(h).unwrap();
-    let q = v.mul_by_cofactor();
-    let t = AffinePoint::from(q);
-    t.to_bytes()
+    bytes_to_extended(h).map_err(|_| ParserError::parser_invalid_diversifier)
 }
 
 #[inline(never)]
-pub fn multwithgd(scalar: &[u8; 32], d: &[u8; 11]) -> [u8; 32] {
-    let h = bolos::blake2s_diversification(d);
+pub fn pkd_group_hash(d: &[u8; 11]) -> Result<[u8; 32], ParserError> {
+    let q = diversifier_to_point(d)?.mul_by_cofactor();
+    Ok(extended_to_bytes(&q))
+}
 
-    let v = AffinePoint::from_bytes(h)
-        .unwrap()
-        .mul_by_cofactor()
-        .to_niels();
+#[inline(never)]
+pub fn multwithgd(scalar: &[u8; 32], d: &[u8; 11]) -> Result<[u8; 32], ParserError> {
+    let v = diversifier_to_point(d)?.mul_by_cofactor().to_niels();
     let t = v.multiply_bits(scalar);
-    extended_to_bytes(&t)
+    Ok(extended_to_bytes(&t))
 }
 
 #[inline(never)]
@@ -270,17 +259,16 @@ pub fn niels_multbits(p: &mut ExtendedPoint, b: &[u8; 32]) {
 }
 
 #[inline(never)]
-pub fn default_pkd(ivk: &[u8; 32], d: &[u8; 11]) -> [u8; 32] {
-    let h = bolos::blake2s_diversification(d);
+pub fn default_pkd(ivk: &[u8; 32], d: &[u8; 11]) -> Result<[u8; 32], ParserError> {
     c_zemu_log_stack(b"default_pkd\x00\n".as_ref());
-    let mut y = bytes_to_extended(h);
+    let mut y = diversifier_to_point(d)?;
     c_zemu_log_stack(b"finished bytes_to_extended\x00".as_ref());
     mul_by_cof(&mut y);
 
     niels_multbits(&mut y, ivk);
     let tmp = extended_to_bytes(&y);
     c_zemu_log_stack(b"finished extended_to_bytes\x00".as_ref());
-    tmp
+    Ok(tmp)
 }
 
 #[inline(never)]
@@ -333,36 +321,36 @@ pub fn expandedspendingkey_zip32(key: &[u8; 32]) -> [u8; 96] {
 
 #[inline(never)]
 pub fn update_dk_zip32(key: &[u8; 32], dk: &mut [u8; 32]) {
-    let mut dkcopy = [0u8; 32];
+    let mut dkcopy = Secret::new([0u8; 32]);
     dkcopy.copy_from_slice(dk);
-    dk.copy_from_slice(&bolos::blake2b_expand_vec_two(key, &[0x16], &dkcopy)[0..32]);
+    dk.copy_from_slice(&bolos::blake2b_expand_vec_two(key, &[0x16], &*dkcopy)[0..32]);
 }
 
 #[inline(never)]
 pub fn update_exk_zip32(key: &[u8; 32], exk: &mut [u8; 96]) {
     exk[0..32].copy_from_slice(&sapling_derive_dummy_ask(key));
     exk[32..64].copy_from_slice(&sapling_derive_dummy_nsk(key));
-    let mut ovkcopy = [0u8; 32];
+    let mut ovkcopy = Secret::new([0u8; 32]);
     ovkcopy.copy_from_slice(&exk[64..96]);
-    exk[64..96].copy_from_slice(&bolos::blake2b_expand_vec_two(key, &[0x15], &ovkcopy)[..32]);
+    exk[64..96].copy_from_slice(&bolos::blake2b_expand_vec_two(key, &[0x15], &*ovkcopy)[..32]);
 }
 
 #[inline(never)]
-pub fn derive_zip32_master(seed: &[u8; 32]) -> [u8; 96] {
-    let tmp = master_spending_key_zip32(seed); //64
-    let mut key = [0u8; 32]; //32
-    let mut chain = [0u8; 32]; //32
+pub fn derive_zip32_master(seed: &[u8; 32]) -> Secret<[u8; 96]> {
+    let tmp = Secret::new(master_spending_key_zip32(seed)); //64
+    let mut key = Secret::new([0u8; 32]); //32
+    let mut chain = Secret::new([0u8; 32]); //32
 
     key.copy_from_slice(&tmp[..32]);
     chain.copy_from_slice(&tmp[32..]);
 
-    let ask = Fr::from_bytes_wide(&prf_expand(&key, &[0x00]));
+    let ask = Secret::new(Fr::from_bytes_wide(&prf_expand(&*key, &[0x00])));
 
-    let nsk = Fr::from_bytes_wide(&prf_expand(&key, &[0x01]));
+    let nsk = Secret::new(Fr::from_bytes_wide(&prf_expand(&*key, &[0x01])));
 
-    let divkey = diversifier_key_zip32(&key); //32
-    let mut result = [0u8; 96];
-    result[0..32].copy_from_slice(&divkey);
+    let divkey = Secret::new(diversifier_key_zip32(&key)); //32
+    let mut result = Secret::new([0u8; 96]);
+    result[0..32].copy_from_slice(&*divkey);
     result[32..64].copy_from_slice(&ask.to_bytes());
     result[64..96].copy_from_slice(&nsk.to_bytes());
     result
@@ -377,21 +365,20 @@ ChildIndex::Hardened(pos)
 pub fn derive_zip32_ovk_fromseedandpath(seed: &[u8; 32], path: &[u32]) -> [u8; 32] {
     //ASSERT: len(path) == len(harden)
 
-    let mut tmp = master_spending_key_zip32(seed); //64
-    let mut key = [0u8; 32]; //32
-    let mut chain = [0u8; 32]; //32
+    let mut tmp = Secret::new(master_spending_key_zip32(seed)); //64
+    let mut key = Secret::new([0u8; 32]); //32
+    let mut chain = Secret::new([0u8; 32]); //32
 
     key.copy_from_slice(&tmp[..32]);
     chain.copy_from_slice(&tmp[32..]);
 
-    let mut ask = Fr::from_bytes_wide(&prf_expand(&key, &[0x00]));
+    let mut ask = Secret::new(Fr::from_bytes_wide(&prf_expand(&*key, &[0x00])));
 
-    let mut nsk = Fr::from_bytes_wide(&prf_expand(&key, &[0x01]));
+    let mut nsk = Secret::new(Fr::from_bytes_wide(&prf_expand(&*key, &[0x01])));
 
-    let mut expkey: [u8; 96];
-    expkey = expandedspendingkey_zip32(&key); //96
-                                              //master divkey
-    let mut divkey = [0u8; 32];
+    let mut expkey = Secret::new(expandedspendingkey_zip32(&key)); //96
+                                                                   //master divkey
+    let mut divkey = Secret::new([0u8; 32]);
     divkey.copy_from_slice(&diversifier_key_zip32(&key)); //32
     for &p in path {
         //compute expkey needed for zip32 child derivation
@@ -403,65 +390,68 @@ pub fn derive_zip32_ovk_fromseedandpath(seed: &[u8; 32], path: &[u32]) -> [u8; 3
             LittleEndian::write_u32(&mut le_i, c + (1 << 31));
             //make index LE
             //zip32 child derivation
-            tmp = bolos::blake2b_expand_vec_four(&chain, &[0x11], &expkey, &divkey, &le_i);
+            *tmp = bolos::blake2b_expand_vec_four(&*chain, &[0x11], &*expkey, &*divkey, &le_i);
         //64
         } else {
             //WARNING: CURRENTLY COMPUTING NON-HARDENED PATHS DO NOT FIT IN MEMORY
             let fvk = full_viewingkey(&key);
             let mut le_i = [0; 4];
             LittleEndian::write_u32(&mut le_i, c);
-            tmp = bolos::blake2b_expand_vec_four(&chain, &[0x12], &fvk, &divkey, &le_i);
+            *tmp = bolos::blake2b_expand_vec_four(&*chain, &[0x12], &fvk, &*divkey, &le_i);
         }
         //extract key and chainkey
         key.copy_from_slice(&tmp[..32]);
         chain.copy_from_slice(&tmp[32..]);
 
-        let ask_cur = Fr::from_bytes_wide(&prf_expand(&key, &[0x13]));
-        let nsk_cur = Fr::from_bytes_wide(&prf_expand(&key, &[0x14]));
+        let ask_cur = Secret::new(Fr::from_bytes_wide(&prf_expand(&*key, &[0x13])));
+        let nsk_cur = Secret::new(Fr::from_bytes_wide(&prf_expand(&*key, &[0x14])));
 
-        ask += ask_cur;
-        nsk += nsk_cur;
+        *ask += *ask_cur;
+        *nsk += *nsk_cur;
 
         //new divkey from old divkey and key
         update_dk_zip32(&key, &mut divkey);
         update_exk_zip32(&key, &mut expkey);
     }
     let mut result = [0u8; 32];
-    result[0..32].copy_from_slice(&key);
+    result[0..32].copy_from_slice(&*key);
     result
 }
 
 #[inline(never)]
-pub fn master_nsk_from_seed(seed: &[u8; 32]) -> [u8; 32] {
+pub fn master_nsk_from_seed(seed: &[u8; 32]) -> Secret<[u8; 32]> {
 
-    let tmp = master_spending_key_zip32(seed); //64
-    let mut key = [0u8; 32]; //32
+    let tmp = Secret::new(master_spending_key_zip32(seed)); //64
+    let mut key = Secret::new([0u8; 32]); //32
 
     key.copy_from_slice(&tmp[..32]);
 
-    let nsk = Fr::from_bytes_wide(&prf_expand(&key, &[0x01]));
-    let mut result = [0u8; 32];
+    let nsk = Secret::new(Fr::from_bytes_wide(&prf_expand(&*key, &[0x01])));
+    let mut result = Secret::new([0u8; 32]);
     result.copy_from_slice(&nsk.to_bytes());
     result
 }
 
 #[inline(never)]
-pub fn derive_zip32_child_fromseedandpath(seed: &[u8; 32], path: &[u32], child_components: u8) -> [u8; 96] {
+pub fn derive_zip32_child_fromseedandpath(
+    seed: &[u8; 32],
+    path: &[u32],
+    child_components: u8,
+) -> Secret<[u8; 96]> {
     //ASSERT: len(path) == len(harden)
     c_zemu_log_stack(b"derive_zip32_child start\x00\n".as_ref());
-    let mut tmp = master_spending_key_zip32(seed); //64
+    let mut tmp = Secret::new(master_spending_key_zip32(seed)); //64
 
     // master secret key sk = tmp[..32]
     // chain = tmp[32..]
 
-    let mut ask = Fr::from_bytes_wide(&prf_expand(tmp[..32].try_into().unwrap(), &[0x00]));
+    let mut ask = Secret::new(Fr::from_bytes_wide(&prf_expand(&tmp[..32], &[0x00])));
 
-    let mut nsk = Fr::from_bytes_wide(&prf_expand(tmp[..32].try_into().unwrap(), &[0x01]));
+    let mut nsk = Secret::new(Fr::from_bytes_wide(&prf_expand(&tmp[..32], &[0x01])));
 
-    let mut expkey: [u8; 96];
-    expkey = expandedspendingkey_zip32(&tmp[..32].try_into().unwrap()); //96
+    let mut expkey = Secret::new(expandedspendingkey_zip32(&tmp[..32].try_into().unwrap())); //96
     //master divkey
-    let mut divkey = [0u8; 32];
+    let mut divkey = Secret::new([0u8; 32]);
     divkey.copy_from_slice(&diversifier_key_zip32(&tmp[..32].try_into().unwrap())); //32
     for &p in path {
         //compute expkey needed for zip32 child derivation
@@ -473,21 +463,21 @@ pub fn derive_zip32_child_fromseedandpath(seed: &[u8; 32], path: &[u32], child_c
             LittleEndian::write_u32(&mut le_i, c + (1 << 31));
             //make index LE
             //zip32 child derivation
-            tmp = bolos::blake2b_expand_vec_four(&tmp[32..], &[0x11], &expkey, &divkey, &le_i);
+            *tmp = bolos::blake2b_expand_vec_four(&tmp[32..], &[0x11], &*expkey, &*divkey, &le_i);
             //64
         } else {
             //WARNING: CURRENTLY COMPUTING NON-HARDENED PATHS DO NOT FIT IN MEMORY
             let fvk = full_viewingkey(&tmp[..32].try_into().unwrap());
             let mut le_i = [0; 4];
             LittleEndian::write_u32(&mut le_i, c);
-            tmp = bolos::blake2b_expand_vec_four(&tmp[32..], &[0x12], &fvk, &divkey, &le_i);
+            *tmp = bolos::blake2b_expand_vec_four(&tmp[32..], &[0x12], &fvk, &*divkey, &le_i);
         }
 
-        let ask_cur = Fr::from_bytes_wide(&prf_expand(&tmp[..32], &[0x13]));
-        let nsk_cur = Fr::from_bytes_wide(&prf_expand(&tmp[..32], &[0x14]));
+        let ask_cur = Secret::new(Fr::from_bytes_wide(&prf_expand(&tmp[..32], &[0x13])));
+        let nsk_cur = Secret::new(Fr::from_bytes_wide(&prf_expand(&tmp[..32], &[0x14])));
 
-        ask += ask_cur;
-        nsk += nsk_cur;
+        *ask += *ask_cur;
+        *nsk += *nsk_cur;
 
         //new divkey from old divkey and key
         update_dk_zip32(&tmp[..32].try_into().unwrap(), &mut divkey);
@@ -503,14 +493,14 @@ pub fn derive_zip32_child_fromseedandpath(seed: &[u8; 32], path: &[u32], child_c
     let nk_tmp = constants::PROVING_KEY_BASE.multiply_bits(&nsk.to_bytes());
     let nk = AffinePoint::from(nk_tmp);//.to_bytes();
 
-    let mut result = [0u8; 96];
+    let mut result = Secret::new([0u8; 96]);
     match child_components{
         constants::AK_NK => {
             result[0..32].copy_from_slice(&ak);
             result[32..64].copy_from_slice(&nk.to_bytes());
         }
         constants::DK => {
-            result[0..32].copy_from_slice(&divkey);
+            result[0..32].copy_from_slice(&*divkey);
         }
         constants::AK_NSK => {
             result[0..32].copy_from_slice(&ak);
@@ -521,7 +511,7 @@ pub fn derive_zip32_child_fromseedandpath(seed: &[u8; 32], path: &[u32], child_c
             result[32..64].copy_from_slice(&nsk.to_bytes());
         }
         constants::DK_AK_NK => {
-            result[0..32].copy_from_slice(&divkey);
+            result[0..32].copy_from_slice(&*divkey);
             result[32..64].copy_from_slice(&ak);
             result[64..96].copy_from_slice(&nk.to_bytes());
         }
@@ -534,62 +524,193 @@ pub fn derive_zip32_child_fromseedandpath(seed: &[u8; 32], path: &[u32], child_c
 }
 
 #[inline(never)]
-pub fn group_hash_from_div(diversifier_ptr: *const [u8; 11], gd_ptr: *mut [u8; 32]) {
-    let diversifier = unsafe { &*diversifier_ptr };
-    let gd = unsafe { &mut *gd_ptr };
-    let gd_tmp = pkd_group_hash(diversifier);
-    gd.copy_from_slice(&gd_tmp);
+pub fn group_hash_from_div(diversifier: &Diversifier) -> Result<[u8; 32], ParserError> {
+    pkd_group_hash(&diversifier.0)
 }
 
-#[no_mangle]
-pub fn get_dk(
-    seed_ptr: *const [u8; 32],
-    dk_ptr: *mut [u8; 32],
-    pos: u32,
-) {
-    let seed = unsafe { &*seed_ptr };
-    let dk = unsafe { &mut *dk_ptr };
+#[inline(never)]
+pub fn nsk_to_nk(nsk: &Nsk) -> Nk {
+    Nk(sapling_nsk_to_nk(&nsk.0))
+}
 
-    const FIRSTVALUE: u32 = 32 ^ 0x8000_0000;
-    const COIN_TYPE: u32 = 133 ^ 0x8000_0000; //hardened, fixed value from https://github.com/adityapk00/librustzcash/blob/master/zcash_client_backend/src/constants/mainnet.rs
-    let k = derive_zip32_child_fromseedandpath(seed, &[FIRSTVALUE, COIN_TYPE, pos],
-                                               constants::DK); //consistent with zecwallet
+#[inline(never)]
+pub fn diversifier_is_valid(div: &Diversifier) -> bool {
+    diversifier_group_hash_light(&div.0)
+}
+
+#[inline(never)]
+pub fn derive_dk(sk: &SpendingKey, pos: u32) -> Secret<[u8; 32]> {
+    let k = derive_zip32_child_fromseedandpath(
+        &sk.0,
+        &[constants::FIRSTVALUE, constants::COIN_TYPE, pos],
+        constants::DK,
+    ); //consistent with zecwallet
 
     // k = dk || ...
+    let mut dk = Secret::new([0u8; 32]);
     dk.copy_from_slice(&k[0..32]);
+    dk
+}
+
+#[inline(never)]
+pub fn derive_ivk(sk: &SpendingKey, pos: u32) -> Ivk {
+    c_zemu_log_stack(b"zip32_ivk\x00\n".as_ref());
+    let k = derive_zip32_child_fromseedandpath(
+        &sk.0,
+        &[constants::FIRSTVALUE, constants::COIN_TYPE, pos],
+        constants::AK_NK,
+    ); //consistent with zecwallet
+
+    // k =  ak || nk
+    // ak = k[0..32]
+    // nk = k[32..64]
+    Ivk(aknk_to_ivk(
+        &k[0..32].try_into().unwrap(),
+        &k[32..64].try_into().unwrap(),
+    ))
 }
 
+//this function is consistent with zecwallet code
+#[inline(never)]
+pub fn derive_ovk(sk: &SpendingKey, pos: u32) -> Ovk {
+    Ovk(derive_zip32_ovk_fromseedandpath(
+        &sk.0,
+        &[constants::FIRSTVALUE, constants::COIN_TYPE, pos],
+    ))
+}
 
 #[inline(never)]
-pub fn nsk_to_nk(nsk_ptr: *const [u8; 32], nk_ptr: *mut [u8; 32]) {
-    let nsk = unsafe { &*nsk_ptr };
-    let nk = unsafe { &mut *nk_ptr };
-    let tmp_nk = sapling_nsk_to_nk(&nsk);
-    nk.copy_from_slice(&tmp_nk)
+pub fn derive_proof_key(sk: &SpendingKey, pos: u32) -> (Ak, Secret<Nsk>) {
+    let k = derive_zip32_child_fromseedandpath(
+        &sk.0,
+        &[constants::FIRSTVALUE, constants::COIN_TYPE, pos],
+        constants::AK_NSK,
+    ); //consistent with zecwallet
+
+    // k = ak || nsk
+    (
+        Ak(k[0..32].try_into().unwrap()),
+        Secret::new(Nsk(k[32..64].try_into().unwrap())),
+    )
 }
 
-#[no_mangle]
-pub extern "C" fn zip32_ivk(
-    seed_ptr: *const [u8; 32],
-    ivk_ptr: *mut [u8; 32],
+#[inline(never)]
+pub fn derive_ask_nsk(sk: &SpendingKey, pos: u32) -> (Secret<Ask>, Secret<Nsk>) {
+    let k = derive_zip32_child_fromseedandpath(
+        &sk.0,
+        &[constants::FIRSTVALUE, constants::COIN_TYPE, pos],
+        constants::ASK_NSK,
+    ); //consistent with zecwallet
+
+    // k = ask || nsk
+    (
+        Secret::new(Ask(k[0..32].try_into().unwrap())),
+        Secret::new(Nsk(k[32..64].try_into().unwrap())),
+    )
+}
+
+#[inline(never)]
+pub fn derive_master_nsk(sk: &SpendingKey) -> Secret<Nsk> {
+    Secret::new(Nsk(*master_nsk_from_seed(&sk.0)))
+}
+
+#[inline(never)]
+pub fn diversifier_list_with_startindex(
+    sk: &SpendingKey,
     pos: u32,
-) {
-    c_zemu_log_stack(b"zip32_ivk\x00\n".as_ref());
+    start: &Diversifier,
+) -> [u8; 220] {
+    let dk = derive_dk(sk, pos);
+    let mut list = [0u8; 220];
+    ff1aes_list_with_startingindex(&dk, &start.0, &mut list);
+    list
+}
 
-    let seed = unsafe { &*seed_ptr };
-    let ivk = unsafe { &mut *ivk_ptr };
+/// Computes the next `DIV_DEFAULT_LIST_LEN` diversifiers and advances `start`
+#[inline(never)]
+pub fn default_diversifier_list_with_startindex(
+    sk: &SpendingKey,
+    pos: u32,
+    start: &mut Diversifier,
+) -> [u8; 44] {
+    c_zemu_log_stack(b"get_default_divlist_withstartidx\x00\n".as_ref());
+    let dk = derive_dk(sk, pos);
+    let mut list = [0u8; 44];
+    ff1aes_list_with_startingindex_default(&dk, &mut start.0, &mut list);
+    list
+}
 
-    let k = derive_zip32_child_fromseedandpath(seed,
-                                               &[constants::FIRSTVALUE, constants::COIN_TYPE, pos],
-                                               constants::AK_NK); //consistent with zecwallet
+/// Searches for the first valid diversifier from `start` onwards and
+/// returns the corresponding address, advancing `start` past it
+#[inline(never)]
+pub fn default_payment_address_from_startindex(
+    sk: &SpendingKey,
+    pos: u32,
+    start: &mut Diversifier,
+) -> Result<PaymentAddress, ParserError> {
+    c_zemu_log_stack(b"get_pkd_from_seed\x00\n".as_ref());
+    let mut div_list = [0u8; constants::DIV_SIZE * constants::DIV_DEFAULT_LIST_LEN];
 
-    // k =  ak || nk
-    // ak = k[0..32]
-    // nk = k[32..64]
+    let dk_ak_nk = derive_zip32_child_fromseedandpath(
+        &sk.0,
+        &[constants::FIRSTVALUE, constants::COIN_TYPE, pos],
+        constants::DK_AK_NK,
+    );
+
+    let mut div = Diversifier([0u8; constants::DIV_SIZE]);
+    let mut found = false;
 
-    let tmp_ivk = aknk_to_ivk(&k[0..32].try_into().unwrap(),
-                              &k[32..64].try_into().unwrap());
-    ivk.copy_from_slice(&tmp_ivk)
+    while !found {
+        ff1aes_list_with_startingindex_default(
+            &dk_ak_nk[0..32].try_into().unwrap(),
+            &mut start.0,
+            &mut div_list,
+        );
+        for i in 0..constants::DIV_DEFAULT_LIST_LEN {
+            let candidate = Diversifier(
+                div_list[i * constants::DIV_SIZE..(i + 1) * constants::DIV_SIZE]
+                    .try_into()
+                    .unwrap(),
+            );
+            if !found && diversifier_is_valid(&candidate) {
+                found = true;
+                div = candidate;
+            }
+        }
+    }
+    let ivk = aknk_to_ivk(
+        &dk_ak_nk[32..64].try_into().unwrap(),
+        &dk_ak_nk[64..96].try_into().unwrap(),
+    );
+
+    Ok(PaymentAddress {
+        diversifier: div,
+        pk_d: default_pkd(&ivk, &div.0)?,
+    })
+}
+
+#[inline(never)]
+pub fn payment_address(
+    sk: &SpendingKey,
+    pos: u32,
+    diversifier: &Diversifier,
+) -> Result<PaymentAddress, ParserError> {
+    c_zemu_log_stack(b"get_pkd\x00\n".as_ref());
+    if !diversifier_is_valid(diversifier) {
+        return Err(ParserError::parser_invalid_diversifier);
+    }
+    let ivk = derive_ivk(sk, pos);
+    Ok(PaymentAddress {
+        diversifier: *diversifier,
+        pk_d: default_pkd(&ivk.0, &diversifier.0)?,
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn zip32_ivk(seed_ptr: *const [u8; 32], ivk_ptr: *mut [u8; 32], pos: u32) {
+    let seed = unsafe { SpendingKey::from_ptr(seed_ptr) };
+    let ivk = unsafe { &mut *ivk_ptr };
+    *ivk = derive_ivk(seed, pos).0;
 }
 
 #[no_mangle]
@@ -607,19 +728,13 @@ pub extern "C" fn zip32_master(
     dk.copy_from_slice(&k[32..64])
 }
 
-//this function is consistent with zecwallet code
 #[no_mangle]
 pub extern "C" fn zip32_ovk(seed_ptr: *const [u8; 32], ovk_ptr: *mut [u8; 32], pos: u32) {
-    let seed = unsafe { &*seed_ptr };
+    let seed = unsafe { SpendingKey::from_ptr(seed_ptr) };
     let ovk = unsafe { &mut *ovk_ptr };
-
-    const FIRSTVALUE: u32 = 32 ^ 0x8000_0000;
-    const COIN_TYPE: u32 = 133 ^ 0x8000_0000; //hardened, fixed value from https://github.com/adityapk00/librustzcash/blob/master/zcash_client_backend/src/constants/mainnet.rs
-    let k = derive_zip32_ovk_fromseedandpath(seed, &[FIRSTVALUE, COIN_TYPE, pos]); //consistent with zecwallet
-    ovk.copy_from_slice(&k[0..32]);
+    *ovk = derive_ovk(seed, pos).0;
 }
 
-
 #[no_mangle]
 pub extern "C" fn zip32_child_proof_key(
     seed_ptr: *const [u8; 32],
@@ -627,19 +742,12 @@ pub extern "C" fn zip32_child_proof_key(
     nsk_ptr: *mut [u8; 32],
     pos: u32,
 ) {
-    let seed = unsafe { &*seed_ptr };
+    let seed = unsafe { SpendingKey::from_ptr(seed_ptr) };
     let ak = unsafe { &mut *ak_ptr };
     let nsk = unsafe { &mut *nsk_ptr };
-
-    const FIRSTVALUE: u32 = 32 ^ 0x8000_0000;
-    const COIN_TYPE: u32 = 133 ^ 0x8000_0000; //hardened, fixed value from https://github.com/adityapk00/librustzcash/blob/master/zcash_client_backend/src/constants/mainnet.rs
-    let k = derive_zip32_child_fromseedandpath(seed,
-                                               &[FIRSTVALUE, COIN_TYPE, pos],
-                                               constants::AK_NSK); //consistent with zecwallet
-
-    // k = ak || nsk
-    ak.copy_from_slice(&k[0..32]);
-    nsk.copy_from_slice(&k[32..64]);
+    let (k_ak, k_nsk) = derive_proof_key(seed, pos);
+    *ak = k_ak.0;
+    *nsk = k_nsk.0;
 }
 
 #[no_mangle]
@@ -649,28 +757,19 @@ pub extern "C" fn zip32_child_ask_nsk(
     nsk_ptr: *mut [u8; 32],
     pos: u32,
 ) {
-    let seed = unsafe { &*seed_ptr };
+    let seed = unsafe { SpendingKey::from_ptr(seed_ptr) };
     let ask = unsafe { &mut *ask_ptr };
     let nsk = unsafe { &mut *nsk_ptr };
-
-    let k = derive_zip32_child_fromseedandpath(seed,
-                                               &[constants::FIRSTVALUE, constants::COIN_TYPE, pos],
-                                               constants::ASK_NSK); //consistent with zecwallet;
-    ask.copy_from_slice(&k[0..32]);
-    nsk.copy_from_slice(&k[32..64]);
+    let (k_ask, k_nsk) = derive_ask_nsk(seed, pos);
+    *ask = k_ask.0;
+    *nsk = k_nsk.0;
 }
 
 #[no_mangle]
-pub extern "C" fn zip32_nsk_from_seed(
-    seed_ptr: *const [u8; 32],
-    nsk_ptr: *mut [u8; 32],
-) {
-    let seed = unsafe { &*seed_ptr };
+pub extern "C" fn zip32_nsk_from_seed(seed_ptr: *const [u8; 32], nsk_ptr: *mut [u8; 32]) {
+    let seed = unsafe { SpendingKey::from_ptr(seed_ptr) };
     let nsk = unsafe { &mut *nsk_ptr };
-
-    let k = master_nsk_from_seed(seed);
-
-    nsk.copy_from_slice(&k);
+    *nsk = derive_master_nsk(seed).0;
 }
 
 #[no_mangle]
@@ -690,12 +789,10 @@ pub extern "C" fn get_diversifier_list_withstartindex(
     start_index: *const [u8; 11],
     diversifier_list_ptr: *mut [u8; 220],
 ) {
-    let mut dk =  [0u8; 32];
-    let seed = unsafe { &*seed_ptr };
-    let start = unsafe { &*start_index };
+    let seed = unsafe { SpendingKey::from_ptr(seed_ptr) };
+    let start = unsafe { Diversifier::from_ptr(start_index) };
     let diversifier = unsafe { &mut *diversifier_list_ptr };
-    get_dk(seed,&mut dk,pos);
-    ff1aes_list_with_startingindex(&mut dk, start, diversifier);
+    *diversifier = diversifier_list_with_startindex(seed, pos, start);
 }
 
 #[no_mangle]
@@ -705,13 +802,10 @@ pub extern "C" fn get_default_diversifier_list_withstartindex(
     start_index: *mut [u8; 11],
     diversifier_list_ptr: *mut [u8; 44],
 ) {
-    c_zemu_log_stack(b"get_default_divlist_withstartidx\x00\n".as_ref());
-    let mut dk =  [0u8; 32];
-    let seed = unsafe { &*seed_ptr };
-    let start = unsafe { &mut *start_index };
+    let seed = unsafe { SpendingKey::from_ptr(seed_ptr) };
+    let start = unsafe { &mut *(start_index as *mut Diversifier) };
     let diversifier = unsafe { &mut *diversifier_list_ptr };
-    get_dk(seed,&mut dk,pos);
-    ff1aes_list_with_startingindex_default(&mut dk, start, diversifier);
+    *diversifier = default_diversifier_list_with_startindex(seed, pos, start);
 }
 
 #[no_mangle]
@@ -720,49 +814,25 @@ pub extern "C" fn get_pkd_from_seed(
     pos: u32,
     start_index: *mut [u8; 11],
     diversifier_ptr: *mut [u8; 11],
-    pkd_ptr: *mut [u8; 32])
-{
-    c_zemu_log_stack(b"get_pkd_from_seed\x00\n".as_ref());
-    let seed = unsafe { &*seed_ptr };
-    let start = unsafe { &mut *start_index };
-    let div = unsafe {&mut *diversifier_ptr};
-
-    let mut div_list = [0u8;constants::DIV_SIZE*constants::DIV_DEFAULT_LIST_LEN];
-
-    let dk_ak_nk = derive_zip32_child_fromseedandpath(&seed,
-                                                  &[constants::FIRSTVALUE,
-                                                      constants::COIN_TYPE, pos],
-                                                      constants::DK_AK_NK);
-
-    let mut found = false;
-
-    while !found {
-        ff1aes_list_with_startingindex_default(&mut dk_ak_nk[0..32].try_into().unwrap(),
-                                               start, &mut div_list);
-        for i in 0..constants::DIV_DEFAULT_LIST_LEN
-        {
-            if !found && is_valid_diversifier(
-                &div_list[i*constants::DIV_SIZE..(i+1)*constants::DIV_SIZE].try_into().unwrap())
-            {
-                found = true;
-                div.copy_from_slice(&div_list[i*constants::DIV_SIZE..(i+1)*constants::DIV_SIZE]);
-            }
-        }
-    }
-    let  ivk = aknk_to_ivk(&dk_ak_nk[32..64].try_into().unwrap(),
-                           &dk_ak_nk[64..96].try_into().unwrap());
-
+    pkd_ptr: *mut [u8; 32],
+) -> ParserError {
+    let seed = unsafe { SpendingKey::from_ptr(seed_ptr) };
+    let start = unsafe { &mut *(start_index as *mut Diversifier) };
+    let div = unsafe { &mut *diversifier_ptr };
     let pkd = unsafe { &mut *pkd_ptr };
-    let tmp_pkd = default_pkd(&ivk, div);
-    pkd.copy_from_slice(&tmp_pkd);
+    let addr = match default_payment_address_from_startindex(seed, pos, start) {
+        Ok(addr) => addr,
+        Err(e) => return e,
+    };
+    *div = addr.diversifier.0;
+    *pkd = addr.pk_d;
+    ParserError::parser_ok
 }
 
-
-
 #[no_mangle]
 pub extern "C" fn is_valid_diversifier(div_ptr: *const [u8; 11]) -> bool {
-    let div = unsafe { &*div_ptr };
-    diversifier_group_hash_light(div)
+    let div = unsafe { Diversifier::from_ptr(div_ptr) };
+    diversifier_is_valid(div)
 }
 
 #[no_mangle]
@@ -783,20 +853,23 @@ pub extern "C" fn get_pkd(
     pos: u32,
     diversifier_ptr: *const [u8; 11],
     pkd_ptr: *mut [u8; 32],
-) {
-    c_zemu_log_stack(b"get_pkd\x00\n".as_ref());
-    let ivk_ptr = &mut [0u8;32];
-    let diversifier = unsafe { &*diversifier_ptr };
+) -> ParserError {
+    let seed = unsafe { SpendingKey::from_ptr(seed_ptr) };
+    let diversifier = unsafe { Diversifier::from_ptr(diversifier_ptr) };
     let pkd = unsafe { &mut *pkd_ptr };
-    zip32_ivk(seed_ptr,ivk_ptr, pos);
-
-    let tmp_pkd = default_pkd(ivk_ptr, &diversifier);
-    pkd.copy_from_slice(&tmp_pkd)
+    match payment_address(seed, pos, diversifier) {
+        Ok(addr) => {
+            *pkd = addr.pk_d;
+            ParserError::parser_ok
+        }
+        Err(e) => e,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::secret::harness::wiped_during;
 
     #[test]
     fn test_zip32_master() {
@@ -844,10 +917,10 @@ mod tests {
         dk.copy_from_slice(&dk_ak_nk[0..32]);
 
         let mut ak_derived = [0u8; 32];
-        ak_derived.copy_from_slice(&keys[32..64]);
+        ak_derived.copy_from_slice(&dk_ak_nk[32..64]);
 
         let mut nk_derived = [0u8; 32];
-        nk_derived.copy_from_slice(&keys[64..96]);
+        nk_derived.copy_from_slice(&dk_ak_nk[64..96]);
 
         let mut ask = [0u8; 32];
         ask.copy_from_slice(&ask_nsk[0..32]);
@@ -884,7 +957,7 @@ mod tests {
         ff1aes_list(&dk, &mut listbytes);
         let default_d = default_diversifier_fromlist(&listbytes);
 
-        let pk_d = default_pkd(&ivk, &default_d);
+        let pk_d = default_pkd(&ivk, &default_d).unwrap();
 
         assert_eq!(
             default_d,
@@ -913,18 +986,18 @@ mod tests {
 
         let p: u32 = 1000 | 0x8000_0000;
 
-        let dk_ak_nk = derive_zip32_child_fromseedandpath(&seed, &[p],
+        let dk_ak_nk = derive_zip32_child_fromseedandpath(&seed, &[FIRSTVALUE, COIN_TYPE, p],
                                                           constants::DK_AK_NK);
-        let ask_nsk = derive_zip32_child_fromseedandpath(&seed, &[p],
+        let ask_nsk = derive_zip32_child_fromseedandpath(&seed, &[FIRSTVALUE, COIN_TYPE, p],
                                                          constants::ASK_NSK);
         let mut dk = [0u8; 32];
         dk.copy_from_slice(&dk_ak_nk[0..32]);
 
         let mut ak_derived = [0u8; 32];
-        ak_derived.copy_from_slice(&keys[32..64]);
+        ak_derived.copy_from_slice(&dk_ak_nk[32..64]);
 
         let mut nk_derived = [0u8; 32];
-        nk_derived.copy_from_slice(&keys[64..96]);
+        nk_derived.copy_from_slice(&dk_ak_nk[64..96]);
 
         let mut ask = [0u8; 32];
         ask.copy_from_slice(&ask_nsk[0..32]);
@@ -932,6 +1005,11 @@ mod tests {
         let mut nsk = [0u8; 32];
         nsk.copy_from_slice(&ask_nsk[32..64]);
 
+        let nk: [u8; 32] = sapling_nsk_to_nk(&nsk);
+        let ak: [u8; 32] = sapling_ask_to_ak(&ask);
+
+        assert_eq!(ak, ak_derived);
+        assert_eq!(nk, nk_derived);
 
         let ivk = aknk_to_ivk(&ak, &nk);
 
@@ -947,7 +1025,7 @@ mod tests {
         ff1aes_list(&dk, &mut list);
         let default_d = default_diversifier_fromlist(&list);
 
-        let pk_d = default_pkd(&ivk, &default_d);
+        let pk_d = default_pkd(&ivk, &default_d).unwrap();
 
         assert_eq!(
             default_d,
@@ -988,7 +1066,7 @@ mod tests {
         ff1aes_list(&dk, &mut list);
         let default_d = default_diversifier_fromlist(&list);
 
-        let pk_d = default_pkd(&ivk, &default_d);
+        let pk_d = default_pkd(&ivk, &default_d).unwrap();
 
         assert_eq!(
             default_d,
@@ -1026,7 +1104,7 @@ mod tests {
         ff1aes_list(&dk, &mut list);
         let default_d = default_diversifier_fromlist(&list);
 
-        let pk_d = default_pkd(&ivk, &default_d);
+        let pk_d = default_pkd(&ivk, &default_d).unwrap();
 
         assert_eq!(
             default_d,
@@ -1060,7 +1138,7 @@ mod tests {
             0xf1, 0x9d, 0x9b, 0x79, 0x7e, 0x39, 0xf3, 0x37, 0x44, 0x58, 0x39,
         ];
 
-        let result = pkd_group_hash(&default_d);
+        let result = pkd_group_hash(&default_d).unwrap();
         let x = super::AffinePoint::from_bytes(result);
         if x.is_some().unwrap_u8() == 1 {
             let y = super::ExtendedPoint::from(x.unwrap());
@@ -1096,7 +1174,7 @@ mod tests {
             0xf1, 0x9d, 0x9b, 0x79, 0x7e, 0x39, 0xf3, 0x37, 0x44, 0x58, 0x39,
         ];
 
-        let result = pkd_group_hash(&default_d);
+        let result = pkd_group_hash(&default_d).unwrap();
         let x = super::AffinePoint::from_bytes(result);
         assert_eq!(x.is_some().unwrap_u8(), 1);
         assert_eq!(
@@ -1180,4 +1258,18 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_secrets_wiped_after_ffi() {
+        let seed = [0u8; 32];
+        let mut ask = [0u8; 32];
+        let mut nsk = [0u8; 32];
+        let mut dk = [0u8; 32];
+
+        assert!(wiped_during(|| zip32_child_ask_nsk(&seed, &mut ask, &mut nsk, 1000)) > 0);
+        assert!(wiped_during(|| zip32_child_proof_key(&seed, &mut ask, &mut nsk, 1000)) > 0);
+        assert!(wiped_during(|| zip32_nsk_from_seed(&seed, &mut nsk)) > 0);
+        assert!(wiped_during(|| zip32_master(&seed, &mut ask, &mut dk)) > 0);
+        assert!(wiped_during(|| zip32_ivk(&seed, &mut dk, 1000)) > 0);
+    }
 }
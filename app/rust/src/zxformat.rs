@@ -7,10 +7,29 @@ use nom::error::ParseError;
 
 pub const MAX_STR_BUFF_LEN: usize = 30;
 
+#[cfg(not(any(test, feature = "std")))]
 extern "C" {
     pub fn fp_uint64_to_str(out: *mut i8, outLen: u16, value: u64, decimals: u8) -> u16;
 }
 
+/// Host replacement for the zxlib `fp_uint64_to_str`
+///
+/// # Safety
+/// `out` must be valid for writes of `outLen` bytes.
+#[cfg(any(test, feature = "std"))]
+pub unsafe fn fp_uint64_to_str(out: *mut i8, outLen: u16, value: u64, decimals: u8) -> u16 {
+    let out = core::slice::from_raw_parts_mut(out as *mut u8, outLen as usize);
+    match fpu64_to_str(out, value, decimals) {
+        Ok(len) => len as u16,
+        Err(_) => {
+            let err = b"ERR";
+            let len = err.len().min(out.len().saturating_sub(1));
+            out[..len].copy_from_slice(&err[..len]);
+            len as u16
+        }
+    }
+}
+
 pub struct Writer<'a> {
     buf: &'a mut [u8],
     pub(crate) offset: usize,
@@ -45,7 +64,7 @@ macro_rules! num_to_str {
             if output.len() < 2 {
                 return Err(ParserError::parser_unexpected_buffer_end);
             }
-            let len = if cfg!(test) {
+            let len = if cfg!(any(test, feature = "std")) {
                 let mut writer = Writer::new(output);
                 core::write!(writer, "{}", number)
                     .map_err(|_| ParserError::parser_unexpected_buffer_end)?;
@@ -104,8 +123,8 @@ pub fn fpu64_to_str_check_test(
     value: u64,
     decimals: u8,
 ) -> Result<usize, ParserError> {
-    let len = if cfg!(test) {
-        fpu64_to_str(out, value, decimals)? as usize
+    let len = if cfg!(any(test, feature = "std")) {
+        fpu64_to_str(out, value, decimals)?
     } else {
         unsafe { fp_uint64_to_str(out.as_mut_ptr() as _, out.len() as _, value, decimals) as usize }
     };
@@ -184,6 +203,20 @@ pub(crate) fn fpstr_to_str(
         .map_err(|_| ParserError::parser_unexpected_buffer_end)
 }
 
+/// Lowercase hex encoding of `input`, returning the number of characters written
+pub fn bytes_to_hex_str(out: &mut [u8], input: &[u8]) -> Result<usize, ParserError> {
+    const HEX_CHARS: &[u8; 16] = b"0123456789abcdef";
+    let len = input.len() * 2;
+    if out.len() < len {
+        return Err(ParserError::parser_unexpected_buffer_end);
+    }
+    for (chunk, b) in out.chunks_exact_mut(2).zip(input) {
+        chunk[0] = HEX_CHARS[(b >> 4) as usize];
+        chunk[1] = HEX_CHARS[(b & 0x0f) as usize];
+    }
+    Ok(len)
+}
+
 #[inline(never)]
 pub fn pageString(out_value: &mut [u8], in_value: &[u8], page_idx: u8) -> Result<u8, ParserError> {
     // Just ensure the buffer is clear
@@ -220,6 +253,65 @@ pub fn pageString(out_value: &mut [u8], in_value: &[u8], page_idx: u8) -> Result
     Ok(page_count)
 }
 
+/// Like `pageString`, but shows `in_value` as lowercase hex without
+/// materialising the whole encoding
+#[inline(never)]
+pub fn pageHex(out_value: &mut [u8], in_value: &[u8], page_idx: u8) -> Result<u8, ParserError> {
+    const HEX_CHARS: &[u8; 16] = b"0123456789abcdef";
+    for i in out_value.iter_mut() {
+        *i = 0u8;
+    }
+    let out_len = out_value.len().saturating_sub(1);
+    let in_len = in_value.len() * 2;
+    if out_len == 0 || in_len == 0 {
+        return Err(ParserError::parser_no_data);
+    }
+    let page_count = in_len.div_ceil(out_len);
+    if page_count > u8::MAX as usize {
+        return Err(ParserError::parser_value_out_of_range);
+    }
+
+    let start = page_idx as usize * out_len;
+    let end = in_len.min(start + out_len);
+    for (o, c) in out_value.iter_mut().zip(start..end) {
+        let b = in_value[c / 2];
+        let nibble = if c % 2 == 0 { b >> 4 } else { b & 0x0f };
+        *o = HEX_CHARS[nibble as usize];
+    }
+    Ok(page_count as u8)
+}
+
+/// Like `pageString`, but pages end on character boundaries so that a
+/// multi-byte UTF-8 character is never split between two screens
+#[inline(never)]
+pub fn pageUtf8(out_value: &mut [u8], in_value: &str, page_idx: u8) -> Result<u8, ParserError> {
+    for i in out_value.iter_mut() {
+        *i = 0u8;
+    }
+    let out_len = out_value.len().saturating_sub(1);
+    // every page must have room for the widest character
+    if out_len < 4 || in_value.is_empty() {
+        return Err(ParserError::parser_no_data);
+    }
+
+    let mut page_count = 0u8;
+    let mut start = 0;
+    while start < in_value.len() {
+        let mut end = in_value.len().min(start + out_len);
+        while !in_value.is_char_boundary(end) {
+            end -= 1;
+        }
+        if page_count == page_idx {
+            out_value[..end - start].copy_from_slice(&in_value.as_bytes()[start..end]);
+        }
+        page_count = page_count
+            .checked_add(1)
+            .ok_or(ParserError::parser_value_out_of_range)?;
+        start = end;
+    }
+    Ok(page_count)
+}
+
 #[cfg(test)]
 mod test {
     extern crate std;
@@ -326,6 +418,48 @@ mod test {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_bytes_to_hex_str() {
+        let mut out = [0u8; 8];
+        let len = bytes_to_hex_str(&mut out, &[0x01, 0xab, 0xf0]).unwrap();
+        assert_eq!(&out[..len], b"01abf0");
+        assert_eq!(
+            bytes_to_hex_str(&mut out[..5], &[0x01, 0xab, 0xf0]),
+            Err(ParserError::parser_unexpected_buffer_end)
+        );
+    }
+
+    #[test]
+    fn test_paging_hex() {
+        let data = [0x01u8, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef];
+        let mut out = [0u8; 6];
+        assert_eq!(pageHex(&mut out, &data, 0), Ok(4));
+        assert_eq!(&out, b"01234\0");
+        assert_eq!(pageHex(&mut out, &data, 3), Ok(4));
+        assert_eq!(&out, b"f\0\0\0\0\0");
+        assert_eq!(pageHex(&mut out, &data, 4), Ok(4));
+        assert_eq!(out, [0u8; 6]);
+        assert_eq!(pageHex(&mut out, &[], 0), Err(ParserError::parser_no_data));
+    }
+
+    #[test]
+    fn test_paging_utf8() {
+        // "aé☕" is 1 + 2 + 3 bytes: the 4 byte pages must not cut into "☕"
+        let mut out = [0u8; 5];
+        assert_eq!(pageUtf8(&mut out, "aé☕b", 0), Ok(2));
+        assert_eq!(&out, "aé\0\0".as_bytes());
+        assert_eq!(pageUtf8(&mut out, "aé☕b", 1), Ok(2));
+        assert_eq!(&out, "☕b\0".as_bytes());
+        assert_eq!(pageUtf8(&mut out, "aé☕b", 2), Ok(2));
+        assert_eq!(out, [0u8; 5]);
+
+        let mut small = [0u8; 4];
+        assert_eq!(
+            pageUtf8(&mut small, "a", 0),
+            Err(ParserError::parser_no_data)
+        );
+    }
+
     #[test]
     fn test_paging_string() {
         let inValue = b"abcdabcdabcd";
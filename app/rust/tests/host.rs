@@ -0,0 +1,18 @@
+use rslib::types::{Diversifier, SpendingKey};
+use rslib::zip32::{default_payment_address_from_startindex, payment_address};
+use rslib::zxformat::fpu64_to_str_check_test;
+
+#[test]
+fn host_payment_address() {
+    let sk = SpendingKey([0u8; 32]);
+    let mut start = Diversifier([0u8; 11]);
+    let addr = default_payment_address_from_startindex(&sk, 1000, &mut start).unwrap();
+    assert_eq!(payment_address(&sk, 1000, &addr.diversifier), Ok(addr));
+}
+
+#[test]
+fn host_fixed_point_format() {
+    let mut out = [0u8; 32];
+    let len = fpu64_to_str_check_test(&mut out, 150_000_000, 8).unwrap();
+    assert_eq!(&out[..len], b"1.50000000");
+}
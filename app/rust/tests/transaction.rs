@@ -1 +1,144 @@
+use std::fs;
 
+use serde::Deserialize;
+
+use rslib::errors::ParserError;
+use rslib::transaction::{Transaction, SAPLING_VERSION_GROUP_ID};
+
+#[derive(Deserialize)]
+struct Fixture {
+    raw_tx: String,
+    vin_sz: usize,
+    vout_sz: usize,
+}
+
+fn load(name: &str) -> (Fixture, Vec<u8>) {
+    let path = format!("{}/tests/{}", env!("CARGO_MANIFEST_DIR"), name);
+    let fixture: Fixture = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+    let raw = hex::decode(&fixture.raw_tx).unwrap();
+    (fixture, raw)
+}
+
+fn roundtrip(raw: &[u8]) -> Transaction<'_> {
+    let tx = Transaction::from_bytes(raw).unwrap();
+    let mut out = vec![0u8; raw.len()];
+    let len = tx.write(&mut out).unwrap();
+    assert_eq!(&out[..len], raw);
+    tx
+}
+
+#[test]
+fn v1_fixtures_roundtrip() {
+    for name in &["1i2o_simple.json", "tx2.json", "unspentable_output.json"] {
+        let (fixture, raw) = load(name);
+        let tx = roundtrip(&raw);
+        assert!(!tx.overwintered);
+        assert_eq!(tx.version, 1);
+        assert_eq!(tx.inputs.len(), fixture.vin_sz);
+        assert_eq!(tx.outputs.len(), fixture.vout_sz);
+        assert!(tx.joinsplits.is_empty());
+    }
+}
+
+#[test]
+fn v1_fields() {
+    let (_, raw) = load("1i2o_simple.json");
+    let tx = Transaction::from_bytes(&raw).unwrap();
+
+    let input = tx.inputs.iter().next().unwrap();
+    assert_eq!(input.prevout_index, 1);
+    assert_eq!(input.sequence, 0xffff_ffff);
+    assert_eq!(input.script.len(), 0x6a);
+
+    let values: Vec<u64> = tx.outputs.iter().map(|o| o.value).collect();
+    assert_eq!(values, vec![113_436, 2_376_498]);
+}
+
+#[test]
+fn coinbase_fixture_has_trailing_sighash_type() {
+    // The raw_tx carries the 4-byte sighash type appended for signing
+    let (_, raw) = load("coinbase.json");
+    assert_eq!(
+        Transaction::from_bytes(&raw).err(),
+        Some(ParserError::parser_unexpected_unparsed_bytes)
+    );
+    roundtrip(&raw[..raw.len() - 4]);
+}
+
+#[test]
+fn segwit_fixture_is_rejected() {
+    // Bitcoin segwit encoding is not a valid Zcash transaction
+    let (_, raw) = load("tx_with_witnesses.json");
+    assert!(Transaction::from_bytes(&raw).is_err());
+}
+
+fn sapling_tx(spends: usize, outputs: usize) -> Vec<u8> {
+    let mut raw = vec![];
+    raw.extend_from_slice(&(4u32 | 1 << 31).to_le_bytes());
+    raw.extend_from_slice(&SAPLING_VERSION_GROUP_ID.to_le_bytes());
+    raw.push(0); // vin
+    raw.push(0); // vout
+    raw.extend_from_slice(&0u32.to_le_bytes()); // lock_time
+    raw.extend_from_slice(&1_000_000u32.to_le_bytes()); // expiry_height
+    raw.extend_from_slice(&(-10_000i64).to_le_bytes()); // value_balance
+    raw.push(spends as u8);
+    for i in 0..spends {
+        raw.extend(std::iter::repeat_n(i as u8 + 1, 384));
+    }
+    raw.push(outputs as u8);
+    for i in 0..outputs {
+        raw.extend(std::iter::repeat_n(i as u8 + 0x80, 948));
+    }
+    raw.push(0); // joinsplits
+    if spends + outputs > 0 {
+        raw.extend_from_slice(&[0x55; 64]);
+    }
+    raw
+}
+
+#[test]
+fn sapling_v4_roundtrip() {
+    let raw = sapling_tx(2, 1);
+    let tx = roundtrip(&raw);
+    assert!(tx.overwintered);
+    assert_eq!(tx.version, 4);
+    assert_eq!(tx.expiry_height, 1_000_000);
+    assert_eq!(tx.value_balance, -10_000);
+    assert_eq!(tx.shielded_spends.len(), 2);
+    assert_eq!(tx.shielded_outputs.len(), 1);
+    assert_eq!(tx.binding_sig, Some(&[0x55; 64]));
+
+    let nullifiers: Vec<u8> = tx.shielded_spends.iter().map(|s| s.nullifier[0]).collect();
+    assert_eq!(nullifiers, vec![1, 2]);
+    assert_eq!(tx.shielded_outputs.iter().next().unwrap().cmu, &[0x80; 32]);
+}
+
+#[test]
+fn sapling_v4_without_shielded_parts_has_no_binding_sig() {
+    let raw = sapling_tx(0, 0);
+    let tx = roundtrip(&raw);
+    assert!(tx.binding_sig.is_none());
+}
+
+#[test]
+fn sapling_v4_malformed() {
+    let raw = sapling_tx(1, 1);
+    assert_eq!(
+        Transaction::from_bytes(&raw[..raw.len() - 1]).err(),
+        Some(ParserError::parser_unexpected_buffer_end)
+    );
+
+    let mut extra = raw.clone();
+    extra.push(0);
+    assert_eq!(
+        Transaction::from_bytes(&extra).err(),
+        Some(ParserError::parser_unexpected_unparsed_bytes)
+    );
+
+    let mut bad_balance = raw;
+    bad_balance[18..26].copy_from_slice(&i64::MAX.to_le_bytes());
+    assert_eq!(
+        Transaction::from_bytes(&bad_balance).err(),
+        Some(ParserError::parser_value_out_of_range)
+    );
+}
@@ -93,8 +93,12 @@ __Z_INLINE void handleExtractSpendData(volatile uint32_t *flags,
     }
     zxerr_t err = crypto_extract_spend_proofkeyandrnd(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE - 2);
     view_tx_state();
+    uint16_t replyLen = 128;
     if (err == zxerr_ok) {
-        *tx = 128;
+        err = app_seal_reply(&replyLen);
+    }
+    if (err == zxerr_ok) {
+        *tx = replyLen;
         THROW(APDU_CODE_OK);
     } else {
         *tx = 0;
@@ -118,6 +122,9 @@ __Z_INLINE void handleExtractOutputData(volatile uint32_t *flags,
     uint16_t replyLen = 0;
     zxerr_t err = crypto_extract_output_rnd(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE - 2, &replyLen);
     view_tx_state();
+    if (err == zxerr_ok) {
+        err = app_seal_reply(&replyLen);
+    }
     if (err == zxerr_ok) {
         *tx = replyLen;
         THROW(APDU_CODE_OK);
@@ -162,7 +169,14 @@ __Z_INLINE void handleInitTX(volatile uint32_t *flags,
         THROW(APDU_CODE_HASH_MSG_BUF_FAIL);
     }
 
-////////////
+    // the review is generated from the INITTX data, make sure it can be shown before asking for it
+    uint8_t numItems = 0;
+    if (tx_getNumItems(&numItems) != zxerr_ok) {
+        transaction_reset();
+        MEMZERO(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE);
+        *tx = 0;
+        THROW(APDU_CODE_EXTRACT_TRANSACTION_FAIL);
+    }
 
     view_review_init(tx_getItem, tx_getNumItems, app_reply_hash);
 
@@ -268,6 +282,86 @@ __Z_INLINE void handleGetKeyOVK(volatile uint32_t *flags,
     *flags |= IO_ASYNCH_REPLY;
 }
 
+// Opens an encrypted session: once it is set up, viewing keys, nullifiers and the spend and output
+// randomness are returned sealed with the session keys. The reply carries the device identity key, which the host pins on first
+// use, the device ephemeral key and the identity's signature over both ephemeral keys.
+__Z_INLINE void handleKeyExchange(volatile uint32_t *flags,
+                                  volatile uint32_t *tx, uint32_t rx) {
+    zemu_log("----[handleKeyExchange]\n");
+
+    *tx = 0;
+    if (rx < APDU_MIN_LENGTH || rx - APDU_MIN_LENGTH != DATA_LENGTH_KEY_EXCHANGE) {
+        THROW(APDU_CODE_COMMAND_NOT_ALLOWED);
+    }
+
+    if (G_io_apdu_buffer[OFFSET_DATA_LEN] != DATA_LENGTH_KEY_EXCHANGE) {
+        THROW(APDU_CODE_COMMAND_NOT_ALLOWED);
+    }
+
+    uint8_t host_public[SESSION_KEY_SIZE];
+    MEMCPY(host_public, G_io_apdu_buffer + OFFSET_DATA, SESSION_KEY_SIZE);
+    MEMZERO(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE);
+
+    uint16_t replyLen = 0;
+    zxerr_t err = crypto_session_open(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE - 2, host_public, &replyLen);
+    if (err != zxerr_ok) {
+        session_close();
+        THROW(APDU_CODE_DATA_INVALID);
+    }
+    *tx = replyLen;
+    THROW(APDU_CODE_OK);
+}
+
+// Anti-exfil signing for the spends of the transaction, after all extractions and before
+// checkandsign. P1_ANTI_EXFIL_COMMIT takes the host's commitment to its randomness and returns one
+// nonce commitment R0 per spend; P1_ANTI_EXFIL_REVEAL takes the host randomness itself. The device
+// then signs every spend with a nonce that includes it, so the host can check no key bits leak
+// through the nonces. Transparent inputs are still signed with RFC6979 nonces.
+__Z_INLINE void handleAntiExfil(volatile uint32_t *flags,
+                                volatile uint32_t *tx, uint32_t rx) {
+    zemu_log("----[handleAntiExfil]\n");
+
+    *tx = 0;
+    if (rx < APDU_MIN_LENGTH || rx - APDU_MIN_LENGTH != DATA_LENGTH_ANTI_EXFIL) {
+        THROW(APDU_CODE_COMMAND_NOT_ALLOWED);
+    }
+
+    if (G_io_apdu_buffer[OFFSET_DATA_LEN] != DATA_LENGTH_ANTI_EXFIL) {
+        THROW(APDU_CODE_COMMAND_NOT_ALLOWED);
+    }
+
+    if (get_state() != STATE_PROCESSED_ALL_EXTRACTIONS) {
+        THROW(APDU_CODE_UNPROCESSED_TX);
+    }
+
+    const uint8_t step = G_io_apdu_buffer[OFFSET_P1];
+    uint8_t host_input[DATA_LENGTH_ANTI_EXFIL];
+    MEMCPY(host_input, G_io_apdu_buffer + OFFSET_DATA, DATA_LENGTH_ANTI_EXFIL);
+    MEMZERO(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE);
+
+    switch (step) {
+        case P1_ANTI_EXFIL_COMMIT: {
+            uint16_t replyLen = 0;
+            zxerr_t err = crypto_anti_exfil_commit(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE - 2, host_input, &replyLen);
+            if (err != zxerr_ok) {
+                anti_exfil_reset();
+                THROW(APDU_CODE_DATA_INVALID);
+            }
+            *tx = replyLen;
+            THROW(APDU_CODE_OK);
+        }
+        case P1_ANTI_EXFIL_REVEAL: {
+            if (anti_exfil_reveal(host_input) != parser_ok) {
+                anti_exfil_reset();
+                THROW(APDU_CODE_DATA_INVALID);
+            }
+            THROW(APDU_CODE_OK);
+        }
+        default:
+            THROW(APDU_CODE_INVALIDP1P2);
+    }
+}
+
 // Computing the note nullifier nf is required in order to spend the note.
 // Computing nf requires the associated (private) nullifier deriving key nk
 // and the note position pos.
@@ -444,35 +538,25 @@ __Z_INLINE void handleCheckandSign(volatile uint32_t *flags,
     }
 
     set_state(STATE_VERIFIED_ALL_TXDATA);
-    view_tx_state();
-
-    err = crypto_sign_and_check_transparent(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE - 3, message, messageLength);
-    if (err != zxerr_ok) {
-        MEMZERO(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE);
-        view_idle_show(0, NULL);
-        transaction_reset();
-        THROW(APDU_CODE_CHECK_SIGN_TR_FAIL);
-    }
 
-    err = crypto_signspends_sapling(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE - 3, message, messageLength);
-    if (err != zxerr_ok) {
-        MEMZERO(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE);
-        view_idle_show(0, NULL);
-        transaction_reset();
-        THROW(APDU_SIGN_SPEND_FAIL);
+    // the memos were only decrypted by the checks above, so the user confirms them before anything is signed
+    uint8_t memoItems = 0;
+    if (memo_getNumItems(&memoItems) == zxerr_ok && memoItems > 0) {
+        view_review_init(memo_getItem, memo_getNumItems, app_reply_checkandsign);
+        view_review_show(1);
+        *flags |= IO_ASYNCH_REPLY;
+        return;
     }
+    view_tx_state();
 
-    err = crypto_hash_messagebuffer(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE - 3, message, messageLength);
-    if (err != zxerr_ok) {
+    const uint16_t code = app_sign_checked_tx();
+    if (code != APDU_CODE_OK) {
         MEMZERO(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE);
         view_idle_show(0, NULL);
         transaction_reset();
-        THROW(APDU_CODE_HASH_MSG_BUF_FAIL);
+        THROW(code);
     }
 
-    set_state(STATE_SIGNED_TX);
-    view_tx_state();
-
     *tx = 32;
     THROW(APDU_CODE_OK);
 }
@@ -751,6 +835,12 @@ void handleApdu(volatile uint32_t *flags, volatile uint32_t *tx, uint32_t rx) {
                     break;
                 }
 
+                case INS_KEY_EXCHANGE: {
+                    CHECK_PIN_VALIDATED();
+                    handleKeyExchange(flags, tx, rx);
+                    break;
+                }
+
                 case INS_INIT_TX: {
                     CHECK_PIN_VALIDATED();
                     handleInitTX(flags, tx, rx);
@@ -781,6 +871,12 @@ void handleApdu(volatile uint32_t *flags, volatile uint32_t *tx, uint32_t rx) {
                     break;
                 }
 
+                case INS_ANTI_EXFIL: {
+                    CHECK_PIN_VALIDATED();
+                    handleAntiExfil(flags, tx, rx);
+                    break;
+                }
+
                 case INS_EXTRACT_TRANSSIG: {
                     CHECK_PIN_VALIDATED();
                     handleExtractTransparentSignature(flags, tx, rx);
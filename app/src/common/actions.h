@@ -24,6 +24,7 @@
 #include "app_main.h"
 #include "nvdata.h"
 #include "parser.h"
+#include "rslib.h"
 
 typedef struct {
     address_kind_e kind;
@@ -46,9 +47,30 @@ __Z_INLINE void app_reject() {
     io_exchange(CHANNEL_APDU | IO_RETURN_AFTER_TX, 2);
 }
 
+// Replies carrying keys or transaction randomness only leave the device encrypted once the host opened a
+// session: seals the first replyLen bytes of G_io_apdu_buffer in place and updates replyLen to the frame length
+__Z_INLINE zxerr_t app_seal_reply(uint16_t *replyLen) {
+    if (!session_is_active()) {
+        return zxerr_ok;
+    }
+    uint16_t frameLen = 0;
+    if (session_seal(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE - 2, *replyLen, &frameLen) != parser_ok) {
+        MEMZERO(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE);
+        return zxerr_unknown;
+    }
+    *replyLen = frameLen;
+    return zxerr_ok;
+}
+
 __Z_INLINE void app_reply_key() {
-    set_code(G_io_apdu_buffer, key_state.len, APDU_CODE_OK);
-    io_exchange(CHANNEL_APDU | IO_RETURN_AFTER_TX, key_state.len + 2);
+    uint16_t replyLen = key_state.len;
+    if (app_seal_reply(&replyLen) != zxerr_ok) {
+        set_code(G_io_apdu_buffer, 0, APDU_CODE_DATA_INVALID);
+        io_exchange(CHANNEL_APDU | IO_RETURN_AFTER_TX, 2);
+        return;
+    }
+    set_code(G_io_apdu_buffer, replyLen, APDU_CODE_OK);
+    io_exchange(CHANNEL_APDU | IO_RETURN_AFTER_TX, replyLen + 2);
 }
 
 __Z_INLINE void app_reply_address() {
@@ -62,6 +84,41 @@ __Z_INLINE void app_reply_error() {
     io_exchange(CHANNEL_APDU | IO_RETURN_AFTER_TX, 2);
 }
 
+// Signs the transaction data handleCheckandSign has checked, leaving the hash in G_io_apdu_buffer
+__Z_INLINE uint16_t app_sign_checked_tx() {
+    const uint8_t *message = tx_get_buffer();
+    const uint16_t messageLength = tx_get_buffer_length();
+
+    if (crypto_sign_and_check_transparent(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE - 3, message, messageLength) != zxerr_ok) {
+        return APDU_CODE_CHECK_SIGN_TR_FAIL;
+    }
+    if (crypto_signspends_sapling(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE - 3, message, messageLength) != zxerr_ok) {
+        return APDU_SIGN_SPEND_FAIL;
+    }
+    if (crypto_hash_messagebuffer(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE - 3, message, messageLength) != zxerr_ok) {
+        return APDU_CODE_HASH_MSG_BUF_FAIL;
+    }
+
+    set_state(STATE_SIGNED_TX);
+    view_tx_state();
+    return APDU_CODE_OK;
+}
+
+// Approval of the memo review of handleCheckandSign
+__Z_INLINE void app_reply_checkandsign() {
+    const uint16_t code = app_sign_checked_tx();
+    if (code != APDU_CODE_OK) {
+        MEMZERO(G_io_apdu_buffer, IO_APDU_BUFFER_SIZE);
+        transaction_reset();
+        view_tx_state();
+        set_code(G_io_apdu_buffer, 0, code);
+        io_exchange(CHANNEL_APDU | IO_RETURN_AFTER_TX, 2);
+        return;
+    }
+    set_code(G_io_apdu_buffer, 32, APDU_CODE_OK);
+    io_exchange(CHANNEL_APDU | IO_RETURN_AFTER_TX, 32 + 2);
+}
+
 __Z_INLINE void app_reply_hash() {
     view_tx_state();
     set_code(G_io_apdu_buffer, 32, APDU_CODE_OK);
@@ -38,6 +38,8 @@
 #define DATA_LENGTH_GET_ADDR_SAPLING    4   //ZIP32-path
 #define DATA_LENGTH_GET_DIV_LIST        15   //ZIP32-path + 11-byte index
 #define DATA_LENGTH_GET_ADDR_DIV        15   //ZIP32-path + 11-byte div
+#define DATA_LENGTH_KEY_EXCHANGE        32   //host ephemeral session key
+#define DATA_LENGTH_ANTI_EXFIL          32   //host commitment or host data
 
 #define OFFSET_PAYLOAD_TYPE             OFFSET_P1
 
@@ -56,6 +58,10 @@
 #define INS_CHECKANDSIGN                0xa3
 #define INS_EXTRACT_SPENDSIG            0xa4
 #define INS_EXTRACT_TRANSSIG            0xa5
+#define INS_ANTI_EXFIL                  0xa6
+
+#define P1_ANTI_EXFIL_COMMIT            0x00
+#define P1_ANTI_EXFIL_REVEAL            0x01
 
 #define INS_GET_IVK                     0xf0
 #define INS_GET_OVK                     0xf1
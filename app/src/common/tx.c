@@ -124,3 +124,43 @@ zxerr_t tx_getItem(int8_t displayIdx,
 
     return zxerr_ok;
 }
+
+zxerr_t memo_getNumItems(uint8_t *num_items) {
+    parser_error_t err = parser_getNumMemoItems(num_items);
+
+    if (err != parser_ok) {
+        return zxerr_no_data;
+    }
+
+    return zxerr_ok;
+}
+
+zxerr_t memo_getItem(int8_t displayIdx,
+                     char *outKey, uint16_t outKeyLen,
+                     char *outVal, uint16_t outValLen,
+                     uint8_t pageIdx, uint8_t *pageCount) {
+    uint8_t numItems = 0;
+
+    CHECK_ZXERR(memo_getNumItems(&numItems))
+
+    if (displayIdx < 0 || displayIdx >= numItems) {
+        return zxerr_no_data;
+    }
+
+    parser_error_t err = parser_getMemoItem(tx_get_buffer(),
+                                            displayIdx,
+                                            outKey, outKeyLen,
+                                            outVal, outValLen,
+                                            pageIdx, pageCount);
+
+    // Convert error codes
+    if (err == parser_no_data ||
+        err == parser_display_idx_out_of_range ||
+        err == parser_display_page_out_of_range)
+        return zxerr_no_data;
+
+    if (err != parser_ok)
+        return zxerr_unknown;
+
+    return zxerr_ok;
+}
@@ -60,3 +60,12 @@ zxerr_t tx_getItem(int8_t displayIdx,
                    char *outKey, uint16_t outKeyLen,
                    char *outValue, uint16_t outValueLen,
                    uint8_t pageIdx, uint8_t *pageCount);
+
+/// Return the number of memo items shown before signing the checked transaction
+zxerr_t memo_getNumItems(uint8_t *num_items);
+
+/// Gets an specific memo item of the checked transaction (including paging)
+zxerr_t memo_getItem(int8_t displayIdx,
+                     char *outKey, uint16_t outKeyLen,
+                     char *outValue, uint16_t outValueLen,
+                     uint8_t pageIdx, uint8_t *pageCount);
@@ -82,6 +82,7 @@
 #define MAX_SIZE_BUF_ADDR       143
 
 #define SESSION_KEY_SIZE        32
+#define SESSION_OPEN_REPLY_SIZE 128     // identity key || device ephemeral key || signature
 
 #define OVK_SIZE                32
 #define OVK_SET_SIZE            1 + OVK_SIZE
@@ -206,6 +206,65 @@ zxerr_t crypto_fillSaplingSeed(uint8_t *sk) {
     return error;
 }
 
+typedef struct {
+    uint8_t pubkey[PK_LEN_SECP256K1];
+    uint8_t script[SCRIPT_SIZE];
+    uint8_t key_hashes[T_IN_LIST_SIZE][CX_RIPEMD160_SIZE];
+    uint8_t zip32_seed[ZIP32_SEED_SIZE];
+} tmp_change_s;
+
+// handleInitTX step 2/2
+// Marks the outputs paying back to keys the device derives itself. The P2PKH hashes of the transparent input paths
+// and the seed for the spend accounts are handed to the Rust side, which classifies the outputs of the INITTX data.
+// Nothing the host claims about an input or an output is trusted.
+zxerr_t crypto_mark_change_outputs(const uint8_t *txdata, const uint16_t txdatalen) {
+    zemu_log_stack("crypto_mark_change_outputs");
+    tmp_change_s tmp;
+    MEMZERO(&tmp, sizeof(tmp_change_s));
+
+    for (uint8_t i = 0; i < t_inlist_len(); i++) {
+        t_input_item_t *input = t_inlist_retrieve_item(i);
+        CHECK_ZXERR(crypto_extractPublicKey(input->path, tmp.pubkey, sizeof(tmp.pubkey)));
+        address_to_script(tmp.pubkey, tmp.script);
+        MEMCPY(tmp.key_hashes[i], tmp.script + SCRIPT_CONSTS_SIZE, CX_RIPEMD160_SIZE);
+    }
+
+    zxerr_t error = zxerr_ok;
+    uint8_t t_change = 0;
+    uint8_t s_change = 0;
+    BEGIN_TRY
+    {
+        TRY
+        {
+            if (spendlist_len() > 0 && outputlist_len() > 0) {
+                crypto_fillSaplingSeed(tmp.zip32_seed);
+            }
+            CHECK_APP_CANARY();
+            if (mark_change_outputs(txdata, txdatalen, tmp.zip32_seed, (const uint8_t *) tmp.key_hashes,
+                                    t_inlist_len(), &t_change, &s_change) != parser_ok) {
+                error = zxerr_unknown;
+            }
+            CHECK_APP_CANARY();
+        }
+        CATCH_OTHER(e)
+        {
+            error = zxerr_ledger_api_error;
+        }
+        FINALLY
+        {
+            MEMZERO(&tmp, sizeof(tmp_change_s));
+        }
+    }
+    END_TRY;
+
+    if (error != zxerr_ok) {
+        return error;
+    }
+    t_outlist_set_change_mask(t_change);
+    outputlist_set_change_mask(s_change);
+    return zxerr_ok;
+}
+
 // handleInitTX step 1/2
 zxerr_t crypto_extracttx_sapling(uint8_t *buffer, uint16_t bufferLen, const uint8_t *txdata, const uint16_t txdatalen) {
     zemu_log_stack("crypto_extracttxdata_sapling");
@@ -332,7 +391,8 @@ zxerr_t crypto_extracttx_sapling(uint8_t *buffer, uint16_t bufferLen, const uint
         uint8_t rnd1[RND_SIZE];
         uint8_t rnd2[RND_SIZE];
         random_fr(rnd1);
-        cx_rng(rnd2, RND_SIZE);
+        // before Canopy rseed is used as rcm, so it must be a scalar as well
+        random_fr(rnd2);
         zxerr_t err = outputlist_append_item(div, pkd, v, *memotype, ovk, rnd1, rnd2);
         if (err != zxerr_ok){
             return zxerr_unknown;
@@ -340,8 +400,13 @@ zxerr_t crypto_extracttx_sapling(uint8_t *buffer, uint16_t bufferLen, const uint
         start += OUTPUT_INPUT_LEN;
     }
 
+    // the legacy fixed fee, or the ZIP-317 fee for these inputs and outputs
     uint64_t value_flash = get_totalvalue();
-    if (value_flash != 1000){
+    if (!inittx_fee_is_supported(value_flash, t_in_len, t_out_len, spend_len, output_len)){
+        return zxerr_unknown;
+    }
+
+    if (crypto_mark_change_outputs(txdata, txdatalen) != zxerr_ok){
         return zxerr_unknown;
     }
 
@@ -650,7 +715,11 @@ zxerr_t crypto_checkspend_sapling(uint8_t *buffer, uint16_t bufferLen, const uin
                 // we later need nsk
                 zip32_child_ask_nsk(tmp.step1.zip32_seed, tmp.step2.ask, tmp.step2.nsk, item->path);
 
-                get_rk(tmp.step2.ask, (uint8_t *)item->alpha, tmp.step3.rk);
+                if (get_rk(tmp.step2.ask, (uint8_t *)item->alpha, tmp.step3.rk) != parser_ok){
+                    MEMZERO(&tmp, sizeof(tmp_checkspend));
+                    CLOSE_TRY;
+                    return zxerr_unknown;
+                }
 
                 if(MEMCMP(tmp.step3.rk, start_spenddata + INDEX_SPEND_RK + i * SPEND_TX_LEN,PUB_KEY_SIZE) != 0){
                     CLOSE_TRY;
@@ -667,7 +736,12 @@ zxerr_t crypto_checkspend_sapling(uint8_t *buffer, uint16_t bufferLen, const uin
                     return zxerr_unknown;
                 }
 
-                compute_note_commitment_fullpoint(tmp_buf->pedersen_hash, start_spendolddata + INDEX_SPEND_OLD_RCM + i * SPEND_OLD_TX_LEN,item->value, item->div, item->pkd);
+                if (compute_note_commitment_fullpoint(tmp_buf->pedersen_hash, start_spendolddata + INDEX_SPEND_OLD_RCM + i * SPEND_OLD_TX_LEN,item->value, item->div, item->pkd) != parser_ok){
+                    MEMZERO(&tmp, sizeof(tmp_checkspend));
+                    MEMZERO(out, bufferLen);
+                    CLOSE_TRY;
+                    return zxerr_unknown;
+                }
 
                 uint64_t notepos = 0;
                 {
@@ -683,7 +757,12 @@ zxerr_t crypto_checkspend_sapling(uint8_t *buffer, uint16_t bufferLen, const uin
                         return zxerr_unknown;
                     }
                 }
-                compute_nullifier(tmp_buf->ncm_full, notepos, tmp.step4.nsk, tmp_buf->nf);
+                if (compute_nullifier(tmp_buf->ncm_full, notepos, tmp.step4.nsk, tmp_buf->nf) != parser_ok){
+                    MEMZERO(out, bufferLen);
+                    MEMZERO(&tmp, sizeof(tmp_checkspend));
+                    CLOSE_TRY;
+                    return zxerr_unknown;
+                }
                 if (MEMCMP(tmp_buf->nf, start_spenddata + INDEX_SPEND_NF + i * SPEND_TX_LEN, NULLIFIER_SIZE) != 0){
                     //maybe spendlist_reset();
                     zemu_log_stack("Nullifier is bad\n");
@@ -743,6 +822,19 @@ typedef struct {
     };
 } tmp_checkoutput;
 
+// ZIP-212 lead byte of the notes created by the transaction, 0x00 if the hash data is unreadable
+static uint8_t output_lead_byte(const uint8_t *txdata) {
+    parser_context_t pars_ctx;
+    pars_ctx.offset = 0;
+    pars_ctx.buffer = txdata + start_sighashdata() + INDEX_HASH_EXPIRYHEIGHT;
+    pars_ctx.bufferLen = 4;
+    uint32_t expiry_height = 0;
+    if (_readUInt32(&pars_ctx, &expiry_height) != parser_ok){
+        return 0x00;
+    }
+    return zip212_output_lead_byte(isTestnet(), expiry_height);
+}
+
 // handleCheckandSign step 7/11
 zxerr_t crypto_checkoutput_sapling(uint8_t *buffer, uint16_t bufferLen, const uint8_t *txdata, const uint16_t txdatalen) {
     MEMZERO(buffer, bufferLen);
@@ -766,6 +858,7 @@ zxerr_t crypto_checkoutput_sapling(uint8_t *buffer, uint16_t bufferLen, const ui
     MEMZERO(&ncm, sizeof(tmp_checkoutput));
 
     uint8_t rcm[RCM_SIZE];
+    const uint8_t leadbyte = output_lead_byte(txdata);
 
     //the path in zip32 is [FIRST_VALUE, COIN_TYPE, p] where p is u32 and last part of hdPath
     BEGIN_TRY
@@ -779,9 +872,18 @@ zxerr_t crypto_checkoutput_sapling(uint8_t *buffer, uint16_t bufferLen, const ui
                     return zxerr_unknown;
                 }
 
-                rseed_get_rcm(item->rseed,rcm);
+                // before Canopy the 32 bytes are rcm itself, afterwards rcm is derived from them
+                if (get_note_rcm(leadbyte, item->rseed, rcm) != parser_ok){
+                    MEMZERO(&ncm, sizeof(tmp_checkoutput));
+                    CLOSE_TRY;
+                    return zxerr_unknown;
+                }
 
-                compute_note_commitment(ncm.step4.notecommitment,rcm,item->value, item->div, item->pkd);
+                if (compute_note_commitment(ncm.step4.notecommitment,rcm,item->value, item->div, item->pkd) != parser_ok){
+                    MEMZERO(&ncm, sizeof(tmp_checkoutput));
+                    CLOSE_TRY;
+                    return zxerr_unknown;
+                }
                 compute_value_commitment(item->value, item->rcmvalue, ncm.step4.valuecommitment);
 
                 if (MEMCMP(ncm.step4.valuecommitment, start_outputdata + INDEX_OUTPUT_VALUECMT + i * OUTPUT_TX_LEN,VALUE_COMMITMENT_SIZE) != 0){
@@ -895,6 +997,7 @@ zxerr_t crypto_checkencryptions_sapling(uint8_t *buffer, uint16_t bufferLen, con
     zemu_log_stack("crypto_checkencryptions_sapling");
 
     uint8_t *start_outputdata = (uint8_t *)(txdata + length_t_in_data() + length_spenddata());
+    const uint8_t leadbyte = output_lead_byte(txdata);
 
     //the path in zip32 is [FIRST_VALUE, COIN_TYPE, p] where p is u32 and last part of hdPath
     for(uint8_t i = 0; i < outputlist_len(); i++){
@@ -904,8 +1007,12 @@ zxerr_t crypto_checkencryptions_sapling(uint8_t *buffer, uint16_t bufferLen, con
             MEMZERO(out, bufferLen);
             return zxerr_unknown;
         }
-        // compute random ephemeral private and public keys (esk,epk) from seed and diversifier
-        rseed_get_esk_epk(item->rseed,(uint8_t *) item->div, tmp->step1.esk, tmp->step1.epk);
+        // compute ephemeral private and public keys (esk,epk) from seed and diversifier; before Canopy
+        // esk only has to be random, so hosts derive it from rcm the same way (NoteRandomness::output_esk)
+        if (rseed_get_esk_epk(item->rseed,(uint8_t *) item->div, tmp->step1.esk, tmp->step1.epk) != parser_ok){
+            MEMZERO(out, bufferLen);
+            return zxerr_unknown;
+        }
         CHECK_APP_CANARY();
 
         // compare the computed epk to that provided in the transaction data
@@ -915,10 +1022,17 @@ zxerr_t crypto_checkencryptions_sapling(uint8_t *buffer, uint16_t bufferLen, con
         }
 
         // get shared key (used as encryption key) from esk, epk and pkd
-        ka_to_key(tmp->step1.esk, (uint8_t *) item->pkd, tmp->step1.epk, tmp->step2.sharedkey);
+        if (ka_to_key(tmp->step1.esk, (uint8_t *) item->pkd, tmp->step1.epk, tmp->step2.sharedkey) != parser_ok){
+            MEMZERO(out, bufferLen);
+            return zxerr_unknown;
+        }
         CHECK_APP_CANARY();
-        // encode (div, value rseed and memotype) into step2.compactout ready to be encrypted
-        prepare_enccompact_input((uint8_t *) item->div, item->value, (uint8_t *) item->rseed, item->memotype, tmp->step2.compactout);
+        // encode (leadbyte, div, value, rcm or rseed and memotype) into step2.compactout ready to be encrypted
+        if (prepare_enccompact_input(leadbyte, (uint8_t *) item->div, item->value, (uint8_t *) item->rseed, item->memotype,
+                                     tmp->step2.compactout) != parser_ok){
+            MEMZERO(out, bufferLen);
+            return zxerr_unknown;
+        }
         CHECK_APP_CANARY();
         MEMZERO(tmp->step2.chachanonce,CHACHA_NONCE_SIZE);
         // encrypt the previously obtained encoding, and store it in step2.compactoutput (reusing the same memory for input and output)
@@ -929,6 +1043,13 @@ zxerr_t crypto_checkencryptions_sapling(uint8_t *buffer, uint16_t bufferLen, con
             MEMZERO(out, bufferLen);
             return zxerr_unknown;
         }
+        // check the whole enc_ciphertext, memo and authentication tag included, so that the
+        // memo type shown for this output is the one the recipient decrypts
+        if (check_enc_ciphertext(leadbyte, (uint8_t *) item->div, item->value, (uint8_t *) item->rseed, item->memotype,
+                                 tmp->step2.sharedkey, start_outputdata + INDEX_OUTPUT_ENC + i * OUTPUT_TX_LEN) != parser_ok){
+            MEMZERO(out, bufferLen);
+            return zxerr_unknown;
+        }
 
         // if an ovk was provided
         if(item->ovk[0] != 0x00){
@@ -1162,6 +1283,27 @@ typedef struct {
     };
 } tmp_sign_s;
 
+// handleAntiExfil
+zxerr_t crypto_anti_exfil_commit(uint8_t *buffer, uint16_t bufferLen, const uint8_t *host_commitment, uint16_t *replyLen) {
+    zemu_log_stack("crypto_anti_exfil_commit");
+    MEMZERO(buffer, bufferLen);
+
+    const uint8_t count = spendlist_len();
+    if (count == 0 || bufferLen < count * SIG_R_SIZE) {
+        return zxerr_unknown;
+    }
+
+    uint8_t signer_commitments[SPEND_LIST_SIZE * SIG_R_SIZE];
+    MEMZERO(signer_commitments, sizeof(signer_commitments));
+    if (anti_exfil_commit(host_commitment, count, signer_commitments) != parser_ok) {
+        return zxerr_unknown;
+    }
+
+    MEMCPY(buffer, signer_commitments, count * SIG_R_SIZE);
+    *replyLen = count * SIG_R_SIZE;
+    return zxerr_ok;
+}
+
 // handleCheckandSign step 10/11
 zxerr_t crypto_signspends_sapling(uint8_t *buffer, uint16_t bufferLen, const uint8_t *txdata, const uint16_t txdatalen) {
     zemu_log_stack("crypto_signspends_sapling");
@@ -1206,8 +1348,20 @@ zxerr_t crypto_signspends_sapling(uint8_t *buffer, uint16_t bufferLen, const uin
                     return zxerr_unknown;
                 }
                 // combining these causes a stack overflow
-                randomized_secret_from_seed(tmp.step1.zip32_seed,item->path, (uint8_t *)item->alpha, tmp.step3.rsk);
-                sign_redjubjub((uint8_t *)tmp.step3.rsk, (uint8_t *)sighash, (uint8_t *)out);
+                if (randomized_secret_from_seed(tmp.step1.zip32_seed,item->path, (uint8_t *)item->alpha, tmp.step3.rsk) != parser_ok){
+                    MEMZERO(&tmp, sizeof(tmp_sign_s));
+                    CLOSE_TRY;
+                    return zxerr_unknown;
+                }
+                // with anti-exfil set up, the nonce committed to for spend i signs it and is used up
+                const parser_error_t sigerr = anti_exfil_is_armed()
+                    ? sign_redjubjub_anti_exfil(i, tmp.step3.rsk, sighash, out)
+                    : sign_redjubjub((uint8_t *)tmp.step3.rsk, (uint8_t *)sighash, (uint8_t *)out);
+                if (sigerr != parser_ok){
+                    MEMZERO(&tmp, sizeof(tmp_sign_s));
+                    CLOSE_TRY;
+                    return zxerr_unknown;
+                }
                 zxerr_t zxerr = spend_signatures_append(out);
                 if(zxerr != zxerr_ok){
                     CLOSE_TRY;
@@ -1314,6 +1468,45 @@ zxerr_t crypto_ivk_sapling(uint8_t *buffer, uint16_t bufferLen, uint32_t p, uint
     return zxerr_ok;
 }
 
+// handleKeyExchange
+zxerr_t crypto_session_open(uint8_t *buffer, uint16_t bufferLen, const uint8_t *host_public, uint16_t *replyLen) {
+    MEMZERO(buffer, bufferLen);
+
+    zemu_log_stack("crypto_session_open");
+
+    if (bufferLen < SESSION_OPEN_REPLY_SIZE) {
+        return zxerr_buffer_too_small;
+    }
+
+    tmp_sapling_addr_s tmp;
+    MEMZERO(&tmp, sizeof(tmp_sapling_addr_s));
+    parser_error_t err = parser_unexpected_error;
+
+    BEGIN_TRY
+    {
+        TRY
+        {
+            // the identity key that signs the session transcript is derived from the seed
+            crypto_fillSaplingSeed(tmp.zip32_seed);
+            CHECK_APP_CANARY();
+            err = session_open(tmp.zip32_seed, host_public, buffer);
+            CHECK_APP_CANARY();
+        }
+        FINALLY
+        {
+            MEMZERO(&tmp, sizeof(tmp_sapling_addr_s));
+        }
+    }
+    END_TRY;
+
+    if (err != parser_ok) {
+        MEMZERO(buffer, bufferLen);
+        return zxerr_unknown;
+    }
+    *replyLen = SESSION_OPEN_REPLY_SIZE;
+    return zxerr_ok;
+}
+
 // handleGetKeyOVK
 zxerr_t crypto_ovk_sapling(uint8_t *buffer, uint16_t bufferLen, uint32_t p, uint16_t *replyLen){
     MEMZERO(buffer, bufferLen);
@@ -1376,7 +1569,12 @@ zxerr_t crypto_nullifier_sapling(uint8_t *buffer, uint16_t bufferLen, uint32_t p
             // nk can be computed from nsk which itself can be computed from the seed.
             zip32_nsk_from_seed(tmp.zip32_seed,tmp.nsk);
 
-            compute_nullifier(cm, notepos, tmp.nsk,nf_out);
+            if (compute_nullifier(cm, notepos, tmp.nsk,nf_out) != parser_ok){
+                MEMZERO(&tmp,sizeof(tmp));
+                MEMZERO(buffer, bufferLen);
+                CLOSE_TRY;
+                return zxerr_unknown;
+            }
 
             MEMZERO(&tmp,sizeof(tmp));
             CHECK_APP_CANARY();
@@ -1476,7 +1674,12 @@ zxerr_t crypto_fillAddress_with_diversifier_sapling(uint8_t *buffer, uint16_t bu
             crypto_fillSaplingSeed(tmp.zip32_seed);
             CHECK_APP_CANARY();
 
-            get_pkd(tmp.zip32_seed, p, out->diversifier, out->pkd);
+            if (get_pkd(tmp.zip32_seed, p, out->diversifier, out->pkd) != parser_ok){
+                MEMZERO(&tmp, sizeof(tmp_sapling_addr_s));
+                MEMZERO(out, bufferLen);
+                CLOSE_TRY;
+                return zxerr_unknown;
+            }
 
             CHECK_APP_CANARY();
             MEMZERO(tmp.zip32_seed, sizeof_field(tmp_sapling_addr_s, zip32_seed));
@@ -1535,7 +1738,12 @@ zxerr_t crypto_fillAddress_sapling(uint8_t *buffer, uint16_t bufferLen, uint32_t
             crypto_fillSaplingSeed(tmp.zip32_seed);
             CHECK_APP_CANARY();
 
-            get_pkd_from_seed(tmp.zip32_seed, p, out->startindex, out->diversifier, out->pkd);
+            if (get_pkd_from_seed(tmp.zip32_seed, p, out->startindex, out->diversifier, out->pkd) != parser_ok){
+                MEMZERO(&tmp, sizeof(tmp_sapling_addr_s));
+                MEMZERO(out, bufferLen);
+                CLOSE_TRY;
+                return zxerr_unknown;
+            }
 
             MEMZERO(out + DIV_SIZE, MAX_SIZE_BUF_ADDR - DIV_SIZE);
             CHECK_APP_CANARY();
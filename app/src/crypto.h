@@ -48,6 +48,7 @@ zxerr_t crypto_diversifier_with_startindex(uint8_t *buffer, uint16_t bufferLen,
 
 zxerr_t crypto_ivk_sapling(uint8_t *buffer, uint16_t bufferLen, uint32_t p, uint16_t *replyLen);
 zxerr_t crypto_ovk_sapling(uint8_t *buffer, uint16_t bufferLen, uint32_t p, uint16_t *replyLen);
+zxerr_t crypto_session_open(uint8_t *buffer, uint16_t bufferLen, const uint8_t *host_public, uint16_t *replyLen);
 zxerr_t crypto_nullifier_sapling(uint8_t *buffer, uint16_t bufferLen, uint32_t p, uint64_t notepos,
                                  uint8_t *cm, uint16_t *replyLen);
 
@@ -63,11 +64,14 @@ zxerr_t crypto_checkencryptions_sapling(uint8_t *buffer, uint16_t bufferLen, con
 
 uint16_t crypto_key_exchange(uint8_t *buffer, uint16_t bufferLen, const uint8_t *txdata, const uint16_t txdatalen);
 zxerr_t crypto_extracttx_sapling(uint8_t *buffer, uint16_t bufferLen, const uint8_t *txdata, const uint16_t txdatalen);
+zxerr_t crypto_mark_change_outputs(const uint8_t *txdata, const uint16_t txdatalen);
 
 zxerr_t crypto_extract_spend_proofkeyandrnd(uint8_t *buffer, uint16_t bufferLen);
 
 zxerr_t crypto_extract_output_rnd(uint8_t *buffer, uint16_t bufferLen, uint16_t *replyLen);
 
+zxerr_t crypto_anti_exfil_commit(uint8_t *buffer, uint16_t bufferLen, const uint8_t *host_commitment, uint16_t *replyLen);
+
 zxerr_t crypto_signspends_sapling(uint8_t *buffer, uint16_t bufferLen, const uint8_t *signdata, uint16_t signdatalen);
 
 
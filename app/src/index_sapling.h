@@ -72,6 +72,7 @@
 #define INDEX_HASH_JOINSPLITSHASH       104
 #define INDEX_HASH_SHIELDEDSPENDHASH    136
 #define INDEX_HASH_SHIELDEDOUTPUTHASH   168
+#define INDEX_HASH_EXPIRYHEIGHT         204
 #define INDEX_HASH_VALUEBALANCE         208
 
 uint16_t length_t_in_data();
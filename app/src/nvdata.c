@@ -21,6 +21,7 @@
 #include "nvdata.h"
 #include "constants.h"
 #include "view.h"
+#include "rslib.h"
 
 t_inlist_t NV_CONST
 N_t_inlist_impl __attribute__ ((aligned(64)));
@@ -171,6 +172,7 @@ zxerr_t get_next_spend_signature(uint8_t *result) {
 void transaction_reset() {
     MEMZERO(&transaction_header, sizeof(transaction_header_t));
     zeroize_flashstorage();
+    anti_exfil_reset();
 }
 
 bool spendlist_is_active() {
@@ -280,6 +282,22 @@ uint8_t outputlist_len() {
     return transaction_header.outputlist_len;
 }
 
+void t_outlist_set_change_mask(uint8_t mask) {
+    transaction_header.t_out_change = mask & (uint8_t) ((1u << transaction_header.t_out_len) - 1);
+}
+
+uint8_t t_outlist_change_mask() {
+    return transaction_header.t_out_change;
+}
+
+void outputlist_set_change_mask(uint8_t mask) {
+    transaction_header.output_change = mask & (uint8_t) ((1u << transaction_header.outputlist_len) - 1);
+}
+
+uint8_t outputlist_change_mask() {
+    return transaction_header.output_change;
+}
+
 // valueBalance is not the total value, but the
 // net value of Sapling Spend transfers minus Output transfers.
 // i.e. the contents of the Sapling value pool
@@ -61,6 +61,8 @@ typedef struct {
     uint8_t outputdata_extract_index;
     uint8_t spends_sign_index;
     uint8_t t_sign_index;
+    uint8_t t_out_change;
+    uint8_t output_change;
 } transaction_header_t;
 
 typedef struct {
@@ -172,6 +174,15 @@ output_item_t *outputlist_extract_next();
 
 bool outputlist_more_extract();
 
+//change outputs API: bit i is set when output i pays back to the device's own keys
+void t_outlist_set_change_mask(uint8_t mask);
+
+uint8_t t_outlist_change_mask();
+
+void outputlist_set_change_mask(uint8_t mask);
+
+uint8_t outputlist_change_mask();
+
 void zeroize_flashstorage();
 
 #ifdef __cplusplus
@@ -26,12 +26,14 @@
 #include "parser_txdef.h"
 #include "rslib.h"
 #include "nvdata.h"
+#include "index_sapling.h"
+#include "tx.h"
 #include "bech32.h"
-#include "base58.h"
 #include "view.h"
 #include <os_io_seproxyhal.h>
 
 #define DEFAULT_MEMOTYPE        0xf6
+#define NUM_ITEMS_MEMO          2
 
 #if defined(TARGET_NANOX) || defined(TARGET_NANOS2)
 // For some reason NanoX requires this function
@@ -40,19 +42,6 @@ void __assert_fail(const char * assertion, const char * file, unsigned int line,
 }
 #endif
 
-typedef enum {
-    type_tin = 0,
-    type_tout = 1,
-    type_sspend = 2,
-    type_sout = 3,
-    type_txfee = 4,
-} sapling_parser_type_e;
-
-typedef struct {
-    sapling_parser_type_e type;
-    uint8_t index;
-} parser_sapling_t;
-
 parser_error_t parser_sapling_path_with_div(const uint8_t *data, size_t dataLen, parser_addr_div_t *prs) {
     if (dataLen < 15) {
         return parser_context_unexpected_size;
@@ -155,49 +144,6 @@ parser_error_t parser_validate(const parser_context_t *ctx) {
     return parser_ok;
 }
 
-parser_error_t parser_sapling_display_value(uint64_t value, char *outVal,
-                                            uint16_t outValLen, uint8_t pageIdx,
-                                            uint8_t *pageCount) {
-    char tmpBuffer[100];
-    fpuint64_to_str(tmpBuffer, sizeof(tmpBuffer), value, 8);
-    pageString(outVal, outValLen, tmpBuffer, pageIdx, pageCount);
-    return parser_ok;
-}
-
-parser_error_t parser_sapling_display_address_t(uint8_t *addr, char *outVal,
-                                                uint16_t outValLen, uint8_t pageIdx,
-                                                uint8_t *pageCount) {
-    ZEMU_LOGF(50, "[parser_sapling_display_address_t]\n")
-    MEMZERO(outVal, outValLen);
-
-    uint8_t address[VERSION_SIZE + CX_RIPEMD160_SIZE + CX_SHA256_SIZE];
-    address[0] = VERSION_P2PKH >> 8;
-    address[1] = VERSION_P2PKH & 0xFF;
-    MEMCPY(address + VERSION_SIZE, addr + 4, CX_RIPEMD160_SIZE);
-
-    cx_hash_sha256(address,
-                   VERSION_SIZE + CX_RIPEMD160_SIZE,
-                   address + VERSION_SIZE + CX_RIPEMD160_SIZE,
-                   CX_SHA256_SIZE);
-
-    cx_hash_sha256(address + VERSION_SIZE + CX_RIPEMD160_SIZE, CX_SHA256_SIZE,
-                   address + VERSION_SIZE + CX_RIPEMD160_SIZE, CX_SHA256_SIZE);
-
-    uint8_t tmpBuffer[60];
-    size_t outLen = sizeof(tmpBuffer);
-
-    int err = encode_base58(address, VERSION_SIZE + CX_RIPEMD160_SIZE + CHECKSUM_SIZE, tmpBuffer, &outLen);
-    if (err != 0) {
-        ZEMU_LOGF(50, "[parser_sapling_display_address_t] ERR %d\n", err)
-        return parser_unexpected_error;
-    }
-
-    ZEMU_LOGF(50, "addr size %d\n", outLen)
-
-    pageString(outVal, outValLen, (char *) tmpBuffer, pageIdx, pageCount);
-    return parser_ok;
-}
-
 parser_error_t parser_sapling_display_address_s(uint8_t *div, uint8_t *pkd, char *outVal,
                                                 uint16_t outValLen, uint8_t pageIdx,
                                                 uint8_t *pageCount) {
@@ -215,44 +161,11 @@ parser_error_t parser_sapling_display_address_s(uint8_t *div, uint8_t *pkd, char
     return parser_ok;
 }
 
-parser_error_t parser_sapling_getTypes(const uint16_t displayIdx, parser_sapling_t *prs) {
-    uint16_t index = displayIdx;
-
-    if (index < t_inlist_len() * NUM_ITEMS_TIN && t_inlist_len() > 0) {
-        prs->type = type_tin;
-        prs->index = index;
-        return parser_ok;
-    }
-    index -= t_inlist_len() * NUM_ITEMS_TIN;
-    if (index < t_outlist_len() * NUM_ITEMS_TOUT && t_outlist_len() > 0) {
-        prs->type = type_tout;
-        prs->index = index;
-        return parser_ok;
-    }
-    index -= t_outlist_len() * NUM_ITEMS_TOUT;
-    if (index < spendlist_len() * NUM_ITEMS_SSPEND && spendlist_len() > 0) {
-        prs->type = type_sspend;
-        prs->index = index;
-        return parser_ok;
-    }
-    index -= spendlist_len() * NUM_ITEMS_SSPEND;
-    if (index < outputlist_len() * NUM_ITEMS_SOUT && outputlist_len() > 0) {
-        prs->type = type_sout;
-        prs->index = index;
-        return parser_ok;
-    }
-    prs->type = type_txfee;
-    return parser_ok;
-}
-
+// The review items are generated in Rust (txdisplay.rs) from the INITTX data, which stays in the transaction buffer
+// while they are shown; only the change masks computed by mark_change_outputs (change.rs) come from flash
 parser_error_t parser_getNumItems(const parser_context_t *ctx, uint8_t *num_items) {
-    *num_items = t_inlist_len() * NUM_ITEMS_TIN +
-                 t_outlist_len() * NUM_ITEMS_TOUT +
-                 spendlist_len() * NUM_ITEMS_SSPEND +
-                 outputlist_len() * NUM_ITEMS_SOUT +
-                 NUM_ITEMS_CONST;
-
-    return parser_ok;
+    return tx_display_num_items(tx_get_buffer(), tx_get_buffer_length(),
+                                t_outlist_change_mask(), outputlist_change_mask(), num_items);
 }
 
 parser_error_t parser_getItem(const parser_context_t *ctx,
@@ -268,133 +181,75 @@ parser_error_t parser_getItem(const parser_context_t *ctx,
     snprintf(outVal, outValLen, "?");
     *pageCount = 1;
 
-    uint8_t numItems;
-    CHECK_PARSER_ERR(parser_getNumItems(ctx, &numItems))
-    CHECK_APP_CANARY()
-
-    ZEMU_LOGF(50, "[tx_getItem] items: %d\n", numItems)
-
-    if (displayIdx < 0 || displayIdx >= numItems) {
-        return parser_no_data;
-    }
+    return tx_display_get_item(tx_get_buffer(), tx_get_buffer_length(),
+                               t_outlist_change_mask(), outputlist_change_mask(),
+                               displayIdx,
+                               (uint8_t *) outKey, outKeyLen,
+                               (uint8_t *) outVal, outValLen,
+                               pageIdx, pageCount);
+}
 
-    parser_sapling_t prs;
-    MEMZERO(&prs, sizeof(parser_sapling_t));
-    CHECK_PARSER_ERR(parser_sapling_getTypes(displayIdx, &prs));
-
-    // FIXME: what decimals to take for ZECs?
-
-    switch (prs.type) {
-        case type_tin : {
-            ZEMU_LOGF(50, "[tx_getItem] type: type_tin\n")
-
-            uint8_t itemnum = prs.index / NUM_ITEMS_TIN;
-            t_input_item_t *item = t_inlist_retrieve_item(itemnum);
-            uint8_t itemtype = prs.index % NUM_ITEMS_TIN;
-
-            switch (itemtype) {
-                case 0: {
-                    snprintf(outKey, outKeyLen, "T-in addr");
-                    return parser_sapling_display_address_t(item->script, outVal, outValLen, pageIdx, pageCount);
-                }
-                case 1: {
-                    snprintf(outKey, outKeyLen, "T-in (ZEC)");
-                    return parser_sapling_display_value(item->value, outVal, outValLen, pageIdx, pageCount);
-                }
+// Index of the n-th shielded output carrying a memo
+static uint8_t outputlist_memo_index(uint8_t n) {
+    for (uint8_t i = 0; i < outputlist_len(); i++) {
+        if (outputlist_retrieve_item(i)->memotype != DEFAULT_MEMOTYPE) {
+            if (n == 0) {
+                return i;
             }
+            n--;
         }
+    }
+    return outputlist_len();
+}
 
-        case type_tout : {
-            ZEMU_LOGF(50, "[tx_getItem] type: type_tout\n")
-
-            uint8_t itemnum = prs.index / NUM_ITEMS_TOUT;
-            t_output_item_t *item = t_outlist_retrieve_item(itemnum);
-            uint8_t itemtype = prs.index % NUM_ITEMS_TOUT;
-            switch (itemtype) {
-                case 0: {
-                    snprintf(outKey, outKeyLen, "T-out addr");
-                    return parser_sapling_display_address_t(item->address, outVal, outValLen, pageIdx, pageCount);
-                }
-                case 1: {
-                    snprintf(outKey, outKeyLen, "T-out (ZEC)");
-                    return parser_sapling_display_value(item->value, outVal, outValLen, pageIdx, pageCount);
-                }
-            }
-        }
-        case type_sspend: {
-            ZEMU_LOGF(50, "[tx_getItem] type: type_sspend\n")
-
-            uint8_t itemnum = prs.index / NUM_ITEMS_SSPEND;
-            spend_item_t *item = spendlist_retrieve_item(itemnum);
-            uint8_t itemtype = prs.index % NUM_ITEMS_SSPEND;
-            switch (itemtype) {
-                case 0: {
-                    snprintf(outKey, outKeyLen, "S-in addr");
-                    return parser_sapling_display_address_s(item->div, item->pkd, outVal, outValLen, pageIdx,
-                                                            pageCount);
-                }
-                case 1: {
-                    snprintf(outKey, outKeyLen, "S-in (ZEC)");
-                    return parser_sapling_display_value(item->value, outVal, outValLen, pageIdx, pageCount);
-                }
-            }
+parser_error_t parser_getNumMemoItems(uint8_t *num_items) {
+    uint8_t memos = 0;
+    for (uint8_t i = 0; i < outputlist_len(); i++) {
+        if (outputlist_retrieve_item(i)->memotype != DEFAULT_MEMOTYPE) {
+            memos++;
         }
+    }
+    *num_items = memos * NUM_ITEMS_MEMO;
+    return parser_ok;
+}
 
-        case type_sout: {
-            ZEMU_LOGF(50, "[tx_getItem] type: type_sout\n")
-
-            uint8_t itemnum = prs.index / NUM_ITEMS_SOUT;
-            output_item_t *item = outputlist_retrieve_item(itemnum);
-            uint8_t itemtype = prs.index % NUM_ITEMS_SOUT;
-            switch (itemtype) {
-                case 0: {
-                    snprintf(outKey, outKeyLen, "S-out addr");
-                    return parser_sapling_display_address_s(item->div, item->pkd, outVal, outValLen, pageIdx,
-                                                            pageCount);
-                }
-                case 1: {
-                    snprintf(outKey, outKeyLen, "S-out (ZEC)");
-                    return parser_sapling_display_value(item->value, outVal, outValLen, pageIdx, pageCount);
-                }
-                case 2: {
-                    snprintf(outKey, outKeyLen, "S-out Memotype");
-                    if (item->memotype == DEFAULT_MEMOTYPE) {
-                        snprintf(outVal, outValLen, "Default");
-                    } else {
-                        snprintf(outVal, outValLen, "Custom");
-                    }
-                    return parser_ok;
-                }
-
-                case 3: {
-                    snprintf(outKey, outKeyLen, "S-out OVK");
-                    uint8_t dummy[OVK_SIZE];
-                    MEMZERO(dummy, sizeof(dummy));
-                    if (item->ovk[0] == 0x01) {
-                        char tmpBuffer[100];
-                        array_to_hexstr(tmpBuffer, sizeof(tmpBuffer), item->ovk + 1, OVK_SIZE);
-                        pageString(outVal, outValLen, tmpBuffer, pageIdx, pageCount);
-                        return parser_ok;
-                    } else {
-                        snprintf(outVal, outValLen, "None");
-                    }
-                    return parser_ok;
-                }
-            }
-        }
+// The memos are only known once crypto_checkencryptions_sapling decrypted them from the transaction data, so they
+// are shown in a second review between the checks and the signatures of handleCheckandSign
+parser_error_t parser_getMemoItem(const uint8_t *txdata,
+                                  uint8_t displayIdx,
+                                  char *outKey, uint16_t outKeyLen,
+                                  char *outVal, uint16_t outValLen,
+                                  uint8_t pageIdx, uint8_t *pageCount) {
+    ZEMU_LOGF(50, "[memo_getItem] %d/%d\n", displayIdx, pageIdx)
 
-        case type_txfee: {
-            ZEMU_LOGF(50, "[tx_getItem] type: type_txfee\n")
+    MEMZERO(outKey, outKeyLen);
+    MEMZERO(outVal, outValLen);
+    snprintf(outKey, outKeyLen, "?");
+    snprintf(outVal, outValLen, "?");
+    *pageCount = 1;
 
-            snprintf(outKey, outKeyLen, "Fee");
-            return parser_sapling_display_value(get_totalvalue(), outVal, outValLen, pageIdx, pageCount);
-        }
+    uint8_t numItems;
+    CHECK_PARSER_ERR(parser_getNumMemoItems(&numItems))
+    if (displayIdx >= numItems) {
+        return parser_no_data;
+    }
 
-        default: {
-            return parser_no_data;
-        }
+    uint8_t itemnum = outputlist_memo_index(displayIdx / NUM_ITEMS_MEMO);
+    output_item_t *item = outputlist_retrieve_item(itemnum);
+    if (item == NULL) {
+        return parser_no_data;
     }
-    return parser_ok;
+
+    if (displayIdx % NUM_ITEMS_MEMO == 0) {
+        snprintf(outKey, outKeyLen, "S-out addr");
+        return parser_sapling_display_address_s(item->div, item->pkd, outVal, outValLen, pageIdx, pageCount);
+    }
+
+    snprintf(outKey, outKeyLen, "S-out Memo");
+    const uint8_t *enc_ciphertext = txdata + length_t_in_data() + length_spenddata() +
+                                    INDEX_OUTPUT_ENC + itemnum * OUTPUT_TX_LEN;
+    return get_memo_page(item->div, item->pkd, item->rseed, enc_ciphertext,
+                         (uint8_t *) outVal, outValLen, pageIdx, pageCount);
 }
 
 const char *parser_getErrorDescription(parser_error_t err) {
@@ -443,6 +298,12 @@ const char *parser_getErrorDescription(parser_error_t err) {
             return "Value out of range";
         case parser_invalid_address:
             return "Invalid address format";
+        case parser_invalid_scalar:
+            return "Invalid scalar";
+        case parser_invalid_point:
+            return "Invalid curve point";
+        case parser_invalid_diversifier:
+            return "Invalid diversifier";
         default:
             return "Unrecognized error code";
     }
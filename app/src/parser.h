@@ -25,12 +25,6 @@ extern "C" {
 #include "hexutils.h"
 #include "crypto.h"
 
-#define NUM_ITEMS_TIN       2       //address, value
-#define NUM_ITEMS_TOUT      2       //address, value
-#define NUM_ITEMS_SSPEND    2       //address, value
-#define NUM_ITEMS_SOUT      4       //address, value, memotype, OVK?
-#define NUM_ITEMS_CONST     1       //txfee
-
 typedef struct {
     uint32_t path;
     uint8_t div[11];
@@ -59,6 +53,16 @@ parser_error_t parser_getItem(const parser_context_t *ctx,
                               char *outValue, uint16_t outValueLen,
                               uint8_t pageIdx, uint8_t *pageCount);
 
+//// returns the number of memo items shown before signing the checked transaction
+parser_error_t parser_getNumMemoItems(uint8_t *num_items);
+
+// retrieves a readable output for each memo field / page of the checked transaction data
+parser_error_t parser_getMemoItem(const uint8_t *txdata,
+                                  uint8_t displayIdx,
+                                  char *outKey, uint16_t outKeyLen,
+                                  char *outValue, uint16_t outValueLen,
+                                  uint8_t pageIdx, uint8_t *pageCount);
+
 void parser_resetState();
 
 #ifdef __cplusplus
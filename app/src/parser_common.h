@@ -65,6 +65,10 @@ typedef enum {
     parser_tx_nesting_not_supported,
     parser_tx_nesting_limit_reached,
     parser_tx_call_vec_too_large,
+    // Crypto related errors
+    parser_invalid_scalar,
+    parser_invalid_point,
+    parser_invalid_diversifier,
 } parser_error_t;
 
 typedef struct {
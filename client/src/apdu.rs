@@ -0,0 +1,166 @@
+//! APDU framing as described in `docs/APDUSPEC.md`.
+
+use crate::errors::AppError;
+
+pub const CLA: u8 = 0x85;
+pub const CHUNK_SIZE: usize = 250;
+
+pub const INS_GET_VERSION: u8 = 0x00;
+pub const INS_GET_ADDR_SECP256K1: u8 = 0x01;
+pub const INS_GET_DIV_LIST: u8 = 0x09;
+pub const INS_GET_ADDR_SAPLING_DIV: u8 = 0x10;
+pub const INS_GET_ADDR_SAPLING: u8 = 0x11;
+pub const INS_INIT_TX: u8 = 0xa0;
+pub const INS_EXTRACT_SPEND: u8 = 0xa1;
+pub const INS_EXTRACT_OUTPUT: u8 = 0xa2;
+pub const INS_CHECKANDSIGN: u8 = 0xa3;
+pub const INS_EXTRACT_SPENDSIG: u8 = 0xa4;
+pub const INS_EXTRACT_TRANSSIG: u8 = 0xa5;
+pub const INS_KEY_EXCHANGE: u8 = 0xaa;
+pub const INS_GET_IVK: u8 = 0xf0;
+pub const INS_GET_OVK: u8 = 0xf1;
+pub const INS_GET_NF: u8 = 0xf2;
+
+pub const P1_ONLY_RETRIEVE: u8 = 0x00;
+pub const P1_SHOW_IN_DEVICE: u8 = 0x01;
+
+pub const PAYLOAD_INIT: u8 = 0x00;
+pub const PAYLOAD_ADD: u8 = 0x01;
+pub const PAYLOAD_LAST: u8 = 0x02;
+
+pub const SW_OK: u16 = 0x9000;
+
+/// Command sent to the device
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApduCommand {
+    pub cla: u8,
+    pub ins: u8,
+    pub p1: u8,
+    pub p2: u8,
+    pub data: Vec<u8>,
+}
+
+impl ApduCommand {
+    pub fn new(ins: u8, p1: u8, data: Vec<u8>) -> Self {
+        ApduCommand {
+            cla: CLA,
+            ins,
+            p1,
+            p2: 0,
+            data,
+        }
+    }
+
+    /// `CLA || INS || P1 || P2 || L || payload`
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(5 + self.data.len());
+        out.extend_from_slice(&[self.cla, self.ins, self.p1, self.p2, self.data.len() as u8]);
+        out.extend_from_slice(&self.data);
+        out
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 5 || bytes.len() != 5 + bytes[4] as usize {
+            return None;
+        }
+        Some(ApduCommand {
+            cla: bytes[0],
+            ins: bytes[1],
+            p1: bytes[2],
+            p2: bytes[3],
+            data: bytes[5..].to_vec(),
+        })
+    }
+}
+
+/// Response payload followed by the status word
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApduAnswer {
+    pub data: Vec<u8>,
+    pub retcode: u16,
+}
+
+impl ApduAnswer {
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 2 {
+            return None;
+        }
+        let (data, sw) = bytes.split_at(bytes.len() - 2);
+        Some(ApduAnswer {
+            data: data.to_vec(),
+            retcode: u16::from_be_bytes([sw[0], sw[1]]),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = self.data.clone();
+        out.extend_from_slice(&self.retcode.to_be_bytes());
+        out
+    }
+
+    /// The payload if the device answered `0x9000`
+    pub fn ok_data(self) -> Result<Vec<u8>, AppError> {
+        if self.retcode == SW_OK {
+            Ok(self.data)
+        } else {
+            Err(AppError::from_status_word(self.retcode))
+        }
+    }
+}
+
+/// Splits a chunked payload (INIT_TX, CHECKANDSIGN) into commands: an empty
+/// `INIT` chunk followed by `CHUNK_SIZE` pieces, the last one marked `LAST`
+pub fn chunk_commands(ins: u8, message: &[u8]) -> Vec<ApduCommand> {
+    let mut commands = vec![ApduCommand::new(ins, PAYLOAD_INIT, Vec::new())];
+    let chunks: Vec<&[u8]> = message.chunks(CHUNK_SIZE).collect();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let p1 = if i + 1 == chunks.len() {
+            PAYLOAD_LAST
+        } else {
+            PAYLOAD_ADD
+        };
+        commands.push(ApduCommand::new(ins, p1, chunk.to_vec()));
+    }
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_roundtrip() {
+        let cmd = ApduCommand::new(INS_GET_IVK, P1_SHOW_IN_DEVICE, vec![0xe8, 0x03, 0, 0]);
+        let bytes = cmd.serialize();
+        assert_eq!(
+            bytes,
+            [0x85, 0xf0, 0x01, 0x00, 0x04, 0xe8, 0x03, 0x00, 0x00]
+        );
+        assert_eq!(ApduCommand::deserialize(&bytes), Some(cmd));
+        assert_eq!(ApduCommand::deserialize(&bytes[..8]), None);
+    }
+
+    #[test]
+    fn test_answer_status_word() {
+        let answer = ApduAnswer::from_bytes(&[1, 2, 0x69, 0x86]).unwrap();
+        assert_eq!(answer.data, [1, 2]);
+        assert_eq!(answer.retcode, 0x6986);
+        assert_eq!(answer.to_bytes(), [1, 2, 0x69, 0x86]);
+        assert_eq!(answer.ok_data(), Err(AppError::CommandNotAllowed));
+        assert!(ApduAnswer::from_bytes(&[0x90]).is_none());
+    }
+
+    #[test]
+    fn test_chunk_commands() {
+        let message = vec![7u8; 2 * CHUNK_SIZE + 1];
+        let commands = chunk_commands(INS_INIT_TX, &message);
+        let p1: Vec<u8> = commands.iter().map(|c| c.p1).collect();
+        assert_eq!(p1, [PAYLOAD_INIT, PAYLOAD_ADD, PAYLOAD_ADD, PAYLOAD_LAST]);
+        assert!(commands[0].data.is_empty());
+        assert_eq!(commands[3].data.len(), 1);
+
+        let single = chunk_commands(INS_CHECKANDSIGN, &[1, 2, 3]);
+        assert_eq!(single.len(), 2);
+        assert_eq!(single[1].p1, PAYLOAD_LAST);
+    }
+}
@@ -0,0 +1,195 @@
+//! Status words returned by the app and the client error type.
+
+use std::fmt;
+
+/// Non-success status word reported by the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AppError {
+    Busy,
+    ExecutionError,
+    WrongLength,
+    EmptyBuffer,
+    OutputBufferTooSmall,
+    DataInvalid,
+    ConditionsNotSatisfied,
+    CommandNotAllowed,
+    TxNotInitialized,
+    DataTooLong,
+    ExtractTransactionFail,
+    HashMsgBufFail,
+    UnprocessedTx,
+    PrevoutInvalid,
+    SequenceInvalid,
+    OutputsInvalid,
+    JoinsplitInvalid,
+    SpendInvalid,
+    OutputContentInvalid,
+    EncryptionInvalid,
+    CheckSignTrFail,
+    SignSpendFail,
+    BadValueBalance,
+    BadKeyHandle,
+    InvalidP1P2,
+    InsNotSupported,
+    ClaNotSupported,
+    Unknown,
+    SignVerifyError,
+    /// Status word not listed in `apdu_codes.h`
+    Unlisted(u16),
+}
+
+const STATUS_WORDS: [(u16, AppError); 29] = [
+    (0x9001, AppError::Busy),
+    (0x6400, AppError::ExecutionError),
+    (0x6700, AppError::WrongLength),
+    (0x6982, AppError::EmptyBuffer),
+    (0x6983, AppError::OutputBufferTooSmall),
+    (0x6984, AppError::DataInvalid),
+    (0x6985, AppError::ConditionsNotSatisfied),
+    (0x6986, AppError::CommandNotAllowed),
+    (0x6987, AppError::TxNotInitialized),
+    (0x6988, AppError::DataTooLong),
+    (0x6989, AppError::ExtractTransactionFail),
+    (0x6990, AppError::HashMsgBufFail),
+    (0x6991, AppError::UnprocessedTx),
+    (0x6992, AppError::PrevoutInvalid),
+    (0x6993, AppError::SequenceInvalid),
+    (0x6994, AppError::OutputsInvalid),
+    (0x6995, AppError::JoinsplitInvalid),
+    (0x6996, AppError::SpendInvalid),
+    (0x6997, AppError::OutputContentInvalid),
+    (0x6998, AppError::EncryptionInvalid),
+    (0x6999, AppError::CheckSignTrFail),
+    (0x69A0, AppError::SignSpendFail),
+    (0x69A1, AppError::BadValueBalance),
+    (0x6A80, AppError::BadKeyHandle),
+    (0x6B00, AppError::InvalidP1P2),
+    (0x6D00, AppError::InsNotSupported),
+    (0x6E00, AppError::ClaNotSupported),
+    (0x6F00, AppError::Unknown),
+    (0x6F01, AppError::SignVerifyError),
+];
+
+impl AppError {
+    pub fn from_status_word(sw: u16) -> Self {
+        STATUS_WORDS
+            .iter()
+            .find(|(code, _)| *code == sw)
+            .map(|(_, err)| *err)
+            .unwrap_or(AppError::Unlisted(sw))
+    }
+
+    pub fn status_word(&self) -> u16 {
+        if let AppError::Unlisted(sw) = self {
+            return *sw;
+        }
+        STATUS_WORDS
+            .iter()
+            .find(|(_, err)| err == self)
+            .map(|(code, _)| *code)
+            .unwrap_or(0x6F00)
+    }
+
+    /// Same wording as `ERROR_DESCRIPTION` in the JS package
+    pub fn description(&self) -> &'static str {
+        match self {
+            AppError::Busy => "Device is busy",
+            AppError::ExecutionError => "Execution Error",
+            AppError::WrongLength => "Wrong Length",
+            AppError::EmptyBuffer => "Empty Buffer",
+            AppError::OutputBufferTooSmall => "Output buffer too small",
+            AppError::DataInvalid => "Data is invalid",
+            AppError::ConditionsNotSatisfied => "Conditions not satisfied",
+            AppError::CommandNotAllowed => "Transaction rejected",
+            AppError::TxNotInitialized => "Transaction not initialized",
+            AppError::DataTooLong => "Data too long",
+            AppError::ExtractTransactionFail => "Failed to extract transaction",
+            AppError::HashMsgBufFail => "Failed to hash message buffer",
+            AppError::UnprocessedTx => "Transaction extraction incomplete",
+            AppError::PrevoutInvalid => "Prevout check failed",
+            AppError::SequenceInvalid => "Sequence check failed",
+            AppError::OutputsInvalid => "Hash of outputs check failed",
+            AppError::JoinsplitInvalid => "Joinsplit check failed",
+            AppError::SpendInvalid => "Spend check failed",
+            AppError::OutputContentInvalid => "Outputs content check failed",
+            AppError::EncryptionInvalid => "Check of encryption failed",
+            AppError::CheckSignTrFail => "Check/sign transparent failed",
+            AppError::SignSpendFail => "Failed to sign spends",
+            AppError::BadValueBalance => "Bad value balance",
+            AppError::BadKeyHandle => "Bad key handle",
+            AppError::InvalidP1P2 => "Invalid P1/P2",
+            AppError::InsNotSupported => "Instruction not supported",
+            AppError::ClaNotSupported => "App does not seem to be open",
+            AppError::Unknown => "Unknown error",
+            AppError::SignVerifyError => "Sign/verify error",
+            AppError::Unlisted(_) => "Unknown Status Code",
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (0x{:04X})", self.description(), self.status_word())
+    }
+}
+
+impl std::error::Error for AppError {}
+
+/// Errors returned by [`crate::ZcashApp`]
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error<E> {
+    /// The transport failed to exchange the command
+    Transport(E),
+    /// The device answered with a non-success status word
+    App(AppError),
+    /// The device answered `0x9000` with an unexpected payload
+    InvalidResponse(&'static str),
+    /// The request cannot be encoded
+    InvalidInput(&'static str),
+    /// The session could not be opened, or a sealed answer did not open
+    Session(&'static str),
+}
+
+impl<E> From<AppError> for Error<E> {
+    fn from(err: AppError) -> Self {
+        Error::App(err)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Transport(e) => write!(f, "transport error: {}", e),
+            Error::App(e) => write!(f, "{}", e),
+            Error::InvalidResponse(msg) => write!(f, "invalid response: {}", msg),
+            Error::InvalidInput(msg) => write!(f, "invalid input: {}", msg),
+            Error::Session(msg) => write!(f, "session error: {}", msg),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> std::error::Error for Error<E> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_word_roundtrip() {
+        for (sw, err) in STATUS_WORDS.iter() {
+            assert_eq!(AppError::from_status_word(*sw), *err);
+            assert_eq!(err.status_word(), *sw);
+        }
+        let unlisted = AppError::from_status_word(0x1234);
+        assert_eq!(unlisted, AppError::Unlisted(0x1234));
+        assert_eq!(unlisted.status_word(), 0x1234);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            AppError::ClaNotSupported.to_string(),
+            "App does not seem to be open (0x6E00)"
+        );
+    }
+}
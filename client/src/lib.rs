@@ -0,0 +1,602 @@
+//! Host-side client for the Zcash Ledger app.
+//!
+//! Encodes the commands listed in `docs/APDUSPEC.md`, sends them through a
+//! [`Transport`] and decodes the answers into typed values. Once
+//! [`ZcashApp::open_session`] succeeded, answers carrying keys or transaction
+//! randomness come back sealed and are opened transparently.
+
+pub mod apdu;
+pub mod errors;
+pub mod transport;
+
+use std::cell::RefCell;
+use std::convert::TryInto;
+
+use rslib::session::{EphemeralKey, Session, SESSION_OPEN_REPLY_SIZE};
+use rslib::types::Signature;
+
+use crate::apdu::*;
+pub use crate::apdu::{ApduAnswer, ApduCommand};
+pub use crate::errors::{AppError, Error};
+pub use crate::transport::{Exchange, MockError, MockTransport, RecordingTransport, Transport};
+
+pub const PK_LEN: usize = 33;
+pub const SAPLING_ADDR_LEN: usize = 43;
+pub const DIV_LEN: usize = 11;
+pub const DIV_LIST_LEN: usize = 20;
+
+/// Answer to GET_VERSION
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Version {
+    pub test_mode: bool,
+    pub major: u8,
+    pub minor: u8,
+    pub patch: u8,
+    pub locked: bool,
+    pub target_id: u32,
+}
+
+/// Transparent public key and its base58 address
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnshieldedAddress {
+    pub public_key: [u8; PK_LEN],
+    pub address: String,
+}
+
+/// Raw Sapling payment address and its bech32 encoding
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShieldedAddress {
+    pub raw: [u8; SAPLING_ADDR_LEN],
+    pub address: String,
+}
+
+/// Randomness for one spend, as returned by EXTRACT_SPEND
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpendData {
+    pub ak: [u8; 32],
+    pub nsk: [u8; 32],
+    pub rcv: [u8; 32],
+    pub alpha: [u8; 32],
+}
+
+/// Randomness for one output, as returned by EXTRACT_OUTPUT
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutputData {
+    pub rcv: [u8; 32],
+    pub rseed: [u8; 32],
+    /// Only present for outputs without an OVK
+    pub hash_seed: Option<[u8; 32]>,
+}
+
+/// Zcash app reachable through transport `T`
+pub struct ZcashApp<T> {
+    transport: T,
+    session: RefCell<Option<Session>>,
+}
+
+impl<T: Transport> ZcashApp<T> {
+    pub fn new(transport: T) -> Self {
+        ZcashApp {
+            transport,
+            session: RefCell::new(None),
+        }
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+
+    fn send(&self, command: ApduCommand) -> Result<Vec<u8>, Error<T::Error>> {
+        let answer = self
+            .transport
+            .exchange(&command)
+            .map_err(Error::Transport)?;
+        Ok(answer.ok_data()?)
+    }
+
+    /// Sends a command whose answer the device seals when a session is open
+    fn send_sealed(&self, command: ApduCommand) -> Result<Vec<u8>, Error<T::Error>> {
+        let mut data = self.send(command)?;
+        match self.session.borrow_mut().as_mut() {
+            Some(session) => session
+                .open(&mut data)
+                .map(|payload| payload.to_vec())
+                .map_err(|_| Error::Session("sealed answer rejected")),
+            None => Ok(data),
+        }
+    }
+
+    fn send_chunks(&self, ins: u8, message: &[u8]) -> Result<Vec<u8>, Error<T::Error>> {
+        if message.is_empty() {
+            return Err(Error::InvalidInput("empty message"));
+        }
+        let mut last = Vec::new();
+        for command in chunk_commands(ins, message) {
+            last = self.send(command)?;
+        }
+        Ok(last)
+    }
+
+    pub fn get_version(&self) -> Result<Version, Error<T::Error>> {
+        let data = self.send(ApduCommand::new(INS_GET_VERSION, 0, Vec::new()))?;
+        if data.len() < 5 {
+            return Err(Error::InvalidResponse("version too short"));
+        }
+        let target_id = match data.get(5..9) {
+            Some(id) => u32::from_be_bytes(id.try_into().unwrap()),
+            None => 0,
+        };
+        Ok(Version {
+            test_mode: data[0] != 0,
+            major: data[1],
+            minor: data[2],
+            patch: data[3],
+            locked: data[4] == 1,
+            target_id,
+        })
+    }
+
+    /// Transparent address for a BIP44 path `m/44'/133'/...`
+    pub fn get_address_unshielded(
+        &self,
+        path: &[u32; 5],
+        show: bool,
+    ) -> Result<UnshieldedAddress, Error<T::Error>> {
+        let payload = path.iter().flat_map(|p| p.to_le_bytes().to_vec()).collect();
+        let data = self.send(ApduCommand::new(INS_GET_ADDR_SECP256K1, p1(show), payload))?;
+        if data.len() <= PK_LEN {
+            return Err(Error::InvalidResponse("address too short"));
+        }
+        let (pk, address) = data.split_at(PK_LEN);
+        Ok(UnshieldedAddress {
+            public_key: pk.try_into().unwrap(),
+            address: utf8(address)?,
+        })
+    }
+
+    /// Default Sapling address for ZIP32 account `path`
+    pub fn get_address_shielded(
+        &self,
+        path: u32,
+        show: bool,
+    ) -> Result<ShieldedAddress, Error<T::Error>> {
+        let payload = path.to_le_bytes().to_vec();
+        let data = self.send(ApduCommand::new(INS_GET_ADDR_SAPLING, p1(show), payload))?;
+        shielded_address(&data)
+    }
+
+    /// Sapling address for ZIP32 account `path` and diversifier `div`
+    pub fn get_address_shielded_with_div(
+        &self,
+        path: u32,
+        div: &[u8; DIV_LEN],
+        show: bool,
+    ) -> Result<ShieldedAddress, Error<T::Error>> {
+        let mut payload = path.to_le_bytes().to_vec();
+        payload.extend_from_slice(div);
+        let data = self.send(ApduCommand::new(
+            INS_GET_ADDR_SAPLING_DIV,
+            p1(show),
+            payload,
+        ))?;
+        shielded_address(&data)
+    }
+
+    /// Valid diversifiers among the 20 following `start_index`
+    pub fn get_div_list(
+        &self,
+        path: u32,
+        start_index: &[u8; DIV_LEN],
+    ) -> Result<Vec<[u8; DIV_LEN]>, Error<T::Error>> {
+        let mut payload = path.to_le_bytes().to_vec();
+        payload.extend_from_slice(start_index);
+        let data = self.send(ApduCommand::new(
+            INS_GET_DIV_LIST,
+            P1_ONLY_RETRIEVE,
+            payload,
+        ))?;
+        if data.len() < DIV_LEN * DIV_LIST_LEN {
+            return Err(Error::InvalidResponse("diversifier list too short"));
+        }
+        Ok(data[..DIV_LEN * DIV_LIST_LEN]
+            .chunks(DIV_LEN)
+            .filter(|d| d.iter().any(|b| *b != 0))
+            .map(|d| d.try_into().unwrap())
+            .collect())
+    }
+
+    /// Opens an encrypted session and returns the device identity key. Pass
+    /// the identity returned by the first session as `pinned_identity` to
+    /// refuse any other device.
+    pub fn open_session(
+        &self,
+        pinned_identity: Option<&[u8; 32]>,
+    ) -> Result<[u8; 32], Error<T::Error>> {
+        self.session.replace(None);
+        let key = EphemeralKey::generate();
+        let data = self.send(ApduCommand::new(INS_KEY_EXCHANGE, 0, key.public.to_vec()))?;
+        if data.len() < SESSION_OPEN_REPLY_SIZE {
+            return Err(Error::InvalidResponse("key exchange answer too short"));
+        }
+        let identity: [u8; 32] = data[..32].try_into().unwrap();
+        if pinned_identity.is_some_and(|pinned| *pinned != identity) {
+            return Err(Error::Session("device identity changed"));
+        }
+        let device_public: [u8; 32] = data[32..64].try_into().unwrap();
+        let signature = Signature(data[64..128].try_into().unwrap());
+        let session = Session::connect(key, &device_public, &identity, &signature)
+            .map_err(|_| Error::Session("transcript signature rejected"))?;
+        self.session.replace(Some(session));
+        Ok(identity)
+    }
+
+    /// Forgets the session keys; the device keeps sealing its answers until
+    /// it is restarted or a new session is opened
+    pub fn close_session(&self) {
+        self.session.replace(None);
+    }
+
+    pub fn has_session(&self) -> bool {
+        self.session.borrow().is_some()
+    }
+
+    /// Incoming viewing key; always requires confirmation on the device
+    pub fn get_ivk(&self, path: u32) -> Result<[u8; 32], Error<T::Error>> {
+        let payload = path.to_le_bytes().to_vec();
+        let data = self.send_sealed(ApduCommand::new(INS_GET_IVK, P1_SHOW_IN_DEVICE, payload))?;
+        bytes32(&data)
+    }
+
+    /// Outgoing viewing key; always requires confirmation on the device
+    pub fn get_ovk(&self, path: u32) -> Result<[u8; 32], Error<T::Error>> {
+        let payload = path.to_le_bytes().to_vec();
+        let data = self.send_sealed(ApduCommand::new(INS_GET_OVK, P1_SHOW_IN_DEVICE, payload))?;
+        bytes32(&data)
+    }
+
+    /// Nullifier of the note with commitment `cm` at tree position `pos`
+    pub fn get_nullifier(
+        &self,
+        path: u32,
+        pos: u64,
+        cm: &[u8; 32],
+    ) -> Result<[u8; 32], Error<T::Error>> {
+        let mut payload = path.to_le_bytes().to_vec();
+        payload.extend_from_slice(&pos.to_le_bytes());
+        payload.extend_from_slice(cm);
+        let data = self.send_sealed(ApduCommand::new(INS_GET_NF, P1_SHOW_IN_DEVICE, payload))?;
+        bytes32(&data)
+    }
+
+    /// Sends the INIT_TX message; returns its SHA256 as computed by the device
+    pub fn init_tx(&self, message: &[u8]) -> Result<[u8; 32], Error<T::Error>> {
+        let data = self.send_chunks(INS_INIT_TX, message)?;
+        bytes32(&data)
+    }
+
+    pub fn extract_spend_data(&self) -> Result<SpendData, Error<T::Error>> {
+        let data = self.send_sealed(ApduCommand::new(INS_EXTRACT_SPEND, 0, Vec::new()))?;
+        if data.len() < 128 {
+            return Err(Error::InvalidResponse("spend data too short"));
+        }
+        Ok(SpendData {
+            ak: data[0..32].try_into().unwrap(),
+            nsk: data[32..64].try_into().unwrap(),
+            rcv: data[64..96].try_into().unwrap(),
+            alpha: data[96..128].try_into().unwrap(),
+        })
+    }
+
+    pub fn extract_output_data(&self) -> Result<OutputData, Error<T::Error>> {
+        let data = self.send_sealed(ApduCommand::new(INS_EXTRACT_OUTPUT, 0, Vec::new()))?;
+        if data.len() < 64 {
+            return Err(Error::InvalidResponse("output data too short"));
+        }
+        Ok(OutputData {
+            rcv: data[0..32].try_into().unwrap(),
+            rseed: data[32..64].try_into().unwrap(),
+            hash_seed: data.get(64..96).map(|h| h.try_into().unwrap()),
+        })
+    }
+
+    /// Sends the transaction blob to check and sign; returns its SHA256
+    pub fn check_and_sign(&self, message: &[u8]) -> Result<[u8; 32], Error<T::Error>> {
+        let data = self.send_chunks(INS_CHECKANDSIGN, message)?;
+        bytes32(&data)
+    }
+
+    pub fn extract_spend_sig(&self) -> Result<[u8; 64], Error<T::Error>> {
+        let data = self.send(ApduCommand::new(INS_EXTRACT_SPENDSIG, 0, Vec::new()))?;
+        bytes64(&data)
+    }
+
+    pub fn extract_transparent_sig(&self) -> Result<[u8; 64], Error<T::Error>> {
+        let data = self.send(ApduCommand::new(INS_EXTRACT_TRANSSIG, 0, Vec::new()))?;
+        bytes64(&data)
+    }
+}
+
+fn p1(show: bool) -> u8 {
+    if show {
+        P1_SHOW_IN_DEVICE
+    } else {
+        P1_ONLY_RETRIEVE
+    }
+}
+
+fn utf8<E>(bytes: &[u8]) -> Result<String, Error<E>> {
+    String::from_utf8(bytes.to_vec()).map_err(|_| Error::InvalidResponse("address is not utf8"))
+}
+
+fn shielded_address<E>(data: &[u8]) -> Result<ShieldedAddress, Error<E>> {
+    if data.len() <= SAPLING_ADDR_LEN {
+        return Err(Error::InvalidResponse("address too short"));
+    }
+    let (raw, address) = data.split_at(SAPLING_ADDR_LEN);
+    Ok(ShieldedAddress {
+        raw: raw.try_into().unwrap(),
+        address: utf8(address)?,
+    })
+}
+
+fn bytes32<E>(data: &[u8]) -> Result<[u8; 32], Error<E>> {
+    data.get(..32)
+        .map(|b| b.try_into().unwrap())
+        .ok_or(Error::InvalidResponse("expected 32 bytes"))
+}
+
+fn bytes64<E>(data: &[u8]) -> Result<[u8; 64], Error<E>> {
+    data.get(..64)
+        .map(|b| b.try_into().unwrap())
+        .ok_or(Error::InvalidResponse("expected 64 bytes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(mock: MockTransport) -> ZcashApp<MockTransport> {
+        ZcashApp::new(mock)
+    }
+
+    #[test]
+    fn test_get_version() {
+        let mock = MockTransport::default();
+        mock.expect(
+            &ApduCommand::new(INS_GET_VERSION, 0, Vec::new()),
+            &[0xff, 3, 1, 0, 0, 0x33, 0x00, 0x00, 0x04],
+            0x9000,
+        );
+        let version = app(mock).get_version().unwrap();
+        assert_eq!(
+            version,
+            Version {
+                test_mode: true,
+                major: 3,
+                minor: 1,
+                patch: 0,
+                locked: false,
+                target_id: 0x3300_0004,
+            }
+        );
+    }
+
+    #[test]
+    fn test_get_address_shielded() {
+        let mock = MockTransport::default();
+        let mut answer = vec![7u8; SAPLING_ADDR_LEN];
+        answer.extend_from_slice(b"zs1test");
+        mock.expect(
+            &ApduCommand::new(INS_GET_ADDR_SAPLING, P1_SHOW_IN_DEVICE, vec![1, 0, 0, 0]),
+            &answer,
+            0x9000,
+        );
+        let addr = app(mock).get_address_shielded(1, true).unwrap();
+        assert_eq!(addr.raw, [7u8; SAPLING_ADDR_LEN]);
+        assert_eq!(addr.address, "zs1test");
+    }
+
+    #[test]
+    fn test_get_div_list_skips_invalid() {
+        let mock = MockTransport::default();
+        let mut answer = vec![0u8; DIV_LEN * DIV_LIST_LEN];
+        answer[DIV_LEN] = 1;
+        let mut payload = vec![0, 0, 0, 0];
+        payload.extend_from_slice(&[0u8; DIV_LEN]);
+        mock.expect(
+            &ApduCommand::new(INS_GET_DIV_LIST, P1_ONLY_RETRIEVE, payload),
+            &answer,
+            0x9000,
+        );
+        let list = app(mock).get_div_list(0, &[0u8; DIV_LEN]).unwrap();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0][0], 1);
+    }
+
+    #[test]
+    fn test_get_nullifier_payload() {
+        let mock = MockTransport::default();
+        let mut payload = 1000u32.to_le_bytes().to_vec();
+        payload.extend_from_slice(&2u64.to_le_bytes());
+        payload.extend_from_slice(&[9u8; 32]);
+        mock.expect(
+            &ApduCommand::new(INS_GET_NF, P1_SHOW_IN_DEVICE, payload),
+            &[5u8; 32],
+            0x9000,
+        );
+        assert_eq!(
+            app(mock).get_nullifier(1000, 2, &[9u8; 32]).unwrap(),
+            [5u8; 32]
+        );
+    }
+
+    #[test]
+    fn test_init_tx_chunks() {
+        let message = vec![3u8; CHUNK_SIZE + 10];
+        let mock = MockTransport::default();
+        mock.expect(
+            &ApduCommand::new(INS_INIT_TX, PAYLOAD_INIT, Vec::new()),
+            &[],
+            0x9000,
+        )
+        .expect(
+            &ApduCommand::new(INS_INIT_TX, PAYLOAD_ADD, message[..CHUNK_SIZE].to_vec()),
+            &[],
+            0x9000,
+        )
+        .expect(
+            &ApduCommand::new(INS_INIT_TX, PAYLOAD_LAST, message[CHUNK_SIZE..].to_vec()),
+            &[8u8; 32],
+            0x9000,
+        );
+        let app = app(mock);
+        assert_eq!(app.init_tx(&message).unwrap(), [8u8; 32]);
+        assert_eq!(app.transport().remaining(), 0);
+        assert_eq!(app.init_tx(&[]), Err(Error::InvalidInput("empty message")));
+    }
+
+    #[test]
+    fn test_check_and_sign_rejected() {
+        let mock = MockTransport::default();
+        mock.expect(
+            &ApduCommand::new(INS_CHECKANDSIGN, PAYLOAD_INIT, Vec::new()),
+            &[],
+            0x9000,
+        )
+        .expect(
+            &ApduCommand::new(INS_CHECKANDSIGN, PAYLOAD_LAST, vec![1, 2]),
+            &[],
+            0x6986,
+        );
+        assert_eq!(
+            app(mock).check_and_sign(&[1, 2]),
+            Err(Error::App(AppError::CommandNotAllowed))
+        );
+    }
+
+    #[test]
+    fn test_extract_output_data() {
+        let mock = MockTransport::default();
+        let cmd = ApduCommand::new(INS_EXTRACT_OUTPUT, 0, Vec::new());
+        mock.expect(&cmd, &[1u8; 64], 0x9000)
+            .expect(&cmd, &[2u8; 96], 0x9000);
+        let app = app(mock);
+        assert_eq!(app.extract_output_data().unwrap().hash_seed, None);
+        assert_eq!(
+            app.extract_output_data().unwrap().hash_seed,
+            Some([2u8; 32])
+        );
+    }
+
+    /// Device side of the session, answering IVK requests with `[5; 32]`
+    struct SessionDevice {
+        identity: rslib::session::Identity,
+        session: RefCell<Option<Session>>,
+        tamper: bool,
+    }
+
+    impl SessionDevice {
+        fn new(seed: u8) -> Self {
+            SessionDevice {
+                identity: rslib::session::Identity::from_seed(
+                    &rslib::types::SpendingKey::from_bytes([seed; 32]),
+                ),
+                session: RefCell::new(None),
+                tamper: false,
+            }
+        }
+    }
+
+    impl Transport for SessionDevice {
+        type Error = MockError;
+
+        fn exchange(&self, command: &ApduCommand) -> Result<ApduAnswer, MockError> {
+            let data = match command.ins {
+                INS_KEY_EXCHANGE => {
+                    let host_public = command.data[..].try_into().unwrap();
+                    let key = EphemeralKey::generate();
+                    let device_public = key.public;
+                    let (session, signature) =
+                        Session::accept(&self.identity, key, &host_public).unwrap();
+                    self.session.replace(Some(session));
+                    let mut data = self.identity.public.to_vec();
+                    data.extend_from_slice(&device_public);
+                    data.extend_from_slice(&signature.0);
+                    data
+                }
+                INS_GET_IVK => {
+                    let mut frame = vec![0u8; 32 + rslib::session::FRAME_OVERHEAD];
+                    let mut session = self.session.borrow_mut();
+                    session
+                        .as_mut()
+                        .unwrap()
+                        .seal(&[5u8; 32], &mut frame)
+                        .unwrap();
+                    if self.tamper {
+                        frame[10] ^= 1;
+                    }
+                    frame
+                }
+                _ => {
+                    return Err(MockError::Exhausted {
+                        command: command.serialize(),
+                    })
+                }
+            };
+            Ok(ApduAnswer {
+                data,
+                retcode: SW_OK,
+            })
+        }
+    }
+
+    #[test]
+    fn test_session_unseals_keys() {
+        let app = ZcashApp::new(SessionDevice::new(1));
+        assert!(!app.has_session());
+        let identity = app.open_session(None).unwrap();
+        assert_eq!(app.open_session(Some(&identity)).unwrap(), identity);
+        assert!(app.has_session());
+        assert_eq!(app.get_ivk(1000).unwrap(), [5u8; 32]);
+        // the second answer uses the next counter
+        assert_eq!(app.get_ivk(1000).unwrap(), [5u8; 32]);
+
+        // another device does not match the pinned identity
+        let other = ZcashApp::new(SessionDevice::new(2));
+        assert_eq!(
+            other.open_session(Some(&identity)),
+            Err(Error::Session("device identity changed"))
+        );
+        assert!(!other.has_session());
+    }
+
+    #[test]
+    fn test_session_rejects_tampered_answer() {
+        let mut device = SessionDevice::new(1);
+        device.tamper = true;
+        let app = ZcashApp::new(device);
+        app.open_session(None).unwrap();
+        assert_eq!(
+            app.get_ivk(1000),
+            Err(Error::Session("sealed answer rejected"))
+        );
+    }
+
+    #[test]
+    fn test_short_signature() {
+        let mock = MockTransport::default();
+        mock.expect(
+            &ApduCommand::new(INS_EXTRACT_SPENDSIG, 0, Vec::new()),
+            &[0u8; 10],
+            0x9000,
+        );
+        assert_eq!(
+            app(mock).extract_spend_sig(),
+            Err(Error::InvalidResponse("expected 64 bytes"))
+        );
+    }
+}
@@ -0,0 +1,257 @@
+//! Transport abstraction plus a scripted mock for tests.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::apdu::{ApduAnswer, ApduCommand};
+
+/// Anything able to exchange one APDU with the device (HID, TCP to Speculos, ...)
+pub trait Transport {
+    type Error;
+
+    fn exchange(&self, command: &ApduCommand) -> Result<ApduAnswer, Self::Error>;
+}
+
+impl<T: Transport + ?Sized> Transport for &T {
+    type Error = T::Error;
+
+    fn exchange(&self, command: &ApduCommand) -> Result<ApduAnswer, Self::Error> {
+        (**self).exchange(command)
+    }
+}
+
+/// One command and the raw answer (payload || status word) it produced
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Exchange {
+    pub command: Vec<u8>,
+    pub answer: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MockError {
+    /// The script ran out of exchanges
+    Exhausted { command: Vec<u8> },
+    /// The command does not match the scripted one
+    Unexpected { expected: Vec<u8>, got: Vec<u8> },
+    /// The scripted answer is shorter than a status word
+    MalformedAnswer,
+    /// A script line could not be parsed
+    InvalidScript(usize),
+}
+
+impl fmt::Display for MockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MockError::Exhausted { command } => {
+                write!(f, "no scripted answer for {}", hex::encode(command))
+            }
+            MockError::Unexpected { expected, got } => write!(
+                f,
+                "expected command {}, got {}",
+                hex::encode(expected),
+                hex::encode(got)
+            ),
+            MockError::MalformedAnswer => write!(f, "scripted answer too short"),
+            MockError::InvalidScript(line) => write!(f, "invalid script at line {}", line),
+        }
+    }
+}
+
+impl std::error::Error for MockError {}
+
+/// Replays a fixed list of exchanges, failing on any command that differs
+/// from the script
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    script: RefCell<VecDeque<Exchange>>,
+}
+
+impl MockTransport {
+    pub fn new(exchanges: Vec<Exchange>) -> Self {
+        MockTransport {
+            script: RefCell::new(exchanges.into()),
+        }
+    }
+
+    /// Queues `command` to be answered with `data` and status word `retcode`
+    pub fn expect(&self, command: &ApduCommand, data: &[u8], retcode: u16) -> &Self {
+        let answer = ApduAnswer {
+            data: data.to_vec(),
+            retcode,
+        };
+        self.script.borrow_mut().push_back(Exchange {
+            command: command.serialize(),
+            answer: answer.to_bytes(),
+        });
+        self
+    }
+
+    /// Parses a script of `=> <command hex>` / `<= <answer hex>` line pairs,
+    /// the format produced by [`to_script`]
+    pub fn from_script(script: &str) -> Result<Self, MockError> {
+        let mut exchanges = Vec::new();
+        let mut command = None;
+        for (i, line) in script.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let decode =
+                |s: &str| hex::decode(s.trim()).map_err(|_| MockError::InvalidScript(i + 1));
+            if let Some(rest) = line.strip_prefix("=>") {
+                if command.is_some() {
+                    return Err(MockError::InvalidScript(i + 1));
+                }
+                command = Some(decode(rest)?);
+            } else if let Some(rest) = line.strip_prefix("<=") {
+                let command = command.take().ok_or(MockError::InvalidScript(i + 1))?;
+                exchanges.push(Exchange {
+                    command,
+                    answer: decode(rest)?,
+                });
+            } else {
+                return Err(MockError::InvalidScript(i + 1));
+            }
+        }
+        if command.is_some() {
+            return Err(MockError::InvalidScript(script.lines().count()));
+        }
+        Ok(MockTransport::new(exchanges))
+    }
+
+    /// Number of scripted exchanges not yet consumed
+    pub fn remaining(&self) -> usize {
+        self.script.borrow().len()
+    }
+}
+
+impl Transport for MockTransport {
+    type Error = MockError;
+
+    fn exchange(&self, command: &ApduCommand) -> Result<ApduAnswer, MockError> {
+        let got = command.serialize();
+        let next = self
+            .script
+            .borrow_mut()
+            .pop_front()
+            .ok_or_else(|| MockError::Exhausted {
+                command: got.clone(),
+            })?;
+        if next.command != got {
+            return Err(MockError::Unexpected {
+                expected: next.command,
+                got,
+            });
+        }
+        ApduAnswer::from_bytes(&next.answer).ok_or(MockError::MalformedAnswer)
+    }
+}
+
+/// Forwards to an inner transport and keeps every successful exchange, so a
+/// session against a real device can be replayed later with [`MockTransport`]
+#[derive(Debug)]
+pub struct RecordingTransport<T> {
+    inner: T,
+    exchanges: RefCell<Vec<Exchange>>,
+}
+
+impl<T: Transport> RecordingTransport<T> {
+    pub fn new(inner: T) -> Self {
+        RecordingTransport {
+            inner,
+            exchanges: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn exchanges(&self) -> Vec<Exchange> {
+        self.exchanges.borrow().clone()
+    }
+
+    pub fn into_exchanges(self) -> Vec<Exchange> {
+        self.exchanges.into_inner()
+    }
+}
+
+impl<T: Transport> Transport for RecordingTransport<T> {
+    type Error = T::Error;
+
+    fn exchange(&self, command: &ApduCommand) -> Result<ApduAnswer, T::Error> {
+        let answer = self.inner.exchange(command)?;
+        self.exchanges.borrow_mut().push(Exchange {
+            command: command.serialize(),
+            answer: answer.to_bytes(),
+        });
+        Ok(answer)
+    }
+}
+
+/// Serializes exchanges in the format read by [`MockTransport::from_script`]
+pub fn to_script(exchanges: &[Exchange]) -> String {
+    let mut out = String::new();
+    for e in exchanges {
+        out.push_str("=> ");
+        out.push_str(&hex::encode(&e.command));
+        out.push_str("\n<= ");
+        out.push_str(&hex::encode(&e.answer));
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apdu::{INS_GET_VERSION, P1_ONLY_RETRIEVE};
+
+    #[test]
+    fn test_mock_replay() {
+        let cmd = ApduCommand::new(INS_GET_VERSION, P1_ONLY_RETRIEVE, Vec::new());
+        let mock = MockTransport::default();
+        mock.expect(&cmd, &[1, 2], 0x9000);
+
+        let other = ApduCommand::new(INS_GET_VERSION, 1, Vec::new());
+        assert!(matches!(
+            mock.exchange(&other),
+            Err(MockError::Unexpected { .. })
+        ));
+        assert!(matches!(
+            mock.exchange(&cmd),
+            Err(MockError::Exhausted { .. })
+        ));
+    }
+
+    #[test]
+    fn test_record_and_replay_script() {
+        let cmd = ApduCommand::new(INS_GET_VERSION, P1_ONLY_RETRIEVE, Vec::new());
+        let mock = MockTransport::default();
+        mock.expect(&cmd, &[0, 3, 1, 0], 0x9000);
+
+        let recorder = RecordingTransport::new(&mock);
+        let answer = recorder.exchange(&cmd).unwrap();
+        assert_eq!(mock.remaining(), 0);
+
+        let script = to_script(&recorder.into_exchanges());
+        assert_eq!(script, "=> 8500000000\n<= 000301009000\n");
+
+        let replay = MockTransport::from_script(&script).unwrap();
+        assert_eq!(replay.exchange(&cmd).unwrap(), answer);
+        assert_eq!(replay.remaining(), 0);
+    }
+
+    #[test]
+    fn test_invalid_script() {
+        assert_eq!(
+            MockTransport::from_script("<= 9000").unwrap_err(),
+            MockError::InvalidScript(1)
+        );
+        assert_eq!(
+            MockTransport::from_script("# comment\n=> 85\n=> 85").unwrap_err(),
+            MockError::InvalidScript(3)
+        );
+        assert_eq!(
+            MockTransport::from_script("=> zz\n<= 9000").unwrap_err(),
+            MockError::InvalidScript(1)
+        );
+    }
+}
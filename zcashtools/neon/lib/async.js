@@ -0,0 +1,26 @@
+// Promise wrappers around the background tasks of the `zcashtools` builder.
+// Proof generation runs on the libuv thread pool, so the event loop stays free
+// while a transaction is being built.
+
+const { fromNative } = require('./errors');
+
+function buildAsync(builder, spendPath, outputPath, onProgress) {
+  return new Promise((resolve, reject) => {
+    const done = (err, value) => (err ? reject(fromNative(err)) : resolve(value));
+    if (onProgress) {
+      builder.build_async(spendPath, outputPath, done, onProgress);
+    } else {
+      builder.build_async(spendPath, outputPath, done);
+    }
+  });
+}
+
+function finalizeAsync(builder) {
+  return new Promise((resolve, reject) => {
+    builder.finalize_async((err, value) =>
+      err ? reject(fromNative(err)) : resolve(value)
+    );
+  });
+}
+
+module.exports = { buildAsync, finalizeAsync };
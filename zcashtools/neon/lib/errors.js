@@ -0,0 +1,102 @@
+// Error classes of the `zcashtools` addon.
+// The native module throws `Error`s carrying a `name` and a stable `code`;
+// `fromNative` turns them into instances of the matching class below.
+
+class ZcashToolsError extends Error {
+  constructor(message, code) {
+    super(message);
+    this.name = this.constructor.name;
+    this.code = code;
+  }
+}
+
+class InvalidArgumentError extends ZcashToolsError {}
+class BuilderError extends ZcashToolsError {}
+class BalanceError extends ZcashToolsError {}
+class ParamsError extends ZcashToolsError {}
+class SessionError extends ZcashToolsError {}
+class PcztError extends ZcashToolsError {}
+class DecodeError extends ZcashToolsError {}
+
+const classes = {
+  InvalidArgumentError,
+  BuilderError,
+  BalanceError,
+  ParamsError,
+  SessionError,
+  PcztError,
+  DecodeError,
+};
+
+function fromNative(err) {
+  if (!(err instanceof Error) || err instanceof ZcashToolsError) {
+    return err;
+  }
+  const Class = classes[err.name];
+  if (!Class || typeof err.code !== 'string') {
+    return err;
+  }
+  const typed = new Class(err.message, err.code);
+  typed.stack = err.stack;
+  return typed;
+}
+
+function wrapFunction(fn) {
+  return function (...args) {
+    try {
+      return fn.apply(this, args);
+    } catch (err) {
+      throw fromNative(err);
+    }
+  };
+}
+
+// `*_async` methods report through a node-style callback, the first function
+// argument they take
+function wrapCallback(args) {
+  const i = args.findIndex((arg) => typeof arg === 'function');
+  if (i >= 0) {
+    const callback = args[i];
+    args[i] = (err, value) => callback(err ? fromNative(err) : err, value);
+  }
+  return args;
+}
+
+function wrapInstance(instance) {
+  return new Proxy(instance, {
+    get(target, prop) {
+      const value = target[prop];
+      if (typeof value !== 'function') {
+        return value;
+      }
+      const isAsync = typeof prop === 'string' && prop.endsWith('_async');
+      return (...args) => {
+        try {
+          return value.apply(target, isAsync ? wrapCallback(args) : args);
+        } catch (err) {
+          throw fromNative(err);
+        }
+      };
+    },
+  });
+}
+
+function wrapClass(Native) {
+  return new Proxy(Native, {
+    construct(target, args) {
+      try {
+        return wrapInstance(Reflect.construct(target, args));
+      } catch (err) {
+        throw fromNative(err);
+      }
+    },
+  });
+}
+
+module.exports = {
+  ZcashToolsError,
+  ...classes,
+  fromNative,
+  wrapFunction,
+  wrapClass,
+};
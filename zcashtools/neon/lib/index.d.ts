@@ -0,0 +1,306 @@
+// Type declarations of `@zondax/zcashtools`.
+
+/// <reference types="node" />
+
+/** Bytes given as a `Buffer`, an array of numbers or, where noted, hex */
+export type Bytes = Buffer | number[];
+
+// Inputs of `get_inittx_data`, i.e. what the device shows to the user
+
+export interface TinData {
+  /** BIP44 path of the key owning the input */
+  path: number[];
+  /** Hex `scriptPubKey` of the output being spent, with its length prefix */
+  address: string;
+  value: number;
+}
+
+export interface ToutData {
+  /** Hex `scriptPubKey`, with its length prefix */
+  address: string;
+  value: number;
+}
+
+export interface SpendData {
+  /** ZIP32 account */
+  path: number;
+  /** Hex Sapling payment address */
+  address: string;
+  value: number;
+}
+
+export interface OutputData {
+  /** Hex Sapling payment address */
+  address: string;
+  value: number;
+  memo_type: number;
+  /** Hex outgoing viewing key, or `null` to use none */
+  ovk: string | null;
+}
+
+export interface InitData {
+  t_in: TinData[];
+  t_out: ToutData[];
+  s_spend: SpendData[];
+  s_output: OutputData[];
+}
+
+// Inputs of the builder, completed with the randomness from the device
+
+export interface TransparentInputBuilderInfo {
+  /** Hex outpoint: txid followed by the output index */
+  outp: string;
+  /** Hex compressed public key */
+  pk: string;
+  address: string;
+  value: number;
+}
+
+export interface TransparentOutputBuilderInfo {
+  address: string;
+  value: number;
+}
+
+export interface SpendBuilderInfo {
+  proofkey: Bytes;
+  rcv: Bytes;
+  alpha: Bytes;
+  address: string;
+  value: number;
+  /** Hex Merkle path of the note */
+  witness: string;
+  /** Hex */
+  rseed: string;
+}
+
+export interface OutputBuilderInfo {
+  rcv: Bytes;
+  rseed: Bytes;
+  ovk: string | null;
+  address: string;
+  value: number;
+  /** Hex */
+  memo: string;
+  hash_seed?: Bytes;
+}
+
+/** Output receiving the change, whose value is set by `build` */
+export type ChangeAddress =
+  | Omit<TransparentOutputBuilderInfo, 'value'>
+  | Omit<OutputBuilderInfo, 'value'>;
+
+export interface TransactionSignatures {
+  transparent_sigs: Bytes[];
+  spend_sigs: Bytes[];
+}
+
+/** Fee in zatoshi, or ZIP-317 computed from the inputs and outputs */
+export type FeeRule = number | 'zip317';
+
+export type ProgressCallback = (kind: 'spend' | 'output', index: number, total: number) => void;
+
+export type NodeCallback<T> = (err: ZcashToolsError | null, value: T) => void;
+
+export class zcashtools {
+  constructor(fee: FeeRule);
+
+  add_transparent_input(input: TransparentInputBuilderInfo): boolean;
+  add_transparent_output(output: TransparentOutputBuilderInfo): boolean;
+  add_sapling_spend(spend: SpendBuilderInfo): boolean;
+  add_sapling_output(output: OutputBuilderInfo): boolean;
+  add_change_address(change: ChangeAddress): boolean;
+
+  fee(): number;
+  /** Value of the change output, or `null` without a change address */
+  change_value(): number | null;
+
+  /** Ledger blob to send with CHECKANDSIGN */
+  build(spendPath: string, outputPath: string): number[];
+  build_async(
+    spendPath: string,
+    outputPath: string,
+    callback: NodeCallback<number[]>,
+    onProgress?: ProgressCallback,
+  ): void;
+  cancel(): void;
+
+  add_signatures(signatures: TransactionSignatures): boolean;
+  /** Serialized transaction */
+  finalize(): number[];
+  finalize_async(callback: NodeCallback<number[]>): void;
+
+  export_session(key: Bytes): Buffer;
+  resume_session(blob: Bytes, key: Bytes): boolean;
+
+  export_pczt(): Buffer;
+  import_pczt(pczt: Buffer | Pczt): boolean;
+  add_pczt_signatures(pczt: Buffer | Pczt): boolean;
+}
+
+/** Response of the software signer, shaped like the ones of `ledger-zcash` */
+export interface SignerResponse {
+  return_code: number;
+  error_message: string;
+}
+
+export class softwaresigner {
+  constructor(saplingSeed: Bytes, bip39Seed: Bytes);
+
+  inittx(data: Bytes): SignerResponse & { txdata?: Buffer };
+  extractspenddata(): SignerResponse & { key_raw?: Buffer; rcv_raw?: Buffer; alpha_raw?: Buffer };
+  extractoutputdata(): SignerResponse & { rcv_raw?: Buffer; rseed_raw?: Buffer; hash_seed?: Buffer };
+  checkandsign(data: Bytes): SignerResponse & { signdata?: Buffer };
+  extractspendsig(): SignerResponse & { sig_raw?: Buffer };
+  extracttranssig(): SignerResponse & { sig_raw?: Buffer };
+}
+
+export type PcztInputKind = 'transparent_input' | 'transparent_output' | 'sapling_spend' | 'sapling_output';
+
+export interface Pczt {
+  version: number;
+  fee: number;
+  /** How `build` settles the fee; the fixed `fee` if missing */
+  fee_rule?: FeeRule;
+  change?: ChangeAddress;
+  inputs: { kind: PcztInputKind; data: unknown }[];
+  txdata?: Buffer;
+  signatures?: TransactionSignatures;
+  transaction?: Buffer;
+}
+
+export interface DecodedTransaction {
+  txid: string;
+  version: number;
+  versionGroupId: number;
+  lockTime: number;
+  expiryHeight: number;
+  transparentInputs: {
+    prevoutTxid: string;
+    prevoutIndex: number;
+    scriptSig: string;
+    sequence: number;
+    /** Address of the public key of a P2PKH scriptSig */
+    address: string | null;
+  }[];
+  transparentOutputs: {
+    value: number;
+    scriptPubkey: string;
+    address: string | null;
+  }[];
+  saplingSpends: {
+    cv: string;
+    anchor: string;
+    nullifier: string;
+    rk: string;
+    zkproof: string;
+    spendAuthSig: string | null;
+  }[];
+  saplingOutputs: {
+    cv: string;
+    cmu: string;
+    ephemeralKey: string;
+    zkproof: string;
+  }[];
+  valueBalance: number;
+  fee: number | null;
+  bindingSig: string | null;
+}
+
+export function get_inittx_data(data: InitData): number[];
+export function decode_transaction(raw: Bytes, inputValues?: number[] | null): DecodedTransaction;
+export function load_params(spendPath: string, outputPath: string): boolean;
+export function zip317_fee(data: Partial<InitData>): number;
+export function pczt_parse(pczt: Buffer | Pczt): Pczt;
+export function pczt_serialize(pczt: Buffer | Pczt): Buffer;
+export function anti_exfil_commitment(hostData: Bytes): number[];
+export function verify_anti_exfil(signerCommitment: Bytes, hostData: Bytes, signature: Bytes): boolean;
+
+// Sapling primitives of the firmware, for tests
+
+export interface SaplingAddress {
+  /** Bech32 */
+  address: string;
+  /** 43 bytes: diversifier followed by `pk_d` */
+  address_raw: Buffer;
+}
+
+export interface SaplingKeys extends SaplingAddress {
+  ask: Buffer;
+  nsk: Buffer;
+  ak: Buffer;
+  nk: Buffer;
+  ivk: Buffer;
+  ovk: Buffer;
+  diversifier: Buffer;
+}
+
+export function sapling_keys(seed: Bytes, account: number): SaplingKeys;
+export function sapling_address(seed: Bytes, account: number, diversifier: Bytes): SaplingAddress;
+/** 20 diversifiers of 11 bytes following `start` */
+export function diversifier_list(seed: Bytes, account: number, start: Bytes): Buffer;
+export function compute_note_commitment(
+  value: number,
+  diversifier: Bytes,
+  pkd: Bytes,
+  rcm: Bytes,
+): { cmu: Buffer; cm: Buffer };
+export function compute_nullifier(cm: Bytes, position: number, nsk: Bytes): Buffer;
+export function value_commitment(value: number, rcv: Bytes): Buffer;
+/** Signs with `key + alpha` when `alpha` is given */
+export function redjubjub_sign(
+  key: Bytes,
+  message: Bytes,
+  alpha?: Bytes | null,
+): { signature: Buffer; public_key: Buffer };
+
+export function buildAsync(
+  builder: zcashtools,
+  spendPath: string,
+  outputPath: string,
+  onProgress?: ProgressCallback,
+): Promise<number[]>;
+export function finalizeAsync(builder: zcashtools): Promise<number[]>;
+
+/** Error thrown by the addon; `code` is stable across releases */
+export class ZcashToolsError extends Error {
+  code: string;
+  constructor(message: string, code: string);
+}
+
+/** `INVALID_ARGUMENT`, or `INVALID_SCALAR`, `INVALID_POINT`, ... from the Sapling primitives */
+export class InvalidArgumentError extends ZcashToolsError {}
+
+/**
+ * `BUILDER_BUSY`, `BUILD_CANCELLED`, `BUILDER_POISONED`, `TXDATA_MISMATCH`,
+ * `INVALID_BUILDER_INPUT`, `INVALID_SIGNATURES`, or from `zcash-hsmbuilder`:
+ * `ANCHOR_MISMATCH`, `BINDING_SIG`, `CHANGE_IS_NEGATIVE`, `INVALID_ADDRESS`,
+ * `INVALID_AMOUNT`, `NO_CHANGE_ADDRESS`, `SPEND_PROOF`, `SPEND_SIG`,
+ * `TRANSPARENT_SIG`, `FINALIZATION`, `MIN_SHIELDED_OUTPUTS`, `BUILDER_NO_KEYS`,
+ * `READ_WRITE_ERROR`, `INVALID_OVK_HASH_SEED`, `ALREADY_AUTHORIZED`,
+ * `UNAUTHORIZED`
+ */
+export class BuilderError extends ZcashToolsError {}
+
+/** `INVALID_VALUE`, `VALUE_OVERFLOW`, `INSUFFICIENT_FUNDS`, `UNBALANCED_TRANSACTION` */
+export class BalanceError extends ZcashToolsError {}
+
+/** `PARAMS_MISSING`, `PARAMS_IO`, `PARAMS_CORRUPT` */
+export class ParamsError extends ZcashToolsError {}
+
+/**
+ * `SESSION_BAD_MAGIC`, `SESSION_UNSUPPORTED_VERSION`, `SESSION_BAD_TAG`,
+ * `SESSION_MALFORMED`, `SESSION_BUILDER_NOT_EMPTY`
+ */
+export class SessionError extends ZcashToolsError {}
+
+/**
+ * `PCZT_BAD_MAGIC`, `PCZT_UNSUPPORTED_VERSION`, `PCZT_BAD_CHECKSUM`,
+ * `PCZT_MALFORMED`, `PCZT_NOT_BUILT`, `PCZT_MISMATCH`, `PCZT_NO_SIGNATURES`
+ */
+export class PcztError extends ZcashToolsError {}
+
+/**
+ * `TRANSACTION_TOO_SHORT`, `UNSUPPORTED_TRANSACTION_VERSION`,
+ * `INVALID_TRANSACTION`, `INVALID_INPUT_VALUES`
+ */
+export class DecodeError extends ZcashToolsError {}
@@ -0,0 +1,381 @@
+//! Structured view of a raw transaction, for tests and support tooling.
+
+use std::fmt;
+
+use ripemd160::Ripemd160;
+use serde_derive::Serialize;
+use sha2::{Digest, Sha256};
+use zcash_primitives::transaction::Transaction;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    TooShort,
+    UnsupportedVersion(u32),
+    Invalid(String),
+    /// Input values given, but not one non-negative amount per transparent
+    /// input, or the fee overflows
+    InputValues,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::TooShort => write!(f, "transaction too short"),
+            DecodeError::UnsupportedVersion(v) => {
+                write!(f, "unsupported transaction version {}", v)
+            }
+            DecodeError::Invalid(e) => write!(f, "invalid transaction: {}", e),
+            DecodeError::InputValues => write!(
+                f,
+                "input values must be one non-negative amount per transparent input"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Transparent input; its value is not part of the transaction
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodedTransparentInput {
+    pub prevout_txid: String,
+    pub prevout_index: u32,
+    pub script_sig: String,
+    pub sequence: u32,
+    /// Mainnet address of the public key a P2PKH scriptSig reveals
+    pub address: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodedTransparentOutput {
+    pub value: i64,
+    pub script_pubkey: String,
+    /// Mainnet address for P2PKH/P2SH scripts, as shown by the device
+    pub address: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodedSpend {
+    pub cv: String,
+    pub anchor: String,
+    pub nullifier: String,
+    pub rk: String,
+    pub zkproof: String,
+    pub spend_auth_sig: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodedOutput {
+    pub cv: String,
+    pub cmu: String,
+    pub ephemeral_key: String,
+    pub zkproof: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DecodedTransaction {
+    pub txid: String,
+    pub version: u32,
+    pub version_group_id: u32,
+    pub lock_time: u32,
+    pub expiry_height: u32,
+    pub transparent_inputs: Vec<DecodedTransparentInput>,
+    pub transparent_outputs: Vec<DecodedTransparentOutput>,
+    pub sapling_spends: Vec<DecodedSpend>,
+    pub sapling_outputs: Vec<DecodedOutput>,
+    pub value_balance: i64,
+    /// Only known when the values of the transparent inputs are given
+    pub fee: Option<i64>,
+    pub binding_sig: Option<String>,
+}
+
+/// Parses a v4 (Sapling) transaction. `input_values` are the values of the
+/// transparent inputs, in order, and are only used to compute the fee.
+pub fn decode_transaction(
+    raw: &[u8],
+    input_values: Option<&[i64]>,
+) -> Result<DecodedTransaction, DecodeError> {
+    if raw.len() < 8 {
+        return Err(DecodeError::TooShort);
+    }
+    let header = u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+    let version_group_id = u32::from_le_bytes([raw[4], raw[5], raw[6], raw[7]]);
+    let version = header & 0x7fff_ffff;
+    if version != 4 {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+
+    let tx = Transaction::read(raw).map_err(|e| DecodeError::Invalid(e.to_string()))?;
+
+    let transparent_inputs = tx
+        .vin
+        .iter()
+        .map(|input| DecodedTransparentInput {
+            prevout_txid: reversed_hex(input.prevout.hash()),
+            prevout_index: input.prevout.n(),
+            script_sig: hex(&input.script_sig.0),
+            sequence: input.sequence,
+            address: script_sig_address(&input.script_sig.0),
+        })
+        .collect();
+
+    let transparent_outputs = tx
+        .vout
+        .iter()
+        .map(|output| DecodedTransparentOutput {
+            value: i64::from(output.value),
+            script_pubkey: hex(&output.script_pubkey.0),
+            address: transparent_address(&output.script_pubkey.0),
+        })
+        .collect::<Vec<_>>();
+
+    let sapling_spends = tx
+        .shielded_spends
+        .iter()
+        .map(|spend| DecodedSpend {
+            cv: point_hex(&spend.cv),
+            anchor: hex(&spend.anchor.to_bytes()),
+            nullifier: hex(&spend.nullifier.0),
+            rk: written_hex(|w| spend.rk.write(w)),
+            zkproof: hex(&spend.zkproof),
+            spend_auth_sig: spend
+                .spend_auth_sig
+                .as_ref()
+                .map(|sig| written_hex(|w| sig.write(w))),
+        })
+        .collect();
+
+    let sapling_outputs = tx
+        .shielded_outputs
+        .iter()
+        .map(|output| DecodedOutput {
+            cv: point_hex(&output.cv),
+            cmu: hex(&output.cmu.to_bytes()),
+            ephemeral_key: point_hex(&output.ephemeral_key),
+            zkproof: hex(&output.zkproof),
+        })
+        .collect();
+
+    let value_balance = i64::from(tx.value_balance);
+    let fee = match input_values {
+        Some(values) if values.len() == tx.vin.len() => Some(
+            checked_fee(values, &transparent_outputs, value_balance)
+                .ok_or(DecodeError::InputValues)?,
+        ),
+        Some(_) => return Err(DecodeError::InputValues),
+        None => None,
+    };
+
+    Ok(DecodedTransaction {
+        txid: tx.txid().to_string(),
+        version,
+        version_group_id,
+        lock_time: tx.lock_time,
+        expiry_height: u32::from(tx.expiry_height),
+        transparent_inputs,
+        transparent_outputs,
+        sapling_spends,
+        sapling_outputs,
+        value_balance,
+        fee,
+        binding_sig: tx
+            .binding_sig
+            .as_ref()
+            .map(|sig| written_hex(|w| sig.write(w))),
+    })
+}
+
+/// Transparent inputs minus transparent outputs plus the value balance, or
+/// `None` for a negative input value or an overflow
+fn checked_fee(
+    input_values: &[i64],
+    outputs: &[DecodedTransparentOutput],
+    value_balance: i64,
+) -> Option<i64> {
+    let transparent_in = input_values.iter().try_fold(0i64, |sum, value| {
+        if *value < 0 {
+            return None;
+        }
+        sum.checked_add(*value)
+    })?;
+    let transparent_out = outputs
+        .iter()
+        .try_fold(0i64, |sum, output| sum.checked_add(output.value))?;
+    transparent_in
+        .checked_sub(transparent_out)?
+        .checked_add(value_balance)
+}
+
+/// Address of a P2PKH input: its scriptSig pushes a signature and the public
+/// key whose hash the spent script pays to
+fn script_sig_address(script_sig: &[u8]) -> Option<String> {
+    let (_sig, rest) = push_data(script_sig)?;
+    let (pubkey, rest) = push_data(rest)?;
+    if !rest.is_empty() || !matches!(pubkey.len(), 33 | 65) {
+        return None;
+    }
+    let mut script = vec![0x76, 0xa9, 0x14];
+    script.extend_from_slice(&Ripemd160::digest(&Sha256::digest(pubkey)));
+    script.extend_from_slice(&[0x88, 0xac]);
+    transparent_address(&script)
+}
+
+/// Data of the direct push (opcodes 0x01 to 0x4b) `script` starts with, and
+/// the rest of the script
+fn push_data(script: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (&len, rest) = script.split_first()?;
+    let len = len as usize;
+    if !(1..=0x4b).contains(&len) || rest.len() < len {
+        return None;
+    }
+    Some(rest.split_at(len))
+}
+
+fn transparent_address(script: &[u8]) -> Option<String> {
+    let mut out = [0u8; 64];
+    let len = rslib::address::transparent_from_script(script, &mut out).ok()?;
+    String::from_utf8(out[..len].to_vec()).ok()
+}
+
+fn point_hex(point: &jubjub::ExtendedPoint) -> String {
+    hex(&jubjub::AffinePoint::from(point).to_bytes())
+}
+
+fn written_hex(write: impl FnOnce(&mut Vec<u8>) -> std::io::Result<()>) -> String {
+    let mut bytes = Vec::new();
+    write(&mut bytes).expect("writing to a Vec cannot fail");
+    hex(&bytes)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Txids are displayed byte-reversed
+fn reversed_hex(bytes: &[u8; 32]) -> String {
+    let mut reversed = *bytes;
+    reversed.reverse();
+    hex(&reversed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compressed secp256k1 generator and its HASH160
+    const PUBKEY: &str = "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798";
+    const PUBKEY_HASH: &str = "751e76e8199196d454941c45d1b3a323f1433bd6";
+
+    fn unhex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn p2pkh_script() -> Vec<u8> {
+        let mut script = vec![0x76, 0xa9, 0x14];
+        script.extend_from_slice(&unhex(PUBKEY_HASH));
+        script.extend_from_slice(&[0x88, 0xac]);
+        script
+    }
+
+    /// `<DER signature || SIGHASH_ALL> <PUBKEY>`
+    fn p2pkh_script_sig() -> Vec<u8> {
+        let sig = [0x30, 0x06, 0x02, 0x01, 0x01, 0x02, 0x01, 0x01, 0x01];
+        let pubkey = unhex(PUBKEY);
+        let mut script_sig = vec![sig.len() as u8];
+        script_sig.extend_from_slice(&sig);
+        script_sig.push(pubkey.len() as u8);
+        script_sig.extend_from_slice(&pubkey);
+        script_sig
+    }
+
+    /// Sapling v4 transaction spending `inputs` P2PKH coins of PUBKEY to one
+    /// output of 50000 zatoshi back to it
+    fn v4_transaction(inputs: u8) -> Vec<u8> {
+        let mut tx = Vec::new();
+        tx.extend_from_slice(&0x8000_0004u32.to_le_bytes());
+        tx.extend_from_slice(&0x892f_2085u32.to_le_bytes());
+        tx.push(inputs);
+        for i in 0..inputs {
+            tx.extend_from_slice(&[0x11; 32]);
+            tx.extend_from_slice(&u32::from(i).to_le_bytes());
+            let script_sig = p2pkh_script_sig();
+            tx.push(script_sig.len() as u8);
+            tx.extend_from_slice(&script_sig);
+            tx.extend_from_slice(&0xffff_fffeu32.to_le_bytes());
+        }
+        tx.push(1);
+        tx.extend_from_slice(&50000i64.to_le_bytes());
+        let script = p2pkh_script();
+        tx.push(script.len() as u8);
+        tx.extend_from_slice(&script);
+        tx.extend_from_slice(&0u32.to_le_bytes());
+        tx.extend_from_slice(&1_000_040u32.to_le_bytes());
+        tx.extend_from_slice(&0i64.to_le_bytes());
+        // no spends, outputs or JoinSplits
+        tx.extend_from_slice(&[0, 0, 0]);
+        tx
+    }
+
+    #[test]
+    fn test_decode_v4_transaction() {
+        let tx = decode_transaction(&v4_transaction(1), Some(&[60000])).unwrap();
+        assert_eq!(tx.version, 4);
+        assert_eq!(tx.version_group_id, 0x892f_2085);
+        assert_eq!(tx.expiry_height, 1_000_040);
+        assert_eq!(tx.fee, Some(10000));
+        assert!(tx.binding_sig.is_none());
+
+        let input = &tx.transparent_inputs[0];
+        assert_eq!(input.prevout_txid, "11".repeat(32));
+        assert_eq!(input.prevout_index, 0);
+        assert_eq!(input.sequence, 0xffff_fffe);
+        assert_eq!(input.script_sig, hex(&p2pkh_script_sig()));
+
+        let output = &tx.transparent_outputs[0];
+        assert_eq!(output.value, 50000);
+        assert_eq!(output.script_pubkey, hex(&p2pkh_script()));
+        assert_eq!(
+            output.address.as_deref(),
+            Some("t1UYsZVJkLPeMjxEtACvSxfWuNmddpWfxzs")
+        );
+        // the input spends a coin of the key it pays back to
+        assert_eq!(input.address, output.address);
+    }
+
+    #[test]
+    fn test_decode_rejects_bad_input_values() {
+        let raw = v4_transaction(2);
+        assert!(decode_transaction(&raw, None).unwrap().fee.is_none());
+        assert_eq!(
+            decode_transaction(&raw, Some(&[60000])).err(),
+            Some(DecodeError::InputValues)
+        );
+        assert_eq!(
+            decode_transaction(&raw, Some(&[60000, -1])).err(),
+            Some(DecodeError::InputValues)
+        );
+        assert_eq!(
+            decode_transaction(&raw, Some(&[i64::MAX, 1])).err(),
+            Some(DecodeError::InputValues)
+        );
+    }
+
+    #[test]
+    fn test_script_sig_address_needs_p2pkh() {
+        assert!(script_sig_address(&p2pkh_script_sig()).is_some());
+        assert!(script_sig_address(&[]).is_none());
+        // a signature alone, as in a P2PK spend
+        assert!(script_sig_address(&p2pkh_script_sig()[..10]).is_none());
+        let mut trailing = p2pkh_script_sig();
+        trailing.push(0x51);
+        assert!(script_sig_address(&trailing).is_none());
+    }
+}
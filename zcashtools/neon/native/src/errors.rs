@@ -0,0 +1,221 @@
+//! Errors thrown to JS.
+//!
+//! Every failure reaches JS as an `Error` with a `name`, which `lib/errors.js`
+//! maps to the matching `Error` subclass, and a `code` that is stable across
+//! releases. Errors of `zcash-hsmbuilder` are coded one by one in
+//! `BridgeError::builder`, e.g. `ChangeIsNegative` becomes `CHANGE_IS_NEGATIVE`.
+
+use std::fmt;
+use std::sync::PoisonError;
+
+use neon::prelude::*;
+use rslib::errors::ParserError;
+use zcash_hsmbuilder::errors::Error;
+
+use crate::decode::DecodeError;
+use crate::fees::BalanceError;
+use crate::params::ParamsError;
+use crate::pczt::PcztError;
+use crate::proven::ProvenError;
+use crate::session::SessionError;
+use crate::tasks::{BUILDER_BUSY, BUILD_CANCELLED};
+
+pub const INVALID_ARGUMENT: &str = "InvalidArgumentError";
+pub const BUILDER: &str = "BuilderError";
+pub const BALANCE: &str = "BalanceError";
+pub const PARAMS: &str = "ParamsError";
+pub const SESSION: &str = "SessionError";
+pub const PCZT: &str = "PcztError";
+pub const DECODE: &str = "DecodeError";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BridgeError {
+    pub name: &'static str,
+    pub code: String,
+    pub message: String,
+}
+
+impl BridgeError {
+    pub fn new(name: &'static str, code: impl Into<String>, message: impl fmt::Display) -> Self {
+        BridgeError {
+            name,
+            code: code.into(),
+            message: message.to_string(),
+        }
+    }
+
+    pub fn invalid_argument(message: impl fmt::Display) -> Self {
+        BridgeError::new(INVALID_ARGUMENT, "INVALID_ARGUMENT", message)
+    }
+
+    pub fn busy() -> Self {
+        BridgeError::new(BUILDER, "BUILDER_BUSY", BUILDER_BUSY)
+    }
+
+    pub fn cancelled() -> Self {
+        BridgeError::new(BUILDER, "BUILD_CANCELLED", BUILD_CANCELLED)
+    }
+
+    /// Error of `zcash-hsmbuilder`
+    pub fn builder(error: Error) -> Self {
+        let code = match error {
+            Error::AnchorMismatch => "ANCHOR_MISMATCH",
+            Error::BindingSig => "BINDING_SIG",
+            Error::ChangeIsNegative => "CHANGE_IS_NEGATIVE",
+            Error::InvalidAddress => "INVALID_ADDRESS",
+            Error::InvalidAmount => "INVALID_AMOUNT",
+            Error::NoChangeAddress => "NO_CHANGE_ADDRESS",
+            Error::SpendProof => "SPEND_PROOF",
+            Error::SpendSig => "SPEND_SIG",
+            Error::TransparentSig => "TRANSPARENT_SIG",
+            Error::Finalization => "FINALIZATION",
+            Error::MinShieldedOuputs => "MIN_SHIELDED_OUTPUTS",
+            Error::BuilderNoKeys => "BUILDER_NO_KEYS",
+            Error::ReadWriteError => "READ_WRITE_ERROR",
+            Error::InvalidOVKHashSeed => "INVALID_OVK_HASH_SEED",
+            Error::AlreadyAuthorized => "ALREADY_AUTHORIZED",
+            Error::Unauthorized => "UNAUTHORIZED",
+        };
+        BridgeError::new(BUILDER, code, error)
+    }
+
+    pub fn to_js<'a, C: Context<'a>>(&self, cx: &mut C) -> JsResult<'a, JsError> {
+        let error = JsError::error(cx, &self.message)?;
+        let name = cx.string(self.name);
+        error.set(cx, "name", name)?;
+        let code = cx.string(&self.code);
+        error.set(cx, "code", code)?;
+        Ok(error)
+    }
+
+    pub fn throw<'a, C: Context<'a>, T>(&self, cx: &mut C) -> NeonResult<T> {
+        let error = self.to_js(cx)?;
+        cx.throw(error)
+    }
+}
+
+impl fmt::Display for BridgeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for BridgeError {}
+
+impl From<BalanceError> for BridgeError {
+    fn from(error: BalanceError) -> Self {
+        let code = match error {
+            BalanceError::InvalidValue { .. } => "INVALID_VALUE",
+            BalanceError::Overflow => "VALUE_OVERFLOW",
+            BalanceError::Insufficient { .. } => "INSUFFICIENT_FUNDS",
+            BalanceError::Unbalanced { .. } => "UNBALANCED_TRANSACTION",
+        };
+        BridgeError::new(BALANCE, code, error)
+    }
+}
+
+impl From<ParamsError> for BridgeError {
+    fn from(error: ParamsError) -> Self {
+        let code = match error {
+            ParamsError::Missing { .. } => "PARAMS_MISSING",
+            ParamsError::Io { .. } => "PARAMS_IO",
+            ParamsError::Corrupt { .. } => "PARAMS_CORRUPT",
+        };
+        BridgeError::new(PARAMS, code, error)
+    }
+}
+
+impl From<SessionError> for BridgeError {
+    fn from(error: SessionError) -> Self {
+        let code = match error {
+            SessionError::BadMagic => "SESSION_BAD_MAGIC",
+            SessionError::UnsupportedVersion(_) => "SESSION_UNSUPPORTED_VERSION",
+            SessionError::BadTag => "SESSION_BAD_TAG",
+            SessionError::Malformed => "SESSION_MALFORMED",
+        };
+        BridgeError::new(SESSION, code, error)
+    }
+}
+
+impl From<PcztError> for BridgeError {
+    fn from(error: PcztError) -> Self {
+        let code = match error {
+            PcztError::BadMagic => "PCZT_BAD_MAGIC",
+            PcztError::UnsupportedVersion(_) => "PCZT_UNSUPPORTED_VERSION",
+            PcztError::BadChecksum => "PCZT_BAD_CHECKSUM",
+            PcztError::Malformed => "PCZT_MALFORMED",
+            PcztError::NotBuilt => "PCZT_NOT_BUILT",
+            PcztError::TxdataMismatch => "PCZT_MISMATCH",
+        };
+        BridgeError::new(PCZT, code, error)
+    }
+}
+
+impl From<ProvenError> for BridgeError {
+    fn from(error: ProvenError) -> Self {
+        let code = match error {
+            ProvenError::TxdataMismatch => "TXDATA_MISMATCH",
+            ProvenError::InvalidInput { .. } => "INVALID_BUILDER_INPUT",
+            ProvenError::InvalidSignatures => "INVALID_SIGNATURES",
+        };
+        BridgeError::new(BUILDER, code, error)
+    }
+}
+
+impl From<DecodeError> for BridgeError {
+    fn from(error: DecodeError) -> Self {
+        let code = match error {
+            DecodeError::TooShort => "TRANSACTION_TOO_SHORT",
+            DecodeError::UnsupportedVersion(_) => "UNSUPPORTED_TRANSACTION_VERSION",
+            DecodeError::Invalid(_) => "INVALID_TRANSACTION",
+            DecodeError::InputValues => "INVALID_INPUT_VALUES",
+        };
+        BridgeError::new(DECODE, code, error)
+    }
+}
+
+/// Inputs rejected by the `rslib` primitives, e.g. `parser_invalid_point`
+/// becomes `INVALID_POINT`
+impl From<ParserError> for BridgeError {
+    fn from(error: ParserError) -> Self {
+        let name = format!("{:?}", error);
+        let name = name.trim_start_matches("parser_");
+        BridgeError::new(
+            INVALID_ARGUMENT,
+            name.to_ascii_uppercase(),
+            name.replace('_', " "),
+        )
+    }
+}
+
+/// A background task panicked while holding the builder
+impl<T> From<PoisonError<T>> for BridgeError {
+    fn from(_: PoisonError<T>) -> Self {
+        BridgeError::new(
+            BUILDER,
+            "BUILDER_POISONED",
+            "builder is unusable after a panic",
+        )
+    }
+}
+
+/// Bad arguments rejected by `neon_serde`
+impl From<neon_serde::errors::Error> for BridgeError {
+    fn from(error: neon_serde::errors::Error) -> Self {
+        BridgeError::invalid_argument(error)
+    }
+}
+
+/// Throws the error of a `Result` as a typed JS error
+pub trait OrThrow<T> {
+    fn or_throw<'a, C: Context<'a>>(self, cx: &mut C) -> NeonResult<T>;
+}
+
+impl<T, E: Into<BridgeError>> OrThrow<T> for Result<T, E> {
+    fn or_throw<'a, C: Context<'a>>(self, cx: &mut C) -> NeonResult<T> {
+        match self {
+            Ok(value) => Ok(value),
+            Err(e) => e.into().throw(cx),
+        }
+    }
+}
@@ -0,0 +1,314 @@
+//! Fee rules and balance checks for the builder bridge.
+//!
+//! The builder is given its fee up front, but with ZIP-317 the fee depends on
+//! the number of inputs and outputs, and the change is whatever is left once
+//! the fee is paid. Both are worked out from the recorded inputs right before
+//! `build`, so an unbalanced transaction is rejected before any proof is made.
+
+use std::fmt;
+
+use neon::prelude::*;
+use rslib::fee::conventional_fee;
+
+use crate::errors::BridgeError;
+use crate::session::{Call, Recorded};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeeRule {
+    Fixed(u64),
+    Zip317,
+}
+
+impl Default for FeeRule {
+    fn default() -> Self {
+        FeeRule::Fixed(0)
+    }
+}
+
+impl FeeRule {
+    /// Reads a fee given either as a number of zatoshi or as `"zip317"`
+    pub fn from_js<'a, C: Context<'a>>(cx: &mut C, value: Handle<JsValue>) -> NeonResult<Self> {
+        if let Ok(rule) = value.downcast::<JsString>() {
+            return match rule.value().as_str() {
+                "zip317" => Ok(FeeRule::Zip317),
+                other => {
+                    BridgeError::invalid_argument(format!("unknown fee rule {}", other)).throw(cx)
+                }
+            };
+        }
+        let fee = value.downcast_or_throw::<JsNumber, _>(cx)?.value();
+        if fee < 0.0 || fee.fract() != 0.0 || fee > u64::MAX as f64 {
+            return BridgeError::invalid_argument("fee must be a non-negative integer").throw(cx);
+        }
+        Ok(FeeRule::Fixed(fee as u64))
+    }
+
+    pub fn to_js<'a, C: Context<'a>>(&self, cx: &mut C) -> JsResult<'a, JsValue> {
+        Ok(match self {
+            FeeRule::Fixed(fee) => cx.number(*fee as f64).upcast(),
+            FeeRule::Zip317 => cx.string("zip317").upcast(),
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BalanceError {
+    /// An input has no `value` that is a non-negative integer
+    InvalidValue {
+        call: Call,
+        index: usize,
+    },
+    Overflow,
+    /// Inputs do not cover outputs and fee
+    Insufficient {
+        inputs: u64,
+        required: u64,
+    },
+    /// Inputs exceed outputs and fee, and there is no change address
+    Unbalanced {
+        inputs: u64,
+        required: u64,
+    },
+}
+
+impl fmt::Display for BalanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BalanceError::InvalidValue { call, index } => {
+                write!(f, "{} {} has no valid value", call.name(), index)
+            }
+            BalanceError::Overflow => write!(f, "transaction value overflows"),
+            BalanceError::Insufficient { inputs, required } => write!(
+                f,
+                "insufficient funds: inputs are {} but outputs and fee need {}",
+                inputs, required
+            ),
+            BalanceError::Unbalanced { inputs, required } => write!(
+                f,
+                "unbalanced transaction: inputs are {} but outputs and fee are {}, \
+                 add a change address",
+                inputs, required
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BalanceError {}
+
+/// Values and counts of the inputs recorded so far
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Balance {
+    pub transparent_inputs: usize,
+    pub transparent_outputs: usize,
+    pub sapling_spends: usize,
+    pub sapling_outputs: usize,
+    pub inputs: u64,
+    pub outputs: u64,
+}
+
+impl Balance {
+    pub fn from_calls(calls: &[(Call, Recorded)]) -> Result<Self, BalanceError> {
+        let mut balance = Balance::default();
+        for (call, recorded) in calls {
+            let index = balance.count(*call);
+            let value = recorded_value(recorded)
+                .ok_or(BalanceError::InvalidValue { call: *call, index })?;
+            balance.add(*call, value)?;
+        }
+        Ok(balance)
+    }
+
+    fn count(&self, call: Call) -> usize {
+        match call {
+            Call::TransparentInput => self.transparent_inputs,
+            Call::TransparentOutput => self.transparent_outputs,
+            Call::SaplingSpend => self.sapling_spends,
+            Call::SaplingOutput => self.sapling_outputs,
+        }
+    }
+
+    pub fn add(&mut self, call: Call, value: u64) -> Result<(), BalanceError> {
+        let (count, total) = match call {
+            Call::TransparentInput => (&mut self.transparent_inputs, &mut self.inputs),
+            Call::TransparentOutput => (&mut self.transparent_outputs, &mut self.outputs),
+            Call::SaplingSpend => (&mut self.sapling_spends, &mut self.inputs),
+            Call::SaplingOutput => (&mut self.sapling_outputs, &mut self.outputs),
+        };
+        *total = total.checked_add(value).ok_or(BalanceError::Overflow)?;
+        *count += 1;
+        Ok(())
+    }
+
+    /// ZIP-317 fee, as the device app computes it, plus one output of kind
+    /// `change` if given
+    pub fn zip317_fee(&self, change: Option<Call>) -> u64 {
+        let mut balance = *self;
+        if let Some(call) = change {
+            let _ = balance.add(call, 0);
+        }
+        conventional_fee(
+            balance.transparent_inputs,
+            balance.transparent_outputs,
+            balance.sapling_spends,
+            balance.sapling_outputs,
+        )
+    }
+
+    pub fn fee(&self, rule: FeeRule, change: Option<Call>) -> u64 {
+        match rule {
+            FeeRule::Fixed(fee) => fee,
+            FeeRule::Zip317 => self.zip317_fee(change),
+        }
+    }
+
+    /// What is left of the inputs once outputs and `fee` are paid
+    pub fn change(&self, fee: u64) -> Result<u64, BalanceError> {
+        let required = self
+            .outputs
+            .checked_add(fee)
+            .ok_or(BalanceError::Overflow)?;
+        self.inputs
+            .checked_sub(required)
+            .ok_or(BalanceError::Insufficient {
+                inputs: self.inputs,
+                required,
+            })
+    }
+
+    /// Checks that inputs pay exactly for outputs and `fee`
+    pub fn check(&self, fee: u64) -> Result<(), BalanceError> {
+        match self.change(fee)? {
+            0 => Ok(()),
+            _ => Err(BalanceError::Unbalanced {
+                inputs: self.inputs,
+                required: self.outputs + fee,
+            }),
+        }
+    }
+}
+
+pub(crate) fn recorded_value(recorded: &Recorded) -> Option<u64> {
+    match recorded.field("value")? {
+        Recorded::Number(n) if *n >= 0.0 && n.fract() == 0.0 && *n <= u64::MAX as f64 => {
+            Some(*n as u64)
+        }
+        _ => None,
+    }
+}
+
+/// Returns `change` with its `value` set, as the output to add to the builder
+pub fn change_output(change: &Recorded, value: u64) -> Recorded {
+    let mut fields = match change {
+        Recorded::Object(fields) => fields.clone(),
+        _ => Vec::new(),
+    };
+    fields.retain(|(name, _)| name != "value");
+    fields.push(("value".to_string(), Recorded::Number(value as f64)));
+    Recorded::Object(fields)
+}
+
+/// Sapling change carries the output randomness from the device, transparent
+/// change only an address
+pub fn change_kind(change: &Recorded) -> Option<Call> {
+    change.field("address")?;
+    match change.field("rcv") {
+        Some(_) => Some(Call::SaplingOutput),
+        None => Some(Call::TransparentOutput),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(value: f64) -> Recorded {
+        Recorded::Object(vec![("value".to_string(), Recorded::Number(value))])
+    }
+
+    #[test]
+    fn test_balance_from_calls() {
+        let calls = vec![
+            (Call::TransparentInput, input(60_000.0)),
+            (Call::SaplingSpend, input(40_000.0)),
+            (Call::TransparentOutput, input(50_000.0)),
+        ];
+        let balance = Balance::from_calls(&calls).unwrap();
+        assert_eq!(balance.inputs, 100_000);
+        assert_eq!(balance.outputs, 50_000);
+        assert_eq!(balance.sapling_spends, 1);
+
+        let calls = vec![
+            (Call::TransparentOutput, input(1.0)),
+            (Call::TransparentOutput, input(0.5)),
+        ];
+        assert_eq!(
+            Balance::from_calls(&calls),
+            Err(BalanceError::InvalidValue {
+                call: Call::TransparentOutput,
+                index: 1
+            })
+        );
+        let mut balance = Balance::default();
+        balance.add(Call::TransparentInput, u64::MAX).unwrap();
+        assert_eq!(
+            balance.add(Call::SaplingSpend, 1),
+            Err(BalanceError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_zip317_fee_counts_change() {
+        let balance = Balance {
+            transparent_inputs: 3,
+            transparent_outputs: 3,
+            sapling_spends: 1,
+            sapling_outputs: 2,
+            ..Balance::default()
+        };
+        assert_eq!(balance.zip317_fee(None), 25_000);
+        assert_eq!(balance.zip317_fee(Some(Call::SaplingOutput)), 30_000);
+        assert_eq!(balance.fee(FeeRule::Fixed(1_000), None), 1_000);
+        // grace actions
+        assert_eq!(Balance::default().fee(FeeRule::Zip317, None), 10_000);
+    }
+
+    #[test]
+    fn test_balance_change_and_check() {
+        let balance = Balance {
+            inputs: 60_000,
+            outputs: 50_000,
+            ..Balance::default()
+        };
+        assert_eq!(balance.change(1_000), Ok(9_000));
+        assert_eq!(balance.check(10_000), Ok(()));
+        assert_eq!(
+            balance.check(1_000),
+            Err(BalanceError::Unbalanced {
+                inputs: 60_000,
+                required: 51_000
+            })
+        );
+        assert_eq!(
+            balance.change(20_000),
+            Err(BalanceError::Insufficient {
+                inputs: 60_000,
+                required: 70_000
+            })
+        );
+        assert_eq!(balance.change(u64::MAX), Err(BalanceError::Overflow));
+    }
+
+    #[test]
+    fn test_change_output() {
+        let change = Recorded::Object(vec![
+            (
+                "address".to_string(),
+                Recorded::String("1976a914".to_string()),
+            ),
+            ("value".to_string(), Recorded::Number(1.0)),
+        ]);
+        assert_eq!(change_kind(&change), Some(Call::TransparentOutput));
+        assert_eq!(recorded_value(&change_output(&change, 9_000)), Some(9_000));
+        assert_eq!(change_kind(&Recorded::Object(vec![])), None);
+    }
+}
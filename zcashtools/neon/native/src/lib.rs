@@ -1,11 +1,36 @@
 use neon::prelude::*;
 
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use neon::event::EventHandler;
 
 use neon_serde::ResultExt;
+use rslib::redjubjub::{anti_exfil_host_commitment, verify_anti_exfil_nonce};
+use rslib::types::Signature;
 use zcash_hsmbuilder::errors::Error;
 use zcash_hsmbuilder::*;
 
+mod decode;
+mod errors;
+mod fees;
+mod params;
+mod pczt;
+mod primitives;
+mod proven;
+mod session;
+mod softsigner;
+mod tasks;
+
+use errors::{BridgeError, OrThrow, PCZT, SESSION};
+use fees::{change_kind, change_output, Balance, BalanceError, FeeRule};
+use params::load_prover;
+use pczt::Pczt;
+use session::{Call, Recorded, Session};
+use softsigner::{ReturnCode, SoftwareSigner};
+use tasks::{BuildProducts, BuildTask, FinalizeTask, ProgressProver};
+
 // reference
 // https://neon-bindings.com/docs/primitives
 
@@ -14,68 +39,634 @@ use zcash_hsmbuilder::*;
 fn get_inittx_data(mut cx: FunctionContext) -> JsResult<JsValue> {
     // First get call arguments
     let arg0 = cx.argument::<JsValue>(0)?;
-    let arg0_value: InitData = neon_serde::from_value(&mut cx, arg0).throw(&mut cx)?;
-    let output = arg0_value.to_hsm_bytes();
-    let js_value;
-    if output.is_ok() {
-        js_value = neon_serde::to_value(&mut cx, &output.unwrap()).throw(&mut cx)?;
-        Ok(js_value)
-    } else {
-        cx.throw_error(output.err().unwrap().to_string())
+    let arg0_value: InitData = neon_serde::from_value(&mut cx, arg0).or_throw(&mut cx)?;
+    let output = arg0_value
+        .to_hsm_bytes()
+        .map_err(BridgeError::builder)
+        .or_throw(&mut cx)?;
+    neon_serde::to_value(&mut cx, &output).throw(&mut cx)
+}
+
+fn to_array32(bytes: &[u8]) -> Option<[u8; 32]> {
+    if bytes.len() != 32 {
+        return None;
+    }
+    let mut out = [0u8; 32];
+    out.copy_from_slice(bytes);
+    Some(out)
+}
+
+fn anti_exfil_commitment(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let arg0 = cx.argument::<JsValue>(0)?;
+    let host_data: Vec<u8> = neon_serde::from_value(&mut cx, arg0).or_throw(&mut cx)?;
+    match to_array32(&host_data) {
+        Some(host_data) => {
+            let commitment = anti_exfil_host_commitment(&host_data).to_vec();
+            let js_value = neon_serde::to_value(&mut cx, &commitment).throw(&mut cx)?;
+            Ok(js_value)
+        }
+        None => BridgeError::invalid_argument("host data must be 32 bytes").throw(&mut cx),
+    }
+}
+
+fn verify_anti_exfil(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let arg0 = cx.argument::<JsValue>(0)?;
+    let arg1 = cx.argument::<JsValue>(1)?;
+    let arg2 = cx.argument::<JsValue>(2)?;
+    let signer_commitment: Vec<u8> = neon_serde::from_value(&mut cx, arg0).or_throw(&mut cx)?;
+    let host_data: Vec<u8> = neon_serde::from_value(&mut cx, arg1).or_throw(&mut cx)?;
+    let signature: Vec<u8> = neon_serde::from_value(&mut cx, arg2).or_throw(&mut cx)?;
+    match (to_array32(&signer_commitment), to_array32(&host_data)) {
+        (Some(r0), Some(host_data)) => {
+            let valid = signature.len() == 64 && {
+                let mut sig = Signature([0u8; 64]);
+                sig.0.copy_from_slice(&signature);
+                verify_anti_exfil_nonce(&r0, &host_data, &sig).is_ok()
+            };
+            Ok(cx.boolean(valid).upcast())
+        }
+        _ => BridgeError::invalid_argument("signer commitment and host data must be 32 bytes")
+            .throw(&mut cx),
+    }
+}
+
+/// Checks and loads the Sapling parameters ahead of the first build
+fn load_params(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let spendpath = cx.argument::<JsString>(0)?.value();
+    let outputpath = cx.argument::<JsString>(1)?.value();
+    load_prover(Path::new(&spendpath), Path::new(&outputpath)).or_throw(&mut cx)?;
+    Ok(cx.boolean(true).upcast())
+}
+
+/// Decodes a raw transaction; the optional second argument lists the values
+/// of its transparent inputs so that the fee can be computed
+fn decode_transaction(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let raw = bytes_argument(&mut cx, 0)?;
+    let input_values: Option<Vec<i64>> = match cx.argument_opt(1) {
+        Some(arg) if !arg.is_a::<JsUndefined>() && !arg.is_a::<JsNull>() => {
+            Some(neon_serde::from_value(&mut cx, arg).or_throw(&mut cx)?)
+        }
+        _ => None,
+    };
+    let decoded = decode::decode_transaction(&raw, input_values.as_deref()).or_throw(&mut cx)?;
+    neon_serde::to_value(&mut cx, &decoded).throw(&mut cx)
+}
+
+/// Reads a byte argument given either as a `Buffer` or as an array of numbers
+fn bytes_argument<T: This>(cx: &mut CallContext<T>, i: i32) -> NeonResult<Vec<u8>> {
+    let arg = cx.argument::<JsValue>(i)?;
+    if let Ok(buffer) = arg.downcast::<JsBuffer>() {
+        return Ok(cx.borrow(&buffer, |data| data.as_slice::<u8>().to_vec()));
+    }
+    neon_serde::from_value(cx, arg).or_throw(cx)
+}
+
+/// Reads a PCZT given either serialized or as the object of `pczt_parse`
+fn pczt_argument<T: This>(cx: &mut CallContext<T>, i: i32) -> NeonResult<Pczt> {
+    let arg = cx.argument::<JsValue>(i)?;
+    if arg.is_a::<JsBuffer>() {
+        let bytes = bytes_argument(cx, i)?;
+        return Pczt::from_bytes(&bytes).or_throw(cx);
+    }
+    Pczt::from_js(cx, arg)
+}
+
+fn pczt_parse(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let pczt = pczt_argument(&mut cx, 0)?;
+    Ok(pczt.to_js(&mut cx)?.upcast())
+}
+
+fn pczt_serialize(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let pczt = pczt_argument(&mut cx, 0)?;
+    Recorded::Bytes(pczt.to_bytes()).to_js(&mut cx)
+}
+
+/// Reads a fee given either as a number of zatoshi or as `"zip317"`
+fn fee_rule_argument<T: This>(cx: &mut CallContext<T>, i: i32) -> NeonResult<FeeRule> {
+    let arg = cx.argument::<JsValue>(i)?;
+    FeeRule::from_js(cx, arg)
+}
+
+/// ZIP-317 fee of a transaction described like the input of
+/// `get_inittx_data`, so that the device can be given the change value
+fn zip317_fee(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let data = cx.argument::<JsObject>(0)?;
+    let mut counts = [0usize; 4];
+    for (count, name) in counts
+        .iter_mut()
+        .zip(&["t_in", "t_out", "s_spend", "s_output"])
+    {
+        let list = data.get(&mut cx, *name)?;
+        if !list.is_a::<JsUndefined>() {
+            *count = list.downcast_or_throw::<JsArray, _>(&mut cx)?.len() as usize;
+        }
+    }
+    let balance = Balance {
+        transparent_inputs: counts[0],
+        transparent_outputs: counts[1],
+        sapling_spends: counts[2],
+        sapling_outputs: counts[3],
+        ..Balance::default()
+    };
+    Ok(cx.number(balance.zip317_fee(None) as f64).upcast())
+}
+
+/// Adds the change output and settles the fee before any proof is generated,
+/// replaying the inputs into a new builder when the fee has changed
+fn prepare_build(cx: &mut CallContext<JsZcashBuilder>) -> NeonResult<()> {
+    let plan;
+    {
+        let this = cx.this();
+        let guard = cx.lock();
+        let thishandler = this.borrow(&guard);
+        plan = thishandler.plan_build();
+    }
+    let plan = plan.or_throw(cx)?;
+
+    let replay = match plan.replay {
+        Some(calls) => {
+            let mut inputs = Vec::new();
+            for (call, recorded) in calls {
+                let arg = recorded.to_js(cx)?;
+                inputs.push((BuilderInput::from_js(cx, call, arg)?, recorded));
+            }
+            Some(inputs)
+        }
+        None => None,
+    };
+    let change = match plan.change {
+        Some((call, recorded)) => {
+            let arg = recorded.to_js(cx)?;
+            Some((BuilderInput::from_js(cx, call, arg)?, recorded))
+        }
+        None => None,
+    };
+
+    let value;
+    {
+        let mut this = cx.this();
+        let guard = cx.lock();
+        let mut thishandler = this.borrow_mut(&guard);
+        value = thishandler.apply_plan(plan.fee, replay, change);
+    }
+    value.or_throw(cx)
+}
+
+fn session_key_argument<T: This>(cx: &mut CallContext<T>, i: i32) -> NeonResult<[u8; 32]> {
+    let key = bytes_argument(cx, i)?;
+    match to_array32(&key) {
+        Some(key) => Ok(key),
+        None => BridgeError::invalid_argument("session key must be 32 bytes").throw(cx),
+    }
+}
+
+/// Builds a response shaped like the ones of the `ledger-zcash` JS client
+fn signer_reply<'a, C: Context<'a>>(
+    cx: &mut C,
+    result: Result<Vec<(&'static str, Vec<u8>)>, ReturnCode>,
+) -> JsResult<'a, JsValue> {
+    let (code, fields) = match result {
+        Ok(fields) => (ReturnCode::Ok, fields),
+        Err(e) => (e, Vec::new()),
+    };
+    let obj = cx.empty_object();
+    let return_code = cx.number(code.code());
+    obj.set(cx, "return_code", return_code)?;
+    let error_message = cx.string(code.message());
+    obj.set(cx, "error_message", error_message)?;
+    for (name, bytes) in fields {
+        let mut buffer = JsBuffer::new(cx, bytes.len() as u32)?;
+        cx.borrow_mut(&mut buffer, |data| {
+            data.as_mut_slice::<u8>().copy_from_slice(&bytes)
+        });
+        obj.set(cx, name, buffer)?;
     }
+    Ok(obj.upcast())
 }
 
 pub struct ZcashBuilderBridge {
-    zcashbuilder: ZcashBuilder,
+    zcashbuilder: Arc<Mutex<ZcashBuilder>>,
+    cancel: Arc<AtomicBool>,
+    spends: usize,
+    outputs: usize,
+    session: Session,
+    signatures: Option<Recorded>,
+    products: Arc<Mutex<BuildProducts>>,
+}
+
+/// Fee and change settled from the recorded inputs, see `prepare_build`
+pub struct BuildPlan {
+    fee: u64,
+    /// Inputs to replay into a builder created with `fee`
+    replay: Option<Vec<(Call, Recorded)>>,
+    change: Option<(Call, Recorded)>,
+}
+
+/// Typed builder input, paired with the `Call` it is recorded under
+pub enum BuilderInput {
+    TransparentInput(TransparentInputBuilderInfo),
+    TransparentOutput(TransparentOutputBuilderInfo),
+    SaplingSpend(SpendBuilderInfo),
+    SaplingOutput(OutputBuilderInfo),
+}
+
+impl BuilderInput {
+    fn from_js<'a, C: Context<'a>>(
+        cx: &mut C,
+        call: Call,
+        value: Handle<JsValue>,
+    ) -> NeonResult<Self> {
+        Ok(match call {
+            Call::TransparentInput => {
+                BuilderInput::TransparentInput(neon_serde::from_value(cx, value).or_throw(cx)?)
+            }
+            Call::TransparentOutput => {
+                BuilderInput::TransparentOutput(neon_serde::from_value(cx, value).or_throw(cx)?)
+            }
+            Call::SaplingSpend => {
+                BuilderInput::SaplingSpend(neon_serde::from_value(cx, value).or_throw(cx)?)
+            }
+            Call::SaplingOutput => {
+                BuilderInput::SaplingOutput(neon_serde::from_value(cx, value).or_throw(cx)?)
+            }
+        })
+    }
 }
 
 impl ZcashBuilderBridge {
-    pub fn add_transparent_input(&mut self, t: TransparentInputBuilderInfo) -> Result<(), Error> {
-        self.zcashbuilder.add_transparent_input(t)
+    pub fn new(fee: u64) -> Self {
+        ZcashBuilderBridge {
+            zcashbuilder: Arc::new(Mutex::new(ZcashBuilder::new(fee))),
+            cancel: Arc::new(AtomicBool::new(false)),
+            spends: 0,
+            outputs: 0,
+            session: Session::new(fee),
+            signatures: None,
+            products: Arc::new(Mutex::new(BuildProducts::default())),
+        }
+    }
+
+    /// Builder whose fee is only known once all inputs are in, see
+    /// `plan_build`
+    pub fn with_fee_rule(rule: FeeRule) -> Self {
+        let mut bridge = match rule {
+            FeeRule::Fixed(fee) => ZcashBuilderBridge::new(fee),
+            FeeRule::Zip317 => ZcashBuilderBridge::new(0),
+        };
+        bridge.session.fee_rule = rule;
+        bridge
+    }
+
+    /// Sets the address that receives what is left of the inputs after
+    /// outputs and fee. Sapling change needs the output randomness from the
+    /// device, like any other Sapling output.
+    pub fn set_change_address(&mut self, change: Recorded) -> Result<(), BridgeError> {
+        match change_kind(&change) {
+            Some(call) => {
+                self.session.change = Some((call, change));
+                Ok(())
+            }
+            None => Err(BridgeError::invalid_argument(
+                "change address must be an object with an address",
+            )),
+        }
+    }
+
+    /// Fee of the transaction with the inputs added so far
+    pub fn fee(&self) -> Result<u64, BalanceError> {
+        let balance = Balance::from_calls(&self.session.calls)?;
+        Ok(balance.fee(self.session.fee_rule, self.change_call()))
+    }
+
+    /// Value the change output will get, if there is a change address
+    pub fn change_value(&self) -> Result<Option<u64>, BalanceError> {
+        if self.session.change.is_none() {
+            return Ok(None);
+        }
+        let balance = Balance::from_calls(&self.session.calls)?;
+        balance.change(self.fee()?).map(Some)
+    }
+
+    fn change_call(&self) -> Option<Call> {
+        self.session.change.as_ref().map(|(call, _)| *call)
+    }
+
+    /// Works out fee and change, failing if the inputs do not balance
+    pub fn plan_build(&self) -> Result<BuildPlan, BalanceError> {
+        let balance = Balance::from_calls(&self.session.calls)?;
+        let fee = balance.fee(self.session.fee_rule, self.change_call());
+        let change = match &self.session.change {
+            Some((call, address)) => Some((*call, change_output(address, balance.change(fee)?))),
+            None => {
+                balance.check(fee)?;
+                None
+            }
+        };
+        let replay = if fee != self.session.fee {
+            Some(self.session.calls.clone())
+        } else {
+            None
+        };
+        Ok(BuildPlan {
+            fee,
+            replay,
+            change,
+        })
+    }
+
+    pub fn apply_plan(
+        &mut self,
+        fee: u64,
+        replay: Option<Vec<(BuilderInput, Recorded)>>,
+        change: Option<(BuilderInput, Recorded)>,
+    ) -> Result<(), BridgeError> {
+        if let Some(inputs) = replay {
+            let mut replayed = ZcashBuilderBridge::new(fee);
+            for (input, recorded) in inputs {
+                replayed.add_input(input, recorded)?;
+            }
+            replayed.session.fee_rule = self.session.fee_rule;
+            replayed.session.change = self.session.change.take();
+            *self = replayed;
+        }
+        if let Some((input, recorded)) = change {
+            self.add_input(input, recorded)?;
+            self.session.change = None;
+        }
+        Ok(())
+    }
+
+    /// Adds an input to the builder and records it in the session
+    pub fn add_input(
+        &mut self,
+        input: BuilderInput,
+        recorded: Recorded,
+    ) -> Result<(), BridgeError> {
+        let call = match input {
+            BuilderInput::TransparentInput(t) => {
+                self.add_transparent_input(t)?;
+                Call::TransparentInput
+            }
+            BuilderInput::TransparentOutput(t) => {
+                self.add_transparent_output(t)?;
+                Call::TransparentOutput
+            }
+            BuilderInput::SaplingSpend(s) => {
+                self.add_sapling_spend(s)?;
+                Call::SaplingSpend
+            }
+            BuilderInput::SaplingOutput(o) => {
+                self.add_sapling_output(o)?;
+                Call::SaplingOutput
+            }
+        };
+        self.session.record(call, recorded);
+        Ok(())
+    }
+
+    /// Session of this builder, with the ledger blob of its last `build`
+    pub fn export_session(&self, key: &[u8; 32]) -> Result<Vec<u8>, BridgeError> {
+        let products = self.products.try_lock().map_err(|_| BridgeError::busy())?;
+        let mut session = self.session.clone();
+        session.txdata = products.txdata.clone();
+        Ok(session.to_blob(key))
+    }
+
+    /// Replaces this (still empty) builder with the one described by a session,
+    /// whose calls are given typed as `inputs`. With the ledger blob of a past
+    /// `build`, signatures are applied to that transaction and the builder
+    /// does not need to be built again.
+    pub fn resume_session(
+        &mut self,
+        session: Session,
+        inputs: Vec<BuilderInput>,
+    ) -> Result<(), BridgeError> {
+        if !self.session.calls.is_empty() {
+            return Err(BridgeError::new(
+                SESSION,
+                "SESSION_BUILDER_NOT_EMPTY",
+                "a session can only be resumed into an empty builder",
+            ));
+        }
+        let mut resumed = ZcashBuilderBridge::new(session.fee);
+        for (input, (_, recorded)) in inputs.into_iter().zip(session.calls) {
+            resumed.add_input(input, recorded)?;
+        }
+        resumed.session.fee_rule = session.fee_rule;
+        if let Some((_, address)) = session.change {
+            resumed.set_change_address(address)?;
+        }
+        if let Some(txdata) = session.txdata {
+            *resumed.products.lock()? = BuildProducts {
+                txdata: Some(txdata),
+                transaction: None,
+                restored: true,
+            };
+        }
+        *self = resumed;
+        Ok(())
+    }
+
+    pub fn export_pczt(&self) -> Result<Pczt, BridgeError> {
+        let products = self.products.try_lock().map_err(|_| BridgeError::busy())?;
+        Ok(Pczt {
+            fee: self.session.fee,
+            fee_rule: self.session.fee_rule,
+            change: self.session.change.clone(),
+            inputs: self.session.calls.clone(),
+            txdata: products.txdata.clone(),
+            signatures: self.signatures.clone(),
+            transaction: products.transaction.clone(),
+        })
+    }
+
+    /// Checks that a PCZT was signed over the ledger blob of this builder's
+    /// last `build` or import, the only one whose proofs the signatures can match
+    pub fn check_pczt_txdata(&self, pczt: &Pczt) -> Result<(), BridgeError> {
+        let products = self.products.try_lock().map_err(|_| BridgeError::busy())?;
+        Ok(pczt.check_txdata(products.txdata.as_deref())?)
+    }
+
+    /// Runs `f` on the builder, failing instead of blocking the event loop
+    /// while a background task holds it
+    fn with_builder<T>(
+        &self,
+        f: impl FnOnce(&mut ZcashBuilder) -> Result<T, Error>,
+    ) -> Result<T, BridgeError> {
+        let mut builder = self
+            .zcashbuilder
+            .try_lock()
+            .map_err(|_| BridgeError::busy())?;
+        f(&mut builder).map_err(BridgeError::builder)
+    }
+
+    pub fn add_transparent_input(
+        &mut self,
+        t: TransparentInputBuilderInfo,
+    ) -> Result<(), BridgeError> {
+        self.with_builder(|b| b.add_transparent_input(t))
     }
 
     pub fn add_transparent_output(
         &mut self,
         input: TransparentOutputBuilderInfo,
-    ) -> Result<(), Error> {
-        self.zcashbuilder.add_transparent_output(input)
+    ) -> Result<(), BridgeError> {
+        self.with_builder(|b| b.add_transparent_output(input))
     }
 
-    pub fn add_sapling_spend(&mut self, input: SpendBuilderInfo) -> Result<(), Error> {
-        self.zcashbuilder.add_sapling_spend(input)
+    pub fn add_sapling_spend(&mut self, input: SpendBuilderInfo) -> Result<(), BridgeError> {
+        self.with_builder(|b| b.add_sapling_spend(input))?;
+        self.spends += 1;
+        Ok(())
     }
 
-    pub fn add_sapling_output(&mut self, input: OutputBuilderInfo) -> Result<(), Error> {
-        self.zcashbuilder.add_sapling_output(input)
+    pub fn add_sapling_output(&mut self, input: OutputBuilderInfo) -> Result<(), BridgeError> {
+        self.with_builder(|b| b.add_sapling_output(input))?;
+        self.outputs += 1;
+        Ok(())
     }
 
-    pub fn build(&mut self, spendpath: &String, outputpath: &String) -> Result<Vec<u8>, Error> {
-        let mut prover = txprover::LocalTxProver::new(Path::new(spendpath), Path::new(outputpath));
-        self.zcashbuilder.build(&mut prover)
+    pub fn build(
+        &mut self,
+        spendpath: &String,
+        outputpath: &String,
+    ) -> Result<Vec<u8>, BridgeError> {
+        let params = load_prover(Path::new(spendpath), Path::new(outputpath))?;
+        let mut prover = ProgressProver::new(
+            params,
+            Arc::new(AtomicBool::new(false)),
+            self.spends,
+            self.outputs,
+            |_| {},
+        );
+        let txdata = self.with_builder(|b| b.build(&mut prover))?;
+        *self.products.lock()? = BuildProducts {
+            txdata: Some(txdata.clone()),
+            transaction: None,
+            restored: false,
+        };
+        Ok(txdata)
+    }
+
+    pub fn build_task(
+        &self,
+        spendpath: String,
+        outputpath: String,
+        progress: Option<EventHandler>,
+    ) -> BuildTask {
+        self.cancel.store(false, Ordering::SeqCst);
+        BuildTask {
+            builder: self.zcashbuilder.clone(),
+            products: self.products.clone(),
+            cancel: self.cancel.clone(),
+            spendpath,
+            outputpath,
+            spends: self.spends,
+            outputs: self.outputs,
+            progress,
+        }
+    }
+
+    /// Asks a running `build_async` to stop before its next proof
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::SeqCst);
+    }
+
+    pub fn add_signatures(
+        &mut self,
+        input: TransactionSignatures,
+        recorded: Recorded,
+    ) -> Result<(), BridgeError> {
+        let products = self.products.try_lock().map_err(|_| BridgeError::busy())?;
+        if products.restored {
+            // the resumed builder was never built; check the signatures
+            // against the restored transaction instead
+            products.assemble(&self.session.calls, Some(&recorded))?;
+        } else {
+            self.with_builder(|b| b.add_signatures(input))?;
+        }
+        drop(products);
+        self.signatures = Some(recorded);
+        Ok(())
     }
 
-    pub fn add_signatures(&mut self, input: TransactionSignatures) -> Result<(), Error> {
-        self.zcashbuilder.add_signatures(input)
+    pub fn finalize(&mut self) -> Result<Vec<u8>, BridgeError> {
+        let mut products = self.products.try_lock().map_err(|_| BridgeError::busy())?;
+        let transaction = if products.restored {
+            products.assemble(&self.session.calls, self.signatures.as_ref())?
+        } else {
+            self.with_builder(|b| b.finalize_js())?
+        };
+        products.transaction = Some(transaction.clone());
+        Ok(transaction)
     }
 
-    pub fn finalize(&mut self) -> Result<Vec<u8>, Error> {
-        self.zcashbuilder.finalize_js()
+    pub fn finalize_task(&self) -> FinalizeTask {
+        FinalizeTask {
+            builder: self.zcashbuilder.clone(),
+            products: self.products.clone(),
+            calls: self.session.calls.clone(),
+            signatures: self.signatures.clone(),
+        }
     }
 }
 
 declare_types! {
     pub class JsZcashBuilder for ZcashBuilderBridge {
         init(mut cx) {
-            let f = cx.argument::<JsNumber>(0)?.value();
-            let b = ZcashBuilder::new(f as u64);
-            Ok(ZcashBuilderBridge {
-                zcashbuilder: b,
-            })
+            let rule = fee_rule_argument(&mut cx, 0)?;
+            Ok(ZcashBuilderBridge::with_fee_rule(rule))
+        }
+
+        method add_change_address(mut cx) {
+            let arg0 = cx.argument::<JsValue>(0)?;
+            let recorded = Recorded::from_js(&mut cx, arg0)?;
+            let value;
+            {
+            let mut this = cx.this();
+            let guard = cx.lock();
+            let mut thishandler = this.borrow_mut(&guard);
+            value = thishandler.set_change_address(recorded);
+            }
+            match value {
+                Ok(()) => Ok(cx.boolean(true).upcast()),
+                Err(e) => e.throw(&mut cx),
+            }
+        }
+
+        method fee(mut cx) {
+            let value;
+            {
+            let this = cx.this();
+            let guard = cx.lock();
+            let thishandler = this.borrow(&guard);
+            value = thishandler.fee();
+            }
+            match value {
+                Ok(fee) => Ok(cx.number(fee as f64).upcast()),
+                Err(e) => BridgeError::from(e).throw(&mut cx),
+            }
+        }
+
+        method change_value(mut cx) {
+            let value;
+            {
+            let this = cx.this();
+            let guard = cx.lock();
+            let thishandler = this.borrow(&guard);
+            value = thishandler.change_value();
+            }
+            match value {
+                Ok(Some(change)) => Ok(cx.number(change as f64).upcast()),
+                Ok(None) => Ok(cx.null().upcast()),
+                Err(e) => BridgeError::from(e).throw(&mut cx),
+            }
         }
 
         method add_transparent_input(mut cx) {
             let arg0 = cx.argument::<JsValue>(0)?;
-            let arg0_value :TransparentInputBuilderInfo = neon_serde::from_value(&mut cx, arg0).throw(&mut cx)?;
+            let arg0_value = BuilderInput::from_js(&mut cx, Call::TransparentInput, arg0)?;
+            let recorded = Recorded::from_js(&mut cx, arg0)?;
             let value;
             {
             let mut this = cx.this();
@@ -83,18 +674,19 @@ declare_types! {
             let mut thishandler = this.borrow_mut(&guard);
 
             //grab input
-            value = thishandler.add_transparent_input(arg0_value);
+            value = thishandler.add_input(arg0_value, recorded);
             }
             if value.is_ok(){
                 Ok(cx.boolean(true).upcast())
             }else{
-                cx.throw_error(value.err().unwrap().to_string())
+                value.err().unwrap().throw(&mut cx)
             }
         }
 
         method add_transparent_output(mut cx) {
             let arg0 = cx.argument::<JsValue>(0)?;
-            let arg0_value :TransparentOutputBuilderInfo = neon_serde::from_value(&mut cx, arg0).throw(&mut cx)?;
+            let arg0_value = BuilderInput::from_js(&mut cx, Call::TransparentOutput, arg0)?;
+            let recorded = Recorded::from_js(&mut cx, arg0)?;
             let value;
             {
             let mut this = cx.this();
@@ -102,18 +694,19 @@ declare_types! {
             let mut thishandler = this.borrow_mut(&guard);
 
             //grab input
-            value = thishandler.add_transparent_output(arg0_value);
+            value = thishandler.add_input(arg0_value, recorded);
             }
             if value.is_ok(){
                 Ok(cx.boolean(true).upcast())
             }else{
-                cx.throw_error(value.err().unwrap().to_string())
+                value.err().unwrap().throw(&mut cx)
             }
         }
 
         method add_sapling_spend(mut cx) {
             let arg0 = cx.argument::<JsValue>(0)?;
-            let arg0_value :SpendBuilderInfo = neon_serde::from_value(&mut cx, arg0).throw(&mut cx)?;
+            let arg0_value = BuilderInput::from_js(&mut cx, Call::SaplingSpend, arg0)?;
+            let recorded = Recorded::from_js(&mut cx, arg0)?;
             let value;
             {
             let mut this = cx.this();
@@ -121,18 +714,19 @@ declare_types! {
             let mut thishandler = this.borrow_mut(&guard);
 
             //grab input
-            value = thishandler.add_sapling_spend(arg0_value);
+            value = thishandler.add_input(arg0_value, recorded);
             }
             if value.is_ok(){
                 Ok(cx.boolean(true).upcast())
             }else{
-                cx.throw_error(value.err().unwrap().to_string())
+                value.err().unwrap().throw(&mut cx)
             }
         }
 
         method add_sapling_output(mut cx) {
             let arg0 = cx.argument::<JsValue>(0)?;
-            let arg0_value :OutputBuilderInfo = neon_serde::from_value(&mut cx, arg0).throw(&mut cx)?;
+            let arg0_value = BuilderInput::from_js(&mut cx, Call::SaplingOutput, arg0)?;
+            let recorded = Recorded::from_js(&mut cx, arg0)?;
             let value;
             {
             let mut this = cx.this();
@@ -140,18 +734,19 @@ declare_types! {
             let mut thishandler = this.borrow_mut(&guard);
 
             //grab input
-            value = thishandler.add_sapling_output(arg0_value);
+            value = thishandler.add_input(arg0_value, recorded);
             }
             if value.is_ok(){
                 Ok(cx.boolean(true).upcast())
             }else{
-                cx.throw_error(value.err().unwrap().to_string())
+                value.err().unwrap().throw(&mut cx)
             }
         }
 
         method build(mut cx){
             let spendpath: String = cx.argument::<JsString>(0)?.value();
             let outputpath: String = cx.argument::<JsString>(1)?.value();
+            prepare_build(&mut cx)?;
             let value;
             {
             let mut this = cx.this();
@@ -165,13 +760,151 @@ declare_types! {
                 let js_value = neon_serde::to_value(&mut cx, &value.unwrap()).throw(&mut cx)?;
                 Ok(js_value)
             }else{
-                cx.throw_error(value.err().unwrap().to_string())
+                value.err().unwrap().throw(&mut cx)
+            }
+        }
+
+        method build_async(mut cx) {
+            let spendpath: String = cx.argument::<JsString>(0)?.value();
+            let outputpath: String = cx.argument::<JsString>(1)?.value();
+            let this = cx.this();
+            let callback = cx.argument::<JsFunction>(2)?;
+            prepare_build(&mut cx)?;
+            // optional onProgress(kind, index, total), called after every proof
+            let progress = match cx.argument_opt(3) {
+                Some(arg) => {
+                    let on_progress = arg.downcast_or_throw::<JsFunction, _>(&mut cx)?;
+                    Some(EventHandler::new(&cx, this, on_progress))
+                }
+                None => None,
+            };
+            let task;
+            {
+            let guard = cx.lock();
+            let thishandler = this.borrow(&guard);
+            task = thishandler.build_task(spendpath, outputpath, progress);
+            }
+            task.schedule(callback);
+            Ok(cx.undefined().upcast())
+        }
+
+        method cancel(mut cx) {
+            {
+            let this = cx.this();
+            let guard = cx.lock();
+            let thishandler = this.borrow(&guard);
+            thishandler.cancel();
+            }
+            Ok(cx.undefined().upcast())
+        }
+
+        method export_session(mut cx) {
+            let key = session_key_argument(&mut cx, 0)?;
+            let blob;
+            {
+            let this = cx.this();
+            let guard = cx.lock();
+            let thishandler = this.borrow(&guard);
+            blob = thishandler.export_session(&key);
+            }
+            let blob = match blob {
+                Ok(blob) => blob,
+                Err(e) => return e.throw(&mut cx),
+            };
+            let mut buffer = JsBuffer::new(&mut cx, blob.len() as u32)?;
+            cx.borrow_mut(&mut buffer, |data| data.as_mut_slice::<u8>().copy_from_slice(&blob));
+            Ok(buffer.upcast())
+        }
+
+        method resume_session(mut cx) {
+            let blob = bytes_argument(&mut cx, 0)?;
+            let key = session_key_argument(&mut cx, 1)?;
+            let session = match Session::from_blob(&blob, &key) {
+                Ok(session) => session,
+                Err(e) => return BridgeError::from(e).throw(&mut cx),
+            };
+            let mut inputs = Vec::new();
+            for (call, recorded) in &session.calls {
+                let arg = recorded.to_js(&mut cx)?;
+                inputs.push(BuilderInput::from_js(&mut cx, *call, arg)?);
+            }
+            let value;
+            {
+            let mut this = cx.this();
+            let guard = cx.lock();
+            let mut thishandler = this.borrow_mut(&guard);
+            value = thishandler.resume_session(session, inputs);
+            }
+            if value.is_ok(){
+                Ok(cx.boolean(true).upcast())
+            }else{
+                value.err().unwrap().throw(&mut cx)
+            }
+        }
+
+        method export_pczt(mut cx) {
+            let value;
+            {
+            let this = cx.this();
+            let guard = cx.lock();
+            let thishandler = this.borrow(&guard);
+            value = thishandler.export_pczt();
+            }
+            match value {
+                Ok(pczt) => Ok(Recorded::Bytes(pczt.to_bytes()).to_js(&mut cx)?),
+                Err(e) => e.throw(&mut cx),
+            }
+        }
+
+        method import_pczt(mut cx) {
+            let session = pczt_argument(&mut cx, 0)?.session();
+            let mut inputs = Vec::new();
+            for (call, recorded) in &session.calls {
+                let arg = recorded.to_js(&mut cx)?;
+                inputs.push(BuilderInput::from_js(&mut cx, *call, arg)?);
+            }
+            let value;
+            {
+            let mut this = cx.this();
+            let guard = cx.lock();
+            let mut thishandler = this.borrow_mut(&guard);
+            value = thishandler.resume_session(session, inputs);
+            }
+            if value.is_ok(){
+                Ok(cx.boolean(true).upcast())
+            }else{
+                value.err().unwrap().throw(&mut cx)
+            }
+        }
+
+        method add_pczt_signatures(mut cx) {
+            let pczt = pczt_argument(&mut cx, 0)?;
+            let recorded = match &pczt.signatures {
+                Some(signatures) => signatures.clone(),
+                None => return BridgeError::new(PCZT, "PCZT_NO_SIGNATURES", "PCZT carries no signatures").throw(&mut cx),
+            };
+            let arg = recorded.to_js(&mut cx)?;
+            let signatures: TransactionSignatures = neon_serde::from_value(&mut cx, arg).or_throw(&mut cx)?;
+            let value;
+            {
+            let mut this = cx.this();
+            let guard = cx.lock();
+            let mut thishandler = this.borrow_mut(&guard);
+            value = thishandler
+                .check_pczt_txdata(&pczt)
+                .and_then(|_| thishandler.add_signatures(signatures, recorded));
+            }
+            if value.is_ok(){
+                Ok(cx.boolean(true).upcast())
+            }else{
+                value.err().unwrap().throw(&mut cx)
             }
         }
 
         method add_signatures(mut cx) {
             let arg0 = cx.argument::<JsValue>(0)?;
-            let arg0_value :TransactionSignatures = neon_serde::from_value(&mut cx, arg0).throw(&mut cx)?;
+            let arg0_value :TransactionSignatures = neon_serde::from_value(&mut cx, arg0).or_throw(&mut cx)?;
+            let recorded = Recorded::from_js(&mut cx, arg0)?;
             let value;
             {
             let mut this = cx.this();
@@ -179,12 +912,12 @@ declare_types! {
             let mut thishandler = this.borrow_mut(&guard);
 
             //grab input
-            value = thishandler.add_signatures(arg0_value);
+            value = thishandler.add_signatures(arg0_value, recorded);
             }
             if value.is_ok(){
                 Ok(cx.boolean(true).upcast())
             }else{
-                cx.throw_error(value.err().unwrap().to_string())
+                value.err().unwrap().throw(&mut cx)
             }
         }
 
@@ -201,14 +934,229 @@ declare_types! {
                 let js_value = neon_serde::to_value(&mut cx, &value.unwrap()).throw(&mut cx)?;
                 Ok(js_value)
             }else{
-                cx.throw_error(value.err().unwrap().to_string())
+                value.err().unwrap().throw(&mut cx)
+            }
+        }
+
+        method finalize_async(mut cx) {
+            let callback = cx.argument::<JsFunction>(0)?;
+            let task;
+            {
+            let this = cx.this();
+            let guard = cx.lock();
+            let thishandler = this.borrow(&guard);
+            task = thishandler.finalize_task();
+            }
+            task.schedule(callback);
+            Ok(cx.undefined().upcast())
+        }
+    }
+
+    pub class JsSoftwareSigner for SoftwareSigner {
+        init(mut cx) {
+            let sapling_seed = bytes_argument(&mut cx, 0)?;
+            let transparent_seed = bytes_argument(&mut cx, 1)?;
+            if sapling_seed.len() != 32 {
+                return BridgeError::invalid_argument("sapling seed must be 32 bytes").throw(&mut cx);
+            }
+            let mut seed = [0u8; 32];
+            seed.copy_from_slice(&sapling_seed);
+            Ok(SoftwareSigner::new(seed, &transparent_seed))
+        }
+
+        method inittx(mut cx) {
+            let data = bytes_argument(&mut cx, 0)?;
+            let result;
+            {
+            let mut this = cx.this();
+            let guard = cx.lock();
+            let mut signer = this.borrow_mut(&guard);
+            result = signer.init_tx(&data);
+            }
+            signer_reply(&mut cx, result.map(|hash| vec![("txdata", hash.to_vec())]))
+        }
+
+        method extractspenddata(mut cx) {
+            let result;
+            {
+            let mut this = cx.this();
+            let guard = cx.lock();
+            let mut signer = this.borrow_mut(&guard);
+            result = signer.extract_spend();
+            }
+            signer_reply(&mut cx, result.map(|data| vec![
+                ("key_raw", data[..64].to_vec()),
+                ("rcv_raw", data[64..96].to_vec()),
+                ("alpha_raw", data[96..].to_vec()),
+            ]))
+        }
+
+        method extractoutputdata(mut cx) {
+            let result;
+            {
+            let mut this = cx.this();
+            let guard = cx.lock();
+            let mut signer = this.borrow_mut(&guard);
+            result = signer.extract_output();
+            }
+            signer_reply(&mut cx, result.map(|data| {
+                let mut fields = vec![
+                    ("rcv_raw", data[..32].to_vec()),
+                    ("rseed_raw", data[32..64].to_vec()),
+                ];
+                if data.len() > 64 {
+                    fields.push(("hash_seed", data[64..].to_vec()));
+                }
+                fields
+            }))
+        }
+
+        method checkandsign(mut cx) {
+            let data = bytes_argument(&mut cx, 0)?;
+            let result;
+            {
+            let mut this = cx.this();
+            let guard = cx.lock();
+            let mut signer = this.borrow_mut(&guard);
+            result = signer.check_and_sign(&data);
             }
+            signer_reply(&mut cx, result.map(|hash| vec![("signdata", hash.to_vec())]))
+        }
+
+        method extractspendsig(mut cx) {
+            let result;
+            {
+            let mut this = cx.this();
+            let guard = cx.lock();
+            let mut signer = this.borrow_mut(&guard);
+            result = signer.extract_spend_sig();
+            }
+            signer_reply(&mut cx, result.map(|sig| vec![("sig_raw", sig.to_vec())]))
+        }
+
+        method extracttranssig(mut cx) {
+            let result;
+            {
+            let mut this = cx.this();
+            let guard = cx.lock();
+            let mut signer = this.borrow_mut(&guard);
+            result = signer.extract_transparent_sig();
+            }
+            signer_reply(&mut cx, result.map(|sig| vec![("sig_raw", sig.to_vec())]))
         }
     }
 }
 
 register_module!(mut m, {
     m.export_class::<JsZcashBuilder>("zcashtools")?;
+    m.export_class::<JsSoftwareSigner>("softwaresigner")?;
     m.export_function("get_inittx_data", get_inittx_data)?;
+    m.export_function("decode_transaction", decode_transaction)?;
+    m.export_function("load_params", load_params)?;
+    m.export_function("zip317_fee", zip317_fee)?;
+    m.export_function("pczt_parse", pczt_parse)?;
+    m.export_function("pczt_serialize", pczt_serialize)?;
+    m.export_function("anti_exfil_commitment", anti_exfil_commitment)?;
+    m.export_function("verify_anti_exfil", verify_anti_exfil)?;
+    m.export_function("sapling_keys", primitives::sapling_keys)?;
+    m.export_function("sapling_address", primitives::sapling_payment_address)?;
+    m.export_function("diversifier_list", primitives::diversifier_list)?;
+    m.export_function(
+        "compute_note_commitment",
+        primitives::compute_note_commitment,
+    )?;
+    m.export_function("compute_nullifier", primitives::compute_nullifier)?;
+    m.export_function("value_commitment", primitives::compute_value_commitment)?;
+    m.export_function("redjubjub_sign", primitives::redjubjub_sign)?;
     Ok(())
 });
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_value(value: u64) -> Recorded {
+        Recorded::Object(vec![("value".to_string(), Recorded::Number(value as f64))])
+    }
+
+    fn transparent_change() -> Recorded {
+        Recorded::Object(vec![(
+            "address".to_string(),
+            Recorded::String("1976a914".to_string()),
+        )])
+    }
+
+    /// Bridge that recorded one transparent input and output, without
+    /// handing them to the builder
+    fn bridge(rule: FeeRule, input: u64, output: u64) -> ZcashBuilderBridge {
+        let mut bridge = ZcashBuilderBridge::with_fee_rule(rule);
+        bridge
+            .session
+            .record(Call::TransparentInput, with_value(input));
+        bridge
+            .session
+            .record(Call::TransparentOutput, with_value(output));
+        bridge
+    }
+
+    #[test]
+    fn test_plan_build_adds_zip317_change() {
+        let mut bridge = bridge(FeeRule::Zip317, 60_000, 30_000);
+        bridge.set_change_address(transparent_change()).unwrap();
+        let plan = bridge.plan_build().unwrap();
+        // one input, two outputs with the change
+        assert_eq!(plan.fee, 10_000);
+        assert_eq!(bridge.fee(), Ok(10_000));
+        assert_eq!(bridge.change_value(), Ok(Some(20_000)));
+        let (call, change) = plan.change.unwrap();
+        assert_eq!(call, Call::TransparentOutput);
+        assert_eq!(change.field("value"), Some(&Recorded::Number(20_000.0)));
+        // the builder was created before the fee was known
+        assert_eq!(plan.replay.map(|calls| calls.len()), Some(2));
+    }
+
+    #[test]
+    fn test_resume_session_keeps_fee_rule_and_change() {
+        let key = [9u8; 32];
+        let mut bridge = ZcashBuilderBridge::with_fee_rule(FeeRule::Zip317);
+        bridge.set_change_address(transparent_change()).unwrap();
+        let session = Session::from_blob(&bridge.export_session(&key).unwrap(), &key).unwrap();
+
+        let mut resumed = ZcashBuilderBridge::new(1_000);
+        resumed.resume_session(session, Vec::new()).unwrap();
+        assert_eq!(resumed.session, bridge.session);
+        resumed
+            .session
+            .record(Call::TransparentInput, with_value(60_000));
+        resumed
+            .session
+            .record(Call::TransparentOutput, with_value(30_000));
+        assert_eq!(resumed.fee(), Ok(10_000));
+        assert_eq!(resumed.change_value(), Ok(Some(20_000)));
+    }
+
+    #[test]
+    fn test_plan_build_checks_the_balance() {
+        let plan = bridge(FeeRule::Fixed(1_000), 60_000, 59_000)
+            .plan_build()
+            .unwrap();
+        assert_eq!(plan.fee, 1_000);
+        assert!(plan.change.is_none());
+        assert!(plan.replay.is_none());
+
+        assert_eq!(
+            bridge(FeeRule::Zip317, 60_000, 59_000).plan_build().err(),
+            Some(BalanceError::Insufficient {
+                inputs: 60_000,
+                required: 69_000
+            })
+        );
+        assert_eq!(
+            bridge(FeeRule::Zip317, 60_000, 30_000).plan_build().err(),
+            Some(BalanceError::Unbalanced {
+                inputs: 60_000,
+                required: 40_000
+            })
+        );
+    }
+}
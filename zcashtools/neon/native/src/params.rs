@@ -0,0 +1,158 @@
+//! Process-wide cache of Sapling proving parameters.
+//!
+//! The spend and output parameters weigh ~50 MB and take a while to parse, so
+//! they are checked against their published hashes and loaded once per pair of
+//! paths; every builder of the process then shares the same prover.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use sha2::{Digest, Sha256};
+use zcash_hsmbuilder::txprover::LocalTxProver;
+
+/// Published hashes of one parameter file
+struct ParamsFile {
+    name: &'static str,
+    size: u64,
+    sha256: &'static str,
+    blake2b: &'static str,
+}
+
+const SAPLING_SPEND: ParamsFile = ParamsFile {
+    name: "sapling-spend.params",
+    size: 47_958_396,
+    sha256: "8e48ffd23abb3a5fd9c5589204f32d9c31285a04b78096ba40a79b75677efc13",
+    blake2b: "8270785a1a0d0bc77196f000ee6d221c9c9894f55307bd9357c3f0105d31ca63\
+              991ab91324160d8f53e2bbd3c2633a6eb8bdf5205d822e7f3f73edac51b2b70c",
+};
+
+const SAPLING_OUTPUT: ParamsFile = ParamsFile {
+    name: "sapling-output.params",
+    size: 3_592_860,
+    sha256: "2f0ebbcbb9bb0bcffe95a397e7eba89c29eb4dde6191c339db88570e3f3fb0e4",
+    blake2b: "657e3d38dbb5cb5e7dd2970e8b03d69b4787dd907285b5a7f0790dcc8072f60b\
+              f593b32cc2d1c030e00ff5ae64bf84c5c3beb84ddc841d48264b4a171744d028",
+};
+
+#[derive(Debug)]
+pub enum ParamsError {
+    Missing {
+        name: &'static str,
+        path: PathBuf,
+    },
+    Io {
+        name: &'static str,
+        path: PathBuf,
+        error: io::Error,
+    },
+    Corrupt {
+        name: &'static str,
+        path: PathBuf,
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for ParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamsError::Missing { name, path } => {
+                write!(f, "{} not found at {}", name, path.display())
+            }
+            ParamsError::Io { name, path, error } => {
+                write!(f, "cannot read {} at {}: {}", name, path.display(), error)
+            }
+            ParamsError::Corrupt { name, path, reason } => {
+                write!(f, "{} at {} is corrupt: {}", name, path.display(), reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParamsError {}
+
+type Entry = (PathBuf, PathBuf, Arc<LocalTxProver>);
+
+static PROVERS: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+
+/// Returns the prover for these parameter files, verifying and loading them on
+/// first use
+pub fn load_prover(spendpath: &Path, outputpath: &Path) -> Result<Arc<LocalTxProver>, ParamsError> {
+    let spendpath = canonical(&SAPLING_SPEND, spendpath)?;
+    let outputpath = canonical(&SAPLING_OUTPUT, outputpath)?;
+
+    // loading happens under the lock so that concurrent builds wait for the
+    // first one instead of parsing the same files again
+    let mut provers = PROVERS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((_, _, prover)) = provers
+        .iter()
+        .find(|(s, o, _)| *s == spendpath && *o == outputpath)
+    {
+        return Ok(prover.clone());
+    }
+
+    verify(&SAPLING_SPEND, &spendpath)?;
+    verify(&SAPLING_OUTPUT, &outputpath)?;
+    let prover = Arc::new(LocalTxProver::new(&spendpath, &outputpath));
+    provers.push((spendpath, outputpath, prover.clone()));
+    Ok(prover)
+}
+
+fn canonical(file: &ParamsFile, path: &Path) -> Result<PathBuf, ParamsError> {
+    path.canonicalize().map_err(|error| match error.kind() {
+        io::ErrorKind::NotFound => ParamsError::Missing {
+            name: file.name,
+            path: path.to_path_buf(),
+        },
+        _ => ParamsError::Io {
+            name: file.name,
+            path: path.to_path_buf(),
+            error,
+        },
+    })
+}
+
+fn verify(file: &ParamsFile, path: &Path) -> Result<(), ParamsError> {
+    let io_error = |error| ParamsError::Io {
+        name: file.name,
+        path: path.to_path_buf(),
+        error,
+    };
+    let corrupt = |reason| ParamsError::Corrupt {
+        name: file.name,
+        path: path.to_path_buf(),
+        reason,
+    };
+
+    let mut reader = File::open(path).map_err(io_error)?;
+    let len = reader.metadata().map_err(io_error)?.len();
+    if len != file.size {
+        return Err(corrupt("unexpected file size"));
+    }
+
+    let mut sha256 = Sha256::new();
+    let mut blake2b = blake2b_simd::State::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = reader.read(&mut buf).map_err(io_error)?;
+        if n == 0 {
+            break;
+        }
+        sha256.update(&buf[..n]);
+        blake2b.update(&buf[..n]);
+    }
+
+    if hex_string(&sha256.finalize()) != file.sha256 {
+        return Err(corrupt("SHA-256 mismatch"));
+    }
+    if blake2b.finalize().to_hex().as_str() != file.blake2b {
+        return Err(corrupt("BLAKE2b mismatch"));
+    }
+    Ok(())
+}
+
+fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,400 @@
+//! PCZT-style container for splitting a transaction across tools.
+//!
+//! A `Pczt` carries what each role needs and adds to:
+//! - constructor: fee, fee rule, change address and builder inputs (witnesses,
+//!   device randomness)
+//! - prover: the ledger blob returned by `build` (proofs, cv, rk, sighash data)
+//! - signer: the signatures extracted from the device
+//! - finalizer: the serialized transaction
+//!
+//! The layout is `MAGIC || VERSION || body || checksum`, with a BLAKE2b-256
+//! checksum guarding against corruption in transit. It is not the PCZT format
+//! of the `pczt` crate: inputs are kept in the shape the bridge accepts them.
+//!
+//! Signatures are only valid for the proofs in `txdata`. A builder that
+//! imports a PCZT with `txdata` takes that blob as its build, so the finalizer
+//! can run in another process than the prover (see `proven`), and
+//! `add_pczt_signatures` rejects a PCZT whose `txdata` is not its build.
+
+use std::fmt;
+
+use neon::prelude::*;
+
+use crate::errors::BridgeError;
+use crate::fees::{change_kind, FeeRule};
+use crate::session::{
+    constant_time_eq, decode_change, decode_fee_rule, decode_optional_bytes, encode_change,
+    encode_fee_rule, encode_optional_bytes, Call, Reader, Recorded, Session, SessionError,
+};
+
+const MAGIC: &[u8; 4] = b"PCZL";
+const VERSION: u8 = 2;
+const CHECKSUM_PERSONALIZATION: &[u8; 16] = b"ZcashLedgerPczt_";
+const CHECKSUM_SIZE: usize = 32;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PcztError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    BadChecksum,
+    Malformed,
+    /// The builder has no ledger blob to apply signatures to
+    NotBuilt,
+    /// The PCZT was signed over another ledger blob
+    TxdataMismatch,
+}
+
+impl fmt::Display for PcztError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PcztError::BadMagic => write!(f, "not a PCZT"),
+            PcztError::UnsupportedVersion(v) => write!(f, "unsupported PCZT version {}", v),
+            PcztError::BadChecksum => write!(f, "PCZT checksum mismatch"),
+            PcztError::Malformed => write!(f, "malformed PCZT"),
+            PcztError::NotBuilt => write!(f, "builder has not been built yet"),
+            PcztError::TxdataMismatch => write!(f, "PCZT does not belong to this build"),
+        }
+    }
+}
+
+impl std::error::Error for PcztError {}
+
+impl From<SessionError> for PcztError {
+    fn from(_: SessionError) -> Self {
+        PcztError::Malformed
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Pczt {
+    pub fee: u64,
+    pub fee_rule: FeeRule,
+    /// Change address, added as an output by `build`
+    pub change: Option<(Call, Recorded)>,
+    pub inputs: Vec<(Call, Recorded)>,
+    /// Ledger blob produced by `build`, sent to the device with CHECKANDSIGN
+    pub txdata: Option<Vec<u8>>,
+    /// `TransactionSignatures` as passed to `add_signatures`
+    pub signatures: Option<Recorded>,
+    pub transaction: Option<Vec<u8>>,
+}
+
+impl Pczt {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        out.extend_from_slice(&self.fee.to_le_bytes());
+        encode_fee_rule(self.fee_rule, &mut out);
+        encode_change(self.change.as_ref(), &mut out);
+        out.extend_from_slice(&(self.inputs.len() as u32).to_le_bytes());
+        for (call, input) in &self.inputs {
+            out.push(*call as u8);
+            input.encode(&mut out);
+        }
+        encode_optional_bytes(self.txdata.as_deref(), &mut out);
+        match &self.signatures {
+            Some(signatures) => {
+                out.push(1);
+                signatures.encode(&mut out);
+            }
+            None => out.push(0),
+        }
+        encode_optional_bytes(self.transaction.as_deref(), &mut out);
+        let checksum = checksum(&out);
+        out.extend_from_slice(&checksum);
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, PcztError> {
+        if bytes.len() < MAGIC.len() + 1 + CHECKSUM_SIZE || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(PcztError::BadMagic);
+        }
+        let version = bytes[MAGIC.len()];
+        if version != VERSION {
+            return Err(PcztError::UnsupportedVersion(version));
+        }
+        let (data, expected) = bytes.split_at(bytes.len() - CHECKSUM_SIZE);
+        if !constant_time_eq(&checksum(data), expected) {
+            return Err(PcztError::BadChecksum);
+        }
+
+        let mut reader = Reader(&data[MAGIC.len() + 1..]);
+        let mut fee = [0u8; 8];
+        fee.copy_from_slice(reader.take(8)?);
+        let fee_rule = decode_fee_rule(&mut reader)?;
+        let change = decode_change(&mut reader)?;
+        let mut inputs = Vec::new();
+        for _ in 0..reader.u32()? {
+            let call = Call::from_u8(reader.byte()?).ok_or(PcztError::Malformed)?;
+            inputs.push((call, Recorded::decode(&mut reader, 0)?));
+        }
+        let txdata = decode_optional_bytes(&mut reader)?;
+        let signatures = match reader.byte()? {
+            0 => None,
+            1 => Some(Recorded::decode(&mut reader, 0)?),
+            _ => return Err(PcztError::Malformed),
+        };
+        let transaction = decode_optional_bytes(&mut reader)?;
+        if !reader.0.is_empty() {
+            return Err(PcztError::Malformed);
+        }
+        Ok(Pczt {
+            fee: u64::from_le_bytes(fee),
+            fee_rule,
+            change,
+            inputs,
+            txdata,
+            signatures,
+            transaction,
+        })
+    }
+
+    /// Checks that this PCZT was signed over `built`, the ledger blob of the
+    /// builder it is applied to
+    pub fn check_txdata(&self, built: Option<&[u8]>) -> Result<(), PcztError> {
+        match (built, self.txdata.as_deref()) {
+            (None, _) => Err(PcztError::NotBuilt),
+            (Some(ours), Some(theirs)) if constant_time_eq(ours, theirs) => Ok(()),
+            _ => Err(PcztError::TxdataMismatch),
+        }
+    }
+
+    /// Session to resume a builder from, see `ZcashBuilderBridge::resume_session`
+    pub fn session(&self) -> Session {
+        Session {
+            fee: self.fee,
+            fee_rule: self.fee_rule,
+            change: self.change.clone(),
+            calls: self.inputs.clone(),
+            txdata: self.txdata.clone(),
+        }
+    }
+
+    /// `{ version, fee, fee_rule, change?, inputs: [{ kind, data }], txdata?,
+    /// signatures?, transaction? }`
+    pub fn to_js<'a, C: Context<'a>>(&self, cx: &mut C) -> JsResult<'a, JsObject> {
+        let obj = cx.empty_object();
+        let version = cx.number(VERSION);
+        obj.set(cx, "version", version)?;
+        let fee = cx.number(self.fee as f64);
+        obj.set(cx, "fee", fee)?;
+        let fee_rule = self.fee_rule.to_js(cx)?;
+        obj.set(cx, "fee_rule", fee_rule)?;
+        if let Some((_, address)) = &self.change {
+            let change = address.to_js(cx)?;
+            obj.set(cx, "change", change)?;
+        }
+
+        let inputs = JsArray::new(cx, self.inputs.len() as u32);
+        for (i, (call, data)) in self.inputs.iter().enumerate() {
+            let input = cx.empty_object();
+            let kind = cx.string(call.name());
+            input.set(cx, "kind", kind)?;
+            let data = data.to_js(cx)?;
+            input.set(cx, "data", data)?;
+            inputs.set(cx, i as u32, input)?;
+        }
+        obj.set(cx, "inputs", inputs)?;
+
+        if let Some(txdata) = &self.txdata {
+            let txdata = Recorded::Bytes(txdata.clone()).to_js(cx)?;
+            obj.set(cx, "txdata", txdata)?;
+        }
+        if let Some(signatures) = &self.signatures {
+            let signatures = signatures.to_js(cx)?;
+            obj.set(cx, "signatures", signatures)?;
+        }
+        if let Some(transaction) = &self.transaction {
+            let transaction = Recorded::Bytes(transaction.clone()).to_js(cx)?;
+            obj.set(cx, "transaction", transaction)?;
+        }
+        Ok(obj)
+    }
+
+    /// Inverse of [`Pczt::to_js`]; `version` is ignored and a missing
+    /// `fee_rule` is the fixed `fee`
+    pub fn from_js<'a, C: Context<'a>>(cx: &mut C, value: Handle<JsValue>) -> NeonResult<Self> {
+        let obj = value.downcast_or_throw::<JsObject, _>(cx)?;
+        let fee = obj
+            .get(cx, "fee")?
+            .downcast_or_throw::<JsNumber, _>(cx)?
+            .value();
+        if fee < 0.0 || fee.fract() != 0.0 || fee > u64::MAX as f64 {
+            return BridgeError::invalid_argument("PCZT fee must be a non-negative integer")
+                .throw(cx);
+        }
+        let fee_rule = obj.get(cx, "fee_rule")?;
+        let fee_rule = if fee_rule.is_a::<JsUndefined>() || fee_rule.is_a::<JsNull>() {
+            FeeRule::Fixed(fee as u64)
+        } else {
+            FeeRule::from_js(cx, fee_rule)?
+        };
+        let change = obj.get(cx, "change")?;
+        let change = if change.is_a::<JsUndefined>() || change.is_a::<JsNull>() {
+            None
+        } else {
+            let address = Recorded::from_js(cx, change)?;
+            match change_kind(&address) {
+                Some(call) => Some((call, address)),
+                None => {
+                    return BridgeError::invalid_argument("PCZT change must have an address")
+                        .throw(cx)
+                }
+            }
+        };
+
+        let mut inputs = Vec::new();
+        let array = obj.get(cx, "inputs")?.downcast_or_throw::<JsArray, _>(cx)?;
+        for input in array.to_vec(cx)? {
+            let input = input.downcast_or_throw::<JsObject, _>(cx)?;
+            let kind = input
+                .get(cx, "kind")?
+                .downcast_or_throw::<JsString, _>(cx)?
+                .value();
+            let call = match Call::from_name(&kind) {
+                Some(call) => call,
+                None => {
+                    return BridgeError::invalid_argument(format!(
+                        "unknown PCZT input kind {}",
+                        kind
+                    ))
+                    .throw(cx)
+                }
+            };
+            let data = input.get(cx, "data")?;
+            inputs.push((call, Recorded::from_js(cx, data)?));
+        }
+
+        let txdata = optional_bytes(cx, obj, "txdata")?;
+        let signatures = obj.get(cx, "signatures")?;
+        let signatures = if signatures.is_a::<JsUndefined>() || signatures.is_a::<JsNull>() {
+            None
+        } else {
+            Some(Recorded::from_js(cx, signatures)?)
+        };
+        let transaction = optional_bytes(cx, obj, "transaction")?;
+        Ok(Pczt {
+            fee: fee as u64,
+            fee_rule,
+            change,
+            inputs,
+            txdata,
+            signatures,
+            transaction,
+        })
+    }
+}
+
+fn optional_bytes<'a, C: Context<'a>>(
+    cx: &mut C,
+    obj: Handle<JsObject>,
+    name: &str,
+) -> NeonResult<Option<Vec<u8>>> {
+    let value = obj.get(cx, name)?;
+    if value.is_a::<JsUndefined>() || value.is_a::<JsNull>() {
+        return Ok(None);
+    }
+    let buffer = value.downcast_or_throw::<JsBuffer, _>(cx)?;
+    Ok(Some(
+        cx.borrow(&buffer, |data| data.as_slice::<u8>().to_vec()),
+    ))
+}
+
+fn checksum(data: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    let h = blake2b_simd::Params::new()
+        .hash_length(CHECKSUM_SIZE)
+        .personal(CHECKSUM_PERSONALIZATION)
+        .hash(data);
+    let mut out = [0u8; CHECKSUM_SIZE];
+    out.copy_from_slice(h.as_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pczt() -> Pczt {
+        Pczt {
+            fee: 1000,
+            fee_rule: FeeRule::Fixed(1000),
+            change: None,
+            inputs: vec![(
+                Call::TransparentOutput,
+                Recorded::Object(vec![
+                    (
+                        "address".to_string(),
+                        Recorded::String("1976a914".to_string()),
+                    ),
+                    ("value".to_string(), Recorded::Number(50000.0)),
+                ]),
+            )],
+            txdata: Some(vec![0xab; 220]),
+            signatures: Some(Recorded::Object(vec![(
+                "spend_sigs".to_string(),
+                Recorded::Array(vec![Recorded::Bytes(vec![1u8; 64])]),
+            )])),
+            transaction: None,
+        }
+    }
+
+    #[test]
+    fn test_pczt_roundtrip() {
+        let pczt = pczt();
+        assert_eq!(Pczt::from_bytes(&pczt.to_bytes()), Ok(pczt));
+        assert_eq!(
+            Pczt::from_bytes(&Pczt::default().to_bytes()),
+            Ok(Pczt::default())
+        );
+    }
+
+    #[test]
+    fn test_pczt_roundtrip_keeps_fee_rule_and_change() {
+        let pczt = Pczt {
+            fee: 0,
+            fee_rule: FeeRule::Zip317,
+            change: Some((
+                Call::TransparentOutput,
+                Recorded::Object(vec![(
+                    "address".to_string(),
+                    Recorded::String("1976a914".to_string()),
+                )]),
+            )),
+            txdata: None,
+            signatures: None,
+            ..pczt()
+        };
+        let session = pczt.session();
+        assert_eq!(session.fee_rule, FeeRule::Zip317);
+        assert_eq!(session.change, pczt.change);
+        assert_eq!(session.calls, pczt.inputs);
+        assert_eq!(Pczt::from_bytes(&pczt.to_bytes()), Ok(pczt));
+    }
+
+    #[test]
+    fn test_pczt_rejects_corruption() {
+        let mut bytes = pczt().to_bytes();
+        bytes[MAGIC.len() + 1] ^= 1;
+        assert_eq!(Pczt::from_bytes(&bytes), Err(PcztError::BadChecksum));
+        assert_eq!(Pczt::from_bytes(b"ZBSN\x01"), Err(PcztError::BadMagic));
+    }
+
+    #[test]
+    fn test_pczt_txdata_must_match_the_build() {
+        let pczt = pczt();
+        assert_eq!(pczt.check_txdata(Some(&[0xab; 220])), Ok(()));
+        assert_eq!(pczt.check_txdata(None), Err(PcztError::NotBuilt));
+        assert_eq!(
+            pczt.check_txdata(Some(&[0xac; 220])),
+            Err(PcztError::TxdataMismatch)
+        );
+        let unproven = Pczt {
+            txdata: None,
+            ..pczt
+        };
+        assert_eq!(
+            unproven.check_txdata(Some(&[0xab; 220])),
+            Err(PcztError::TxdataMismatch)
+        );
+    }
+}
@@ -0,0 +1,185 @@
+//! Sapling primitives of the firmware, exported for tests.
+//!
+//! These call the same `rslib` code the device runs, so tests can compute the
+//! keys, addresses, commitments, nullifiers and signatures a device should
+//! return instead of hardcoding them. Seeds are handled in process memory:
+//! this is for tests only.
+
+use std::convert::TryInto;
+
+use neon::prelude::*;
+use rslib::address::sapling_address;
+use rslib::commitments::{note_commitment_cmu, note_commitment_full, nullifier, value_commitment};
+use rslib::constants::DIV_SIZE;
+use rslib::redjubjub::{jubjub_sk_to_pk, randomized_secret, sign};
+use rslib::types::{Diversifier, NoteCommitment, Nsk, PaymentAddress, SpendingKey};
+use rslib::zip32::{
+    default_payment_address_from_startindex, derive_ask_nsk, derive_ivk, derive_ovk,
+    derive_proof_key, diversifier_list_with_startindex, nsk_to_nk, payment_address,
+};
+
+use crate::bytes_argument;
+use crate::errors::{BridgeError, OrThrow};
+use crate::session::Recorded;
+
+/// Reads a byte argument of exactly `N` bytes
+fn array_argument<T: This, const N: usize>(
+    cx: &mut CallContext<T>,
+    i: i32,
+    name: &str,
+) -> NeonResult<[u8; N]> {
+    let bytes = bytes_argument(cx, i)?;
+    match bytes.as_slice().try_into() {
+        Ok(array) => Ok(array),
+        Err(_) => BridgeError::invalid_argument(format!("{} must be {} bytes", name, N)).throw(cx),
+    }
+}
+
+/// Reads a non-negative integer argument
+fn u64_argument<T: This>(cx: &mut CallContext<T>, i: i32, name: &str) -> NeonResult<u64> {
+    let n = cx.argument::<JsNumber>(i)?.value();
+    if n < 0.0 || n.fract() != 0.0 || n > u64::MAX as f64 {
+        return BridgeError::invalid_argument(format!("{} must be a non-negative integer", name))
+            .throw(cx);
+    }
+    Ok(n as u64)
+}
+
+fn account_argument<T: This>(cx: &mut CallContext<T>, i: i32) -> NeonResult<u32> {
+    let account = u64_argument(cx, i, "account")?;
+    match account.try_into() {
+        Ok(account) => Ok(account),
+        Err(_) => BridgeError::invalid_argument("account must fit in 32 bits").throw(cx),
+    }
+}
+
+fn seed_argument<T: This>(cx: &mut CallContext<T>, i: i32) -> NeonResult<SpendingKey> {
+    Ok(SpendingKey(array_argument(cx, i, "seed")?))
+}
+
+fn buffer<'a, C: Context<'a>>(cx: &mut C, bytes: &[u8]) -> JsResult<'a, JsValue> {
+    Recorded::Bytes(bytes.to_vec()).to_js(cx)
+}
+
+/// `{ address, address_raw }`, the bech32 and the 43-byte forms
+fn address_object<'a, C: Context<'a>>(
+    cx: &mut C,
+    address: &PaymentAddress,
+) -> JsResult<'a, JsObject> {
+    let mut bech32 = [0u8; 128];
+    let len = sapling_address(address, &mut bech32).or_throw(cx)?;
+    let obj = cx.empty_object();
+    let text = cx.string(String::from_utf8_lossy(&bech32[..len]));
+    obj.set(cx, "address", text)?;
+    let raw = buffer(cx, &address.to_bytes())?;
+    obj.set(cx, "address_raw", raw)?;
+    Ok(obj)
+}
+
+/// `sapling_keys(seed, account)`: keys of a ZIP32 account and its default
+/// address, as returned by the device
+pub fn sapling_keys(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let seed = seed_argument(&mut cx, 0)?;
+    let account = account_argument(&mut cx, 1)?;
+
+    let (ask, nsk) = derive_ask_nsk(&seed, account);
+    let (ak, _) = derive_proof_key(&seed, account);
+    let nk = nsk_to_nk(&nsk);
+    let ivk = derive_ivk(&seed, account);
+    let ovk = derive_ovk(&seed, account);
+    let mut start = Diversifier([0u8; DIV_SIZE]);
+    let address =
+        default_payment_address_from_startindex(&seed, account, &mut start).or_throw(&mut cx)?;
+
+    let obj = address_object(&mut cx, &address)?;
+    let fields: [(&str, &[u8]); 7] = [
+        ("ask", ask.as_bytes()),
+        ("nsk", nsk.as_bytes()),
+        ("ak", ak.as_bytes()),
+        ("nk", nk.as_bytes()),
+        ("ivk", ivk.as_bytes()),
+        ("ovk", ovk.as_bytes()),
+        ("diversifier", address.diversifier.as_bytes()),
+    ];
+    for (name, bytes) in fields.iter() {
+        let value = buffer(&mut cx, bytes)?;
+        obj.set(&mut cx, *name, value)?;
+    }
+    Ok(obj.upcast())
+}
+
+/// `sapling_address(seed, account, diversifier)`
+pub fn sapling_payment_address(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let seed = seed_argument(&mut cx, 0)?;
+    let account = account_argument(&mut cx, 1)?;
+    let diversifier = Diversifier(array_argument(&mut cx, 2, "diversifier")?);
+    let address = payment_address(&seed, account, &diversifier).or_throw(&mut cx)?;
+    Ok(address_object(&mut cx, &address)?.upcast())
+}
+
+/// `diversifier_list(seed, account, start)`: the 20 diversifiers following
+/// `start`, in the layout of the device's diversifier list
+pub fn diversifier_list(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let seed = seed_argument(&mut cx, 0)?;
+    let account = account_argument(&mut cx, 1)?;
+    let start = Diversifier(array_argument(&mut cx, 2, "start index")?);
+    let list = diversifier_list_with_startindex(&seed, account, &start);
+    buffer(&mut cx, &list)
+}
+
+/// `compute_note_commitment(value, diversifier, pk_d, rcm)`: `{ cmu, cm }`,
+/// where `cm` is the full point needed by `compute_nullifier`
+pub fn compute_note_commitment(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let value = u64_argument(&mut cx, 0, "value")?;
+    let diversifier = Diversifier(array_argument(&mut cx, 1, "diversifier")?);
+    let pk_d = array_argument(&mut cx, 2, "pk_d")?;
+    let rcm = array_argument(&mut cx, 3, "rcm")?;
+    let cmu = note_commitment_cmu(value, &diversifier, &pk_d, &rcm).or_throw(&mut cx)?;
+    let cm = note_commitment_full(value, &diversifier, &pk_d, &rcm).or_throw(&mut cx)?;
+
+    let obj = cx.empty_object();
+    let cmu = buffer(&mut cx, &cmu)?;
+    obj.set(&mut cx, "cmu", cmu)?;
+    let cm = buffer(&mut cx, &cm.0)?;
+    obj.set(&mut cx, "cm", cm)?;
+    Ok(obj.upcast())
+}
+
+/// `compute_nullifier(cm, position, nsk)`
+pub fn compute_nullifier(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let cm = NoteCommitment(array_argument(&mut cx, 0, "note commitment")?);
+    let position = u64_argument(&mut cx, 1, "position")?;
+    let nsk = Nsk(array_argument(&mut cx, 2, "nsk")?);
+    let nf = nullifier(&cm, position, &nsk).or_throw(&mut cx)?;
+    buffer(&mut cx, &nf.0)
+}
+
+/// `value_commitment(value, rcv)`
+pub fn compute_value_commitment(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let value = u64_argument(&mut cx, 0, "value")?;
+    let rcv = array_argument(&mut cx, 1, "rcv")?;
+    buffer(&mut cx, &value_commitment(value, &rcv).0)
+}
+
+/// `redjubjub_sign(key, message, alpha?)`: `{ signature, public_key }`,
+/// signing with `key + alpha` when `alpha` is given, as for spend
+/// authorization signatures
+pub fn redjubjub_sign(mut cx: FunctionContext) -> JsResult<JsValue> {
+    let key = array_argument(&mut cx, 0, "key")?;
+    let message = array_argument(&mut cx, 1, "message")?;
+    let key = match cx.argument_opt(2) {
+        Some(arg) if !arg.is_a::<JsUndefined>() && !arg.is_a::<JsNull>() => {
+            let alpha = array_argument(&mut cx, 2, "alpha")?;
+            *randomized_secret(&key, &alpha).or_throw(&mut cx)?
+        }
+        _ => key,
+    };
+    let signature = sign(&key, &message).or_throw(&mut cx)?;
+
+    let obj = cx.empty_object();
+    let sig = buffer(&mut cx, &signature.0)?;
+    obj.set(&mut cx, "signature", sig)?;
+    let pk = buffer(&mut cx, &jubjub_sk_to_pk(&key))?;
+    obj.set(&mut cx, "public_key", pk)?;
+    Ok(obj.upcast())
+}
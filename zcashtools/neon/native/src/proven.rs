@@ -0,0 +1,597 @@
+//! Signed transaction assembled from the ledger blob of a build.
+//!
+//! `zcash-hsmbuilder` keeps the proofs it generates inside the builder, but
+//! the ledger blob returned by `build` carries them as well, next to every
+//! other field of the unsigned transaction except the transparent outputs.
+//! Those and the input public keys are part of the recorded builder inputs,
+//! and the binding signature follows from their `rcv`s. A builder resumed from
+//! a session or a PCZT therefore applies its signatures to the proofs they
+//! were made over: building again would draw new proofs and a new sighash.
+
+use std::fmt;
+
+use blake2b_simd::Params as Blake2bParams;
+use secp256k1::{Message, PublicKey, Secp256k1};
+
+use rslib::commitments::{bytes_to_extended, value_commitment_step1, VALUE_COMMITMENT_RANDOM_BASE};
+use rslib::pedersen::extended_to_bytes;
+use rslib::redjubjub::{bytes_to_scalar, sign_compute_sbar, sign_generate_r, spend_auth_message};
+use rslib::transaction::ByteWriter;
+use rslib::types::Signature;
+use rslib::zip304::verify_spend_auth_sig;
+
+use crate::fees::recorded_value;
+use crate::session::{Call, Recorded};
+
+const T_IN_TX_LEN: usize = 74;
+const SPEND_OLD_TX_LEN: usize = 40;
+const SPEND_TX_LEN: usize = 320;
+const OUTPUT_TX_LEN: usize = 948;
+const LENGTH_HASH_DATA: usize = 220;
+
+const PREVOUT_SIZE: usize = 36;
+const INDEX_TIN_SEQ: usize = 70;
+const INDEX_SPEND_RK: usize = 96;
+const INDEX_HASH_OUTPUTSHASH: usize = 72;
+const INDEX_HASH_LOCKTIME: usize = 200;
+const INDEX_HASH_EXPIRYHEIGHT: usize = 204;
+const INDEX_HASH_VALUEBALANCE: usize = 208;
+
+const OUTPUTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashOutputsHash";
+// "ZcashSigHash" || CONSENSUS_BRANCH_ID (Sapling 0x76b809bb, little endian),
+// as the device app signs
+const SIGHASH_PERSONALIZATION: &[u8; 16] = b"ZcashSigHash\xbb\x09\xb8\x76";
+const SIGHASH_ALL: u8 = 0x01;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProvenError {
+    /// The blob was not built from the recorded inputs
+    TxdataMismatch,
+    /// A recorded input lacks a field the transaction needs
+    InvalidInput { call: Call, index: usize },
+    /// Signatures are missing, or not valid for this transaction
+    InvalidSignatures,
+}
+
+impl fmt::Display for ProvenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProvenError::TxdataMismatch => {
+                write!(f, "ledger blob does not match the builder inputs")
+            }
+            ProvenError::InvalidInput { call, index } => {
+                write!(
+                    f,
+                    "{} {} cannot be put in the transaction",
+                    call.name(),
+                    index
+                )
+            }
+            ProvenError::InvalidSignatures => {
+                write!(f, "signatures do not belong to this transaction")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProvenError {}
+
+/// Sections of the ledger blob, as the device reads it at CHECKANDSIGN
+struct Blob<'a> {
+    t_in: &'a [u8],
+    spends: &'a [u8],
+    outputs: &'a [u8],
+    hashdata: &'a [u8],
+}
+
+impl<'a> Blob<'a> {
+    fn split(txdata: &'a [u8], t_in: usize, spends: usize, outputs: usize) -> Option<Self> {
+        let t_in_len = t_in * T_IN_TX_LEN;
+        let spend_old_len = spends * SPEND_OLD_TX_LEN;
+        let spend_len = spends * SPEND_TX_LEN;
+        let output_len = outputs * OUTPUT_TX_LEN;
+        if txdata.len() != t_in_len + spend_old_len + spend_len + output_len + LENGTH_HASH_DATA {
+            return None;
+        }
+        let (t_in, rest) = txdata.split_at(t_in_len);
+        let (spends, rest) = rest[spend_old_len..].split_at(spend_len);
+        let (outputs, hashdata) = rest.split_at(output_len);
+        Some(Blob {
+            t_in,
+            spends,
+            outputs,
+            hashdata,
+        })
+    }
+
+    fn u32_at(&self, index: usize) -> u32 {
+        let mut b = [0u8; 4];
+        b.copy_from_slice(&self.hashdata[index..index + 4]);
+        u32::from_le_bytes(b)
+    }
+
+    fn value_balance(&self) -> i64 {
+        let mut b = [0u8; 8];
+        b.copy_from_slice(&self.hashdata[INDEX_HASH_VALUEBALANCE..INDEX_HASH_VALUEBALANCE + 8]);
+        i64::from_le_bytes(b)
+    }
+}
+
+fn array<const N: usize>(bytes: &[u8]) -> Option<[u8; N]> {
+    let mut out = [0u8; N];
+    if bytes.len() != N {
+        return None;
+    }
+    out.copy_from_slice(bytes);
+    Some(out)
+}
+
+/// Serialized v4 transaction for the ledger blob `txdata` of a builder that
+/// received `inputs`, with the `TransactionSignatures` taken from the device
+pub fn assemble(
+    txdata: &[u8],
+    inputs: &[(Call, Recorded)],
+    signatures: &Recorded,
+) -> Result<Vec<u8>, ProvenError> {
+    let recorded = |call: Call| -> Vec<&Recorded> {
+        inputs
+            .iter()
+            .filter(|(c, _)| *c == call)
+            .map(|(_, r)| r)
+            .collect()
+    };
+    let t_in = recorded(Call::TransparentInput);
+    let t_out = recorded(Call::TransparentOutput);
+    let spends = recorded(Call::SaplingSpend);
+    let outputs = recorded(Call::SaplingOutput);
+    let blob = Blob::split(txdata, t_in.len(), spends.len(), outputs.len())
+        .ok_or(ProvenError::TxdataMismatch)?;
+
+    let sig_list = |name: &str, len: usize| -> Result<Vec<[u8; 64]>, ProvenError> {
+        let sigs = match signatures.field(name) {
+            Some(Recorded::Array(sigs)) if sigs.len() == len => sigs,
+            _ => return Err(ProvenError::InvalidSignatures),
+        };
+        sigs.iter()
+            .map(|sig| sig.bytes().as_deref().and_then(array::<64>))
+            .collect::<Option<_>>()
+            .ok_or(ProvenError::InvalidSignatures)
+    };
+    let transparent_sigs = sig_list("transparent_sigs", t_in.len())?;
+    let spend_sigs = sig_list("spend_sigs", spends.len())?;
+
+    let mut vout = Vec::with_capacity(t_out.len() * 34);
+    for (index, output) in t_out.iter().enumerate() {
+        let invalid = || ProvenError::InvalidInput {
+            call: Call::TransparentOutput,
+            index,
+        };
+        let value = recorded_value(output).ok_or_else(invalid)?;
+        // the length-prefixed script, as in INITTX
+        let script = output
+            .field("address")
+            .and_then(Recorded::bytes)
+            .ok_or_else(invalid)?;
+        vout.extend_from_slice(&value.to_le_bytes());
+        vout.extend_from_slice(&script);
+    }
+    let outputs_hash = Blake2bParams::new()
+        .hash_length(32)
+        .personal(OUTPUTS_HASH_PERSONALIZATION)
+        .hash(&vout);
+    if outputs_hash.as_bytes()
+        != &blob.hashdata[INDEX_HASH_OUTPUTSHASH..INDEX_HASH_OUTPUTSHASH + 32]
+    {
+        return Err(ProvenError::TxdataMismatch);
+    }
+
+    let sighash = Blake2bParams::new()
+        .hash_length(32)
+        .personal(SIGHASH_PERSONALIZATION)
+        .hash(blob.hashdata);
+    let sighash: [u8; 32] = array(sighash.as_bytes()).ok_or(ProvenError::TxdataMismatch)?;
+
+    let script_sigs = transparent_script_sigs(&blob, &t_in, &transparent_sigs)?;
+    for (spend, sig) in blob.spends.chunks(SPEND_TX_LEN).zip(spend_sigs.iter()) {
+        let rk = array(&spend[INDEX_SPEND_RK..INDEX_SPEND_RK + 32])
+            .ok_or(ProvenError::TxdataMismatch)?;
+        verify_spend_auth_sig(&rk, &spend_auth_message(&rk, &sighash), &Signature(*sig))
+            .map_err(|_| ProvenError::InvalidSignatures)?;
+    }
+    let binding_sig = if spends.is_empty() && outputs.is_empty() {
+        None
+    } else {
+        Some(binding_signature(&blob, &spends, &outputs, &sighash)?)
+    };
+
+    let size = 4 * 4
+        + 8
+        + 5 * 9
+        + script_sigs
+            .iter()
+            .map(|s| PREVOUT_SIZE + 9 + s.len() + 4)
+            .sum::<usize>()
+        + vout.len()
+        + spend_sigs.len() * (SPEND_TX_LEN + 64)
+        + blob.outputs.len()
+        + 64;
+    let mut out = vec![0u8; size];
+    let mut w = ByteWriter::new(&mut out);
+    write_transaction(
+        &mut w,
+        &blob,
+        &script_sigs,
+        &vout,
+        t_out.len(),
+        &spend_sigs,
+        binding_sig,
+    )
+    .map_err(|_| ProvenError::TxdataMismatch)?;
+    let len = w.len();
+    out.truncate(len);
+    Ok(out)
+}
+
+/// `<DER signature || SIGHASH_ALL> <public key>` for every transparent input,
+/// after checking each signature against its key
+fn transparent_script_sigs(
+    blob: &Blob,
+    t_in: &[&Recorded],
+    sigs: &[[u8; 64]],
+) -> Result<Vec<Vec<u8>>, ProvenError> {
+    let secp = Secp256k1::verification_only();
+    let mut script_sigs = Vec::with_capacity(t_in.len());
+    for (index, ((input, tin), sig)) in t_in
+        .iter()
+        .zip(blob.t_in.chunks(T_IN_TX_LEN))
+        .zip(sigs.iter())
+        .enumerate()
+    {
+        let pk = input
+            .field("pk")
+            .and_then(Recorded::bytes)
+            .and_then(|pk| PublicKey::from_slice(&pk).ok())
+            .ok_or(ProvenError::InvalidInput {
+                call: Call::TransparentInput,
+                index,
+            })?;
+        let digest = Blake2bParams::new()
+            .hash_length(32)
+            .personal(SIGHASH_PERSONALIZATION)
+            .to_state()
+            .update(blob.hashdata)
+            .update(tin)
+            .finalize();
+        let msg =
+            Message::from_slice(digest.as_bytes()).map_err(|_| ProvenError::TxdataMismatch)?;
+        let sig =
+            secp256k1::Signature::from_compact(sig).map_err(|_| ProvenError::InvalidSignatures)?;
+        secp.verify(&msg, &sig, &pk)
+            .map_err(|_| ProvenError::InvalidSignatures)?;
+
+        let mut der = sig.serialize_der().to_vec();
+        der.push(SIGHASH_ALL);
+        let pk = pk.serialize();
+        let mut script_sig = Vec::with_capacity(der.len() + pk.len() + 2);
+        script_sig.push(der.len() as u8);
+        script_sig.extend_from_slice(&der);
+        script_sig.push(pk.len() as u8);
+        script_sig.extend_from_slice(&pk);
+        script_sigs.push(script_sig);
+    }
+    Ok(script_sigs)
+}
+
+/// Binding signature with `bsk = sum(rcv_spends) - sum(rcv_outputs)`, once
+/// `bsk` is checked to open the value commitments of the blob
+fn binding_signature(
+    blob: &Blob,
+    spends: &[&Recorded],
+    outputs: &[&Recorded],
+    sighash: &[u8; 32],
+) -> Result<[u8; 64], ProvenError> {
+    let rcv = |call: Call, index: usize, input: &Recorded| {
+        input
+            .field("rcv")
+            .and_then(Recorded::bytes)
+            .as_deref()
+            .and_then(array::<32>)
+            .and_then(|rcv| bytes_to_scalar(&rcv).ok())
+            .ok_or(ProvenError::InvalidInput { call, index })
+    };
+    let cv = |bytes: &[u8]| {
+        array(&bytes[..32])
+            .and_then(|cv| bytes_to_extended(cv).ok())
+            .ok_or(ProvenError::TxdataMismatch)
+    };
+
+    let mut bsk = bytes_to_scalar(&[0u8; 32]).map_err(|_| ProvenError::TxdataMismatch)?;
+    let mut bvk_check = -value_commitment_step1(blob.value_balance().unsigned_abs());
+    if blob.value_balance() < 0 {
+        bvk_check = -bvk_check;
+    }
+    for (index, (input, spend)) in spends
+        .iter()
+        .zip(blob.spends.chunks(SPEND_TX_LEN))
+        .enumerate()
+    {
+        bsk += rcv(Call::SaplingSpend, index, input)?;
+        bvk_check += cv(spend)?;
+    }
+    for (index, (input, output)) in outputs
+        .iter()
+        .zip(blob.outputs.chunks(OUTPUT_TX_LEN))
+        .enumerate()
+    {
+        bsk -= rcv(Call::SaplingOutput, index, input)?;
+        bvk_check -= cv(output)?;
+    }
+
+    let bvk = extended_to_bytes(&VALUE_COMMITMENT_RANDOM_BASE.multiply_bits(&bsk.to_bytes()));
+    if bvk != extended_to_bytes(&bvk_check) {
+        return Err(ProvenError::TxdataMismatch);
+    }
+
+    let mut msg = [0u8; 64];
+    msg[..32].copy_from_slice(&bvk);
+    msg[32..].copy_from_slice(sighash);
+    let r = sign_generate_r(&msg);
+    let rbar = extended_to_bytes(&VALUE_COMMITMENT_RANDOM_BASE.multiply_bits(&r.to_bytes()));
+    let sbar = sign_compute_sbar(&msg, &r, &rbar, &bsk);
+    let mut sig = [0u8; 64];
+    sig[..32].copy_from_slice(&rbar);
+    sig[32..].copy_from_slice(&sbar);
+    Ok(sig)
+}
+
+fn write_transaction(
+    w: &mut ByteWriter,
+    blob: &Blob,
+    script_sigs: &[Vec<u8>],
+    vout: &[u8],
+    t_out: usize,
+    spend_sigs: &[[u8; 64]],
+    binding_sig: Option<[u8; 64]>,
+) -> Result<(), rslib::errors::ParserError> {
+    // header and version group id open the sighash data
+    w.put(&blob.hashdata[..8])?;
+
+    w.put_compact_size(script_sigs.len() as u64)?;
+    for (tin, script_sig) in blob.t_in.chunks(T_IN_TX_LEN).zip(script_sigs.iter()) {
+        w.put(&tin[..PREVOUT_SIZE])?;
+        w.put_var_bytes(script_sig)?;
+        w.put(&tin[INDEX_TIN_SEQ..])?;
+    }
+    // outputs are already value || length-prefixed script
+    w.put_compact_size(t_out as u64)?;
+    w.put(vout)?;
+
+    w.put_u32(blob.u32_at(INDEX_HASH_LOCKTIME))?;
+    w.put_u32(blob.u32_at(INDEX_HASH_EXPIRYHEIGHT))?;
+    w.put(&blob.value_balance().to_le_bytes())?;
+
+    w.put_compact_size(spend_sigs.len() as u64)?;
+    for (spend, sig) in blob.spends.chunks(SPEND_TX_LEN).zip(spend_sigs.iter()) {
+        w.put(spend)?;
+        w.put(sig)?;
+    }
+    w.put_compact_size((blob.outputs.len() / OUTPUT_TX_LEN) as u64)?;
+    w.put(blob.outputs)?;
+    // no JoinSplits
+    w.put_compact_size(0)?;
+    if let Some(sig) = binding_sig {
+        w.put(&sig)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rslib::commitments::value_commitment;
+    use rslib::constants::SPENDING_KEY_BASE;
+    use rslib::redjubjub::{h_star, sign_spend};
+    use zcash_primitives::consensus::BranchId;
+    use zcash_primitives::constants::{
+        SPENDING_KEY_GENERATOR, VALUE_COMMITMENT_RANDOMNESS_GENERATOR,
+        VALUE_COMMITMENT_VALUE_GENERATOR,
+    };
+    use zcash_primitives::redjubjub::PublicKey as RedJubjubKey;
+    use zcash_primitives::transaction::{signature_hash, SignableInput, Transaction, SIGHASH_ALL};
+
+    const ASK: [u8; 32] = scalar(7);
+    const RCV_SPEND: [u8; 32] = scalar(3);
+    const RCV_OUTPUT: [u8; 32] = scalar(5);
+
+    const fn scalar(n: u8) -> [u8; 32] {
+        let mut s = [0u8; 32];
+        s[0] = n;
+        s
+    }
+
+    fn shielded_input(call: Call, rcv: &[u8; 32]) -> (Call, Recorded) {
+        let rcv = rcv.iter().map(|b| Recorded::Number(*b as f64)).collect();
+        (
+            call,
+            Recorded::Object(vec![("rcv".to_string(), Recorded::Array(rcv))]),
+        )
+    }
+
+    fn hash(personal: &[u8; 16], data: &[u8]) -> [u8; 32] {
+        let h = Blake2bParams::new()
+            .hash_length(32)
+            .personal(personal)
+            .hash(data);
+        array(h.as_bytes()).unwrap()
+    }
+
+    /// Ledger blob of one spend of 60000 and one output of 50000, hashed as
+    /// the device does
+    fn txdata() -> Vec<u8> {
+        let mut spend = [0u8; SPEND_TX_LEN];
+        spend[..32].copy_from_slice(&value_commitment(60000, &RCV_SPEND).0);
+        let ask = bytes_to_scalar(&ASK).unwrap();
+        let rk = extended_to_bytes(&SPENDING_KEY_BASE.multiply_bits(&ask.to_bytes()));
+        spend[INDEX_SPEND_RK..INDEX_SPEND_RK + 32].copy_from_slice(&rk);
+        let mut output = [0u8; OUTPUT_TX_LEN];
+        output[..32].copy_from_slice(&value_commitment(50000, &RCV_OUTPUT).0);
+        // any valid point will do for the ephemeral key
+        output[64..96].copy_from_slice(&rk);
+
+        // no transparent inputs, outputs nor JoinSplits
+        let mut hashdata = [0u8; LENGTH_HASH_DATA];
+        hashdata[..4].copy_from_slice(&0x8000_0004u32.to_le_bytes());
+        hashdata[4..8].copy_from_slice(&0x892f_2085u32.to_le_bytes());
+        hashdata[8..40].copy_from_slice(&hash(b"ZcashPrevoutHash", &[]));
+        hashdata[40..72].copy_from_slice(&hash(b"ZcashSequencHash", &[]));
+        hashdata[INDEX_HASH_OUTPUTSHASH..INDEX_HASH_OUTPUTSHASH + 32]
+            .copy_from_slice(&hash(OUTPUTS_HASH_PERSONALIZATION, &[]));
+        hashdata[136..168].copy_from_slice(&hash(b"ZcashSSpendsHash", &spend));
+        hashdata[168..200].copy_from_slice(&hash(b"ZcashSOutputHash", &output));
+        hashdata[INDEX_HASH_EXPIRYHEIGHT..INDEX_HASH_EXPIRYHEIGHT + 4]
+            .copy_from_slice(&1_000_040u32.to_le_bytes());
+        hashdata[INDEX_HASH_VALUEBALANCE..INDEX_HASH_VALUEBALANCE + 8]
+            .copy_from_slice(&10000i64.to_le_bytes());
+        hashdata[216..].copy_from_slice(&SIGHASH_ALL.to_le_bytes());
+
+        let mut txdata = vec![0u8; SPEND_OLD_TX_LEN];
+        txdata.extend_from_slice(&spend);
+        txdata.extend_from_slice(&output);
+        txdata.extend_from_slice(&hashdata);
+        txdata
+    }
+
+    fn sighash(txdata: &[u8]) -> [u8; 32] {
+        hash(
+            SIGHASH_PERSONALIZATION,
+            &txdata[txdata.len() - LENGTH_HASH_DATA..],
+        )
+    }
+
+    fn signatures(spend_sig: &[u8; 64]) -> Recorded {
+        Recorded::Object(vec![
+            ("transparent_sigs".to_string(), Recorded::Array(vec![])),
+            (
+                "spend_sigs".to_string(),
+                Recorded::Array(vec![Recorded::Bytes(spend_sig.to_vec())]),
+            ),
+        ])
+    }
+
+    fn inputs() -> Vec<(Call, Recorded)> {
+        vec![
+            shielded_input(Call::SaplingSpend, &RCV_SPEND),
+            shielded_input(Call::SaplingOutput, &RCV_OUTPUT),
+        ]
+    }
+
+    #[test]
+    fn test_assemble_keeps_proofs_and_signs_binding() {
+        let txdata = txdata();
+        let sighash = sighash(&txdata);
+        let spend_sig = sign_spend(&ASK, &sighash).unwrap();
+        let tx = assemble(&txdata, &inputs(), &signatures(&spend_sig.0)).unwrap();
+
+        // header, no transparent parts, locktime, expiry, value balance
+        assert_eq!(&tx[..8], &txdata[txdata.len() - LENGTH_HASH_DATA..][..8]);
+        assert_eq!(&tx[8..10], &[0, 0]);
+        assert_eq!(&tx[14..18], &1_000_040u32.to_le_bytes());
+        assert_eq!(&tx[18..26], &10000i64.to_le_bytes());
+        // the spend and output of the blob, proofs included
+        assert_eq!(tx[26], 1);
+        assert_eq!(&tx[27..27 + SPEND_TX_LEN], &txdata[40..40 + SPEND_TX_LEN]);
+        assert_eq!(
+            &tx[27 + SPEND_TX_LEN..27 + SPEND_TX_LEN + 64],
+            &spend_sig.0[..]
+        );
+        let outputs = 27 + SPEND_TX_LEN + 64;
+        assert_eq!(tx[outputs], 1);
+        assert_eq!(
+            &tx[outputs + 1..outputs + 1 + OUTPUT_TX_LEN],
+            &txdata[40 + SPEND_TX_LEN..40 + SPEND_TX_LEN + OUTPUT_TX_LEN]
+        );
+        assert_eq!(tx[outputs + 1 + OUTPUT_TX_LEN], 0);
+        assert_eq!(tx.len(), outputs + 2 + OUTPUT_TX_LEN + 64);
+
+        // binding signature under bvk = cv_spend - cv_output - 10000 * V
+        let bsk = bytes_to_scalar(&RCV_SPEND).unwrap() - bytes_to_scalar(&RCV_OUTPUT).unwrap();
+        let bvk = VALUE_COMMITMENT_RANDOM_BASE.multiply_bits(&bsk.to_bytes());
+        let sig = &tx[tx.len() - 64..];
+        let mut msg = [0u8; 64];
+        msg[..32].copy_from_slice(&extended_to_bytes(&bvk));
+        msg[32..].copy_from_slice(&sighash);
+        let rbar = array::<32>(&sig[..32]).unwrap();
+        let sbar = bytes_to_scalar(&array(&sig[32..]).unwrap()).unwrap();
+        let lhs = VALUE_COMMITMENT_RANDOM_BASE.multiply_bits(&sbar.to_bytes());
+        let rhs = bytes_to_extended(rbar).unwrap() + bvk * h_star(&rbar, &msg);
+        assert_eq!(extended_to_bytes(&lhs), extended_to_bytes(&rhs));
+    }
+
+    #[test]
+    fn test_assembled_transaction_verifies() {
+        let txdata = txdata();
+        let sighash = sighash(&txdata);
+        let spend_sig = sign_spend(&ASK, &sighash).unwrap();
+        let raw = assemble(&txdata, &inputs(), &signatures(&spend_sig.0)).unwrap();
+
+        let tx = Transaction::read(&raw[..]).unwrap();
+        assert_eq!(
+            signature_hash(&tx, BranchId::Sapling, SIGHASH_ALL, SignableInput::Shielded),
+            sighash.to_vec()
+        );
+
+        let spend = &tx.shielded_spends[0];
+        let rk = array(&txdata[40 + INDEX_SPEND_RK..40 + INDEX_SPEND_RK + 32]).unwrap();
+        assert!(spend.rk.verify(
+            &spend_auth_message(&rk, &sighash),
+            spend.spend_auth_sig.as_ref().unwrap(),
+            SPENDING_KEY_GENERATOR
+        ));
+
+        let balance = VALUE_COMMITMENT_VALUE_GENERATOR * jubjub::Fr::from(10000u64);
+        let bvk = spend.cv - tx.shielded_outputs[0].cv - jubjub::ExtendedPoint::from(balance);
+        let mut msg = [0u8; 64];
+        msg[..32].copy_from_slice(&jubjub::AffinePoint::from(bvk).to_bytes());
+        msg[32..].copy_from_slice(&sighash);
+        assert!(RedJubjubKey(bvk).verify(
+            &msg,
+            tx.binding_sig.as_ref().unwrap(),
+            VALUE_COMMITMENT_RANDOMNESS_GENERATOR
+        ));
+    }
+
+    #[test]
+    fn test_assemble_rejects_foreign_signatures() {
+        let txdata = txdata();
+        let spend_sig = sign_spend(&ASK, &[1u8; 32]).unwrap();
+        assert_eq!(
+            assemble(&txdata, &inputs(), &signatures(&spend_sig.0)),
+            Err(ProvenError::InvalidSignatures)
+        );
+        assert_eq!(
+            assemble(&txdata, &inputs(), &Recorded::Object(vec![])),
+            Err(ProvenError::InvalidSignatures)
+        );
+    }
+
+    #[test]
+    fn test_assemble_rejects_other_inputs() {
+        let txdata = txdata();
+        let spend_sig = sign_spend(&ASK, &sighash(&txdata)).unwrap();
+        // the rcvs do not open the value commitments
+        let swapped = vec![
+            shielded_input(Call::SaplingSpend, &RCV_OUTPUT),
+            shielded_input(Call::SaplingOutput, &RCV_SPEND),
+        ];
+        assert_eq!(
+            assemble(&txdata, &swapped, &signatures(&spend_sig.0)),
+            Err(ProvenError::TxdataMismatch)
+        );
+        // a blob for another number of outputs
+        assert_eq!(
+            assemble(&txdata, &inputs()[..1], &signatures(&spend_sig.0)),
+            Err(ProvenError::TxdataMismatch)
+        );
+    }
+}
@@ -0,0 +1,583 @@
+//! Save and resume the state of a builder.
+//!
+//! A session records the fee, the fee rule and change address that `build`
+//! settles it with, and every input handed to the builder (including the
+//! rcv/alpha/rseed randomness extracted from the device) exactly as JS passed
+//! it, so it can be replayed into a fresh builder in another process.
+//!
+//! The blob is `MAGIC || VERSION || body || tag`, where `tag` is a keyed
+//! BLAKE2b-256 over everything before it. The key is chosen by the caller.
+//!
+//! Once built, the session also carries the ledger blob, which holds the
+//! proofs. A resumed builder signs and finalizes that transaction (see
+//! `proven`) instead of building again, which would make new proofs and
+//! invalidate signatures taken over the first ones.
+
+use std::fmt;
+
+use neon::prelude::*;
+
+use crate::errors::BridgeError;
+use crate::fees::FeeRule;
+
+const MAGIC: &[u8; 4] = b"ZBSN";
+const VERSION: u8 = 3;
+const TAG_PERSONALIZATION: &[u8; 16] = b"ZcashBuilderSess";
+const TAG_SIZE: usize = 32;
+const MAX_DEPTH: usize = 16;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SessionError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    BadTag,
+    Malformed,
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::BadMagic => write!(f, "not a builder session"),
+            SessionError::UnsupportedVersion(v) => {
+                write!(f, "unsupported builder session version {}", v)
+            }
+            SessionError::BadTag => write!(f, "builder session integrity check failed"),
+            SessionError::Malformed => write!(f, "malformed builder session"),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+/// Builder method a recorded input was passed to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Call {
+    TransparentInput = 0,
+    TransparentOutput = 1,
+    SaplingSpend = 2,
+    SaplingOutput = 3,
+}
+
+impl Call {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Call::TransparentInput => "transparent_input",
+            Call::TransparentOutput => "transparent_output",
+            Call::SaplingSpend => "sapling_spend",
+            Call::SaplingOutput => "sapling_output",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            Call::TransparentInput,
+            Call::TransparentOutput,
+            Call::SaplingSpend,
+            Call::SaplingOutput,
+        ]
+        .iter()
+        .copied()
+        .find(|call| call.name() == name)
+    }
+
+    pub(crate) fn from_u8(tag: u8) -> Option<Self> {
+        match tag {
+            0 => Some(Call::TransparentInput),
+            1 => Some(Call::TransparentOutput),
+            2 => Some(Call::SaplingSpend),
+            3 => Some(Call::SaplingOutput),
+            _ => None,
+        }
+    }
+}
+
+/// Copy of a JS argument, keeping buffers apart from strings so that
+/// replaying it deserializes exactly like the original call
+#[derive(Clone, Debug, PartialEq)]
+pub enum Recorded {
+    Undefined,
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    Array(Vec<Recorded>),
+    Object(Vec<(String, Recorded)>),
+}
+
+impl Recorded {
+    pub fn from_js<'a, C: Context<'a>>(cx: &mut C, value: Handle<JsValue>) -> NeonResult<Self> {
+        Self::from_js_at(cx, value, 0)
+    }
+
+    fn from_js_at<'a, C: Context<'a>>(
+        cx: &mut C,
+        value: Handle<JsValue>,
+        depth: usize,
+    ) -> NeonResult<Self> {
+        if depth > MAX_DEPTH {
+            return BridgeError::invalid_argument("builder input is nested too deeply").throw(cx);
+        }
+        if value.is_a::<JsUndefined>() {
+            return Ok(Recorded::Undefined);
+        }
+        if value.is_a::<JsNull>() {
+            return Ok(Recorded::Null);
+        }
+        if let Ok(b) = value.downcast::<JsBoolean>() {
+            return Ok(Recorded::Bool(b.value()));
+        }
+        if let Ok(n) = value.downcast::<JsNumber>() {
+            return Ok(Recorded::Number(n.value()));
+        }
+        if let Ok(s) = value.downcast::<JsString>() {
+            return Ok(Recorded::String(s.value()));
+        }
+        if let Ok(buffer) = value.downcast::<JsBuffer>() {
+            let bytes = cx.borrow(&buffer, |data| data.as_slice::<u8>().to_vec());
+            return Ok(Recorded::Bytes(bytes));
+        }
+        if let Ok(array) = value.downcast::<JsArray>() {
+            let mut items = Vec::new();
+            for item in array.to_vec(cx)? {
+                items.push(Self::from_js_at(cx, item, depth + 1)?);
+            }
+            return Ok(Recorded::Array(items));
+        }
+        if value.is_a::<JsFunction>() {
+            return BridgeError::invalid_argument("builder input cannot contain functions")
+                .throw(cx);
+        }
+        if let Ok(object) = value.downcast::<JsObject>() {
+            let mut fields = Vec::new();
+            for key in object.get_own_property_names(cx)?.to_vec(cx)? {
+                let name = key.downcast_or_throw::<JsString, _>(cx)?.value();
+                let field = object.get(cx, key)?;
+                fields.push((name, Self::from_js_at(cx, field, depth + 1)?));
+            }
+            return Ok(Recorded::Object(fields));
+        }
+        BridgeError::invalid_argument("unsupported builder input").throw(cx)
+    }
+
+    /// Field `name` of an object
+    pub fn field(&self, name: &str) -> Option<&Recorded> {
+        match self {
+            Recorded::Object(fields) => fields.iter().find(|(n, _)| n == name).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Bytes of a buffer, an array of numbers or a hex string
+    pub fn bytes(&self) -> Option<Vec<u8>> {
+        match self {
+            Recorded::Bytes(bytes) => Some(bytes.clone()),
+            Recorded::Array(items) => items
+                .iter()
+                .map(|item| match item {
+                    Recorded::Number(n) if *n >= 0.0 && *n <= 255.0 && n.fract() == 0.0 => {
+                        Some(*n as u8)
+                    }
+                    _ => None,
+                })
+                .collect(),
+            Recorded::String(hex) if hex.len() % 2 == 0 => (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                .collect(),
+            _ => None,
+        }
+    }
+
+    pub fn to_js<'a, C: Context<'a>>(&self, cx: &mut C) -> JsResult<'a, JsValue> {
+        Ok(match self {
+            Recorded::Undefined => cx.undefined().upcast(),
+            Recorded::Null => cx.null().upcast(),
+            Recorded::Bool(b) => cx.boolean(*b).upcast(),
+            Recorded::Number(n) => cx.number(*n).upcast(),
+            Recorded::String(s) => cx.string(s).upcast(),
+            Recorded::Bytes(bytes) => {
+                let mut buffer = JsBuffer::new(cx, bytes.len() as u32)?;
+                cx.borrow_mut(&mut buffer, |data| {
+                    data.as_mut_slice::<u8>().copy_from_slice(bytes)
+                });
+                buffer.upcast()
+            }
+            Recorded::Array(items) => {
+                let array = JsArray::new(cx, items.len() as u32);
+                for (i, item) in items.iter().enumerate() {
+                    let value = item.to_js(cx)?;
+                    array.set(cx, i as u32, value)?;
+                }
+                array.upcast()
+            }
+            Recorded::Object(fields) => {
+                let object = cx.empty_object();
+                for (name, field) in fields {
+                    let value = field.to_js(cx)?;
+                    object.set(cx, name.as_str(), value)?;
+                }
+                object.upcast()
+            }
+        })
+    }
+
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Recorded::Undefined => out.push(0),
+            Recorded::Null => out.push(1),
+            Recorded::Bool(b) => {
+                out.push(2);
+                out.push(*b as u8);
+            }
+            Recorded::Number(n) => {
+                out.push(3);
+                out.extend_from_slice(&n.to_le_bytes());
+            }
+            Recorded::String(s) => {
+                out.push(4);
+                encode_bytes(s.as_bytes(), out);
+            }
+            Recorded::Bytes(bytes) => {
+                out.push(5);
+                encode_bytes(bytes, out);
+            }
+            Recorded::Array(items) => {
+                out.push(6);
+                out.extend_from_slice(&(items.len() as u32).to_le_bytes());
+                for item in items {
+                    item.encode(out);
+                }
+            }
+            Recorded::Object(fields) => {
+                out.push(7);
+                out.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+                for (name, field) in fields {
+                    encode_bytes(name.as_bytes(), out);
+                    field.encode(out);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn decode(reader: &mut Reader, depth: usize) -> Result<Self, SessionError> {
+        if depth > MAX_DEPTH {
+            return Err(SessionError::Malformed);
+        }
+        Ok(match reader.byte()? {
+            0 => Recorded::Undefined,
+            1 => Recorded::Null,
+            2 => match reader.byte()? {
+                0 => Recorded::Bool(false),
+                1 => Recorded::Bool(true),
+                _ => return Err(SessionError::Malformed),
+            },
+            3 => {
+                let mut n = [0u8; 8];
+                n.copy_from_slice(reader.take(8)?);
+                Recorded::Number(f64::from_le_bytes(n))
+            }
+            4 => Recorded::String(reader.string()?),
+            5 => Recorded::Bytes(reader.bytes()?.to_vec()),
+            6 => {
+                let len = reader.u32()?;
+                let mut items = Vec::new();
+                for _ in 0..len {
+                    items.push(Recorded::decode(reader, depth + 1)?);
+                }
+                Recorded::Array(items)
+            }
+            7 => {
+                let len = reader.u32()?;
+                let mut fields = Vec::new();
+                for _ in 0..len {
+                    let name = reader.string()?;
+                    fields.push((name, Recorded::decode(reader, depth + 1)?));
+                }
+                Recorded::Object(fields)
+            }
+            _ => return Err(SessionError::Malformed),
+        })
+    }
+}
+
+/// Everything needed to rebuild a builder: its fee and how `build` settles
+/// it, the inputs it received and, once built, its ledger blob
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Session {
+    pub fee: u64,
+    pub fee_rule: FeeRule,
+    /// Change address, added as an output once its value is known
+    pub change: Option<(Call, Recorded)>,
+    pub calls: Vec<(Call, Recorded)>,
+    pub txdata: Option<Vec<u8>>,
+}
+
+impl Session {
+    pub fn new(fee: u64) -> Self {
+        Session {
+            fee,
+            fee_rule: FeeRule::Fixed(fee),
+            change: None,
+            calls: Vec::new(),
+            txdata: None,
+        }
+    }
+
+    pub fn record(&mut self, call: Call, input: Recorded) {
+        self.calls.push((call, input));
+    }
+
+    pub fn to_blob(&self, key: &[u8; 32]) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.push(VERSION);
+        out.extend_from_slice(&self.fee.to_le_bytes());
+        encode_fee_rule(self.fee_rule, &mut out);
+        encode_change(self.change.as_ref(), &mut out);
+        out.extend_from_slice(&(self.calls.len() as u32).to_le_bytes());
+        for (call, input) in &self.calls {
+            out.push(*call as u8);
+            input.encode(&mut out);
+        }
+        encode_optional_bytes(self.txdata.as_deref(), &mut out);
+        let tag = session_tag(key, &out);
+        out.extend_from_slice(&tag);
+        out
+    }
+
+    pub fn from_blob(blob: &[u8], key: &[u8; 32]) -> Result<Self, SessionError> {
+        if blob.len() < MAGIC.len() + 1 + TAG_SIZE || &blob[..MAGIC.len()] != MAGIC {
+            return Err(SessionError::BadMagic);
+        }
+        let version = blob[MAGIC.len()];
+        if version != VERSION {
+            return Err(SessionError::UnsupportedVersion(version));
+        }
+        let (data, tag) = blob.split_at(blob.len() - TAG_SIZE);
+        if !constant_time_eq(&session_tag(key, data), tag) {
+            return Err(SessionError::BadTag);
+        }
+
+        let mut reader = Reader(&data[MAGIC.len() + 1..]);
+        let mut fee = [0u8; 8];
+        fee.copy_from_slice(reader.take(8)?);
+        let mut session = Session::new(u64::from_le_bytes(fee));
+        session.fee_rule = decode_fee_rule(&mut reader)?;
+        session.change = decode_change(&mut reader)?;
+        for _ in 0..reader.u32()? {
+            let call = Call::from_u8(reader.byte()?).ok_or(SessionError::Malformed)?;
+            session.record(call, Recorded::decode(&mut reader, 0)?);
+        }
+        session.txdata = decode_optional_bytes(&mut reader)?;
+        if !reader.0.is_empty() {
+            return Err(SessionError::Malformed);
+        }
+        Ok(session)
+    }
+}
+
+fn session_tag(key: &[u8; 32], data: &[u8]) -> [u8; TAG_SIZE] {
+    let h = blake2b_simd::Params::new()
+        .hash_length(TAG_SIZE)
+        .key(key)
+        .personal(TAG_PERSONALIZATION)
+        .hash(data);
+    let mut out = [0u8; TAG_SIZE];
+    out.copy_from_slice(h.as_bytes());
+    out
+}
+
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub(crate) fn encode_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(bytes);
+}
+
+pub(crate) fn encode_optional_bytes(bytes: Option<&[u8]>, out: &mut Vec<u8>) {
+    match bytes {
+        Some(bytes) => {
+            out.push(1);
+            encode_bytes(bytes, out);
+        }
+        None => out.push(0),
+    }
+}
+
+pub(crate) fn decode_optional_bytes(reader: &mut Reader) -> Result<Option<Vec<u8>>, SessionError> {
+    match reader.byte()? {
+        0 => Ok(None),
+        1 => Ok(Some(reader.bytes()?.to_vec())),
+        _ => Err(SessionError::Malformed),
+    }
+}
+
+pub(crate) fn encode_fee_rule(rule: FeeRule, out: &mut Vec<u8>) {
+    match rule {
+        FeeRule::Fixed(fee) => {
+            out.push(0);
+            out.extend_from_slice(&fee.to_le_bytes());
+        }
+        FeeRule::Zip317 => out.push(1),
+    }
+}
+
+pub(crate) fn decode_fee_rule(reader: &mut Reader) -> Result<FeeRule, SessionError> {
+    match reader.byte()? {
+        0 => {
+            let mut fee = [0u8; 8];
+            fee.copy_from_slice(reader.take(8)?);
+            Ok(FeeRule::Fixed(u64::from_le_bytes(fee)))
+        }
+        1 => Ok(FeeRule::Zip317),
+        _ => Err(SessionError::Malformed),
+    }
+}
+
+pub(crate) fn encode_change(change: Option<&(Call, Recorded)>, out: &mut Vec<u8>) {
+    match change {
+        Some((call, address)) => {
+            out.push(1);
+            out.push(*call as u8);
+            address.encode(out);
+        }
+        None => out.push(0),
+    }
+}
+
+pub(crate) fn decode_change(reader: &mut Reader) -> Result<Option<(Call, Recorded)>, SessionError> {
+    match reader.byte()? {
+        0 => Ok(None),
+        1 => {
+            let call = Call::from_u8(reader.byte()?).ok_or(SessionError::Malformed)?;
+            Ok(Some((call, Recorded::decode(reader, 0)?)))
+        }
+        _ => Err(SessionError::Malformed),
+    }
+}
+
+pub(crate) struct Reader<'a>(pub(crate) &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub(crate) fn take(&mut self, n: usize) -> Result<&'a [u8], SessionError> {
+        if self.0.len() < n {
+            return Err(SessionError::Malformed);
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8, SessionError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, SessionError> {
+        let mut n = [0u8; 4];
+        n.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(n))
+    }
+
+    pub(crate) fn bytes(&mut self) -> Result<&'a [u8], SessionError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String, SessionError> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| SessionError::Malformed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [9u8; 32];
+
+    fn session() -> Session {
+        let mut session = Session::new(1000);
+        session.record(
+            Call::TransparentOutput,
+            Recorded::Object(vec![
+                (
+                    "address".to_string(),
+                    Recorded::String("1976a914".to_string()),
+                ),
+                ("value".to_string(), Recorded::Number(50000.0)),
+            ]),
+        );
+        session.record(
+            Call::SaplingOutput,
+            Recorded::Object(vec![("rcv".to_string(), Recorded::Bytes(vec![1, 2, 3]))]),
+        );
+        session.txdata = Some(vec![0xab; 220]);
+        session
+    }
+
+    fn transparent_change() -> (Call, Recorded) {
+        (
+            Call::TransparentOutput,
+            Recorded::Object(vec![(
+                "address".to_string(),
+                Recorded::String("1976a914".to_string()),
+            )]),
+        )
+    }
+
+    #[test]
+    fn test_recorded_bytes() {
+        let bytes = vec![0x19, 0x76, 0xa9];
+        assert_eq!(Recorded::Bytes(bytes.clone()).bytes(), Some(bytes.clone()));
+        assert_eq!(
+            Recorded::String("1976a9".to_string()).bytes(),
+            Some(bytes.clone())
+        );
+        let numbers = bytes.iter().map(|b| Recorded::Number(*b as f64)).collect();
+        assert_eq!(Recorded::Array(numbers).bytes(), Some(bytes));
+        assert_eq!(Recorded::String("197".to_string()).bytes(), None);
+        assert_eq!(Recorded::Array(vec![Recorded::Number(256.0)]).bytes(), None);
+    }
+
+    #[test]
+    fn test_session_roundtrip_keeps_txdata() {
+        let session = session();
+        let blob = session.to_blob(&KEY);
+        assert_eq!(Session::from_blob(&blob, &KEY), Ok(session));
+
+        let unbuilt = Session::new(1000);
+        assert_eq!(
+            Session::from_blob(&unbuilt.to_blob(&KEY), &KEY),
+            Ok(unbuilt)
+        );
+    }
+
+    #[test]
+    fn test_session_roundtrip_keeps_fee_rule_and_change() {
+        let mut session = session();
+        session.txdata = None;
+        session.fee = 0;
+        session.fee_rule = FeeRule::Zip317;
+        session.change = Some(transparent_change());
+        let blob = session.to_blob(&KEY);
+        assert_eq!(Session::from_blob(&blob, &KEY), Ok(session));
+    }
+
+    #[test]
+    fn test_session_rejects_tampered_blob() {
+        let mut blob = session().to_blob(&KEY);
+        let txdata = blob.len() - TAG_SIZE - 1;
+        blob[txdata] ^= 1;
+        assert_eq!(Session::from_blob(&blob, &KEY), Err(SessionError::BadTag));
+    }
+
+    #[test]
+    fn test_session_rejects_wrong_key() {
+        let blob = session().to_blob(&KEY);
+        assert_eq!(
+            Session::from_blob(&blob, &[8u8; 32]),
+            Err(SessionError::BadTag)
+        );
+    }
+}
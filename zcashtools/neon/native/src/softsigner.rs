@@ -0,0 +1,1184 @@
+//! In-process signer speaking the device's shielded transaction protocol.
+//!
+//! `SoftwareSigner` keeps the same state machine as the Ledger app for
+//! `INS_INIT_TX`, `INS_EXTRACT_SPEND`, `INS_EXTRACT_OUTPUT`,
+//! `INS_CHECKANDSIGN`, `INS_EXTRACT_SPENDSIG` and `INS_EXTRACT_TRANSSIG`, and
+//! answers with the same payloads and return codes, so the builder bridge can
+//! be exercised end-to-end without a device or emulator. Keys are held in
+//! memory: this is for tests only.
+
+use std::convert::TryFrom;
+
+use blake2b_simd::Params as Blake2bParams;
+use hmac::{Hmac, Mac, NewMac};
+use ripemd160::Ripemd160;
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+use sha2::{Digest, Sha256, Sha512};
+
+use rslib::commitments::{note_commitment_cmu, note_commitment_full, nullifier, value_commitment};
+use rslib::constants::ENC_CIPHERTEXT_SIZE;
+use rslib::fee::fee_is_supported;
+use rslib::note_encryption::{
+    ciphertext_prefix_matches, enc_ciphertext_matches_output, epk, note_encryption_key,
+};
+use rslib::redjubjub::{random_scalar, randomized_secret_from_sk, rk, sign_spend};
+use rslib::types::{Diversifier, SpendingKey};
+use rslib::zeccrypto::prf_ock;
+use rslib::zip212::{Network, Zip212Policy};
+use rslib::zip32::{derive_ask_nsk, derive_proof_key};
+
+const T_IN_INPUT_LEN: usize = 54;
+const T_OUT_INPUT_LEN: usize = 34;
+const SPEND_INPUT_LEN: usize = 55;
+const OUTPUT_INPUT_LEN: usize = 85;
+
+const T_IN_TX_LEN: usize = 74;
+const SPEND_OLD_TX_LEN: usize = 40;
+const SPEND_TX_LEN: usize = 320;
+const OUTPUT_TX_LEN: usize = 948;
+const LENGTH_HASH_DATA: usize = 220;
+
+const PREVOUT_SIZE: usize = 36;
+const INDEX_TIN_SCRIPT: usize = 36;
+const INDEX_TIN_VALUE: usize = 62;
+const INDEX_TIN_SEQ: usize = 70;
+const INDEX_SPEND_OLD_NOTEPOS: usize = 32;
+const INDEX_SPEND_NF: usize = 64;
+const INDEX_SPEND_RK: usize = 96;
+const INDEX_OUTPUT_NOTECMT: usize = 32;
+const INDEX_OUTPUT_EPK: usize = 64;
+const INDEX_OUTPUT_ENC: usize = 96;
+const INDEX_OUTPUT_OUT: usize = 676;
+
+const INDEX_HASH_PREVOUTSHASH: usize = 8;
+const INDEX_HASH_SEQUENCEHASH: usize = 40;
+const INDEX_HASH_OUTPUTSHASH: usize = 72;
+const INDEX_HASH_JOINSPLITSHASH: usize = 104;
+const INDEX_HASH_SHIELDEDSPENDHASH: usize = 136;
+const INDEX_HASH_SHIELDEDOUTPUTHASH: usize = 168;
+const INDEX_HASH_EXPIRYHEIGHT: usize = 204;
+const INDEX_HASH_VALUEBALANCE: usize = 208;
+
+const LIST_SIZE: usize = 5;
+const SCRIPT_SIZE: usize = 26;
+const OVK_SET_SIZE: usize = 33;
+
+const PREVOUTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashPrevoutHash";
+const SEQUENCE_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashSequencHash";
+const OUTPUTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashOutputsHash";
+const SHIELDED_SPENDS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashSSpendsHash";
+const SHIELDED_OUTPUTS_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashSOutputHash";
+// "ZcashSigHash" || CONSENSUS_BRANCH_ID (Sapling 0x76b809bb, little endian),
+// as the device app signs
+const SIGHASH_PERSONALIZATION: &[u8; 16] = b"ZcashSigHash\xbb\x09\xb8\x76";
+
+/// APDU status words returned by the device for the transaction flow
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u16)]
+pub enum ReturnCode {
+    Ok = 0x9000,
+    DataInvalid = 0x6984,
+    ExtractTransactionFail = 0x6989,
+    UnprocessedTx = 0x6991,
+    PrevoutInvalid = 0x6992,
+    SequenceInvalid = 0x6993,
+    OutputsInvalid = 0x6994,
+    JoinsplitInvalid = 0x6995,
+    SpendInvalid = 0x6996,
+    OutputContentInvalid = 0x6997,
+    EncryptionInvalid = 0x6998,
+    CheckSignTrFail = 0x6999,
+    SignSpendFail = 0x69A0,
+    BadValueBalance = 0x69A1,
+}
+
+impl ReturnCode {
+    pub fn code(self) -> u16 {
+        self as u16
+    }
+
+    /// Same wording as the `ledger-zcash` JS client
+    pub fn message(self) -> &'static str {
+        match self {
+            ReturnCode::Ok => "No errors",
+            ReturnCode::DataInvalid => "Data is invalid",
+            ReturnCode::ExtractTransactionFail => "Failed to extract transaction",
+            ReturnCode::UnprocessedTx => "Transaction has not been processed",
+            ReturnCode::PrevoutInvalid => "Prevout hash is invalid",
+            ReturnCode::SequenceInvalid => "Sequence hash is invalid",
+            ReturnCode::OutputsInvalid => "Outputs hash is invalid",
+            ReturnCode::JoinsplitInvalid => "Joinsplit hash is invalid",
+            ReturnCode::SpendInvalid => "Shielded spend data is invalid",
+            ReturnCode::OutputContentInvalid => "Shielded output data is invalid",
+            ReturnCode::EncryptionInvalid => "Note encryption is invalid",
+            ReturnCode::CheckSignTrFail => "Failed to check or sign transparent input",
+            ReturnCode::SignSpendFail => "Failed to sign shielded spend",
+            ReturnCode::BadValueBalance => "Value balance is invalid",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    Initial,
+    ProcessedInputs,
+    ProcessedSpendExtractions,
+    ProcessedAllExtractions,
+    SignedTx,
+}
+
+struct TransparentInput {
+    path: [u32; 5],
+    script: [u8; SCRIPT_SIZE],
+    value: u64,
+}
+
+struct TransparentOutput {
+    script: [u8; SCRIPT_SIZE],
+    value: u64,
+}
+
+struct SpendItem {
+    path: u32,
+    div: Diversifier,
+    pkd: [u8; 32],
+    value: u64,
+    rcv: [u8; 32],
+    alpha: [u8; 32],
+}
+
+struct OutputItem {
+    div: Diversifier,
+    pkd: [u8; 32],
+    value: u64,
+    memotype: u8,
+    // 0x01 || ovk, or 0x00 || hash seed when no ovk was given
+    ovk: [u8; OVK_SET_SIZE],
+    rcv: [u8; 32],
+    rseed: [u8; 32],
+}
+
+/// Software stand-in for the Ledger app's transaction signing state machine
+pub struct SoftwareSigner {
+    sapling_seed: SpendingKey,
+    transparent_seed: Vec<u8>,
+    state: State,
+    t_in: Vec<TransparentInput>,
+    t_out: Vec<TransparentOutput>,
+    spends: Vec<SpendItem>,
+    outputs: Vec<OutputItem>,
+    spends_extracted: usize,
+    outputs_extracted: usize,
+    transparent_sigs: Vec<[u8; 64]>,
+    spend_sigs: Vec<[u8; 64]>,
+    transparent_sigs_extracted: usize,
+    spend_sigs_extracted: usize,
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    let mut b = [0u8; 4];
+    b.copy_from_slice(&bytes[..4]);
+    u32::from_le_bytes(b)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut b = [0u8; 8];
+    b.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(b)
+}
+
+fn read_array32(bytes: &[u8]) -> [u8; 32] {
+    let mut out = [0u8; 32];
+    out.copy_from_slice(&bytes[..32]);
+    out
+}
+
+fn random_fr() -> [u8; 32] {
+    random_scalar().to_bytes()
+}
+
+fn random_bytes32() -> [u8; 32] {
+    let mut out = [0u8; 32];
+    getrandom::getrandom(&mut out).expect("system randomness unavailable");
+    out
+}
+
+fn blake2b256(personalization: &[u8; 16], data: &[u8]) -> [u8; 32] {
+    let h = Blake2bParams::new()
+        .hash_length(32)
+        .personal(personalization)
+        .hash(data);
+    read_array32(h.as_bytes())
+}
+
+fn sha256(data: &[u8]) -> [u8; 32] {
+    read_array32(&Sha256::digest(data))
+}
+
+fn check(condition: bool, err: ReturnCode) -> Result<(), ReturnCode> {
+    if condition {
+        Ok(())
+    } else {
+        Err(err)
+    }
+}
+
+/// BIP32 secp256k1 key for `path`, as the device derives transparent keys
+fn derive_transparent_key(seed: &[u8], path: &[u32; 5]) -> Option<SecretKey> {
+    let secp = Secp256k1::signing_only();
+    let mut mac = Hmac::<Sha512>::new_from_slice(b"Bitcoin seed").ok()?;
+    mac.update(seed);
+    let i = mac.finalize().into_bytes();
+    let mut key = SecretKey::from_slice(&i[..32]).ok()?;
+    let mut chain_code = read_array32(&i[32..]);
+
+    for &index in path.iter() {
+        let mut mac = Hmac::<Sha512>::new_from_slice(&chain_code).ok()?;
+        if index & 0x8000_0000 != 0 {
+            mac.update(&[0u8]);
+            mac.update(&key[..]);
+        } else {
+            mac.update(&PublicKey::from_secret_key(&secp, &key).serialize());
+        }
+        mac.update(&index.to_be_bytes());
+        let i = mac.finalize().into_bytes();
+        key.add_assign(&i[..32]).ok()?;
+        chain_code = read_array32(&i[32..]);
+    }
+    Some(key)
+}
+
+/// P2PKH script, length prefixed, for a compressed public key
+fn pubkey_to_script(pubkey: &[u8; 33]) -> [u8; SCRIPT_SIZE] {
+    let hash = Ripemd160::digest(&Sha256::digest(pubkey));
+    let mut script = [0u8; SCRIPT_SIZE];
+    script[..4].copy_from_slice(&[0x19, 0x76, 0xa9, 0x14]);
+    script[4..24].copy_from_slice(&hash);
+    script[24] = 0x88;
+    script[25] = 0xac;
+    script
+}
+
+impl SoftwareSigner {
+    /// `sapling_seed` is the 32-byte ZIP32 seed the device derives from its
+    /// ed25519 path, `transparent_seed` the BIP39 seed used for BIP32 keys
+    pub fn new(sapling_seed: [u8; 32], transparent_seed: &[u8]) -> Self {
+        SoftwareSigner {
+            sapling_seed: SpendingKey(sapling_seed),
+            transparent_seed: transparent_seed.to_vec(),
+            state: State::Initial,
+            t_in: Vec::new(),
+            t_out: Vec::new(),
+            spends: Vec::new(),
+            outputs: Vec::new(),
+            spends_extracted: 0,
+            outputs_extracted: 0,
+            transparent_sigs: Vec::new(),
+            spend_sigs: Vec::new(),
+            transparent_sigs_extracted: 0,
+            spend_sigs_extracted: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state = State::Initial;
+        self.t_in.clear();
+        self.t_out.clear();
+        self.spends.clear();
+        self.outputs.clear();
+        self.spends_extracted = 0;
+        self.outputs_extracted = 0;
+        self.transparent_sigs.clear();
+        self.spend_sigs.clear();
+        self.transparent_sigs_extracted = 0;
+        self.spend_sigs_extracted = 0;
+    }
+
+    /// `INS_INIT_TX`: stores the transaction summary and draws the
+    /// randomness for every spend and output. Returns SHA256 of `data`.
+    pub fn init_tx(&mut self, data: &[u8]) -> Result<[u8; 32], ReturnCode> {
+        self.reset();
+        match self.extract_tx(data) {
+            Ok(()) => Ok(sha256(data)),
+            Err(()) => {
+                self.reset();
+                Err(ReturnCode::ExtractTransactionFail)
+            }
+        }
+    }
+
+    fn extract_tx(&mut self, data: &[u8]) -> Result<(), ()> {
+        if data.len() < 4 {
+            return Err(());
+        }
+        let (t_in_len, t_out_len) = (data[0] as usize, data[1] as usize);
+        let (spend_len, output_len) = (data[2] as usize, data[3] as usize);
+
+        if (spend_len > 0 && output_len < 2) || (spend_len == 0 && output_len == 1) {
+            return Err(());
+        }
+        let expected = t_in_len * T_IN_INPUT_LEN
+            + t_out_len * T_OUT_INPUT_LEN
+            + spend_len * SPEND_INPUT_LEN
+            + output_len * OUTPUT_INPUT_LEN;
+        if data.len() - 4 != expected {
+            return Err(());
+        }
+        if t_in_len + t_out_len + spend_len + output_len == 0 {
+            return Err(());
+        }
+        if [t_in_len, t_out_len, spend_len, output_len]
+            .iter()
+            .any(|&n| n > LIST_SIZE)
+        {
+            return Err(());
+        }
+
+        let mut chunks = &data[4..];
+        for _ in 0..t_in_len {
+            let (item, rest) = chunks.split_at(T_IN_INPUT_LEN);
+            let mut path = [0u32; 5];
+            for (i, p) in path.iter_mut().enumerate() {
+                *p = read_u32(&item[i * 4..]);
+            }
+            let mut script = [0u8; SCRIPT_SIZE];
+            script.copy_from_slice(&item[20..46]);
+            self.t_in.push(TransparentInput {
+                path,
+                script,
+                value: read_u64(&item[46..]),
+            });
+            chunks = rest;
+        }
+        for _ in 0..t_out_len {
+            let (item, rest) = chunks.split_at(T_OUT_INPUT_LEN);
+            let mut script = [0u8; SCRIPT_SIZE];
+            script.copy_from_slice(&item[..SCRIPT_SIZE]);
+            self.t_out.push(TransparentOutput {
+                script,
+                value: read_u64(&item[SCRIPT_SIZE..]),
+            });
+            chunks = rest;
+        }
+        for _ in 0..spend_len {
+            let (item, rest) = chunks.split_at(SPEND_INPUT_LEN);
+            let mut div = [0u8; 11];
+            div.copy_from_slice(&item[4..15]);
+            self.spends.push(SpendItem {
+                path: read_u32(item),
+                div: Diversifier(div),
+                pkd: read_array32(&item[15..47]),
+                value: read_u64(&item[47..]),
+                rcv: random_fr(),
+                alpha: random_fr(),
+            });
+            chunks = rest;
+        }
+        for _ in 0..output_len {
+            let (item, rest) = chunks.split_at(OUTPUT_INPUT_LEN);
+            let mut div = [0u8; 11];
+            div.copy_from_slice(&item[..11]);
+            let mut ovk = [0u8; OVK_SET_SIZE];
+            match item[52] {
+                0x00 => ovk[1..].copy_from_slice(&random_bytes32()),
+                0x01 => ovk.copy_from_slice(&item[52..85]),
+                _ => return Err(()),
+            }
+            self.outputs.push(OutputItem {
+                div: Diversifier(div),
+                pkd: read_array32(&item[11..43]),
+                value: read_u64(&item[43..]),
+                memotype: item[51],
+                ovk,
+                rcv: random_fr(),
+                // the rcm of pre-Canopy notes
+                rseed: random_fr(),
+            });
+            chunks = rest;
+        }
+
+        let total = i128::from(self.t_in.iter().map(|t| t.value).sum::<u64>())
+            + i128::from(self.spends.iter().map(|s| s.value).sum::<u64>())
+            - i128::from(self.t_out.iter().map(|t| t.value).sum::<u64>())
+            - i128::from(self.outputs.iter().map(|o| o.value).sum::<u64>());
+        // as the device: the legacy fixed fee or the ZIP-317 one
+        let fee = u64::try_from(total).map_err(|_| ())?;
+        if !fee_is_supported(
+            fee,
+            self.t_in.len(),
+            self.t_out.len(),
+            self.spends.len(),
+            self.outputs.len(),
+        ) {
+            return Err(());
+        }
+
+        self.state = if spend_len > 0 {
+            State::ProcessedInputs
+        } else if output_len > 0 {
+            State::ProcessedSpendExtractions
+        } else {
+            State::ProcessedAllExtractions
+        };
+        Ok(())
+    }
+
+    /// `INS_EXTRACT_SPEND`: `ak || nsk || rcv || alpha` for the next spend
+    pub fn extract_spend(&mut self) -> Result<[u8; 128], ReturnCode> {
+        if self.state != State::ProcessedInputs || self.spends_extracted >= self.spends.len() {
+            return Err(ReturnCode::DataInvalid);
+        }
+        let item = &self.spends[self.spends_extracted];
+        let (ak, nsk) = derive_proof_key(&self.sapling_seed, item.path);
+        let mut out = [0u8; 128];
+        out[..32].copy_from_slice(&ak.0);
+        out[32..64].copy_from_slice(nsk.as_bytes());
+        out[64..96].copy_from_slice(&item.rcv);
+        out[96..].copy_from_slice(&item.alpha);
+
+        self.spends_extracted += 1;
+        if self.spends_extracted == self.spends.len() {
+            self.state = State::ProcessedSpendExtractions;
+        }
+        Ok(out)
+    }
+
+    /// `INS_EXTRACT_OUTPUT`: `rcv || rseed`, followed by the hash seed when
+    /// the output has no ovk
+    pub fn extract_output(&mut self) -> Result<Vec<u8>, ReturnCode> {
+        if self.state != State::ProcessedSpendExtractions
+            || self.outputs_extracted >= self.outputs.len()
+        {
+            return Err(ReturnCode::DataInvalid);
+        }
+        let item = &self.outputs[self.outputs_extracted];
+        let mut out = Vec::with_capacity(96);
+        out.extend_from_slice(&item.rcv);
+        out.extend_from_slice(&item.rseed);
+        if item.ovk[0] == 0x00 {
+            out.extend_from_slice(&item.ovk[1..]);
+        }
+
+        self.outputs_extracted += 1;
+        if self.outputs_extracted == self.outputs.len() {
+            self.state = State::ProcessedAllExtractions;
+        }
+        Ok(out)
+    }
+
+    /// `INS_CHECKANDSIGN`: checks the builder's txdata against the stored
+    /// summary and signs every input. Returns SHA256 of `txdata`.
+    pub fn check_and_sign(&mut self, txdata: &[u8]) -> Result<[u8; 32], ReturnCode> {
+        if self.state != State::ProcessedAllExtractions {
+            self.reset();
+            return Err(ReturnCode::UnprocessedTx);
+        }
+        match self.check_and_sign_inner(txdata) {
+            Ok(()) => {
+                self.state = State::SignedTx;
+                Ok(sha256(txdata))
+            }
+            Err(e) => {
+                self.reset();
+                Err(e)
+            }
+        }
+    }
+
+    fn check_and_sign_inner(&mut self, txdata: &[u8]) -> Result<(), ReturnCode> {
+        let t_in_len = self.t_in.len() * T_IN_TX_LEN;
+        let spend_old_len = self.spends.len() * SPEND_OLD_TX_LEN;
+        let spend_len = self.spends.len() * SPEND_TX_LEN;
+        let output_len = self.outputs.len() * OUTPUT_TX_LEN;
+        let start_hashdata = t_in_len + spend_old_len + spend_len + output_len;
+        // the device reads the hash data before the length check in step 3
+        check(
+            txdata.len() >= start_hashdata + LENGTH_HASH_DATA,
+            ReturnCode::PrevoutInvalid,
+        )?;
+
+        let tin_data = &txdata[..t_in_len];
+        let spend_old_data = &txdata[t_in_len..t_in_len + spend_old_len];
+        let spend_data = &txdata[t_in_len + spend_old_len..t_in_len + spend_old_len + spend_len];
+        let output_data = &txdata[start_hashdata - output_len..start_hashdata];
+        let hashdata = &txdata[start_hashdata..start_hashdata + LENGTH_HASH_DATA];
+        let hash_at = |index: usize| &hashdata[index..index + 32];
+
+        let prevouts: Vec<u8> = tin_data
+            .chunks(T_IN_TX_LEN)
+            .flat_map(|t| t[..PREVOUT_SIZE].iter().copied())
+            .collect();
+        check(
+            blake2b256(PREVOUTS_HASH_PERSONALIZATION, &prevouts)[..]
+                == *hash_at(INDEX_HASH_PREVOUTSHASH),
+            ReturnCode::PrevoutInvalid,
+        )?;
+
+        let sequences: Vec<u8> = tin_data
+            .chunks(T_IN_TX_LEN)
+            .flat_map(|t| t[INDEX_TIN_SEQ..].iter().copied())
+            .collect();
+        check(
+            blake2b256(SEQUENCE_HASH_PERSONALIZATION, &sequences)[..]
+                == *hash_at(INDEX_HASH_SEQUENCEHASH),
+            ReturnCode::SequenceInvalid,
+        )?;
+
+        check(
+            txdata.len() == start_hashdata + LENGTH_HASH_DATA,
+            ReturnCode::OutputsInvalid,
+        )?;
+        let mut t_outputs = Vec::with_capacity(self.t_out.len() * (8 + SCRIPT_SIZE));
+        for t in self.t_out.iter() {
+            t_outputs.extend_from_slice(&t.value.to_le_bytes());
+            t_outputs.extend_from_slice(&t.script);
+        }
+        check(
+            blake2b256(OUTPUTS_HASH_PERSONALIZATION, &t_outputs)[..]
+                == *hash_at(INDEX_HASH_OUTPUTSHASH),
+            ReturnCode::OutputsInvalid,
+        )?;
+
+        check(
+            hash_at(INDEX_HASH_JOINSPLITSHASH).iter().all(|&b| b == 0),
+            ReturnCode::JoinsplitInvalid,
+        )?;
+
+        let value_balance = i128::from(self.spends.iter().map(|s| s.value).sum::<u64>())
+            - i128::from(self.outputs.iter().map(|o| o.value).sum::<u64>());
+        let mut vb = [0u8; 8];
+        vb.copy_from_slice(&hashdata[INDEX_HASH_VALUEBALANCE..INDEX_HASH_VALUEBALANCE + 8]);
+        check(
+            i128::from(i64::from_le_bytes(vb)) == value_balance,
+            ReturnCode::BadValueBalance,
+        )?;
+
+        self.check_spends(spend_old_data, spend_data)
+            .map_err(|_| ReturnCode::SpendInvalid)?;
+        let spends_hash = if self.spends.is_empty() {
+            [0u8; 32]
+        } else {
+            blake2b256(SHIELDED_SPENDS_HASH_PERSONALIZATION, spend_data)
+        };
+        check(
+            spends_hash[..] == *hash_at(INDEX_HASH_SHIELDEDSPENDHASH),
+            ReturnCode::SpendInvalid,
+        )?;
+
+        // like the device app on its default (mainnet) path
+        let policy = Zip212Policy::for_expiry_height(
+            Network::Mainnet,
+            read_u32(&hashdata[INDEX_HASH_EXPIRYHEIGHT..]),
+        );
+        self.check_outputs(output_data, &policy)
+            .map_err(|_| ReturnCode::OutputContentInvalid)?;
+        let outputs_hash = if self.outputs.is_empty() {
+            [0u8; 32]
+        } else {
+            blake2b256(SHIELDED_OUTPUTS_HASH_PERSONALIZATION, output_data)
+        };
+        check(
+            outputs_hash[..] == *hash_at(INDEX_HASH_SHIELDEDOUTPUTHASH),
+            ReturnCode::OutputContentInvalid,
+        )?;
+
+        self.check_encryptions(output_data, &policy)
+            .map_err(|_| ReturnCode::EncryptionInvalid)?;
+
+        self.transparent_sigs = self
+            .sign_transparent(tin_data, hashdata)
+            .map_err(|_| ReturnCode::CheckSignTrFail)?;
+
+        let sighash = blake2b256(SIGHASH_PERSONALIZATION, hashdata);
+        let mut spend_sigs = Vec::with_capacity(self.spends.len());
+        for item in self.spends.iter() {
+            let rsk = randomized_secret_from_sk(&self.sapling_seed, item.path, &item.alpha)
+                .map_err(|_| ReturnCode::SignSpendFail)?;
+            let sig = sign_spend(&rsk, &sighash).map_err(|_| ReturnCode::SignSpendFail)?;
+            spend_sigs.push(sig.0);
+        }
+        self.spend_sigs = spend_sigs;
+        Ok(())
+    }
+
+    fn check_spends(&self, spend_old_data: &[u8], spend_data: &[u8]) -> Result<(), ()> {
+        for (i, item) in self.spends.iter().enumerate() {
+            let old = &spend_old_data[i * SPEND_OLD_TX_LEN..(i + 1) * SPEND_OLD_TX_LEN];
+            let new = &spend_data[i * SPEND_TX_LEN..(i + 1) * SPEND_TX_LEN];
+            let (ask, nsk) = derive_ask_nsk(&self.sapling_seed, item.path);
+
+            let rk = rk(&ask, &item.alpha).map_err(|_| ())?;
+            let cv = value_commitment(item.value, &item.rcv);
+            let rcm = read_array32(old);
+            let cm =
+                note_commitment_full(item.value, &item.div, &item.pkd, &rcm).map_err(|_| ())?;
+            let notepos = read_u64(&old[INDEX_SPEND_OLD_NOTEPOS..]);
+            let nf = nullifier(&cm, notepos, &nsk).map_err(|_| ())?;
+            if new[INDEX_SPEND_RK..INDEX_SPEND_RK + 32] != rk
+                || new[..32] != cv.0
+                || new[INDEX_SPEND_NF..INDEX_SPEND_NF + 32] != nf.0
+            {
+                return Err(());
+            }
+        }
+        Ok(())
+    }
+
+    fn check_outputs(&self, output_data: &[u8], policy: &Zip212Policy) -> Result<(), ()> {
+        for (i, item) in self.outputs.iter().enumerate() {
+            let out = &output_data[i * OUTPUT_TX_LEN..(i + 1) * OUTPUT_TX_LEN];
+            let rcm = policy.output_randomness(item.rseed).rcm();
+            let cmu =
+                note_commitment_cmu(item.value, &item.div, &item.pkd, &rcm).map_err(|_| ())?;
+            let cv = value_commitment(item.value, &item.rcv);
+            if out[..32] != cv.0 || out[INDEX_OUTPUT_NOTECMT..INDEX_OUTPUT_NOTECMT + 32] != cmu {
+                return Err(());
+            }
+        }
+        Ok(())
+    }
+
+    fn check_encryptions(&self, output_data: &[u8], policy: &Zip212Policy) -> Result<(), ()> {
+        for (i, item) in self.outputs.iter().enumerate() {
+            let out = &output_data[i * OUTPUT_TX_LEN..(i + 1) * OUTPUT_TX_LEN];
+            let rnd = policy.output_randomness(item.rseed);
+            let esk = rnd.output_esk();
+            let epk = epk(&esk, &item.div).map_err(|_| ())?;
+            if out[INDEX_OUTPUT_EPK..INDEX_OUTPUT_EPK + 32] != epk {
+                return Err(());
+            }
+
+            let key = note_encryption_key(&esk, &item.pkd, &epk).map_err(|_| ())?;
+            let mut enc_ciphertext = [0u8; ENC_CIPHERTEXT_SIZE];
+            enc_ciphertext
+                .copy_from_slice(&out[INDEX_OUTPUT_ENC..INDEX_OUTPUT_ENC + ENC_CIPHERTEXT_SIZE]);
+            if !enc_ciphertext_matches_output(
+                &key,
+                &item.div,
+                item.value,
+                &rnd,
+                item.memotype,
+                &enc_ciphertext,
+            ) {
+                return Err(());
+            }
+
+            let (out_key, out_plaintext) = if item.ovk[0] != 0x00 {
+                let ovk = read_array32(&item.ovk[1..]);
+                let cv = read_array32(out);
+                let cmu = read_array32(&out[INDEX_OUTPUT_NOTECMT..]);
+                let mut plaintext = [0u8; 64];
+                plaintext[..32].copy_from_slice(&item.pkd);
+                plaintext[32..].copy_from_slice(&esk);
+                (prf_ock(&ovk, &cv, &cmu, &epk), plaintext)
+            } else {
+                // without an ovk the device fills out_ciphertext with values
+                // derived from the hash seed it handed to the builder
+                let mut hash_seed = item.ovk;
+                let key = sha256(&hash_seed);
+                let mut plaintext = [0u8; 64];
+                hash_seed[0] = 0x01;
+                plaintext[..32].copy_from_slice(&sha256(&hash_seed));
+                hash_seed[0] = 0x02;
+                plaintext[32..].copy_from_slice(&sha256(&hash_seed));
+                (key, plaintext)
+            };
+            if !ciphertext_prefix_matches(&out_key, &out_plaintext, &out[INDEX_OUTPUT_OUT..]) {
+                return Err(());
+            }
+        }
+        Ok(())
+    }
+
+    fn sign_transparent(&self, tin_data: &[u8], hashdata: &[u8]) -> Result<Vec<[u8; 64]>, ()> {
+        let secp = Secp256k1::new();
+        let mut signatures = Vec::with_capacity(self.t_in.len());
+        for (i, item) in self.t_in.iter().enumerate() {
+            let tin = &tin_data[i * T_IN_TX_LEN..(i + 1) * T_IN_TX_LEN];
+            let key = derive_transparent_key(&self.transparent_seed, &item.path).ok_or(())?;
+            let pubkey = PublicKey::from_secret_key(&secp, &key).serialize();
+            let script = pubkey_to_script(&pubkey);
+            if tin[INDEX_TIN_SCRIPT..INDEX_TIN_VALUE] != script || item.script != script {
+                return Err(());
+            }
+            if read_u64(&tin[INDEX_TIN_VALUE..]) != item.value {
+                return Err(());
+            }
+
+            let digest = Blake2bParams::new()
+                .hash_length(32)
+                .personal(SIGHASH_PERSONALIZATION)
+                .to_state()
+                .update(hashdata)
+                .update(tin)
+                .finalize();
+            let msg = Message::from_slice(digest.as_bytes()).map_err(|_| ())?;
+            signatures.push(secp.sign(&msg, &key).serialize_compact());
+        }
+        Ok(signatures)
+    }
+
+    /// `INS_EXTRACT_TRANSSIG`: next transparent signature, `r || s`
+    pub fn extract_transparent_sig(&mut self) -> Result<[u8; 64], ReturnCode> {
+        if self.state != State::SignedTx
+            || self.transparent_sigs_extracted >= self.transparent_sigs.len()
+        {
+            return Err(ReturnCode::DataInvalid);
+        }
+        let sig = self.transparent_sigs[self.transparent_sigs_extracted];
+        self.transparent_sigs_extracted += 1;
+        self.reset_if_drained();
+        Ok(sig)
+    }
+
+    /// `INS_EXTRACT_SPENDSIG`: next spend authorization signature
+    pub fn extract_spend_sig(&mut self) -> Result<[u8; 64], ReturnCode> {
+        if self.state != State::SignedTx || self.spend_sigs_extracted >= self.spend_sigs.len() {
+            return Err(ReturnCode::DataInvalid);
+        }
+        let sig = self.spend_sigs[self.spend_sigs_extracted];
+        self.spend_sigs_extracted += 1;
+        self.reset_if_drained();
+        Ok(sig)
+    }
+
+    fn reset_if_drained(&mut self) {
+        if self.transparent_sigs_extracted == self.transparent_sigs.len()
+            && self.spend_sigs_extracted == self.spend_sigs.len()
+        {
+            self.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use jubjub::ExtendedPoint;
+    use rslib::constants::{OUT_CIPHERTEXT_SIZE, OUT_PLAINTEXT_SIZE};
+    use rslib::fee::LEGACY_FEE;
+    use rslib::memo::{MEMO_EMPTY, MEMO_SIZE};
+    use rslib::note_encryption::{encrypt_note, encrypt_out_plaintext, note_plaintext};
+    use rslib::redjubjub::{randomized_pk, spend_auth_message};
+    use rslib::types::PaymentAddress as SaplingAddress;
+    use rslib::types::{Nsk, Signature};
+    use rslib::zip212::{CANOPY_HEIGHT_MAINNET, LEAD_BYTE_RCM};
+    use rslib::zip304::verify_spend_auth_sig;
+    use rslib::zip32::default_payment_address_from_startindex;
+    use serde_json::json;
+    use zcash_hsmbuilder::txprover::HsmTxProver;
+    use zcash_hsmbuilder::{
+        InitData, TransactionSignatures, TransparentInputBuilderInfo, TransparentOutputBuilderInfo,
+        ZcashBuilder,
+    };
+    use zcash_primitives::merkle_tree::MerklePath;
+    use zcash_primitives::redjubjub::{PublicKey as RedJubjubKey, Signature as RedJubjubSig};
+    use zcash_primitives::sapling::{Diversifier, Node, PaymentAddress, ProofGenerationKey, Rseed};
+    use zcash_primitives::transaction::components::{Amount, GROTH_PROOF_SIZE};
+    use zcash_primitives::transaction::Transaction;
+
+    const PATH: [u32; 5] = [0x8000_002c, 0x8000_0085, 0x8000_0000, 0, 0];
+
+    /// A transparent-only transaction needs no proofs nor binding signature
+    struct NoProofs;
+
+    impl HsmTxProver for NoProofs {
+        type SaplingProvingContext = ();
+
+        fn new_sapling_proving_context(&self) {}
+
+        #[allow(clippy::too_many_arguments)]
+        fn spend_proof(
+            &self,
+            _: &mut (),
+            _: ProofGenerationKey,
+            _: Diversifier,
+            _: Rseed,
+            _: jubjub::Fr,
+            _: u64,
+            _: bls12_381::Scalar,
+            _: MerklePath<Node>,
+            _: jubjub::Fr,
+        ) -> Result<([u8; GROTH_PROOF_SIZE], ExtendedPoint, RedJubjubKey), ()> {
+            unreachable!("no sapling spends")
+        }
+
+        fn output_proof(
+            &self,
+            _: &mut (),
+            _: jubjub::Fr,
+            _: PaymentAddress,
+            _: jubjub::Fr,
+            _: u64,
+            _: jubjub::Fr,
+        ) -> ([u8; GROTH_PROOF_SIZE], ExtendedPoint) {
+            unreachable!("no sapling outputs")
+        }
+
+        fn binding_sig(&self, _: &mut (), _: Amount, _: &[u8; 32]) -> Result<RedJubjubSig, ()> {
+            Err(())
+        }
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_builder_roundtrip_signs_transparent_input() {
+        let transparent_seed = [0x11u8; 64];
+        let mut signer = SoftwareSigner::new([0x22u8; 32], &transparent_seed);
+
+        let secp = Secp256k1::new();
+        let key = derive_transparent_key(&transparent_seed, &PATH).unwrap();
+        let pubkey = PublicKey::from_secret_key(&secp, &key);
+        let script = hex(&pubkey_to_script(&pubkey.serialize()));
+        let dest = hex(&pubkey_to_script(&[0x02u8; 33]));
+
+        let init: InitData = serde_json::from_value(json!({
+            "t_in": [{ "path": PATH, "address": script, "value": 60_000 }],
+            "t_out": [{ "address": dest, "value": 59_000 }],
+            "s_spend": [],
+            "s_output": [],
+        }))
+        .unwrap();
+        signer.init_tx(&init.to_hsm_bytes().unwrap()).unwrap();
+
+        let mut builder = ZcashBuilder::new(LEGACY_FEE);
+        let input: TransparentInputBuilderInfo = serde_json::from_value(json!({
+            "outp": hex(&[0x33u8; PREVOUT_SIZE]),
+            "pk": hex(&pubkey.serialize()),
+            "address": script,
+            "value": 60_000,
+        }))
+        .unwrap();
+        builder.add_transparent_input(input).unwrap();
+        let output: TransparentOutputBuilderInfo =
+            serde_json::from_value(json!({ "address": dest, "value": 59_000 })).unwrap();
+        builder.add_transparent_output(output).unwrap();
+        let txdata = builder.build(&mut NoProofs).unwrap();
+
+        assert_eq!(signer.check_and_sign(&txdata), Ok(sha256(&txdata)));
+        let sig = signer.extract_transparent_sig().unwrap();
+        assert_eq!(
+            signer.extract_transparent_sig(),
+            Err(ReturnCode::DataInvalid)
+        );
+
+        // the signature is over the builder's sighash data and input
+        let start_hashdata = T_IN_TX_LEN;
+        let digest = Blake2bParams::new()
+            .hash_length(32)
+            .personal(SIGHASH_PERSONALIZATION)
+            .to_state()
+            .update(&txdata[start_hashdata..start_hashdata + LENGTH_HASH_DATA])
+            .update(&txdata[..T_IN_TX_LEN])
+            .finalize();
+        let msg = Message::from_slice(digest.as_bytes()).unwrap();
+        let sig = secp256k1::Signature::from_compact(&sig).unwrap();
+        assert!(secp.verify(&msg, &sig, &pubkey).is_ok());
+
+        let signatures: TransactionSignatures = serde_json::from_value(json!({
+            "transparent_sigs": [sig.serialize_compact().to_vec()],
+            "spend_sigs": [],
+        }))
+        .unwrap();
+        builder.add_signatures(signatures).unwrap();
+        let tx = Transaction::read(&builder.finalize_js().unwrap()[..]).unwrap();
+        let script_sig = &tx.vin[0].script_sig.0;
+        let der = sig.serialize_der();
+        assert_eq!(&script_sig[1..1 + der.len()], &der[..]);
+    }
+
+    #[test]
+    fn test_fee_mismatch_is_rejected_at_inittx() {
+        let transparent_seed = [0x11u8; 64];
+        let script = hex(&pubkey_to_script(&[0x02u8; 33]));
+        let init_tx = |change: u64| {
+            let mut signer = SoftwareSigner::new([0x22u8; 32], &transparent_seed);
+            let init: InitData = serde_json::from_value(json!({
+                "t_in": [{ "path": PATH, "address": script, "value": 60_000 }],
+                "t_out": [{ "address": script, "value": change }],
+                "s_spend": [],
+                "s_output": [],
+            }))
+            .unwrap();
+            signer.init_tx(&init.to_hsm_bytes().unwrap())
+        };
+        // the legacy fee and the ZIP-317 fee of one input and one output
+        assert!(init_tx(60_000 - LEGACY_FEE).is_ok());
+        assert!(init_tx(50_000).is_ok());
+        assert_eq!(init_tx(45_000), Err(ReturnCode::ExtractTransactionFail));
+        assert_eq!(init_tx(60_000), Err(ReturnCode::ExtractTransactionFail));
+    }
+
+    const SAPLING_SEED: [u8; 32] = [0x22u8; 32];
+    const OVK: [u8; 32] = [0x44u8; 32];
+
+    fn sapling_address() -> SaplingAddress {
+        let mut start = Diversifier([0u8; 11]);
+        default_payment_address_from_startindex(&SpendingKey(SAPLING_SEED), 0, &mut start).unwrap()
+    }
+
+    /// Output description a host builds for `addr` from the randomness the
+    /// signer hands out, with an empty memo and no proof. Without an `ovk`
+    /// the out_ciphertext is filled from the hash seed.
+    fn output_description(
+        signer: &mut SoftwareSigner,
+        addr: &SaplingAddress,
+        value: u64,
+        ovk: Option<&[u8; 32]>,
+        policy: &Zip212Policy,
+    ) -> Vec<u8> {
+        let data = signer.extract_output().unwrap();
+        let rcv = read_array32(&data);
+        let rnd = policy.output_randomness(read_array32(&data[32..]));
+        let cv = value_commitment(value, &rcv).0;
+        let cmu = note_commitment_cmu(value, &addr.diversifier, &addr.pk_d, &rnd.rcm()).unwrap();
+        let esk = rnd.output_esk();
+        let epk = epk(&esk, &addr.diversifier).unwrap();
+
+        let key = note_encryption_key(&esk, &addr.pk_d, &epk).unwrap();
+        let mut memo = [0u8; MEMO_SIZE];
+        memo[0] = MEMO_EMPTY;
+        let enc = encrypt_note(&key, &note_plaintext(&addr.diversifier, value, &rnd, &memo));
+        let mut out_plaintext = [0u8; OUT_PLAINTEXT_SIZE];
+        let out_key = match ovk {
+            Some(ovk) => {
+                out_plaintext[..32].copy_from_slice(&addr.pk_d);
+                out_plaintext[32..].copy_from_slice(&esk);
+                prf_ock(ovk, &cv, &cmu, &epk)
+            }
+            None => {
+                let mut hash_seed = [0u8; 33];
+                hash_seed[1..].copy_from_slice(&data[64..96]);
+                let key = sha256(&hash_seed);
+                hash_seed[0] = 0x01;
+                out_plaintext[..32].copy_from_slice(&sha256(&hash_seed));
+                hash_seed[0] = 0x02;
+                out_plaintext[32..].copy_from_slice(&sha256(&hash_seed));
+                key
+            }
+        };
+        let out = encrypt_out_plaintext(&out_key, &out_plaintext);
+
+        let mut description = vec![0u8; OUTPUT_TX_LEN];
+        description[..32].copy_from_slice(&cv);
+        description[INDEX_OUTPUT_NOTECMT..INDEX_OUTPUT_EPK].copy_from_slice(&cmu);
+        description[INDEX_OUTPUT_EPK..INDEX_OUTPUT_ENC].copy_from_slice(&epk);
+        description[INDEX_OUTPUT_ENC..INDEX_OUTPUT_OUT].copy_from_slice(&enc);
+        description[INDEX_OUTPUT_OUT..INDEX_OUTPUT_OUT + OUT_CIPHERTEXT_SIZE].copy_from_slice(&out);
+        description
+    }
+
+    /// Sends a transparent input of 60000 to two sapling outputs expiring
+    /// at `expiry_height`, built by a host following `host_policy`
+    fn outputs_roundtrip(
+        expiry_height: u32,
+        host_policy: &Zip212Policy,
+    ) -> Result<[u8; 32], ReturnCode> {
+        let transparent_seed = [0x11u8; 64];
+        let mut signer = SoftwareSigner::new(SAPLING_SEED, &transparent_seed);
+        let secp = Secp256k1::new();
+        let key = derive_transparent_key(&transparent_seed, &PATH).unwrap();
+        let script = pubkey_to_script(&PublicKey::from_secret_key(&secp, &key).serialize());
+        let addr = sapling_address();
+        let values = [40_000u64, 20_000 - LEGACY_FEE];
+
+        let mut init = vec![1, 0, 0, values.len() as u8];
+        for p in PATH.iter() {
+            init.extend_from_slice(&p.to_le_bytes());
+        }
+        init.extend_from_slice(&script);
+        init.extend_from_slice(&60_000u64.to_le_bytes());
+        for value in values.iter() {
+            init.extend_from_slice(&addr.diversifier.0);
+            init.extend_from_slice(&addr.pk_d);
+            init.extend_from_slice(&value.to_le_bytes());
+            init.push(MEMO_EMPTY);
+            init.push(0x01);
+            init.extend_from_slice(&OVK);
+        }
+        signer.init_tx(&init).unwrap();
+
+        let mut t_in = vec![0x33u8; PREVOUT_SIZE];
+        t_in.extend_from_slice(&script);
+        t_in.extend_from_slice(&60_000u64.to_le_bytes());
+        t_in.extend_from_slice(&u32::MAX.to_le_bytes());
+        let mut outputs = Vec::new();
+        for value in values.iter() {
+            outputs.extend(output_description(
+                &mut signer,
+                &addr,
+                *value,
+                Some(&OVK),
+                host_policy,
+            ));
+        }
+
+        let mut hashdata = [0u8; LENGTH_HASH_DATA];
+        hashdata[..4].copy_from_slice(&0x8000_0004u32.to_le_bytes());
+        hashdata[4..8].copy_from_slice(&0x892f_2085u32.to_le_bytes());
+        hashdata[INDEX_HASH_PREVOUTSHASH..INDEX_HASH_SEQUENCEHASH].copy_from_slice(&blake2b256(
+            PREVOUTS_HASH_PERSONALIZATION,
+            &t_in[..PREVOUT_SIZE],
+        ));
+        hashdata[INDEX_HASH_SEQUENCEHASH..INDEX_HASH_OUTPUTSHASH].copy_from_slice(&blake2b256(
+            SEQUENCE_HASH_PERSONALIZATION,
+            &t_in[INDEX_TIN_SEQ..],
+        ));
+        hashdata[INDEX_HASH_OUTPUTSHASH..INDEX_HASH_JOINSPLITSHASH]
+            .copy_from_slice(&blake2b256(OUTPUTS_HASH_PERSONALIZATION, &[]));
+        hashdata[INDEX_HASH_SHIELDEDOUTPUTHASH..INDEX_HASH_SHIELDEDOUTPUTHASH + 32]
+            .copy_from_slice(&blake2b256(SHIELDED_OUTPUTS_HASH_PERSONALIZATION, &outputs));
+        hashdata[INDEX_HASH_EXPIRYHEIGHT..INDEX_HASH_VALUEBALANCE]
+            .copy_from_slice(&expiry_height.to_le_bytes());
+        let value_balance = -(values.iter().sum::<u64>() as i64);
+        hashdata[INDEX_HASH_VALUEBALANCE..INDEX_HASH_VALUEBALANCE + 8]
+            .copy_from_slice(&value_balance.to_le_bytes());
+        hashdata[INDEX_HASH_VALUEBALANCE + 8..].copy_from_slice(&1u32.to_le_bytes());
+
+        let txdata = [t_in, outputs, hashdata.to_vec()].concat();
+        signer.check_and_sign(&txdata)
+    }
+
+    /// Spends a 50000 note of account 0 to an output with an ovk and one
+    /// without; `tamper` may change the txdata before it is signed
+    fn shielded_roundtrip(tamper: impl FnOnce(&mut Vec<u8>)) -> Result<(), ReturnCode> {
+        let mut signer = SoftwareSigner::new(SAPLING_SEED, &[0x11u8; 64]);
+        let addr = sapling_address();
+        let spend_value = 50_000u64;
+        let values = [40_000u64, 10_000 - LEGACY_FEE];
+        let ovks = [Some(&OVK), None];
+
+        let mut init = vec![0, 0, 1, values.len() as u8];
+        init.extend_from_slice(&0u32.to_le_bytes());
+        init.extend_from_slice(&addr.to_bytes());
+        init.extend_from_slice(&spend_value.to_le_bytes());
+        for (value, ovk) in values.iter().zip(ovks.iter()) {
+            init.extend_from_slice(&addr.to_bytes());
+            init.extend_from_slice(&value.to_le_bytes());
+            init.push(MEMO_EMPTY);
+            match ovk {
+                Some(ovk) => {
+                    init.push(0x01);
+                    init.extend_from_slice(*ovk);
+                }
+                None => init.extend_from_slice(&[0u8; 33]),
+            }
+        }
+        signer.init_tx(&init).unwrap();
+
+        // the note being spent, as the host's wallet knows it
+        let spend = signer.extract_spend().unwrap();
+        let ak = read_array32(&spend);
+        let nsk = Nsk::from_bytes(read_array32(&spend[32..]));
+        let rcv = read_array32(&spend[64..]);
+        let alpha = read_array32(&spend[96..]);
+        let rcm = random_fr();
+        let notepos = 7u64;
+        let cm = note_commitment_full(spend_value, &addr.diversifier, &addr.pk_d, &rcm).unwrap();
+        let rk = randomized_pk(&ak, &alpha).unwrap();
+
+        let mut spend_old = rcm.to_vec();
+        spend_old.extend_from_slice(&notepos.to_le_bytes());
+        let mut spend_data = vec![0u8; SPEND_TX_LEN];
+        spend_data[..32].copy_from_slice(&value_commitment(spend_value, &rcv).0);
+        spend_data[INDEX_SPEND_NF..INDEX_SPEND_RK]
+            .copy_from_slice(&nullifier(&cm, notepos, &nsk).unwrap().0);
+        spend_data[INDEX_SPEND_RK..INDEX_SPEND_RK + 32].copy_from_slice(&rk);
+
+        let policy = Zip212Policy::for_expiry_height(Network::Mainnet, CANOPY_HEIGHT_MAINNET);
+        let mut outputs = Vec::new();
+        for (value, ovk) in values.iter().zip(ovks.iter()) {
+            outputs.extend(output_description(
+                &mut signer,
+                &addr,
+                *value,
+                *ovk,
+                &policy,
+            ));
+        }
+
+        let mut hashdata = [0u8; LENGTH_HASH_DATA];
+        hashdata[..4].copy_from_slice(&0x8000_0004u32.to_le_bytes());
+        hashdata[4..8].copy_from_slice(&0x892f_2085u32.to_le_bytes());
+        hashdata[INDEX_HASH_PREVOUTSHASH..INDEX_HASH_SEQUENCEHASH]
+            .copy_from_slice(&blake2b256(PREVOUTS_HASH_PERSONALIZATION, &[]));
+        hashdata[INDEX_HASH_SEQUENCEHASH..INDEX_HASH_OUTPUTSHASH]
+            .copy_from_slice(&blake2b256(SEQUENCE_HASH_PERSONALIZATION, &[]));
+        hashdata[INDEX_HASH_OUTPUTSHASH..INDEX_HASH_JOINSPLITSHASH]
+            .copy_from_slice(&blake2b256(OUTPUTS_HASH_PERSONALIZATION, &[]));
+        hashdata[INDEX_HASH_SHIELDEDSPENDHASH..INDEX_HASH_SHIELDEDOUTPUTHASH].copy_from_slice(
+            &blake2b256(SHIELDED_SPENDS_HASH_PERSONALIZATION, &spend_data),
+        );
+        hashdata[INDEX_HASH_SHIELDEDOUTPUTHASH..INDEX_HASH_SHIELDEDOUTPUTHASH + 32]
+            .copy_from_slice(&blake2b256(SHIELDED_OUTPUTS_HASH_PERSONALIZATION, &outputs));
+        hashdata[INDEX_HASH_EXPIRYHEIGHT..INDEX_HASH_VALUEBALANCE]
+            .copy_from_slice(&CANOPY_HEIGHT_MAINNET.to_le_bytes());
+        let value_balance = spend_value as i64 - values.iter().sum::<u64>() as i64;
+        hashdata[INDEX_HASH_VALUEBALANCE..INDEX_HASH_VALUEBALANCE + 8]
+            .copy_from_slice(&value_balance.to_le_bytes());
+        hashdata[INDEX_HASH_VALUEBALANCE + 8..].copy_from_slice(&1u32.to_le_bytes());
+
+        let mut txdata = [spend_old, spend_data, outputs, hashdata.to_vec()].concat();
+        tamper(&mut txdata);
+        assert_eq!(signer.check_and_sign(&txdata)?, sha256(&txdata));
+
+        // the spend signature verifies under rk for the sighash
+        let sighash = blake2b256(SIGHASH_PERSONALIZATION, &hashdata);
+        let sig = Signature(signer.extract_spend_sig().unwrap());
+        verify_spend_auth_sig(&rk, &spend_auth_message(&rk, &sighash), &sig).unwrap();
+        assert_eq!(signer.extract_spend_sig(), Err(ReturnCode::DataInvalid));
+        Ok(())
+    }
+
+    #[test]
+    fn test_shielded_roundtrip() {
+        let start_output = SPEND_OLD_TX_LEN + SPEND_TX_LEN;
+        let second_output = start_output + OUTPUT_TX_LEN;
+        assert_eq!(shielded_roundtrip(|_| ()), Ok(()));
+        // check_spends
+        assert_eq!(
+            shielded_roundtrip(|tx| tx[SPEND_OLD_TX_LEN + INDEX_SPEND_NF] ^= 1),
+            Err(ReturnCode::SpendInvalid)
+        );
+        // check_outputs
+        assert_eq!(
+            shielded_roundtrip(|tx| tx[start_output + INDEX_OUTPUT_NOTECMT] ^= 1),
+            Err(ReturnCode::OutputContentInvalid)
+        );
+        // check_encryptions, through the hash seed of the output without ovk;
+        // the shielded outputs hash is checked first, so it is fixed up
+        let fix_outputs_hash = |tx: &mut Vec<u8>| {
+            let outputs = &tx[start_output..start_output + 2 * OUTPUT_TX_LEN];
+            let hash = blake2b256(SHIELDED_OUTPUTS_HASH_PERSONALIZATION, outputs);
+            let at = start_output + 2 * OUTPUT_TX_LEN + INDEX_HASH_SHIELDEDOUTPUTHASH;
+            tx[at..at + 32].copy_from_slice(&hash);
+        };
+        assert_eq!(
+            shielded_roundtrip(|tx| {
+                tx[second_output + INDEX_OUTPUT_OUT] ^= 1;
+                fix_outputs_hash(tx);
+            }),
+            Err(ReturnCode::EncryptionInvalid)
+        );
+    }
+
+    #[test]
+    fn test_pre_canopy_outputs_roundtrip() {
+        let before = CANOPY_HEIGHT_MAINNET - 1;
+        let policy = Zip212Policy::for_expiry_height(Network::Mainnet, before);
+        assert_eq!(policy.output_lead_byte(), LEAD_BYTE_RCM);
+        assert!(outputs_roundtrip(before, &policy).is_ok());
+
+        // rseed notes are refused before Canopy
+        let canopy = Zip212Policy::for_expiry_height(Network::Mainnet, CANOPY_HEIGHT_MAINNET);
+        assert_eq!(
+            outputs_roundtrip(before, &canopy),
+            Err(ReturnCode::OutputContentInvalid)
+        );
+        assert!(outputs_roundtrip(CANOPY_HEIGHT_MAINNET, &canopy).is_ok());
+    }
+}
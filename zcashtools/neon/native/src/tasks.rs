@@ -0,0 +1,291 @@
+//! Background tasks for the builder bridge.
+//!
+//! Proof generation takes seconds per spend, so `build_async` and
+//! `finalize_async` run on the libuv thread pool and report back through a
+//! node-style callback. Progress is forwarded to JS with an `EventHandler`
+//! after every proof, and a shared flag lets JS cancel a running build.
+
+use std::cell::Cell;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use neon::event::EventHandler;
+use neon::prelude::*;
+use neon_serde::ResultExt;
+use zcash_hsmbuilder::txprover::HsmTxProver;
+use zcash_hsmbuilder::ZcashBuilder;
+use zcash_primitives::merkle_tree::MerklePath;
+use zcash_primitives::redjubjub::{PublicKey, Signature};
+use zcash_primitives::sapling::{Diversifier, Node, PaymentAddress, ProofGenerationKey, Rseed};
+use zcash_primitives::transaction::components::{Amount, GROTH_PROOF_SIZE};
+
+use crate::errors::BridgeError;
+use crate::params::load_prover;
+use crate::proven::{self, ProvenError};
+use crate::session::{Call, Recorded};
+
+pub const BUILD_CANCELLED: &str = "build cancelled";
+pub const BUILDER_BUSY: &str = "builder is busy with a background task";
+
+/// Proof that has just been generated, as reported to the progress callback
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProofStep {
+    Spend { index: usize, total: usize },
+    Output { index: usize, total: usize },
+}
+
+/// Wraps a (shared) prover to report every proof and to stop once `cancel`
+/// is set.
+///
+/// Output proofs cannot fail, so a cancellation raised while they run takes
+/// effect at the binding signature.
+pub struct ProgressProver<P, F> {
+    inner: P,
+    cancel: Arc<AtomicBool>,
+    on_proof: F,
+    spends: (Cell<usize>, usize),
+    outputs: (Cell<usize>, usize),
+}
+
+impl<P, F: Fn(ProofStep)> ProgressProver<P, F> {
+    pub fn new(
+        inner: P,
+        cancel: Arc<AtomicBool>,
+        total_spends: usize,
+        total_outputs: usize,
+        on_proof: F,
+    ) -> Self {
+        ProgressProver {
+            inner,
+            cancel,
+            on_proof,
+            spends: (Cell::new(0), total_spends),
+            outputs: (Cell::new(0), total_outputs),
+        }
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::SeqCst)
+    }
+}
+
+impl<P, T, F> HsmTxProver for ProgressProver<P, F>
+where
+    P: Deref<Target = T>,
+    T: HsmTxProver,
+    F: Fn(ProofStep),
+{
+    type SaplingProvingContext = T::SaplingProvingContext;
+
+    fn new_sapling_proving_context(&self) -> Self::SaplingProvingContext {
+        self.inner.new_sapling_proving_context()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn spend_proof(
+        &self,
+        ctx: &mut Self::SaplingProvingContext,
+        proof_generation_key: ProofGenerationKey,
+        diversifier: Diversifier,
+        rseed: Rseed,
+        ar: jubjub::Fr,
+        value: u64,
+        anchor: bls12_381::Scalar,
+        merkle_path: MerklePath<Node>,
+        rcv: jubjub::Fr,
+    ) -> Result<([u8; GROTH_PROOF_SIZE], jubjub::ExtendedPoint, PublicKey), ()> {
+        if self.cancelled() {
+            return Err(());
+        }
+        let proof = self.inner.spend_proof(
+            ctx,
+            proof_generation_key,
+            diversifier,
+            rseed,
+            ar,
+            value,
+            anchor,
+            merkle_path,
+            rcv,
+        )?;
+        let index = self.spends.0.get();
+        self.spends.0.set(index + 1);
+        (self.on_proof)(ProofStep::Spend {
+            index,
+            total: self.spends.1,
+        });
+        Ok(proof)
+    }
+
+    fn output_proof(
+        &self,
+        ctx: &mut Self::SaplingProvingContext,
+        esk: jubjub::Fr,
+        payment_address: PaymentAddress,
+        rcm: jubjub::Fr,
+        value: u64,
+        rcv: jubjub::Fr,
+    ) -> ([u8; GROTH_PROOF_SIZE], jubjub::ExtendedPoint) {
+        let proof = self
+            .inner
+            .output_proof(ctx, esk, payment_address, rcm, value, rcv);
+        let index = self.outputs.0.get();
+        self.outputs.0.set(index + 1);
+        (self.on_proof)(ProofStep::Output {
+            index,
+            total: self.outputs.1,
+        });
+        proof
+    }
+
+    fn binding_sig(
+        &self,
+        ctx: &mut Self::SaplingProvingContext,
+        value_balance: Amount,
+        sighash: &[u8; 32],
+    ) -> Result<Signature, ()> {
+        if self.cancelled() {
+            return Err(());
+        }
+        self.inner.binding_sig(ctx, value_balance, sighash)
+    }
+}
+
+/// Results of the last `build` and `finalize`, shared with the background
+/// tasks so that they are kept whichever way the builder was driven
+#[derive(Default)]
+pub struct BuildProducts {
+    pub txdata: Option<Vec<u8>>,
+    pub transaction: Option<Vec<u8>>,
+    /// `txdata` comes from a session or PCZT, not from this process's builder
+    pub restored: bool,
+}
+
+impl BuildProducts {
+    /// Signed transaction of a restored build, see `proven`
+    pub fn assemble(
+        &self,
+        calls: &[(Call, Recorded)],
+        signatures: Option<&Recorded>,
+    ) -> Result<Vec<u8>, BridgeError> {
+        let txdata = self.txdata.as_deref().ok_or(ProvenError::TxdataMismatch)?;
+        let signatures = signatures.ok_or(ProvenError::InvalidSignatures)?;
+        Ok(proven::assemble(txdata, calls, signatures)?)
+    }
+}
+
+/// Generates the proofs of a transaction on a worker thread
+pub struct BuildTask {
+    pub builder: Arc<Mutex<ZcashBuilder>>,
+    pub products: Arc<Mutex<BuildProducts>>,
+    pub cancel: Arc<AtomicBool>,
+    pub spendpath: String,
+    pub outputpath: String,
+    pub spends: usize,
+    pub outputs: usize,
+    pub progress: Option<EventHandler>,
+}
+
+impl BuildTask {
+    fn report(&self, step: ProofStep) {
+        if let Some(handler) = &self.progress {
+            handler.schedule(move |cx| {
+                let (kind, index, total) = match step {
+                    ProofStep::Spend { index, total } => ("spend", index, total),
+                    ProofStep::Output { index, total } => ("output", index, total),
+                };
+                let args: Vec<Handle<JsValue>> = vec![
+                    cx.string(kind).upcast(),
+                    cx.number(index as f64).upcast(),
+                    cx.number(total as f64).upcast(),
+                ];
+                args
+            });
+        }
+    }
+}
+
+impl Task for BuildTask {
+    type Output = Vec<u8>;
+    type Error = BridgeError;
+    type JsEvent = JsValue;
+
+    fn perform(&self) -> Result<Vec<u8>, BridgeError> {
+        let mut builder = self.builder.lock()?;
+        if self.cancel.load(Ordering::SeqCst) {
+            return Err(BridgeError::cancelled());
+        }
+        let params = load_prover(Path::new(&self.spendpath), Path::new(&self.outputpath))?;
+        let mut prover = ProgressProver::new(
+            params,
+            self.cancel.clone(),
+            self.spends,
+            self.outputs,
+            |step| self.report(step),
+        );
+        let txdata = builder.build(&mut prover).map_err(|e| {
+            if self.cancel.load(Ordering::SeqCst) {
+                BridgeError::cancelled()
+            } else {
+                BridgeError::builder(e)
+            }
+        })?;
+        let mut products = self.products.lock()?;
+        *products = BuildProducts {
+            txdata: Some(txdata.clone()),
+            transaction: None,
+            restored: false,
+        };
+        Ok(txdata)
+    }
+
+    fn complete(
+        self,
+        mut cx: TaskContext,
+        result: Result<Vec<u8>, BridgeError>,
+    ) -> JsResult<JsValue> {
+        match result {
+            Ok(value) => neon_serde::to_value(&mut cx, &value).throw(&mut cx),
+            Err(e) => e.throw(&mut cx),
+        }
+    }
+}
+
+/// Applies the signatures and serializes the transaction on a worker thread
+pub struct FinalizeTask {
+    pub builder: Arc<Mutex<ZcashBuilder>>,
+    pub products: Arc<Mutex<BuildProducts>>,
+    pub calls: Vec<(Call, Recorded)>,
+    pub signatures: Option<Recorded>,
+}
+
+impl Task for FinalizeTask {
+    type Output = Vec<u8>;
+    type Error = BridgeError;
+    type JsEvent = JsValue;
+
+    fn perform(&self) -> Result<Vec<u8>, BridgeError> {
+        let mut builder = self.builder.lock()?;
+        let mut products = self.products.lock()?;
+        let transaction = if products.restored {
+            products.assemble(&self.calls, self.signatures.as_ref())?
+        } else {
+            builder.finalize_js().map_err(BridgeError::builder)?
+        };
+        products.transaction = Some(transaction.clone());
+        Ok(transaction)
+    }
+
+    fn complete(
+        self,
+        mut cx: TaskContext,
+        result: Result<Vec<u8>, BridgeError>,
+    ) -> JsResult<JsValue> {
+        match result {
+            Ok(value) => neon_serde::to_value(&mut cx, &value).throw(&mut cx),
+            Err(e) => e.throw(&mut cx),
+        }
+    }
+}
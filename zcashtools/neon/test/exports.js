@@ -0,0 +1,35 @@
+// Checks the declarations of `lib/index.d.ts` against what the addon exports
+// in `register_module!` and what `lib/index.js` re-exports, so neither can
+// drift from the other. `test/types.ts` checks their signatures.
+
+const assert = require('assert');
+const fs = require('fs');
+const path = require('path');
+
+const native = require('../native');
+const lib = require('../lib');
+
+const dts = fs.readFileSync(path.resolve(__dirname, '../lib/index.d.ts'), 'utf8');
+
+function sorted(names) {
+  return [...new Set(names)].sort();
+}
+
+const declared = sorted([...dts.matchAll(/^export (?:function|class) (\w+)/gm)].map((m) => m[1]));
+assert.deepStrictEqual(declared, sorted(Object.keys(lib)), 'lib/index.d.ts and lib/index.js export different names');
+
+for (const name of Object.keys(native)) {
+  assert.ok(declared.includes(name), `${name} is exported by the addon but not declared`);
+}
+
+// methods of the native classes, against the body of their declaration
+for (const [, name, body] of dts.matchAll(/^export class (\w+) \{\n([\s\S]*?)^\}/gm)) {
+  if (!(name in native)) {
+    continue;
+  }
+  const methods = [...body.matchAll(/^ {2}(\w+)\(/gm)].map((m) => m[1]).filter((m) => m !== 'constructor');
+  const exported = Object.getOwnPropertyNames(native[name].prototype).filter((m) => m !== 'constructor');
+  assert.deepStrictEqual(sorted(methods), sorted(exported), `methods of ${name} differ from lib/index.d.ts`);
+}
+
+console.log(`lib/index.d.ts declares the ${declared.length} exports of the addon`);
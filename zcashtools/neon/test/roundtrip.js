@@ -0,0 +1,94 @@
+// Shielded build -> sign -> finalize round trip of the addon, with the
+// software signer standing in for the device.
+//
+// Needs the Sapling parameters, by default in `zcashtools/params`:
+//   SPEND_PATH=... OUTPUT_PATH=... node test/roundtrip.js
+
+const assert = require('assert');
+const crypto = require('crypto');
+const path = require('path');
+
+const { zcashtools, softwaresigner, get_inittx_data, decode_transaction, sapling_keys } = require('../');
+
+const SPEND_PATH = process.env.SPEND_PATH || path.resolve(__dirname, '../../params/sapling-spend.params');
+const OUTPUT_PATH = process.env.OUTPUT_PATH || path.resolve(__dirname, '../../params/sapling-output.params');
+
+const OK = 0x9000;
+const FEE = 1000;
+// fake Merkle path of the note, the signer does not check the anchor
+const WITNESS = '01305aef35a6fa9dd43af22d2557f99268fbab70a53e963fa67fc762391510406000000000';
+
+const saplingSeed = Buffer.alloc(32, 0x22);
+const bip39Seed = Buffer.alloc(64, 0x11);
+
+function sha256(bytes) {
+  return crypto.createHash('sha256').update(Buffer.from(bytes)).digest('hex');
+}
+
+function expectOk(reply) {
+  assert.strictEqual(reply.return_code, OK, reply.error_message);
+  return reply;
+}
+
+const keys = sapling_keys(saplingSeed, 0);
+const address = keys.address_raw.toString('hex');
+
+// a 50000 note of account 0 pays 40000 with the account ovk, and the rest
+// minus the fee to an output without ovk, whose out_ciphertext comes from
+// the hash seed of the signer
+const s_spend = { path: 0, address, value: 50000 };
+const s_outputs = [
+  { address, value: 40000, memo_type: 0xf6, ovk: keys.ovk.toString('hex') },
+  { address, value: 50000 - 40000 - FEE, memo_type: 0xf6, ovk: null },
+];
+
+const initdata = get_inittx_data({ t_in: [], t_out: [], s_spend: [s_spend], s_output: s_outputs });
+const signer = new softwaresigner(saplingSeed, bip39Seed);
+const init = expectOk(signer.inittx(initdata));
+assert.strictEqual(init.txdata.toString('hex'), sha256(initdata));
+
+const builder = new zcashtools(FEE);
+
+const spend = expectOk(signer.extractspenddata());
+builder.add_sapling_spend({
+  proofkey: spend.key_raw,
+  rcv: spend.rcv_raw,
+  alpha: spend.alpha_raw,
+  address: s_spend.address,
+  value: s_spend.value,
+  witness: WITNESS,
+  rseed: '00'.repeat(32),
+});
+
+for (const s_output of s_outputs) {
+  const output = expectOk(signer.extractoutputdata());
+  assert.strictEqual(output.hash_seed !== undefined, s_output.ovk === null);
+  builder.add_sapling_output({
+    rcv: output.rcv_raw,
+    rseed: output.rseed_raw,
+    ovk: s_output.ovk,
+    address: s_output.address,
+    value: s_output.value,
+    memo: '0000',
+    hash_seed: output.hash_seed,
+  });
+}
+assert.notStrictEqual(signer.extractoutputdata().return_code, OK);
+
+const txdata = builder.build(SPEND_PATH, OUTPUT_PATH);
+const signed = expectOk(signer.checkandsign(txdata));
+assert.strictEqual(signed.signdata.toString('hex'), sha256(txdata));
+
+const spendSig = expectOk(signer.extractspendsig());
+assert.notStrictEqual(signer.extractspendsig().return_code, OK);
+
+builder.add_signatures({ transparent_sigs: [], spend_sigs: [spendSig.sig_raw] });
+const tx = decode_transaction(builder.finalize());
+
+assert.strictEqual(tx.saplingSpends.length, 1);
+assert.strictEqual(tx.saplingSpends[0].spendAuthSig, spendSig.sig_raw.toString('hex'));
+assert.strictEqual(tx.saplingOutputs.length, s_outputs.length);
+assert.strictEqual(tx.valueBalance, FEE);
+assert.notStrictEqual(tx.bindingSig, null);
+
+console.log(`shielded round trip ok: ${tx.txid}`);
@@ -0,0 +1,149 @@
+// Typed usage of `lib/index.d.ts`, checked with `yarn typecheck`; never run.
+// `test/exports.js` checks the declared names against the addon's exports.
+
+import {
+  anti_exfil_commitment,
+  buildAsync,
+  BalanceError,
+  BuilderError,
+  compute_note_commitment,
+  compute_nullifier,
+  DecodedTransaction,
+  DecodeError,
+  decode_transaction,
+  diversifier_list,
+  finalizeAsync,
+  get_inittx_data,
+  InitData,
+  InvalidArgumentError,
+  load_params,
+  ParamsError,
+  Pczt,
+  PcztError,
+  pczt_parse,
+  pczt_serialize,
+  redjubjub_sign,
+  sapling_address,
+  sapling_keys,
+  SessionError,
+  softwaresigner,
+  value_commitment,
+  verify_anti_exfil,
+  zcashtools,
+  ZcashToolsError,
+  zip317_fee,
+} from '../lib';
+
+declare const bytes: Buffer;
+declare const address: string;
+
+async function usage(): Promise<void> {
+  const init: InitData = {
+    t_in: [{ path: [0x8000002c, 0x80000085, 0x80000000, 0, 0], address, value: 60000 }],
+    t_out: [{ address, value: 50000 }],
+    s_spend: [{ path: 0, address, value: 50000 }],
+    s_output: [{ address, value: 40000, memo_type: 0xf6, ovk: null }],
+  };
+  const initdata: number[] = get_inittx_data(init);
+  const fee: number = zip317_fee({ s_spend: init.s_spend });
+  load_params('sapling-spend.params', 'sapling-output.params');
+
+  const signer = new softwaresigner(bytes, bytes);
+  const reply = signer.inittx(initdata);
+  const code: number = reply.return_code;
+  const txdata: Buffer | undefined = reply.txdata;
+  const spend = signer.extractspenddata();
+  const output = signer.extractoutputdata();
+
+  const builder = new zcashtools('zip317');
+  new zcashtools(fee);
+  builder.add_transparent_input({ outp: address, pk: address, address, value: 60000 });
+  builder.add_transparent_output({ address, value: 50000 });
+  builder.add_sapling_spend({
+    proofkey: spend.key_raw!,
+    rcv: spend.rcv_raw!,
+    alpha: spend.alpha_raw!,
+    address,
+    value: 50000,
+    witness: address,
+    rseed: address,
+  });
+  builder.add_sapling_output({
+    rcv: output.rcv_raw!,
+    rseed: output.rseed_raw!,
+    ovk: null,
+    address,
+    value: 40000,
+    memo: '0000',
+    hash_seed: output.hash_seed,
+  });
+  builder.add_change_address({ address });
+  const total: number = builder.fee();
+  const change: number | null = builder.change_value();
+
+  const ledgerblob: number[] = builder.build('spend', 'output');
+  builder.build_async('spend', 'output', (err, blob) => {
+    const e: ZcashToolsError | null = err;
+    const b: number[] = blob;
+  }, (kind, index, count) => undefined);
+  await buildAsync(builder, 'spend', 'output', (kind: 'spend' | 'output') => undefined);
+  builder.cancel();
+
+  signer.checkandsign(ledgerblob).signdata;
+  builder.add_signatures({
+    transparent_sigs: [signer.extracttranssig().sig_raw!],
+    spend_sigs: [signer.extractspendsig().sig_raw!],
+  });
+  const tx: number[] = builder.finalize();
+  builder.finalize_async((err, raw) => undefined);
+  await finalizeAsync(builder);
+
+  const blob: Buffer = builder.export_session(bytes);
+  builder.resume_session(blob, bytes);
+  const pczt: Pczt = pczt_parse(builder.export_pczt());
+  const serialized: Buffer = pczt_serialize(pczt);
+  builder.import_pczt(serialized);
+  builder.add_pczt_signatures(pczt);
+
+  const decoded: DecodedTransaction = decode_transaction(tx, [60000]);
+  const valueBalance: number = decoded.valueBalance;
+  const sig: string | null = decoded.saplingSpends[0].spendAuthSig;
+
+  const commitment: number[] = anti_exfil_commitment(bytes);
+  const honest: boolean = verify_anti_exfil(commitment, bytes, bytes);
+
+  const keys = sapling_keys(bytes, 0);
+  const ovk: Buffer = keys.ovk;
+  sapling_address(bytes, 0, keys.diversifier).address;
+  const diversifiers: Buffer = diversifier_list(bytes, 0, keys.diversifier);
+  const { cm } = compute_note_commitment(50000, keys.diversifier, bytes, bytes);
+  const nf: Buffer = compute_nullifier(cm, 0, keys.nsk);
+  const cv: Buffer = value_commitment(50000, bytes);
+  redjubjub_sign(keys.ask, bytes, bytes).public_key;
+  redjubjub_sign(keys.ask, bytes);
+
+  try {
+    builder.build('spend', 'output');
+  } catch (e) {
+    if (
+      e instanceof InvalidArgumentError ||
+      e instanceof BuilderError ||
+      e instanceof BalanceError ||
+      e instanceof ParamsError ||
+      e instanceof SessionError ||
+      e instanceof PcztError ||
+      e instanceof DecodeError
+    ) {
+      const c: string = e.code;
+    }
+  }
+
+  // @ts-expect-error fee rules are a number or 'zip317'
+  new zcashtools('legacy');
+  // @ts-expect-error spends are added from the signer's randomness
+  builder.add_sapling_spend({ address, value: 50000 });
+  // @ts-expect-error the signer takes both seeds
+  new softwaresigner(bytes);
+}
+
+export { usage };